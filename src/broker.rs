@@ -1,57 +1,1406 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use serde_json::Value;
+
 use crate::OAuthCardError;
-use crate::model::{OAuthCardInput, TokenSet};
+use crate::correlation::CallContext;
+use crate::model::{
+    AccountSummary, BackChannelLogoutInput, BackendCapabilities, ConnectionSummary,
+    DeviceCodeGrant, OAuthCardInput, OidcEndpoints, SignInProgress, TokenSet, UserProfile,
+};
+use crate::scopes::Scope;
+use crate::validation;
 
+/// Broker the card flows get tokens from. Every call receives the
+/// invocation's [`CallContext`] first, for forwarding to the broker.
 pub trait OAuthBackend {
     fn get_token(
         &self,
+        ctx: &CallContext,
         provider_id: &str,
         subject: &str,
-        scopes: &[String],
+        scopes: &[Scope],
     ) -> Result<Option<TokenSet>, OAuthCardError>;
 
-    fn get_consent_url(
-        &self,
-        provider_id: &str,
-        subject: &str,
-        scopes: &[String],
-        redirect_path: &str,
-        extra_json: Option<String>,
-    ) -> Result<String, OAuthCardError>;
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError>;
+
+    /// Redeem an authorization code. Repeated calls with the same
+    /// `idempotency_key` should return the first exchange's token, or fail with
+    /// [`AlreadyExchanged`](crate::model::BrokerErrorCode::AlreadyExchanged)
+    /// once the token is stored.
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError>;
+
+    /// [`exchange_code`](Self::exchange_code) with the token-request
+    /// parameters of the sign-in (`resource`, `audience`, `dpop_proof`) and
+    /// its `state_id` as a JSON object.
+    /// The default serves brokers that cannot forward them: it ignores the
+    /// `state_id` but fails with `Unsupported` rather than drop a token-request
+    /// parameter.
+    #[allow(clippy::too_many_arguments)]
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        reject_token_request_params(extra_json.as_deref())?;
+        self.exchange_code(
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+        )
+    }
+
+    /// Report whether the authorization identified by `state_id` has finished.
+    fn poll_sign_in(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _state_id: &str,
+    ) -> Result<SignInProgress, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "sign-in polling not supported by this broker".into(),
+        ))
+    }
+
+    /// Invalidate a pending sign-in so its `state_id` can no longer be completed.
+    fn cancel_sign_in(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _state_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "sign-in cancellation not supported by this broker".into(),
+        ))
+    }
+
+    /// Exchange a channel-issued SSO token (on-behalf-of) for a provider token.
+    fn exchange_sso_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _sso_token: &str,
+        _scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "SSO token exchange not supported by this broker".into(),
+        ))
+    }
+
+    /// Use the stored refresh token to obtain a fresh access token without user interaction.
+    fn refresh_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "token refresh not supported by this broker".into(),
+        ))
+    }
+
+    /// Acquire an application (client credentials) token that is not tied to a user.
+    fn get_app_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "client credentials not supported by this broker".into(),
+        ))
+    }
+
+    /// Token exchange (RFC 8693): swap `subject_token` for a token with other
+    /// `scopes` and/or the `resource`/`audience` in `extra_json`.
+    #[allow(clippy::too_many_arguments)]
+    fn exchange_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _subject_token: &str,
+        _subject_token_type: &str,
+        _scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "token exchange not supported by this broker".into(),
+        ))
+    }
+
+    /// Revoke the stored token for a subject so the provider grant is no longer usable.
+    fn revoke_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "token revocation not supported by this broker".into(),
+        ))
+    }
+
+    /// Provider end-session (RP-initiated logout) URL for the subject's session.
+    fn get_end_session_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+    ) -> Result<String, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "end-session URL not supported by this broker".into(),
+        ))
+    }
+
+    /// URL where an administrator of `tenant` approves `scopes` for every user
+    /// of the tenant (Microsoft's `adminconsent` endpoint).
+    fn get_admin_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _tenant: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "admin consent not supported by this broker".into(),
+        ))
+    }
+
+    /// Whether an administrator of `tenant` has approved `scopes`.
+    fn admin_consent_granted(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _tenant: &str,
+        _scopes: &[Scope],
+    ) -> Result<bool, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "admin consent not supported by this broker".into(),
+        ))
+    }
+
+    /// Invalidate connections matching a provider session (`sub` and/or `sid`)
+    /// and return the affected subjects.
+    fn invalidate_sessions(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _sub: Option<&str>,
+        _sid: Option<&str>,
+    ) -> Result<Vec<String>, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "session invalidation not supported by this broker".into(),
+        ))
+    }
+
+    /// List every provider connection the broker holds for a subject.
+    fn list_connections(
+        &self,
+        _ctx: &CallContext,
+        _subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "listing connections not supported by this broker".into(),
+        ))
+    }
+
+    /// List the accounts a subject has connected for one provider.
+    fn list_accounts(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+    ) -> Result<Vec<AccountSummary>, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "multiple accounts not supported by this broker".into(),
+        ))
+    }
+
+    /// Make `account_id` the connection returned by subsequent `get_token` calls.
+    fn select_account(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _account_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "multiple accounts not supported by this broker".into(),
+        ))
+    }
+
+    /// Persist a token obtained elsewhere (token stores and caches implement this).
+    fn store_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "storing tokens not supported by this broker".into(),
+        ))
+    }
+
+    /// Profile of the connected account (OIDC UserInfo), used to label connected cards.
+    fn get_userinfo(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+    ) -> Result<UserProfile, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "userinfo not supported by this broker".into(),
+        ))
+    }
+
+    /// Endpoints from `{issuer}/.well-known/openid-configuration`, for
+    /// providers known only by their OIDC issuer.
+    fn discover_oidc(
+        &self,
+        _ctx: &CallContext,
+        _issuer: &str,
+    ) -> Result<OidcEndpoints, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "OIDC discovery not supported by this broker".into(),
+        ))
+    }
+
+    /// Start a device authorization grant; brokers without device-code support
+    /// keep the default and report `Unsupported`.
+    fn start_device_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> Result<DeviceCodeGrant, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "device code flow not supported by this broker".into(),
+        ))
+    }
+
+    /// Poll a pending device code; `Ok(None)` means the user has not finished yet.
+    fn poll_device_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _device_code: &str,
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "device code flow not supported by this broker".into(),
+        ))
+    }
+
+    /// Probe whether the broker is reachable. Backends without a remote
+    /// dependency keep the default and are always healthy.
+    fn health(&self, _ctx: &CallContext) -> Result<(), OAuthCardError> {
+        Ok(())
+    }
+
+    /// Optional operations this backend supports. Backends that do not
+    /// override it are assumed to support everything.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::all()
+    }
+}
+
+/// Async counterpart of [`OAuthBackend`] for native embedders whose broker client is
+/// async (reqwest, hyper). Every `OAuthBackend` type implements it through a
+/// blanket impl whose futures are immediately ready, which is what lets
+/// [`crate::logic::handle`] drive the shared async handlers synchronously.
+pub trait AsyncOAuthBackend {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> impl Future<Output = Result<Option<TokenSet>, OAuthCardError>> + Send;
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> impl Future<Output = Result<String, OAuthCardError>> + Send;
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send;
+
+    /// [`exchange_code`](Self::exchange_code) with the token-request
+    /// parameters of the sign-in (`resource`, `audience`, `dpop_proof`) and
+    /// its `state_id` as a JSON object.
+    /// The default serves brokers that cannot forward them: it ignores the
+    /// `state_id` but fails with `Unsupported` rather than drop a token-request
+    /// parameter.
+    #[allow(clippy::too_many_arguments)]
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        let rejected = reject_token_request_params(extra_json.as_deref());
+        let exchange = self.exchange_code(
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+        );
+        async move {
+            rejected?;
+            exchange.await
+        }
+    }
+
+    /// Report whether the authorization identified by `state_id` has finished.
+    fn poll_sign_in(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _state_id: &str,
+    ) -> impl Future<Output = Result<SignInProgress, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "sign-in polling not supported by this broker".into(),
+        )))
+    }
+
+    /// Invalidate a pending sign-in so its `state_id` can no longer be completed.
+    fn cancel_sign_in(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _state_id: &str,
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "sign-in cancellation not supported by this broker".into(),
+        )))
+    }
+
+    /// Exchange a channel-issued SSO token (on-behalf-of) for a provider token.
+    fn exchange_sso_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _sso_token: &str,
+        _scopes: &[Scope],
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "SSO token exchange not supported by this broker".into(),
+        )))
+    }
+
+    /// Use the stored refresh token to obtain a fresh access token without user interaction.
+    fn refresh_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "token refresh not supported by this broker".into(),
+        )))
+    }
+
+    /// Acquire an application (client credentials) token that is not tied to a user.
+    fn get_app_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "client credentials not supported by this broker".into(),
+        )))
+    }
+
+    /// Token exchange (RFC 8693): swap `subject_token` for a token with other
+    /// `scopes` and/or the `resource`/`audience` in `extra_json`.
+    #[allow(clippy::too_many_arguments)]
+    fn exchange_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _subject_token: &str,
+        _subject_token_type: &str,
+        _scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "token exchange not supported by this broker".into(),
+        )))
+    }
+
+    /// Revoke the stored token for a subject so the provider grant is no longer usable.
+    fn revoke_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "token revocation not supported by this broker".into(),
+        )))
+    }
+
+    /// Provider end-session (RP-initiated logout) URL for the subject's session.
+    fn get_end_session_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+    ) -> impl Future<Output = Result<String, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "end-session URL not supported by this broker".into(),
+        )))
+    }
+
+    /// URL where an administrator of `tenant` approves `scopes` for every user
+    /// of the tenant (Microsoft's `adminconsent` endpoint).
+    fn get_admin_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _tenant: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+    ) -> impl Future<Output = Result<String, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "admin consent not supported by this broker".into(),
+        )))
+    }
+
+    /// Whether an administrator of `tenant` has approved `scopes`.
+    fn admin_consent_granted(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _tenant: &str,
+        _scopes: &[Scope],
+    ) -> impl Future<Output = Result<bool, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "admin consent not supported by this broker".into(),
+        )))
+    }
+
+    /// Invalidate connections matching a provider session (`sub` and/or `sid`)
+    /// and return the affected subjects.
+    fn invalidate_sessions(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _sub: Option<&str>,
+        _sid: Option<&str>,
+    ) -> impl Future<Output = Result<Vec<String>, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "session invalidation not supported by this broker".into(),
+        )))
+    }
+
+    /// List every provider connection the broker holds for a subject.
+    fn list_connections(
+        &self,
+        _ctx: &CallContext,
+        _subject: &str,
+    ) -> impl Future<Output = Result<Vec<ConnectionSummary>, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "listing connections not supported by this broker".into(),
+        )))
+    }
+
+    /// List the accounts a subject has connected for one provider.
+    fn list_accounts(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+    ) -> impl Future<Output = Result<Vec<AccountSummary>, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "multiple accounts not supported by this broker".into(),
+        )))
+    }
+
+    /// Make `account_id` the connection returned by subsequent `get_token` calls.
+    fn select_account(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _account_id: &str,
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "multiple accounts not supported by this broker".into(),
+        )))
+    }
+
+    /// Persist a token obtained elsewhere (token stores and caches implement this).
+    fn store_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _token: &TokenSet,
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "storing tokens not supported by this broker".into(),
+        )))
+    }
+
+    /// Profile of the connected account (OIDC UserInfo), used to label connected cards.
+    fn get_userinfo(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+    ) -> impl Future<Output = Result<UserProfile, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "userinfo not supported by this broker".into(),
+        )))
+    }
+
+    /// Endpoints from `{issuer}/.well-known/openid-configuration`, for
+    /// providers known only by their OIDC issuer.
+    fn discover_oidc(
+        &self,
+        _ctx: &CallContext,
+        _issuer: &str,
+    ) -> impl Future<Output = Result<OidcEndpoints, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "OIDC discovery not supported by this broker".into(),
+        )))
+    }
+
+    /// Start a device authorization grant; brokers without device-code support
+    /// keep the default and report `Unsupported`.
+    fn start_device_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> impl Future<Output = Result<DeviceCodeGrant, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "device code flow not supported by this broker".into(),
+        )))
+    }
+
+    /// Poll a pending device code; `Ok(None)` means the user has not finished yet.
+    fn poll_device_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _device_code: &str,
+    ) -> impl Future<Output = Result<Option<TokenSet>, OAuthCardError>> + Send {
+        std::future::ready(Err(OAuthCardError::Unsupported(
+            "device code flow not supported by this broker".into(),
+        )))
+    }
+
+    /// Probe whether the broker is reachable. Backends without a remote
+    /// dependency keep the default and are always healthy.
+    fn health(
+        &self,
+        _ctx: &CallContext,
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(Ok(()))
+    }
+
+    /// Optional operations this backend supports. Backends that do not
+    /// override it are assumed to support everything.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::all()
+    }
+}
+
+impl<T: OAuthBackend> AsyncOAuthBackend for T {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> impl Future<Output = Result<Option<TokenSet>, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::get_token(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            scopes,
+        ))
+    }
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> impl Future<Output = Result<String, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::get_consent_url(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            scopes,
+            redirect_path,
+            extra_json,
+        ))
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::exchange_code(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+        ))
+    }
+
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::exchange_code_with_extra(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+            extra_json,
+        ))
+    }
+
+    fn poll_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> impl Future<Output = Result<SignInProgress, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::poll_sign_in(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            state_id,
+        ))
+    }
+
+    fn cancel_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::cancel_sign_in(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            state_id,
+        ))
+    }
+
+    fn exchange_sso_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        sso_token: &str,
+        scopes: &[Scope],
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::exchange_sso_token(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            sso_token,
+            scopes,
+        ))
+    }
+
+    fn refresh_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::refresh_token(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            scopes,
+        ))
+    }
+
+    fn get_app_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::get_app_token(
+            self,
+            ctx,
+            provider_id,
+            scopes,
+            extra_json,
+        ))
+    }
+
+    fn exchange_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        subject_token: &str,
+        subject_token_type: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> impl Future<Output = Result<TokenSet, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::exchange_token(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            subject_token,
+            subject_token_type,
+            scopes,
+            extra_json,
+        ))
+    }
+
+    fn revoke_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::revoke_token(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            scopes,
+        ))
+    }
+
+    fn get_end_session_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> impl Future<Output = Result<String, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::get_end_session_url(
+            self,
+            ctx,
+            provider_id,
+            subject,
+        ))
+    }
+
+    fn get_admin_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+    ) -> impl Future<Output = Result<String, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::get_admin_consent_url(
+            self,
+            ctx,
+            provider_id,
+            tenant,
+            scopes,
+            redirect_path,
+        ))
+    }
+
+    fn admin_consent_granted(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+    ) -> impl Future<Output = Result<bool, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::admin_consent_granted(
+            self,
+            ctx,
+            provider_id,
+            tenant,
+            scopes,
+        ))
+    }
+
+    fn invalidate_sessions(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        sub: Option<&str>,
+        sid: Option<&str>,
+    ) -> impl Future<Output = Result<Vec<String>, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::invalidate_sessions(
+            self,
+            ctx,
+            provider_id,
+            sub,
+            sid,
+        ))
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> impl Future<Output = Result<Vec<ConnectionSummary>, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::list_connections(self, ctx, subject))
+    }
+
+    fn list_accounts(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> impl Future<Output = Result<Vec<AccountSummary>, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::list_accounts(self, ctx, provider_id, subject))
+    }
+
+    fn select_account(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        account_id: &str,
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::select_account(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            account_id,
+        ))
+    }
+
+    fn store_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::store_token(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            token,
+        ))
+    }
+
+    fn get_userinfo(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> impl Future<Output = Result<UserProfile, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::get_userinfo(self, ctx, provider_id, subject))
+    }
+
+    fn discover_oidc(
+        &self,
+        ctx: &CallContext,
+        issuer: &str,
+    ) -> impl Future<Output = Result<OidcEndpoints, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::discover_oidc(self, ctx, issuer))
+    }
+
+    fn start_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> impl Future<Output = Result<DeviceCodeGrant, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::start_device_code(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            scopes,
+            extra_json,
+        ))
+    }
+
+    fn poll_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        device_code: &str,
+    ) -> impl Future<Output = Result<Option<TokenSet>, OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::poll_device_code(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            device_code,
+        ))
+    }
+
+    fn health(&self, ctx: &CallContext) -> impl Future<Output = Result<(), OAuthCardError>> + Send {
+        std::future::ready(OAuthBackend::health(self, ctx))
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        OAuthBackend::capabilities(self)
+    }
+}
+
+/// Forward every broker call of a pointer to a backend (`$ty`, over `T`) to
+/// the backend it points at.
+macro_rules! forward_backend {
+    ($($ty:ty),+) => {$(
+        impl<T: OAuthBackend + ?Sized> OAuthBackend for $ty {
+            fn get_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+            ) -> Result<Option<TokenSet>, OAuthCardError> {
+                (**self).get_token(ctx, provider_id, subject, scopes)
+            }
+
+            fn get_consent_url(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+                redirect_path: &str,
+                extra_json: Option<String>,
+            ) -> Result<String, OAuthCardError> {
+                (**self).get_consent_url(
+                    ctx,
+                    provider_id,
+                    subject,
+                    scopes,
+                    redirect_path,
+                    extra_json,
+                )
+            }
+
+            fn exchange_code(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                code: &str,
+                redirect_path: &str,
+                idempotency_key: Option<&str>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                (**self).exchange_code(
+                    ctx,
+                    provider_id,
+                    subject,
+                    code,
+                    redirect_path,
+                    idempotency_key,
+                )
+            }
+
+            fn exchange_code_with_extra(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                code: &str,
+                redirect_path: &str,
+                idempotency_key: Option<&str>,
+                extra_json: Option<String>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                (**self).exchange_code_with_extra(
+                    ctx,
+                    provider_id,
+                    subject,
+                    code,
+                    redirect_path,
+                    idempotency_key,
+                    extra_json,
+                )
+            }
+
+            fn poll_sign_in(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                state_id: &str,
+            ) -> Result<SignInProgress, OAuthCardError> {
+                (**self).poll_sign_in(ctx, provider_id, subject, state_id)
+            }
+
+            fn cancel_sign_in(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                state_id: &str,
+            ) -> Result<(), OAuthCardError> {
+                (**self).cancel_sign_in(ctx, provider_id, subject, state_id)
+            }
+
+            fn exchange_sso_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                sso_token: &str,
+                scopes: &[Scope],
+            ) -> Result<TokenSet, OAuthCardError> {
+                (**self).exchange_sso_token(ctx, provider_id, subject, sso_token, scopes)
+            }
+
+            fn refresh_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+            ) -> Result<TokenSet, OAuthCardError> {
+                (**self).refresh_token(ctx, provider_id, subject, scopes)
+            }
+
+            fn get_app_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                scopes: &[Scope],
+                extra_json: Option<String>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                (**self).get_app_token(ctx, provider_id, scopes, extra_json)
+            }
+
+            fn exchange_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                subject_token: &str,
+                subject_token_type: &str,
+                scopes: &[Scope],
+                extra_json: Option<String>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                (**self).exchange_token(
+                    ctx,
+                    provider_id,
+                    subject,
+                    subject_token,
+                    subject_token_type,
+                    scopes,
+                    extra_json,
+                )
+            }
+
+            fn revoke_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+            ) -> Result<(), OAuthCardError> {
+                (**self).revoke_token(ctx, provider_id, subject, scopes)
+            }
+
+            fn get_end_session_url(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+            ) -> Result<String, OAuthCardError> {
+                (**self).get_end_session_url(ctx, provider_id, subject)
+            }
+
+            fn get_admin_consent_url(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                tenant: &str,
+                scopes: &[Scope],
+                redirect_path: &str,
+            ) -> Result<String, OAuthCardError> {
+                (**self).get_admin_consent_url(ctx, provider_id, tenant, scopes, redirect_path)
+            }
+
+            fn admin_consent_granted(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                tenant: &str,
+                scopes: &[Scope],
+            ) -> Result<bool, OAuthCardError> {
+                (**self).admin_consent_granted(ctx, provider_id, tenant, scopes)
+            }
+
+            fn invalidate_sessions(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                sub: Option<&str>,
+                sid: Option<&str>,
+            ) -> Result<Vec<String>, OAuthCardError> {
+                (**self).invalidate_sessions(ctx, provider_id, sub, sid)
+            }
+
+            fn list_connections(
+                &self,
+                ctx: &CallContext,
+                subject: &str,
+            ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+                (**self).list_connections(ctx, subject)
+            }
+
+            fn list_accounts(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+            ) -> Result<Vec<AccountSummary>, OAuthCardError> {
+                (**self).list_accounts(ctx, provider_id, subject)
+            }
+
+            fn select_account(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                account_id: &str,
+            ) -> Result<(), OAuthCardError> {
+                (**self).select_account(ctx, provider_id, subject, account_id)
+            }
+
+            fn store_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                token: &TokenSet,
+            ) -> Result<(), OAuthCardError> {
+                (**self).store_token(ctx, provider_id, subject, token)
+            }
 
-    fn exchange_code(
-        &self,
-        provider_id: &str,
-        subject: &str,
-        code: &str,
-        redirect_path: &str,
-    ) -> Result<TokenSet, OAuthCardError>;
+            fn get_userinfo(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+            ) -> Result<UserProfile, OAuthCardError> {
+                (**self).get_userinfo(ctx, provider_id, subject)
+            }
+
+            fn discover_oidc(
+                &self,
+                ctx: &CallContext,
+                issuer: &str,
+            ) -> Result<OidcEndpoints, OAuthCardError> {
+                (**self).discover_oidc(ctx, issuer)
+            }
+
+            fn start_device_code(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+                extra_json: Option<String>,
+            ) -> Result<DeviceCodeGrant, OAuthCardError> {
+                (**self).start_device_code(ctx, provider_id, subject, scopes, extra_json)
+            }
+
+            fn poll_device_code(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                device_code: &str,
+            ) -> Result<Option<TokenSet>, OAuthCardError> {
+                (**self).poll_device_code(ctx, provider_id, subject, device_code)
+            }
+
+            fn health(&self, ctx: &CallContext) -> Result<(), OAuthCardError> {
+                (**self).health(ctx)
+            }
+
+            fn capabilities(&self) -> BackendCapabilities {
+                (**self).capabilities()
+            }
+        }
+    )+};
 }
 
-/// Default backend used in production (host-provided broker for wasm) or a
-/// no-op placeholder on native targets to keep tests predictable.
+// Shared backends (e.g. one registered with [`register_backend`]) and
+// borrowed ones (e.g. wrapped in a `MeteredBackend` by
+// [`crate::handle_message_with_backend`]) forward to the wrapped implementation.
+forward_backend!(Arc<T>, &T);
+
+/// Default backend used in production (host-provided broker for wasm), or a
+/// no-op placeholder on native targets to keep tests predictable. Native hosts
+/// select a real broker (e.g. `HttpBroker`) with [`register_backend`].
 pub fn default_backend() -> DefaultBackend {
     DefaultBackend::default()
 }
 
+static REGISTERED_BACKEND: OnceLock<Arc<dyn OAuthBackend + Send + Sync>> = OnceLock::new();
+
+/// Install the process-wide backend used by [`crate::handle_message`] instead of
+/// [`default_backend`], for native hosts embedding the crate with their own
+/// broker. Only the first registration takes effect.
+pub fn register_backend(
+    backend: impl OAuthBackend + Send + Sync + 'static,
+) -> Result<(), OAuthCardError> {
+    REGISTERED_BACKEND
+        .set(Arc::new(backend))
+        .map_err(|_| OAuthCardError::Invalid("a backend is already registered".into()))
+}
+
+pub(crate) fn registered_backend() -> Option<Arc<dyn OAuthBackend + Send + Sync>> {
+    REGISTERED_BACKEND.get().cloned()
+}
+
 #[cfg(target_arch = "wasm32")]
 type DefaultBackend = HostBroker;
 
 #[cfg(not(target_arch = "wasm32"))]
 type DefaultBackend = NoopBroker;
 
+/// Fail when `extra_json` carries a token-request parameter, for code
+/// exchanges by brokers that would drop it.
+fn reject_token_request_params(extra_json: Option<&str>) -> Result<(), OAuthCardError> {
+    let Some(extra) = extra_json.and_then(|extra| serde_json::from_str::<Value>(extra).ok()) else {
+        return Ok(());
+    };
+    match ["resource", "audience", "dpop_proof"]
+        .into_iter()
+        .find(|key| extra.get(key).is_some_and(|value| !value.is_null()))
+    {
+        Some(key) => Err(OAuthCardError::Unsupported(format!(
+            "this broker cannot send `{key}` with the code exchange"
+        ))),
+        None => Ok(()),
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 #[derive(Default, Clone)]
 pub struct HostBroker;
 
+/// The host bindings take scopes as plain strings.
+#[cfg(target_arch = "wasm32")]
+fn host_scopes(scopes: &[Scope]) -> Vec<String> {
+    scopes.iter().map(ToString::to_string).collect()
+}
+
 #[cfg(target_arch = "wasm32")]
 impl OAuthBackend for HostBroker {
+    // The host bindings take no call context; the host already knows the
+    // invocation's `ExecCtx`.
     fn get_token(
         &self,
+        _ctx: &CallContext,
         provider_id: &str,
         subject: &str,
-        scopes: &[String],
+        scopes: &[Scope],
     ) -> Result<Option<TokenSet>, OAuthCardError> {
-        let json = get_token(provider_id, subject, scopes);
+        let json = get_token(provider_id, subject, &host_scopes(scopes));
         if json.is_empty() {
             return Ok(None);
         }
@@ -62,16 +1411,17 @@ impl OAuthBackend for HostBroker {
 
     fn get_consent_url(
         &self,
+        _ctx: &CallContext,
         provider_id: &str,
         subject: &str,
-        scopes: &[String],
+        scopes: &[Scope],
         redirect_path: &str,
         extra_json: Option<String>,
     ) -> Result<String, OAuthCardError> {
         let url = get_consent_url(
             provider_id,
             subject,
-            scopes,
+            &host_scopes(scopes),
             redirect_path,
             extra_json.as_deref().unwrap_or_default(),
         );
@@ -80,16 +1430,26 @@ impl OAuthBackend for HostBroker {
 
     fn exchange_code(
         &self,
+        _ctx: &CallContext,
         provider_id: &str,
         subject: &str,
         code: &str,
         redirect_path: &str,
+        // The host binding takes no idempotency key, so the host cannot tell a
+        // retried exchange from a new one; `complete_sign_in` de-duplicates
+        // attempts by `state_id` in the state store before calling it.
+        _idempotency_key: Option<&str>,
     ) -> Result<TokenSet, OAuthCardError> {
         let json = exchange_code(provider_id, subject, code, redirect_path);
         let parsed: TokenSet = serde_json::from_str(&json)
             .map_err(|err| OAuthCardError::Parse(format!("exchange json: {err}")))?;
         Ok(parsed)
     }
+
+    /// Only token lookup, consent URLs and code exchange are bound.
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities::default()
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -100,18 +1460,20 @@ pub struct NoopBroker;
 impl OAuthBackend for NoopBroker {
     fn get_token(
         &self,
+        _ctx: &CallContext,
         _provider_id: &str,
         _subject: &str,
-        _scopes: &[String],
+        _scopes: &[Scope],
     ) -> Result<Option<TokenSet>, OAuthCardError> {
         Ok(None)
     }
 
     fn get_consent_url(
         &self,
+        _ctx: &CallContext,
         _provider_id: &str,
         _subject: &str,
-        _scopes: &[String],
+        _scopes: &[Scope],
         _redirect_path: &str,
         _extra_json: Option<String>,
     ) -> Result<String, OAuthCardError> {
@@ -120,15 +1482,82 @@ impl OAuthBackend for NoopBroker {
 
     fn exchange_code(
         &self,
+        _ctx: &CallContext,
         _provider_id: &str,
         _subject: &str,
         _code: &str,
         _redirect_path: &str,
+        _idempotency_key: Option<&str>,
     ) -> Result<TokenSet, OAuthCardError> {
         Err(OAuthCardError::Unsupported(
             "exchange_code unavailable on native test backend".into(),
         ))
     }
+
+    fn revoke_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        Ok(())
+    }
+
+    fn list_connections(
+        &self,
+        _ctx: &CallContext,
+        _subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        Ok(Vec::new())
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            revoke: true,
+            list_connections: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Responses a [`MockBroker`] hands out one per call, in order, before falling
+/// back to its plain fields. Clones share the same queue.
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Script<T>(Arc<Mutex<VecDeque<T>>>);
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl<T> Script<T> {
+    pub fn new(responses: impl IntoIterator<Item = T>) -> Self {
+        Self(Arc::new(Mutex::new(responses.into_iter().collect())))
+    }
+
+    /// Responses not consumed yet.
+    pub fn remaining(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn next(&self) -> Option<T> {
+        self.lock().pop_front()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<T>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T> Default for Script<T> {
+    fn default() -> Self {
+        Self::new([])
+    }
+}
+
+impl<T> Clone for Script<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
 }
 
 /// Simple in-memory broker used in tests.
@@ -137,45 +1566,591 @@ impl OAuthBackend for NoopBroker {
 pub struct MockBroker {
     pub token: Option<TokenSet>,
     pub consent_url: String,
+    pub device_grant: Option<DeviceCodeGrant>,
+    /// When set, `revoke_token` fails with this message.
+    pub revoke_error: Option<String>,
+    /// Token handed out by `refresh_token`; refresh fails when unset.
+    pub refreshed_token: Option<TokenSet>,
+    /// Token handed out by `exchange_token`; unsupported when unset.
+    pub exchanged_token: Option<TokenSet>,
+    pub connections: Vec<ConnectionSummary>,
+    pub accounts: Vec<AccountSummary>,
+    pub end_session_url: Option<String>,
+    /// Returned by `get_admin_consent_url`; unsupported when unset.
+    pub admin_consent_url: Option<String>,
+    /// Reported by `admin_consent_granted`; unsupported when unset.
+    pub admin_consent_granted: Option<bool>,
+    /// Subjects reported by `invalidate_sessions`.
+    pub invalidated_subjects: Vec<String>,
+    /// Result of `poll_sign_in`; falls back to `token` (completed) or pending.
+    pub sign_in_progress: Option<SignInProgress>,
+    pub userinfo: Option<UserProfile>,
+    /// Returned by `discover_oidc`; unsupported when unset.
+    pub oidc_endpoints: Option<OidcEndpoints>,
+    /// When set, `health` fails with this message as a transient error.
+    pub health_error: Option<String>,
+    /// Reported by `capabilities`; `None` means everything.
+    pub capabilities: Option<BackendCapabilities>,
+    /// Scripted `get_token` results, used before `token`.
+    pub get_token_script: Script<Result<Option<TokenSet>, OAuthCardError>>,
+    /// Scripted `exchange_code` results, used before `token`.
+    pub exchange_code_script: Script<Result<TokenSet, OAuthCardError>>,
+    /// Scripted `refresh_token` results, used before `refreshed_token`.
+    pub refresh_token_script: Script<Result<TokenSet, OAuthCardError>>,
+    /// Scripted `revoke_token` results, used before `revoke_error`.
+    pub revoke_token_script: Script<Result<(), OAuthCardError>>,
+    /// Every call received, in order; read it with [`MockBroker::calls`].
+    pub call_log: Arc<Mutex<Vec<BrokerCall>>>,
+}
+
+/// One call received by a [`MockBroker`]. Arguments a method does not take are
+/// left empty (e.g. `subject` for `get_app_token`).
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BrokerCall {
+    pub method: &'static str,
+    pub provider_id: String,
+    pub subject: String,
+    pub scopes: Vec<Scope>,
+    pub redirect_path: Option<String>,
+    pub extra_json: Option<String>,
+    pub idempotency_key: Option<String>,
+    /// Correlation id of the call's [`CallContext`].
+    pub correlation_id: Option<String>,
+    /// Tenant of the call's [`CallContext`].
+    pub tenant: Option<String>,
+    /// Device code polled by `poll_device_code`.
+    pub device_code: Option<String>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl BrokerCall {
+    fn new(ctx: &CallContext, method: &'static str, provider_id: &str, subject: &str) -> Self {
+        Self {
+            method,
+            provider_id: provider_id.into(),
+            subject: subject.into(),
+            correlation_id: ctx.correlation_id.clone(),
+            tenant: ctx.tenant.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl MockBroker {
+    /// Calls received so far, oldest first.
+    pub fn calls(&self) -> Vec<BrokerCall> {
+        self.log().clone()
+    }
+
+    /// Calls to one broker method, oldest first.
+    pub fn calls_to(&self, method: &str) -> Vec<BrokerCall> {
+        self.log()
+            .iter()
+            .filter(|call| call.method == method)
+            .cloned()
+            .collect()
+    }
+
+    fn record(&self, call: BrokerCall) {
+        self.log().push(call);
+    }
+
+    fn log(&self) -> std::sync::MutexGuard<'_, Vec<BrokerCall>> {
+        self.call_log
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 }
 
 impl OAuthBackend for MockBroker {
     fn get_token(
         &self,
-        _provider_id: &str,
-        _subject: &str,
-        _scopes: &[String],
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
     ) -> Result<Option<TokenSet>, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            ..BrokerCall::new(ctx, "get_token", provider_id, subject)
+        });
+        if let Some(response) = self.get_token_script.next() {
+            return response;
+        }
         Ok(self.token.clone())
     }
 
     fn get_consent_url(
         &self,
-        _provider_id: &str,
-        _subject: &str,
-        _scopes: &[String],
-        _redirect_path: &str,
-        _extra_json: Option<String>,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
     ) -> Result<String, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            redirect_path: Some(redirect_path.into()),
+            extra_json,
+            ..BrokerCall::new(ctx, "get_consent_url", provider_id, subject)
+        });
         Ok(self.consent_url.clone())
     }
 
     fn exchange_code(
         &self,
-        _provider_id: &str,
-        _subject: &str,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        OAuthBackend::exchange_code_with_extra(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+            None,
+        )
+    }
+
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
         _code: &str,
-        _redirect_path: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.record(BrokerCall {
+            redirect_path: Some(redirect_path.into()),
+            idempotency_key: idempotency_key.map(str::to_string),
+            extra_json,
+            ..BrokerCall::new(ctx, "exchange_code", provider_id, subject)
+        });
+        if let Some(response) = self.exchange_code_script.next() {
+            return response;
+        }
+        self.token
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no token in mock".into()))
+    }
+
+    fn poll_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _state_id: &str,
+    ) -> Result<SignInProgress, OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "poll_sign_in", provider_id, subject));
+        Ok(match (&self.sign_in_progress, &self.token) {
+            (Some(progress), _) => progress.clone(),
+            (None, Some(token)) => SignInProgress::Completed {
+                token: token.clone(),
+            },
+            (None, None) => SignInProgress::Pending,
+        })
+    }
+
+    fn cancel_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _state_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "cancel_sign_in", provider_id, subject));
+        Ok(())
+    }
+
+    fn exchange_sso_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _sso_token: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            ..BrokerCall::new(ctx, "exchange_sso_token", provider_id, subject)
+        });
+        self.token
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no token in mock".into()))
+    }
+
+    fn refresh_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            ..BrokerCall::new(ctx, "refresh_token", provider_id, subject)
+        });
+        if let Some(response) = self.refresh_token_script.next() {
+            return response;
+        }
+        self.refreshed_token
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no refreshed token in mock".into()))
+    }
+
+    fn get_app_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
     ) -> Result<TokenSet, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            extra_json,
+            ..BrokerCall::new(ctx, "get_app_token", provider_id, "")
+        });
         self.token
             .clone()
             .ok_or_else(|| OAuthCardError::Unsupported("no token in mock".into()))
     }
+
+    fn exchange_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _subject_token: &str,
+        _subject_token_type: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            extra_json,
+            ..BrokerCall::new(ctx, "exchange_token", provider_id, subject)
+        });
+        self.exchanged_token
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no exchanged token in mock".into()))
+    }
+
+    fn revoke_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            ..BrokerCall::new(ctx, "revoke_token", provider_id, subject)
+        });
+        if let Some(response) = self.revoke_token_script.next() {
+            return response;
+        }
+        match &self.revoke_error {
+            Some(message) => Err(OAuthCardError::Unsupported(message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn get_end_session_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.record(BrokerCall::new(
+            ctx,
+            "get_end_session_url",
+            provider_id,
+            subject,
+        ));
+        self.end_session_url
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no end-session url in mock".into()))
+    }
+
+    fn get_admin_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        _tenant: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            redirect_path: Some(redirect_path.to_string()),
+            ..BrokerCall::new(ctx, "get_admin_consent_url", provider_id, "")
+        });
+        self.admin_consent_url
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no admin consent url in mock".into()))
+    }
+
+    fn admin_consent_granted(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        _tenant: &str,
+        scopes: &[Scope],
+    ) -> Result<bool, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            ..BrokerCall::new(ctx, "admin_consent_granted", provider_id, "")
+        });
+        self.admin_consent_granted
+            .ok_or_else(|| OAuthCardError::Unsupported("no admin consent state in mock".into()))
+    }
+
+    fn invalidate_sessions(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        sub: Option<&str>,
+        _sid: Option<&str>,
+    ) -> Result<Vec<String>, OAuthCardError> {
+        self.record(BrokerCall::new(
+            ctx,
+            "invalidate_sessions",
+            provider_id,
+            sub.unwrap_or_default(),
+        ));
+        Ok(self.invalidated_subjects.clone())
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "list_connections", "", subject));
+        Ok(self.connections.clone())
+    }
+
+    fn list_accounts(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<Vec<AccountSummary>, OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "list_accounts", provider_id, subject));
+        Ok(self.accounts.clone())
+    }
+
+    fn select_account(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        account_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "select_account", provider_id, subject));
+        if self.accounts.iter().any(|a| a.account_id == account_id) {
+            Ok(())
+        } else {
+            Err(OAuthCardError::Invalid(format!(
+                "unknown account {account_id}"
+            )))
+        }
+    }
+
+    fn get_userinfo(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<UserProfile, OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "get_userinfo", provider_id, subject));
+        self.userinfo
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no userinfo in mock".into()))
+    }
+
+    fn discover_oidc(
+        &self,
+        ctx: &CallContext,
+        issuer: &str,
+    ) -> Result<OidcEndpoints, OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "discover_oidc", issuer, ""));
+        self.oidc_endpoints
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no discovery in mock".into()))
+    }
+
+    fn start_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<DeviceCodeGrant, OAuthCardError> {
+        self.record(BrokerCall {
+            scopes: scopes.to_vec(),
+            extra_json,
+            ..BrokerCall::new(ctx, "start_device_code", provider_id, subject)
+        });
+        self.device_grant
+            .clone()
+            .ok_or_else(|| OAuthCardError::Unsupported("no device grant in mock".into()))
+    }
+
+    fn poll_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        device_code: &str,
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        self.record(BrokerCall {
+            device_code: Some(device_code.into()),
+            ..BrokerCall::new(ctx, "poll_device_code", provider_id, subject)
+        });
+        Ok(self.token.clone())
+    }
+
+    fn health(&self, ctx: &CallContext) -> Result<(), OAuthCardError> {
+        self.record(BrokerCall::new(ctx, "health", "", ""));
+        match &self.health_error {
+            Some(message) => Err(OAuthCardError::Transient(message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        self.capabilities.unwrap_or_else(BackendCapabilities::all)
+    }
 }
 
+/// Newest input payload version. Version 1 is the flat `OAuthCardInput`
+/// object; version 2 wraps it as `{"version": 2, "input": {...}}` so request
+/// metadata can sit next to the card input.
+pub const CURRENT_INPUT_VERSION: u32 = 2;
+
+/// Parse an input payload of any supported version.
 pub fn parse_input(input: &str) -> Result<OAuthCardInput, OAuthCardError> {
-    serde_json::from_str::<OAuthCardInput>(input.trim())
-        .map_err(|err| OAuthCardError::Parse(format!("input json: {err}")))
+    let payload = serde_json::from_str::<Value>(input.trim())
+        .map_err(|err| OAuthCardError::Parse(format!("input json: {err}")))?;
+    let payload = upgrade_input(payload)?;
+    let input = serde_json::from_value::<OAuthCardInput>(payload.clone()).map_err(|err| {
+        match validation::reject(validation::field_errors(&payload)) {
+            Err(invalid) => invalid,
+            Ok(()) => OAuthCardError::Parse(format!("input json: {err}")),
+        }
+    })?;
+    if input.strict {
+        reject_unknown_fields(&input, &payload)?;
+    }
+    Ok(input)
+}
+
+/// Strict mode: every payload key must name an `OAuthCardInput` field, either
+/// as written (snake_case) or as its camelCase alias.
+fn reject_unknown_fields(input: &OAuthCardInput, payload: &Value) -> Result<(), OAuthCardError> {
+    let known = serde_json::to_value(input)
+        .map_err(|err| OAuthCardError::Parse(format!("input json: {err}")))?;
+    let (Some(known), Some(fields)) = (known.as_object(), payload.as_object()) else {
+        return Ok(());
+    };
+    match fields.keys().find(|name| {
+        !known
+            .keys()
+            .any(|field| field == *name || camel_case(field) == **name)
+    }) {
+        Some(name) => Err(OAuthCardError::Parse(format!(
+            "unknown input field `{name}`"
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn camel_case(field: &str) -> String {
+    let mut words = field.split('_');
+    let mut camel = words.next().unwrap_or_default().to_string();
+    for word in words {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+/// Rewrite an older payload into the current `OAuthCardInput` shape, keeping
+/// the version it arrived in.
+fn upgrade_input(mut payload: Value) -> Result<Value, OAuthCardError> {
+    let version = match payload.get("version") {
+        None | Some(Value::Null) => 1,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| OAuthCardError::Parse(format!("input version {version}")))?,
+    };
+    let mut input = match version {
+        1 => payload,
+        2 => match payload.get_mut("input").map(Value::take) {
+            Some(input @ Value::Object(_)) => input,
+            _ => {
+                return Err(OAuthCardError::Parse(
+                    "version 2 input needs an `input` object".into(),
+                ));
+            }
+        },
+        other => {
+            return Err(OAuthCardError::Parse(format!(
+                "unsupported input version {other} (newest is {CURRENT_INPUT_VERSION})"
+            )));
+        }
+    };
+    if let Some(fields) = input.as_object_mut() {
+        fields.insert("version".into(), Value::from(version));
+    }
+    Ok(input)
+}
+
+/// Serialize `input` as a payload of the given version, as `parse_input`
+/// accepts it.
+pub fn encode_input(input: &OAuthCardInput, version: u32) -> Result<String, OAuthCardError> {
+    let mut fields = serde_json::to_value(input)
+        .map_err(|err| OAuthCardError::Parse(format!("input json: {err}")))?;
+    if let Some(fields) = fields.as_object_mut() {
+        fields.insert("version".into(), Value::from(version));
+    }
+    let payload = match version {
+        1 => fields,
+        2 => {
+            if let Some(fields) = fields.as_object_mut() {
+                fields.remove("version");
+            }
+            serde_json::json!({ "version": 2, "input": fields })
+        }
+        other => {
+            return Err(OAuthCardError::Invalid(format!(
+                "unsupported input version {other}"
+            )));
+        }
+    };
+    Ok(payload.to_string())
+}
+
+pub fn parse_logout_input(input: &str) -> Result<BackChannelLogoutInput, OAuthCardError> {
+    serde_json::from_str::<BackChannelLogoutInput>(input.trim())
+        .map_err(|err| OAuthCardError::Parse(format!("logout input json: {err}")))
 }
 #[cfg(target_arch = "wasm32")]
 use greentic_interfaces_guest::oauth_broker_client::{exchange_code, get_consent_url, get_token};
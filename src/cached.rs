@@ -0,0 +1,417 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::clock::now_secs;
+use crate::correlation::CallContext;
+use crate::model::{
+    AccountSummary, BackendCapabilities, ConnectionSummary, DeviceCodeGrant, OidcEndpoints,
+    SignInProgress, TokenSet, UserProfile,
+};
+use crate::scopes::Scope;
+
+/// Cache key: provider, subject and the sorted, de-duplicated scopes.
+type Key = (String, String, Vec<Scope>);
+
+struct Entry {
+    token: Option<TokenSet>,
+    cached_at: u64,
+}
+
+/// Memoizes `get_token` per provider/subject/scopes so flows calling
+/// ensure-token several times per turn hit the broker once.
+///
+/// Without a TTL entries live as long as the wrapper (build one per invocation);
+/// with [`CachedBackend::with_ttl`] a long-lived wrapper re-fetches after the TTL.
+/// Tokens are never served past their `expires_at`, and every call that changes
+/// a subject's tokens (exchange, refresh, revoke, account switch, ...) drops
+/// that subject's entries.
+pub struct CachedBackend<B> {
+    inner: B,
+    ttl_secs: Option<u64>,
+    entries: Mutex<BTreeMap<Key, Entry>>,
+}
+
+impl<B: OAuthBackend> CachedBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            ttl_secs: None,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Keep entries for at most `ttl_secs` seconds.
+    pub fn with_ttl(mut self, ttl_secs: u64) -> Self {
+        self.ttl_secs = Some(ttl_secs);
+        self
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Drop every cached entry.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<Key, Entry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn forget(&self, provider_id: &str, subject: &str) {
+        self.lock()
+            .retain(|(provider, owner, _), _| provider != provider_id || owner != subject);
+    }
+
+    fn forget_provider(&self, provider_id: &str) {
+        self.lock()
+            .retain(|(provider, _, _), _| provider != provider_id);
+    }
+
+    fn is_fresh(&self, entry: &Entry, now: u64) -> bool {
+        let within_ttl = self
+            .ttl_secs
+            .is_none_or(|ttl| now < entry.cached_at.saturating_add(ttl));
+        let unexpired = entry
+            .token
+            .as_ref()
+            .and_then(|token| token.expires_at)
+            .is_none_or(|expires_at| now < expires_at);
+        within_ttl && unexpired
+    }
+
+    fn invalidating<T>(
+        &self,
+        provider_id: &str,
+        subject: &str,
+        result: Result<T, OAuthCardError>,
+    ) -> Result<T, OAuthCardError> {
+        self.forget(provider_id, subject);
+        result
+    }
+}
+
+fn key(provider_id: &str, subject: &str, scopes: &[Scope]) -> Key {
+    let mut scopes = scopes.to_vec();
+    scopes.sort();
+    scopes.dedup();
+    (provider_id.to_string(), subject.to_string(), scopes)
+}
+
+impl<B: OAuthBackend> OAuthBackend for CachedBackend<B> {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        let key = key(provider_id, subject, scopes);
+        let now = now_secs();
+        if let Some(entry) = self.lock().get(&key)
+            && self.is_fresh(entry, now)
+        {
+            return Ok(entry.token.clone());
+        }
+        let token = self.inner.get_token(ctx, provider_id, subject, scopes)?;
+        self.lock().insert(
+            key,
+            Entry {
+                token: token.clone(),
+                cached_at: now,
+            },
+        );
+        Ok(token)
+    }
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        self.inner
+            .get_consent_url(ctx, provider_id, subject, scopes, redirect_path, extra_json)
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        let result = self.inner.exchange_code(
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+        );
+        self.invalidating(provider_id, subject, result)
+    }
+
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        let result = self.inner.exchange_code_with_extra(
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+            extra_json,
+        );
+        self.invalidating(provider_id, subject, result)
+    }
+
+    fn poll_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> Result<SignInProgress, OAuthCardError> {
+        let result = self.inner.poll_sign_in(ctx, provider_id, subject, state_id);
+        if matches!(result, Ok(SignInProgress::Completed { .. })) {
+            self.forget(provider_id, subject);
+        }
+        result
+    }
+
+    fn cancel_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        self.inner
+            .cancel_sign_in(ctx, provider_id, subject, state_id)
+    }
+
+    fn exchange_sso_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        sso_token: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        let result = self
+            .inner
+            .exchange_sso_token(ctx, provider_id, subject, sso_token, scopes);
+        self.invalidating(provider_id, subject, result)
+    }
+
+    fn refresh_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        let result = self.inner.refresh_token(ctx, provider_id, subject, scopes);
+        self.invalidating(provider_id, subject, result)
+    }
+
+    fn get_app_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.inner
+            .get_app_token(ctx, provider_id, scopes, extra_json)
+    }
+
+    fn exchange_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        subject_token: &str,
+        subject_token_type: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.inner.exchange_token(
+            ctx,
+            provider_id,
+            subject,
+            subject_token,
+            subject_token_type,
+            scopes,
+            extra_json,
+        )
+    }
+
+    fn revoke_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        let result = self.inner.revoke_token(ctx, provider_id, subject, scopes);
+        self.invalidating(provider_id, subject, result)
+    }
+
+    fn get_end_session_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.inner.get_end_session_url(ctx, provider_id, subject)
+    }
+
+    fn get_admin_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.inner
+            .get_admin_consent_url(ctx, provider_id, tenant, scopes, redirect_path)
+    }
+
+    fn admin_consent_granted(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+    ) -> Result<bool, OAuthCardError> {
+        self.inner
+            .admin_consent_granted(ctx, provider_id, tenant, scopes)
+    }
+
+    fn invalidate_sessions(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        sub: Option<&str>,
+        sid: Option<&str>,
+    ) -> Result<Vec<String>, OAuthCardError> {
+        let result = self.inner.invalidate_sessions(ctx, provider_id, sub, sid);
+        self.forget_provider(provider_id);
+        result
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        self.inner.list_connections(ctx, subject)
+    }
+
+    fn list_accounts(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<Vec<AccountSummary>, OAuthCardError> {
+        self.inner.list_accounts(ctx, provider_id, subject)
+    }
+
+    fn select_account(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        account_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        let result = self
+            .inner
+            .select_account(ctx, provider_id, subject, account_id);
+        self.invalidating(provider_id, subject, result)
+    }
+
+    fn store_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        let result = self.inner.store_token(ctx, provider_id, subject, token);
+        self.invalidating(provider_id, subject, result)
+    }
+
+    fn get_userinfo(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<UserProfile, OAuthCardError> {
+        self.inner.get_userinfo(ctx, provider_id, subject)
+    }
+
+    fn discover_oidc(
+        &self,
+        ctx: &CallContext,
+        issuer: &str,
+    ) -> Result<OidcEndpoints, OAuthCardError> {
+        self.inner.discover_oidc(ctx, issuer)
+    }
+
+    fn start_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<DeviceCodeGrant, OAuthCardError> {
+        self.inner
+            .start_device_code(ctx, provider_id, subject, scopes, extra_json)
+    }
+
+    fn poll_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        device_code: &str,
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        let result = self
+            .inner
+            .poll_device_code(ctx, provider_id, subject, device_code);
+        if matches!(result, Ok(Some(_))) {
+            self.forget(provider_id, subject);
+        }
+        result
+    }
+
+    fn health(&self, ctx: &CallContext) -> Result<(), OAuthCardError> {
+        self.inner.health(ctx)
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        self.inner.capabilities()
+    }
+}
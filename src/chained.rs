@@ -0,0 +1,411 @@
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::correlation::CallContext;
+use crate::model::{
+    AccountSummary, BackendCapabilities, ConnectionSummary, DeviceCodeGrant, OidcEndpoints,
+    SignInProgress, TokenSet, UserProfile,
+};
+use crate::scopes::Scope;
+
+/// Which backend receives state changes in a [`ChainedBackend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritePolicy {
+    /// Only the primary is written; the secondary is a read-only fallback.
+    #[default]
+    PrimaryOnly,
+    /// Tokens returned by the primary are mirrored into the secondary (via
+    /// `store_token`) and revocations/invalidations are applied to both.
+    WriteThrough,
+}
+
+/// Backend that asks `primary` first and falls back to `secondary` when the
+/// primary fails, e.g. the host broker first and a local token store second so
+/// flows keep working while the central broker is briefly unavailable.
+///
+/// When both fail, the primary's error is reported.
+#[derive(Debug, Clone, Default)]
+pub struct ChainedBackend<P, S> {
+    primary: P,
+    secondary: S,
+    write_policy: WritePolicy,
+}
+
+impl<P: OAuthBackend, S: OAuthBackend> ChainedBackend<P, S> {
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self {
+            primary,
+            secondary,
+            write_policy: WritePolicy::default(),
+        }
+    }
+
+    pub fn with_write_policy(mut self, write_policy: WritePolicy) -> Self {
+        self.write_policy = write_policy;
+        self
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    fn read<T>(
+        &self,
+        call: impl Fn(&dyn OAuthBackend) -> Result<T, OAuthCardError>,
+    ) -> Result<T, OAuthCardError> {
+        call(&self.primary).or_else(|err| call(&self.secondary).map_err(|_| err))
+    }
+
+    /// Primary-first token fetch; with write-through, a token the primary
+    /// produced is copied into the secondary on a best-effort basis.
+    fn token_from(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        call: impl Fn(&dyn OAuthBackend) -> Result<TokenSet, OAuthCardError>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        match call(&self.primary) {
+            Ok(token) => {
+                self.mirror(ctx, provider_id, subject, &token);
+                Ok(token)
+            }
+            Err(err) => call(&self.secondary).map_err(|_| err),
+        }
+    }
+
+    fn mirror(&self, ctx: &CallContext, provider_id: &str, subject: &str, token: &TokenSet) {
+        if self.write_policy == WritePolicy::WriteThrough {
+            let _ = self.secondary.store_token(ctx, provider_id, subject, token);
+        }
+    }
+
+    fn write<T>(
+        &self,
+        call: impl Fn(&dyn OAuthBackend) -> Result<T, OAuthCardError>,
+    ) -> Result<T, OAuthCardError> {
+        let result = call(&self.primary);
+        if self.write_policy == WritePolicy::WriteThrough {
+            let _ = call(&self.secondary);
+        }
+        result
+    }
+}
+
+impl<P: OAuthBackend, S: OAuthBackend> OAuthBackend for ChainedBackend<P, S> {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        match self.primary.get_token(ctx, provider_id, subject, scopes) {
+            Ok(Some(token)) => {
+                self.mirror(ctx, provider_id, subject, &token);
+                Ok(Some(token))
+            }
+            Ok(None) => Ok(None),
+            Err(err) => self
+                .secondary
+                .get_token(ctx, provider_id, subject, scopes)
+                .map_err(|_| err),
+        }
+    }
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        self.read(|backend| {
+            backend.get_consent_url(
+                ctx,
+                provider_id,
+                subject,
+                scopes,
+                redirect_path,
+                extra_json.clone(),
+            )
+        })
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.token_from(ctx, provider_id, subject, |backend| {
+            backend.exchange_code(
+                ctx,
+                provider_id,
+                subject,
+                code,
+                redirect_path,
+                idempotency_key,
+            )
+        })
+    }
+
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.token_from(ctx, provider_id, subject, |backend| {
+            backend.exchange_code_with_extra(
+                ctx,
+                provider_id,
+                subject,
+                code,
+                redirect_path,
+                idempotency_key,
+                extra_json.clone(),
+            )
+        })
+    }
+
+    fn poll_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> Result<SignInProgress, OAuthCardError> {
+        let progress =
+            self.read(|backend| backend.poll_sign_in(ctx, provider_id, subject, state_id))?;
+        if let SignInProgress::Completed { token } = &progress {
+            self.mirror(ctx, provider_id, subject, token);
+        }
+        Ok(progress)
+    }
+
+    fn cancel_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        self.write(|backend| backend.cancel_sign_in(ctx, provider_id, subject, state_id))
+    }
+
+    fn exchange_sso_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        sso_token: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.token_from(ctx, provider_id, subject, |backend| {
+            backend.exchange_sso_token(ctx, provider_id, subject, sso_token, scopes)
+        })
+    }
+
+    fn refresh_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.token_from(ctx, provider_id, subject, |backend| {
+            backend.refresh_token(ctx, provider_id, subject, scopes)
+        })
+    }
+
+    fn get_app_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.read(|backend| backend.get_app_token(ctx, provider_id, scopes, extra_json.clone()))
+    }
+
+    fn exchange_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        subject_token: &str,
+        subject_token_type: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.read(|backend| {
+            backend.exchange_token(
+                ctx,
+                provider_id,
+                subject,
+                subject_token,
+                subject_token_type,
+                scopes,
+                extra_json.clone(),
+            )
+        })
+    }
+
+    fn revoke_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        self.write(|backend| backend.revoke_token(ctx, provider_id, subject, scopes))
+    }
+
+    fn get_end_session_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.read(|backend| backend.get_end_session_url(ctx, provider_id, subject))
+    }
+
+    fn get_admin_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.read(|backend| {
+            backend.get_admin_consent_url(ctx, provider_id, tenant, scopes, redirect_path)
+        })
+    }
+
+    fn admin_consent_granted(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+    ) -> Result<bool, OAuthCardError> {
+        self.read(|backend| backend.admin_consent_granted(ctx, provider_id, tenant, scopes))
+    }
+
+    fn invalidate_sessions(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        sub: Option<&str>,
+        sid: Option<&str>,
+    ) -> Result<Vec<String>, OAuthCardError> {
+        self.write(|backend| backend.invalidate_sessions(ctx, provider_id, sub, sid))
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        self.read(|backend| backend.list_connections(ctx, subject))
+    }
+
+    fn list_accounts(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<Vec<AccountSummary>, OAuthCardError> {
+        self.read(|backend| backend.list_accounts(ctx, provider_id, subject))
+    }
+
+    fn select_account(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        account_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        self.write(|backend| backend.select_account(ctx, provider_id, subject, account_id))
+    }
+
+    fn store_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        self.write(|backend| backend.store_token(ctx, provider_id, subject, token))
+    }
+
+    fn get_userinfo(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<UserProfile, OAuthCardError> {
+        self.read(|backend| backend.get_userinfo(ctx, provider_id, subject))
+    }
+
+    fn discover_oidc(
+        &self,
+        ctx: &CallContext,
+        issuer: &str,
+    ) -> Result<OidcEndpoints, OAuthCardError> {
+        self.read(|backend| backend.discover_oidc(ctx, issuer))
+    }
+
+    fn start_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<DeviceCodeGrant, OAuthCardError> {
+        self.read(|backend| {
+            backend.start_device_code(ctx, provider_id, subject, scopes, extra_json.clone())
+        })
+    }
+
+    fn poll_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        device_code: &str,
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        let token =
+            self.read(|backend| backend.poll_device_code(ctx, provider_id, subject, device_code))?;
+        if let Some(token) = &token {
+            self.mirror(ctx, provider_id, subject, token);
+        }
+        Ok(token)
+    }
+
+    fn health(&self, ctx: &CallContext) -> Result<(), OAuthCardError> {
+        self.read(|backend| backend.health(ctx))
+    }
+
+    /// Anything either backend can do, since calls fall back to the secondary.
+    fn capabilities(&self) -> BackendCapabilities {
+        self.primary
+            .capabilities()
+            .union(self.secondary.capabilities())
+    }
+}
@@ -0,0 +1,175 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde_json::{Map, Value};
+
+use crate::OAuthCardError;
+use crate::model::{IdTokenVerification, TokenSet};
+use crate::provider_config::provider_config;
+
+/// Decode the payload segment of a compact JWT without verifying its signature.
+///
+/// The broker is trusted to hand back tokens it obtained directly from the
+/// provider, so claims are only used to describe the signed-in account. See
+/// [`verified_id_token_claims`] for providers configured to verify them.
+pub fn decode_jwt_claims(jwt: &str) -> Result<Map<String, Value>, OAuthCardError> {
+    let mut segments = jwt.split('.');
+    let payload = match (segments.next(), segments.next(), segments.next()) {
+        (Some(_), Some(payload), Some(_)) => payload,
+        _ => {
+            return Err(OAuthCardError::Parse(
+                "id_token is not a compact JWT".into(),
+            ));
+        }
+    };
+    let bytes = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|err| OAuthCardError::Parse(format!("id_token payload: {err}")))?;
+    match serde_json::from_slice::<Value>(&bytes)
+        .map_err(|err| OAuthCardError::Parse(format!("id_token claims: {err}")))?
+    {
+        Value::Object(claims) => Ok(claims),
+        _ => Err(OAuthCardError::Parse(
+            "id_token claims are not a JSON object".into(),
+        )),
+    }
+}
+
+/// Claims of a freshly issued id_token. With `id_token_verification` in the
+/// provider config, they are only returned once the signature, issuer,
+/// audience and `exp`/`iat`/`nbf` check out (which needs the `verify-jwt`
+/// feature).
+pub(crate) fn verified_id_token_claims(
+    provider_id: &str,
+    jwt: &str,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    match provider_config(provider_id).and_then(|config| config.id_token_verification) {
+        Some(verification) => verify_fresh(jwt, &verification),
+        None => decode_jwt_claims(jwt),
+    }
+}
+
+/// [`verified_id_token_claims`] for a stored token, whose id_token may have
+/// lapsed since sign-in: the signature, issuer and audience are still checked.
+pub(crate) fn signed_id_token_claims(
+    provider_id: &str,
+    jwt: &str,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    match provider_config(provider_id).and_then(|config| config.id_token_verification) {
+        Some(verification) => verify(jwt, &verification),
+        None => decode_jwt_claims(jwt),
+    }
+}
+
+#[cfg(feature = "verify-jwt")]
+fn verify(
+    jwt: &str,
+    verification: &IdTokenVerification,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    crate::jwt::verify_jwt(jwt, verification)
+}
+
+#[cfg(feature = "verify-jwt")]
+fn verify_fresh(
+    jwt: &str,
+    verification: &IdTokenVerification,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    crate::jwt::verify_id_token(jwt, verification)
+}
+
+#[cfg(not(feature = "verify-jwt"))]
+fn verify(
+    _jwt: &str,
+    _verification: &IdTokenVerification,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    Err(OAuthCardError::Unsupported(
+        "id_token verification needs the `verify-jwt` feature".into(),
+    ))
+}
+
+#[cfg(not(feature = "verify-jwt"))]
+fn verify_fresh(
+    jwt: &str,
+    verification: &IdTokenVerification,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    verify(jwt, verification)
+}
+
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// How old a logout token's `iat` may be, and how far ahead of our clock.
+const LOGOUT_TOKEN_MAX_AGE_SECS: u64 = 300;
+const LOGOUT_TOKEN_CLOCK_SKEW_SECS: u64 = 60;
+
+/// Identifiers carried by a validated back-channel logout token.
+pub struct LogoutClaims {
+    pub sub: Option<String>,
+    pub sid: Option<String>,
+}
+
+/// Validate a back-channel logout token of `provider_id`: its signature,
+/// `iss` and `aud` against the provider's `id_token_verification`, a recent
+/// `iat`, the logout event, `sub` and/or `sid`, and no `nonce`.
+///
+/// Anyone can post to the logout endpoint, so unlike sign-in id_tokens a
+/// logout token is never trusted without a verification config.
+pub fn validate_logout_token(provider_id: &str, jwt: &str) -> Result<LogoutClaims, OAuthCardError> {
+    let verification = provider_config(provider_id)
+        .and_then(|config| config.id_token_verification)
+        .ok_or_else(|| {
+            OAuthCardError::Unsupported(format!(
+                "back-channel logout for `{provider_id}` needs `id_token_verification` in its provider config"
+            ))
+        })?;
+    let claims = verify(jwt, &verification)?;
+    if claim_str(&claims, "iss").is_none() {
+        return Err(OAuthCardError::Invalid("logout token has no iss".into()));
+    }
+    let now = crate::clock::now_secs();
+    match claims.get("iat").and_then(Value::as_u64) {
+        None => return Err(OAuthCardError::Invalid("logout token has no iat".into())),
+        Some(iat) if iat > now + LOGOUT_TOKEN_CLOCK_SKEW_SECS => {
+            return Err(OAuthCardError::Invalid(
+                "logout token was issued in the future".into(),
+            ));
+        }
+        Some(iat) if iat + LOGOUT_TOKEN_MAX_AGE_SECS < now => {
+            return Err(OAuthCardError::Invalid("logout token is too old".into()));
+        }
+        Some(_) => {}
+    }
+    let has_event = claims
+        .get("events")
+        .and_then(Value::as_object)
+        .is_some_and(|events| events.contains_key(BACKCHANNEL_LOGOUT_EVENT));
+    if !has_event {
+        return Err(OAuthCardError::Invalid(
+            "logout token is missing the back-channel logout event".into(),
+        ));
+    }
+    if claims.contains_key("nonce") {
+        return Err(OAuthCardError::Invalid(
+            "logout token must not contain a nonce".into(),
+        ));
+    }
+    let sub = claim_str(&claims, "sub");
+    let sid = claim_str(&claims, "sid");
+    if sub.is_none() && sid.is_none() {
+        return Err(OAuthCardError::Invalid(
+            "logout token needs a sub or sid".into(),
+        ));
+    }
+    Ok(LogoutClaims { sub, sid })
+}
+
+pub fn claim_str(claims: &Map<String, Value>, name: &str) -> Option<String> {
+    claims.get(name).and_then(Value::as_str).map(str::to_string)
+}
+
+/// Account label for a stored token: the `email`, `preferred_username` or
+/// `name` claim of its id_token, if any.
+pub fn account_label(token: &TokenSet) -> Option<String> {
+    let claims = decode_jwt_claims(token.id_token.as_deref()?).ok()?;
+    ["email", "preferred_username", "name"]
+        .into_iter()
+        .find_map(|name| claim_str(&claims, name))
+}
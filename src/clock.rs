@@ -0,0 +1,48 @@
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::OAuthCardError;
+
+/// Source of the current time for token expiry, flow-state TTLs and signed
+/// states.
+pub trait Clock {
+    /// Seconds since the Unix epoch.
+    fn now_secs(&self) -> u64;
+}
+
+/// The platform wall clock (the host's `wasi:clocks/wall-clock` on wasm).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_secs(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now_secs(&self) -> u64 {
+        self()
+    }
+}
+
+static REGISTERED_CLOCK: OnceLock<Arc<dyn Clock + Send + Sync>> = OnceLock::new();
+
+/// Install the process-wide clock, e.g. a host-provided or fixed one in tests.
+/// Defaults to [`SystemClock`]; only the first registration takes effect.
+pub fn register_clock(clock: impl Clock + Send + Sync + 'static) -> Result<(), OAuthCardError> {
+    REGISTERED_CLOCK
+        .set(Arc::new(clock))
+        .map_err(|_| OAuthCardError::Invalid("a clock is already registered".into()))
+}
+
+/// Current time of the registered clock.
+pub(crate) fn now_secs() -> u64 {
+    match REGISTERED_CLOCK.get() {
+        Some(clock) => clock.now_secs(),
+        None => SystemClock.now_secs(),
+    }
+}
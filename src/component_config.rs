@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::OAuthCardError;
+use crate::model::{Branding, OAuthCardInput, ProviderConfig};
+use crate::provider_config::DEFAULT_REDIRECT_PREFIX;
+use crate::rate_limit::SignInRateLimit;
+use crate::scopes::Scope;
+use crate::template::CardTemplate;
+use crate::{output_policy, provider_config, rate_limit, signed_state};
+
+static LOADED: RwLock<Option<Arc<LoadedConfig>>> = RwLock::new(None);
+
+/// Everything read from the component configuration. It is replaced as one
+/// value, so a reload never leaves one key from the old configuration next
+/// to another from the new one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoadedConfig {
+    pub(crate) component: ComponentConfig,
+    pub(crate) providers: BTreeMap<String, ProviderConfig>,
+    pub(crate) state_signing_key: Option<String>,
+    pub(crate) include_auth_header: Option<bool>,
+    pub(crate) sign_in_rate_limit: Option<SignInRateLimit>,
+}
+
+/// Component-wide defaults from the component configuration
+/// (`schemas/component.schema.json`). The keys owned by the other loaders
+/// (`providers`, `state_signing_key`, `include_auth_header`,
+/// `sign_in_rate_limit`) are read by [`load_component_config`] as well.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ComponentConfig {
+    /// Provider used when an input leaves `provider_id` empty.
+    pub default_provider: Option<String>,
+    /// Path default redirect paths are built under
+    /// (`{redirect_base}/{provider_id}`, default `/oauth/callback`); input
+    /// redirect paths must start with it unless the provider sets
+    /// `redirect_prefixes`.
+    pub redirect_base: Option<String>,
+    /// Default for the input's `refresh_margin_secs`.
+    pub refresh_margin_secs: Option<u64>,
+    /// Branding for every card; a provider's `branding` and then the input's
+    /// `extra_json.branding` override it field by field.
+    pub branding: Option<Branding>,
+    /// Card copy keyed by mode name, for every provider; a provider's
+    /// `card_templates` and then the input's `extra_json.card_templates`
+    /// override it field by field.
+    pub card_templates: BTreeMap<String, CardTemplate>,
+    /// Scopes requests may ask for at providers without their own
+    /// `allowed_scopes`; empty means no limit.
+    #[serde(deserialize_with = "crate::scopes::deserialize_list")]
+    pub allowed_scopes: Vec<Scope>,
+}
+
+/// Load the whole component configuration, as the wasm component's
+/// `configure` operation does: the [`ComponentConfig`] defaults plus every
+/// key of [`load_provider_configs`](crate::load_provider_configs),
+/// [`load_state_signing_key`](crate::load_state_signing_key),
+/// [`load_output_policy`](crate::load_output_policy) and
+/// [`load_sign_in_rate_limit`](crate::load_sign_in_rate_limit). Every key is
+/// parsed and checked before any is stored, then all of them replace what
+/// was loaded before at once; nothing changes when the configuration is
+/// invalid.
+pub fn load_component_config(config_json: &str) -> Result<(), OAuthCardError> {
+    let component: ComponentConfig = serde_json::from_str(config_json)
+        .map_err(|err| OAuthCardError::Parse(format!("component config json: {err}")))?;
+    if let Some(base) = component.redirect_base.as_deref()
+        && (!base.starts_with('/') || base.starts_with("//"))
+    {
+        return Err(OAuthCardError::validation(
+            "redirect_base",
+            format!("{base:?} must be a path on this host"),
+        ));
+    }
+    let loaded = LoadedConfig {
+        component,
+        providers: provider_config::parse(config_json)?,
+        state_signing_key: signed_state::parse(config_json)?,
+        include_auth_header: output_policy::parse(config_json)?,
+        sign_in_rate_limit: rate_limit::parse(config_json)?,
+    };
+    *LOADED
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(loaded));
+    Ok(())
+}
+
+/// The loaded configuration (defaults when none was loaded).
+pub fn component_config() -> ComponentConfig {
+    loaded().component.clone()
+}
+
+/// Snapshot of everything loaded, for the modules owning its keys.
+pub(crate) fn loaded() -> Arc<LoadedConfig> {
+    LOADED
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Replace one part of the loaded configuration, for the loaders of a single
+/// key; the rest stays as it was.
+pub(crate) fn update(change: impl FnOnce(&mut LoadedConfig)) {
+    let mut guard = LOADED
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut next = guard.as_deref().cloned().unwrap_or_default();
+    change(&mut next);
+    *guard = Some(Arc::new(next));
+}
+
+/// Fill the input's `provider_id` and `refresh_margin_secs` from the
+/// configuration when it left them empty.
+pub(crate) fn with_defaults(mut input: OAuthCardInput) -> OAuthCardInput {
+    let config = component_config();
+    if input.provider_id.trim().is_empty()
+        && let Some(provider) = config.default_provider
+    {
+        input.provider_id = provider;
+    }
+    input.refresh_margin_secs = input.refresh_margin_secs.or(config.refresh_margin_secs);
+    input
+}
+
+/// Prefix of default redirect paths, ending in `/`.
+pub(crate) fn redirect_prefix() -> String {
+    match component_config().redirect_base {
+        Some(base) => format!("{}/", base.trim_end_matches('/')),
+        None => DEFAULT_REDIRECT_PREFIX.to_string(),
+    }
+}
@@ -0,0 +1,33 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Invocation details every broker call runs under, so broker-side logs can
+/// be joined to component invocations.
+///
+/// Every [`OAuthBackend`](crate::OAuthBackend) method receives it as its
+/// first argument, so backends can forward it (e.g. as request headers).
+/// Hosts supply their context to [`crate::handle_message_with_context`] or
+/// [`crate::handle_with_context`]; its tenant is authoritative (an input
+/// naming another tenant is rejected), while the input's `correlation_id`
+/// takes precedence over the host's.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallContext {
+    pub correlation_id: Option<String>,
+    pub tenant: Option<String>,
+    /// Unix time in milliseconds by which the host needs an answer.
+    pub deadline_unix_ms: Option<u64>,
+    /// Flow and node the host runs the component as, for host-side logs.
+    pub flow_id: Option<String>,
+    pub node_id: Option<String>,
+}
+
+impl CallContext {
+    /// Time left until the deadline (zero once it has passed).
+    pub fn remaining(&self) -> Option<Duration> {
+        let deadline = self.deadline_unix_ms?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+            .unwrap_or_default();
+        Some(Duration::from_millis(deadline.saturating_sub(now)))
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::Mutex;
+
+use crate::OAuthCardError;
+use crate::broker::AsyncOAuthBackend;
+use crate::correlation::CallContext;
+use crate::model::{
+    AccountSummary, BackendCapabilities, ConnectionSummary, DeviceCodeGrant, OidcEndpoints,
+    SignInProgress, TokenSet, UserProfile,
+};
+use crate::observed::{CallHook, observed_backend};
+use crate::scopes::Scope;
+
+/// Backend wrapper noting which broker methods an invocation called, for the
+/// `diagnostics` of `debug` inputs.
+pub(crate) struct Traced<'a, B> {
+    inner: &'a B,
+    calls: Mutex<Vec<String>>,
+}
+
+impl<'a, B: AsyncOAuthBackend> Traced<'a, B> {
+    pub(crate) fn new(inner: &'a B) -> Self {
+        Self {
+            inner,
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Broker methods called so far, in call order.
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+impl<B> CallHook for Traced<'_, B> {
+    fn started(&self, method: &'static str) {
+        self.calls
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(method.to_string());
+    }
+}
+
+observed_backend!(async impl[B: AsyncOAuthBackend] Traced<'_, B>);
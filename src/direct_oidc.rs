@@ -0,0 +1,678 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use url::Url;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::clock::now_secs;
+use crate::correlation::CallContext;
+use crate::memory_broker::InMemoryBroker;
+use crate::model::{
+    BackendCapabilities, BrokerErrorCode, ConnectionSummary, OidcEndpoints, TokenSet, UserProfile,
+};
+use crate::scopes::Scope;
+use crate::state_store::SIGN_IN_STATE_TTL_SECS;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+/// `extra_json` key carrying the sign-in's `state_id`, sent as the OAuth `state`.
+const STATE_ID_KEY: &str = "state_id";
+/// Authorization parameters the backend sets itself; `extra_json` may not
+/// override them.
+const RESERVED_PARAMS: [&str; 8] = [
+    "response_type",
+    "client_id",
+    "client_secret",
+    "redirect_uri",
+    "scope",
+    "state",
+    "code_challenge",
+    "code_challenge_method",
+];
+
+/// Client registration for one provider.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcClientConfig {
+    /// Issuer URL; discovery is read from `{issuer}/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    /// Omitted for public clients.
+    pub client_secret: Option<String>,
+    /// Public origin the callback paths are served from (e.g. `https://bot.example`).
+    pub redirect_base_url: String,
+}
+
+/// An authorization request awaiting its callback, keyed by its `state`.
+struct PendingAuthorization {
+    provider_id: String,
+    subject: String,
+    redirect_uri: String,
+    /// PKCE (RFC 7636) verifier whose S256 challenge went into the URL.
+    code_verifier: Zeroizing<String>,
+    expires_at: u64,
+}
+
+/// Token endpoint response (RFC 6749 §5.1).
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+    token_type: Option<String>,
+    id_token: Option<String>,
+    scope: Option<String>,
+}
+
+/// Backend that talks OIDC to the providers itself instead of going through a
+/// greentic broker host, for native deployments running the crate standalone.
+///
+/// Each provider is configured with an [`OidcClientConfig`]; endpoints come from
+/// discovery (fetched once per provider). Authorization URLs carry the
+/// sign-in's `state_id` (from `extra_json`, or a fresh one) as `state` and an
+/// S256 PKCE challenge; code exchange must name the same `state_id` and spends
+/// the matching verifier, so a code from another authorization is refused.
+/// Tokens obtained through code
+/// exchange and refresh are kept in memory per provider/subject, so pair it
+/// with a persistent store (e.g. a chained backend with write-through) when
+/// tokens must survive restarts.
+pub struct DirectOidcBackend {
+    clients: BTreeMap<String, OidcClientConfig>,
+    metadata: Mutex<BTreeMap<String, OidcEndpoints>>,
+    pending: Mutex<BTreeMap<String, PendingAuthorization>>,
+    tokens: InMemoryBroker,
+    /// Idempotency keys of completed code exchanges.
+    exchanged: Mutex<BTreeSet<String>>,
+    agent: ureq::Agent,
+}
+
+impl Default for DirectOidcBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DirectOidcBackend {
+    pub fn new() -> Self {
+        Self {
+            clients: BTreeMap::new(),
+            metadata: Mutex::new(BTreeMap::new()),
+            pending: Mutex::new(BTreeMap::new()),
+            tokens: InMemoryBroker::new(),
+            exchanged: Mutex::new(BTreeSet::new()),
+            agent: ureq::AgentBuilder::new().timeout(DEFAULT_TIMEOUT).build(),
+        }
+    }
+
+    /// Overall timeout for each provider call; exceeding it reports
+    /// [`OAuthCardError::Timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.agent = ureq::AgentBuilder::new().timeout(timeout).build();
+        self
+    }
+
+    pub fn with_provider(
+        mut self,
+        provider_id: impl Into<String>,
+        config: OidcClientConfig,
+    ) -> Self {
+        self.clients.insert(provider_id.into(), config);
+        self
+    }
+
+    /// Load clients from a JSON object mapping provider ids to [`OidcClientConfig`]s.
+    pub fn from_json(config: &str) -> Result<Self, OAuthCardError> {
+        let clients: BTreeMap<String, OidcClientConfig> = serde_json::from_str(config)
+            .map_err(|err| OAuthCardError::Parse(format!("oidc config: {err}")))?;
+        Ok(clients
+            .into_iter()
+            .fold(Self::new(), |backend, (provider_id, config)| {
+                backend.with_provider(provider_id, config)
+            }))
+    }
+
+    fn client(&self, provider_id: &str) -> Result<&OidcClientConfig, OAuthCardError> {
+        self.clients.get(provider_id).ok_or_else(|| {
+            OAuthCardError::Invalid(format!("no OIDC client configured for {provider_id}"))
+        })
+    }
+
+    fn metadata(&self, provider_id: &str) -> Result<OidcEndpoints, OAuthCardError> {
+        if let Some(metadata) = self.lock().get(provider_id) {
+            return Ok(metadata.clone());
+        }
+        self.discover(provider_id)
+    }
+
+    /// Fetch (and cache) the provider's discovery document.
+    fn discover(&self, provider_id: &str) -> Result<OidcEndpoints, OAuthCardError> {
+        let metadata = self.fetch_discovery(&self.client(provider_id)?.issuer)?;
+        self.lock()
+            .insert(provider_id.to_string(), metadata.clone());
+        Ok(metadata)
+    }
+
+    fn fetch_discovery(&self, issuer: &str) -> Result<OidcEndpoints, OAuthCardError> {
+        let url = format!("{}{DISCOVERY_PATH}", issuer.trim_end_matches('/'));
+        let response = self.agent.get(&url).call().map_err(oidc_error)?;
+        response
+            .into_json()
+            .map_err(|err| OAuthCardError::Parse(format!("discovery json: {err}")))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, OidcEndpoints>> {
+        self.metadata
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn pending(&self) -> MutexGuard<'_, BTreeMap<String, PendingAuthorization>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Take the authorization `state` was issued for, checking it belongs to
+    /// this provider, subject and redirect URI and has not expired.
+    fn take_pending(
+        &self,
+        state: Option<&str>,
+        provider_id: &str,
+        subject: &str,
+        redirect_uri: &str,
+    ) -> Result<PendingAuthorization, OAuthCardError> {
+        let state = state.ok_or_else(|| {
+            OAuthCardError::InvalidState(format!(
+                "{provider_id} code exchange needs the sign-in's state_id"
+            ))
+        })?;
+        let pending = self.pending().remove(state).ok_or_else(|| {
+            OAuthCardError::InvalidState(format!("no pending {provider_id} authorization"))
+        })?;
+        if pending.provider_id != provider_id
+            || pending.subject != subject
+            || pending.redirect_uri != redirect_uri
+            || pending.expires_at <= now_secs()
+        {
+            return Err(OAuthCardError::InvalidState(format!(
+                "state does not match a pending {provider_id} authorization"
+            )));
+        }
+        Ok(pending)
+    }
+
+    fn exchanged_keys(&self) -> MutexGuard<'_, BTreeSet<String>> {
+        self.exchanged
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn redirect_uri(
+        &self,
+        provider_id: &str,
+        redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        let base = &self.client(provider_id)?.redirect_base_url;
+        Ok(format!("{}{redirect_path}", base.trim_end_matches('/')))
+    }
+
+    /// POST a grant to the token endpoint with the client's credentials and,
+    /// when the caller made one, its `DPoP` proof.
+    fn token_request(
+        &self,
+        provider_id: &str,
+        grant: &[(&str, &str)],
+        dpop_proof: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        let client = self.client(provider_id)?;
+        let metadata = self.metadata(provider_id)?;
+        let mut form = grant.to_vec();
+        form.push(("client_id", &client.client_id));
+        if let Some(secret) = &client.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let mut request = self.agent.post(&metadata.token_endpoint);
+        if let Some(proof) = dpop_proof {
+            request = request.set("DPoP", proof);
+        }
+        let response = request.send_form(&form).map_err(oidc_error)?;
+        let body: TokenResponse = response
+            .into_json()
+            .map_err(|err| OAuthCardError::Parse(format!("token json: {err}")))?;
+        Ok(TokenSet {
+            access_token: body.access_token,
+            refresh_token: body.refresh_token,
+            expires_at: body.expires_in.map(|secs| now_secs() + secs),
+            token_type: body.token_type,
+            extra: None,
+            id_token: body.id_token,
+            granted_scopes: body
+                .scope
+                .as_deref()
+                .map(|scope| scope.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+            scope: body.scope,
+        })
+    }
+
+    fn store(&self, provider_id: &str, subject: &str, token: TokenSet) -> TokenSet {
+        self.tokens.seed(provider_id, subject, token.clone());
+        token
+    }
+}
+
+/// Timeouts map to `Timeout` and other transport failures to `Transient`.
+/// Error responses carrying an OAuth `error` value (e.g. `invalid_grant`), 429s
+/// and 5xx become categorized `Broker` errors; remaining statuses are invalid
+/// requests.
+fn oidc_error(err: ureq::Error) -> OAuthCardError {
+    match err {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let oauth_error = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body.get("error")?.as_str().map(str::to_string));
+            let message = format!("provider returned {status}: {body}");
+            match BrokerErrorCode::from_response(status, oauth_error.as_deref()) {
+                BrokerErrorCode::Unknown if oauth_error.is_none() => {
+                    OAuthCardError::Backend(message)
+                }
+                BrokerErrorCode::ConsentDenied => OAuthCardError::Denied(message),
+                code => OAuthCardError::Broker { code, message },
+            }
+        }
+        ureq::Error::Transport(transport)
+            if std::error::Error::source(&transport)
+                .and_then(|source| source.downcast_ref::<std::io::Error>())
+                .is_some_and(|err| {
+                    matches!(
+                        err.kind(),
+                        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+                    )
+                }) =>
+        {
+            OAuthCardError::Timeout(format!("provider did not answer: {transport}"))
+        }
+        ureq::Error::Transport(transport) => {
+            OAuthCardError::Transient(format!("provider unreachable: {transport}"))
+        }
+    }
+}
+
+impl OAuthBackend for DirectOidcBackend {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        self.tokens.get_token(ctx, provider_id, subject, scopes)
+    }
+
+    /// Authorization-code URL; string and number values of `extra_json` (e.g.
+    /// `prompt`, `login_hint`, `max_age`) are added as query parameters, except
+    /// `state_id` (sent as `state`). Keys naming a parameter the backend sets
+    /// itself (`redirect_uri`, `client_id`, `state`, ...) are rejected.
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        let client = self.client(provider_id)?;
+        let metadata = self.metadata(provider_id)?;
+        let redirect_uri = self.redirect_uri(provider_id, redirect_path)?;
+        let extra: serde_json::Value = match extra_json {
+            Some(extra) => serde_json::from_str(&extra)
+                .map_err(|err| OAuthCardError::Parse(format!("extra_json: {err}")))?,
+            None => serde_json::Value::Null,
+        };
+        let options = extra.as_object().cloned().unwrap_or_default();
+        if let Some(key) = options
+            .keys()
+            .find(|key| RESERVED_PARAMS.contains(&key.as_str()))
+        {
+            return Err(OAuthCardError::validation(
+                format!("extra_json.{key}"),
+                "is set by the backend and cannot be overridden",
+            ));
+        }
+        let state = match options
+            .get(STATE_ID_KEY)
+            .and_then(serde_json::Value::as_str)
+        {
+            Some(state) if !state.is_empty() => state.to_string(),
+            _ => Uuid::new_v4().to_string(),
+        };
+        let code_verifier = Zeroizing::new(format!(
+            "{}{}",
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        ));
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+        let mut url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", client.client_id.as_str()),
+                ("redirect_uri", redirect_uri.as_str()),
+                ("scope", scopes.join(" ").as_str()),
+                ("state", state.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|err| OAuthCardError::Parse(format!("authorization endpoint: {err}")))?;
+        {
+            let mut query = url.query_pairs_mut();
+            for (key, value) in options.iter().filter(|(key, _)| *key != STATE_ID_KEY) {
+                match value {
+                    serde_json::Value::String(value) => {
+                        query.append_pair(key, value);
+                    }
+                    serde_json::Value::Number(value) => {
+                        query.append_pair(key, &value.to_string());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut pending = self.pending();
+        let now = now_secs();
+        pending.retain(|_, pending| pending.expires_at > now);
+        pending.insert(
+            state,
+            PendingAuthorization {
+                provider_id: provider_id.to_string(),
+                subject: subject.to_string(),
+                redirect_uri,
+                code_verifier,
+                expires_at: now + SIGN_IN_STATE_TTL_SECS,
+            },
+        );
+        Ok(url.into())
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        OAuthBackend::exchange_code_with_extra(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+            None,
+        )
+    }
+
+    /// Spends the authorization named by `extra_json.state_id`, sending its
+    /// PKCE verifier; string `resource`/`audience` entries of `extra_json` are
+    /// added to the token request (RFC 8707).
+    fn exchange_code_with_extra(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        // Providers reject a reused code with `invalid_grant`; report the
+        // replay instead so the caller can pick up the stored token.
+        if let Some(key) = idempotency_key
+            && self.exchanged_keys().contains(key)
+        {
+            return Err(OAuthCardError::Broker {
+                code: BrokerErrorCode::AlreadyExchanged,
+                message: format!("{provider_id} code was already exchanged"),
+            });
+        }
+        let redirect_uri = self.redirect_uri(provider_id, redirect_path)?;
+        let extra: serde_json::Value = match extra_json {
+            Some(extra) => serde_json::from_str(&extra)
+                .map_err(|err| OAuthCardError::Parse(format!("extra_json: {err}")))?,
+            None => serde_json::Value::Null,
+        };
+        let pending = self.take_pending(
+            extra.get(STATE_ID_KEY).and_then(serde_json::Value::as_str),
+            provider_id,
+            subject,
+            &redirect_uri,
+        )?;
+        let mut grant = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ];
+        for key in ["resource", "audience"] {
+            if let Some(value) = extra.get(key).and_then(serde_json::Value::as_str) {
+                grant.push((key, value));
+            }
+        }
+        let proof = extra.get("dpop_proof").and_then(serde_json::Value::as_str);
+        let token = self.token_request(provider_id, &grant, proof)?;
+        if let Some(key) = idempotency_key {
+            self.exchanged_keys().insert(key.to_string());
+        }
+        Ok(self.store(provider_id, subject, token))
+    }
+
+    fn refresh_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        let current = self.tokens.stored(provider_id, subject);
+        let Some(refresh_token) = current.as_ref().and_then(|t| t.refresh_token.clone()) else {
+            return Err(OAuthCardError::Invalid(format!(
+                "no {provider_id} refresh token for {subject}"
+            )));
+        };
+        let mut token = self.token_request(
+            provider_id,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &refresh_token),
+            ],
+            None,
+        )?;
+        // Providers may omit unchanged values from refresh responses.
+        if let Some(current) = current {
+            token.refresh_token = token.refresh_token.or(Some(refresh_token));
+            token.id_token = token.id_token.or(current.id_token);
+            if token.granted_scopes.is_empty() {
+                token.granted_scopes = current.granted_scopes;
+            }
+        }
+        Ok(self.store(provider_id, subject, token))
+    }
+
+    fn get_app_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.token_request(
+            provider_id,
+            &[
+                ("grant_type", "client_credentials"),
+                ("scope", &scopes.join(" ")),
+            ],
+            None,
+        )
+    }
+
+    /// RFC 8693 token exchange at the token endpoint; string `resource` and
+    /// `audience` entries of `extra_json` are sent along. The derived token
+    /// is not stored.
+    fn exchange_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        _subject: &str,
+        subject_token: &str,
+        subject_token_type: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        let extra: serde_json::Value = match extra_json {
+            Some(extra) => serde_json::from_str(&extra)
+                .map_err(|err| OAuthCardError::Parse(format!("extra_json: {err}")))?,
+            None => serde_json::Value::Null,
+        };
+        let scope = scopes.join(" ");
+        let mut grant = vec![
+            (
+                "grant_type",
+                "urn:ietf:params:oauth:grant-type:token-exchange",
+            ),
+            ("subject_token", subject_token),
+            ("subject_token_type", subject_token_type),
+        ];
+        if !scope.is_empty() {
+            grant.push(("scope", scope.as_str()));
+        }
+        for key in ["resource", "audience"] {
+            if let Some(value) = extra.get(key).and_then(serde_json::Value::as_str) {
+                grant.push((key, value));
+            }
+        }
+        let proof = extra.get("dpop_proof").and_then(serde_json::Value::as_str);
+        self.token_request(provider_id, &grant, proof)
+    }
+
+    /// Revokes the refresh token (or access token) at the provider when it
+    /// advertises a revocation endpoint, then forgets the stored token.
+    fn revoke_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        if let Some(token) = self.tokens.stored(provider_id, subject)
+            && let Some(endpoint) = self.metadata(provider_id)?.revocation_endpoint
+        {
+            let client = self.client(provider_id)?;
+            let value = token.refresh_token.unwrap_or(token.access_token);
+            let mut form = vec![("token", value.as_str()), ("client_id", &client.client_id)];
+            if let Some(secret) = &client.client_secret {
+                form.push(("client_secret", secret));
+            }
+            self.agent
+                .post(&endpoint)
+                .send_form(&form)
+                .map_err(oidc_error)?;
+        }
+        self.tokens.revoke_token(ctx, provider_id, subject, scopes)
+    }
+
+    fn get_end_session_url(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        _subject: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.metadata(provider_id)?
+            .end_session_endpoint
+            .ok_or_else(|| {
+                OAuthCardError::Unsupported(format!("{provider_id} has no end-session endpoint"))
+            })
+    }
+
+    fn discover_oidc(
+        &self,
+        _ctx: &CallContext,
+        issuer: &str,
+    ) -> Result<OidcEndpoints, OAuthCardError> {
+        self.fetch_discovery(issuer)
+    }
+
+    fn store_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        self.tokens.store_token(ctx, provider_id, subject, token)
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        self.tokens.list_connections(ctx, subject)
+    }
+
+    fn get_userinfo(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<UserProfile, OAuthCardError> {
+        let Some(endpoint) = self.metadata(provider_id)?.userinfo_endpoint else {
+            return Err(OAuthCardError::Unsupported(format!(
+                "{provider_id} has no userinfo endpoint"
+            )));
+        };
+        let token = self.tokens.stored(provider_id, subject).ok_or_else(|| {
+            OAuthCardError::Invalid(format!("no {provider_id} connection for {subject}"))
+        })?;
+        let response = self
+            .agent
+            .get(&endpoint)
+            .set("Authorization", &format!("Bearer {}", token.access_token))
+            .call()
+            .map_err(oidc_error)?;
+        response
+            .into_json()
+            .map_err(|err| OAuthCardError::Parse(format!("userinfo json: {err}")))
+    }
+
+    /// Healthy when every configured provider's discovery document loads.
+    fn health(&self, _ctx: &CallContext) -> Result<(), OAuthCardError> {
+        for provider_id in self.clients.keys() {
+            self.discover(provider_id)?;
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            refresh: true,
+            revoke: true,
+            client_credentials: true,
+            end_session: true,
+            list_connections: true,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use serde_json::{Value, json};
+
+use crate::OAuthCardError;
+use crate::broker::AsyncOAuthBackend;
+use crate::clock::now_secs;
+use crate::correlation::CallContext;
+use crate::logging;
+use crate::model::{OAuthCardInput, OAuthCardMode, OidcEndpoints};
+use crate::provider_config;
+
+/// How long discovered endpoints are reused before the document is fetched
+/// again.
+const DISCOVERY_TTL_SECS: u64 = 3_600;
+
+/// How long a failed discovery is remembered before it is tried again, so
+/// an unreachable issuer or a broker without discovery is not asked on
+/// every request.
+const DISCOVERY_FAILURE_TTL_SECS: u64 = 300;
+
+/// `extra_json` key the discovered endpoints reach the broker and the card
+/// metadata under.
+pub(crate) const OIDC_ENDPOINTS_KEY: &str = "oidc_endpoints";
+
+/// Discovery outcome per issuer (the endpoints, or why there are none) with
+/// the time it was fetched.
+type Cache = BTreeMap<String, (Result<OidcEndpoints, String>, u64)>;
+
+static CACHE: Mutex<Cache> = Mutex::new(BTreeMap::new());
+
+fn cache() -> MutexGuard<'static, Cache> {
+    CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// OIDC issuer of the input's provider: `extra_json.issuer`, else the
+/// provider config's `issuer`.
+pub(crate) fn issuer(input: &OAuthCardInput) -> Option<String> {
+    input
+        .extra_json
+        .as_ref()
+        .and_then(|extra| extra.get("issuer"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| provider_config::provider_config(&input.provider_id)?.issuer)
+        .filter(|issuer| !issuer.trim().is_empty())
+}
+
+/// Whether handlers of `mode` use the discovered endpoints: sign-in cards
+/// and consent requests need the authorize endpoint, grants and DPoP proofs
+/// the token endpoint, logout the end-session endpoint.
+pub(crate) fn needed_by(mode: &OAuthCardMode) -> bool {
+    matches!(
+        mode,
+        OAuthCardMode::StartSignIn
+            | OAuthCardMode::EnsureToken
+            | OAuthCardMode::CompleteSignIn
+            | OAuthCardMode::DeviceCode
+            | OAuthCardMode::ClientCredentials
+            | OAuthCardMode::TokenExchange
+            | OAuthCardMode::Logout
+    )
+}
+
+/// Endpoints of `issuer`, from the cache or else from the broker's
+/// discovery. A document naming no issuer or another one is rejected (OIDC
+/// Discovery §4.3), so a compromised location cannot redirect sign-ins.
+/// Failures are cached too, for a shorter time.
+pub(crate) async fn endpoints<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    issuer: &str,
+) -> Result<OidcEndpoints, OAuthCardError> {
+    let issuer = issuer.trim_end_matches('/');
+    let now = now_secs();
+    if let Some((cached, fetched_at)) = cache().get(issuer) {
+        let ttl = match cached {
+            Ok(_) => DISCOVERY_TTL_SECS,
+            Err(_) => DISCOVERY_FAILURE_TTL_SECS,
+        };
+        if now.saturating_sub(*fetched_at) < ttl {
+            return cached.clone().map_err(|message| {
+                OAuthCardError::Backend(format!("discovery of {issuer} failed recently: {message}"))
+            });
+        }
+    }
+    let result = match backend.discover_oidc(ctx, issuer).await {
+        Ok(endpoints) if endpoints.issuer.trim_end_matches('/') != issuer => {
+            Err(OAuthCardError::Invalid(format!(
+                "discovery document of {issuer} names issuer {:?}",
+                endpoints.issuer
+            )))
+        }
+        result => result,
+    };
+    let cached = result.as_ref().cloned().map_err(ToString::to_string);
+    cache().insert(issuer.to_string(), (cached, now));
+    result
+}
+
+/// `input` with its issuer's endpoints under `extra_json.oidc_endpoints`.
+/// `None` when the provider has no issuer, the input already carries
+/// endpoints, or discovery fails; failures are logged and the request goes
+/// on without them.
+pub(crate) async fn with_endpoints<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Option<OAuthCardInput> {
+    let issuer = issuer(input)?;
+    let mut extra = match &input.extra_json {
+        Some(Value::Object(extra)) => extra.clone(),
+        Some(_) => return None,
+        None => serde_json::Map::new(),
+    };
+    if extra.contains_key(OIDC_ENDPOINTS_KEY) {
+        return None;
+    }
+    let endpoints = match endpoints(backend, ctx, &issuer).await {
+        Ok(endpoints) => endpoints,
+        Err(err) => {
+            logging::warn(
+                ctx,
+                "oidc discovery failed",
+                json!({
+                    "provider_id": input.provider_id,
+                    "issuer": issuer,
+                    "error": err.to_string(),
+                }),
+            );
+            return None;
+        }
+    };
+    extra.insert(OIDC_ENDPOINTS_KEY.into(), json!(endpoints));
+    Some(OAuthCardInput {
+        extra_json: Some(Value::Object(extra)),
+        ..input.clone()
+    })
+}
+
+/// Discovered end-session endpoint carried by `input`, if any.
+pub(crate) fn end_session_endpoint(input: &OAuthCardInput) -> Option<String> {
+    input
+        .extra_json
+        .as_ref()?
+        .get(OIDC_ENDPOINTS_KEY)?
+        .get("end_session_endpoint")?
+        .as_str()
+        .map(str::to_string)
+}
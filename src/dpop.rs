@@ -0,0 +1,117 @@
+use std::sync::Mutex;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::OAuthCardError;
+
+/// Environment variable (host secret) holding the component's DPoP key pair
+/// as base64url PKCS#8 (ES256), shared by every instance so tokens bound to
+/// it stay usable across restarts.
+pub const DPOP_KEY_ENV: &str = "GREENTIC_OAUTH_DPOP_KEY";
+
+static PKCS8: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+fn failed(reason: impl std::fmt::Display) -> OAuthCardError {
+    OAuthCardError::Backend(format!("DPoP key: {reason}"))
+}
+
+/// The component's ES256 key pair: the one in [`DPOP_KEY_ENV`], else one
+/// generated for the life of the process (tokens bound to it need re-consent
+/// after a restart). The lock is held while the key is created, so
+/// concurrent first calls agree on one key.
+fn key_pair() -> Result<EcdsaKeyPair, OAuthCardError> {
+    let mut cached = PKCS8
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if cached.is_none() {
+        *cached = Some(load_or_generate()?);
+    }
+    EcdsaKeyPair::from_pkcs8(
+        &ECDSA_P256_SHA256_FIXED_SIGNING,
+        cached.as_deref().unwrap_or_default(),
+        &SystemRandom::new(),
+    )
+    .map_err(failed)
+}
+
+fn load_or_generate() -> Result<Vec<u8>, OAuthCardError> {
+    if let Some(configured) = std::env::var(DPOP_KEY_ENV)
+        .ok()
+        .filter(|key| !key.is_empty())
+    {
+        return URL_SAFE_NO_PAD
+            .decode(configured.trim())
+            .map_err(|err| failed(format!("{DPOP_KEY_ENV} is not base64url: {err}")));
+    }
+    Ok(
+        EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+            .map_err(failed)?
+            .as_ref()
+            .to_vec(),
+    )
+}
+
+/// Public key as a JWK, members in the lexicographic order RFC 7638
+/// thumbprints hash them in.
+fn public_jwk(key_pair: &EcdsaKeyPair) -> Value {
+    // Uncompressed SEC1 point: 0x04 || x || y.
+    let point = key_pair.public_key().as_ref();
+    json!({
+        "crv": "P-256",
+        "kty": "EC",
+        "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+        "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+    })
+}
+
+/// RFC 7638 thumbprint of the public key, sent to the broker as `dpop_jkt`
+/// so issued tokens are bound to it.
+pub(crate) fn thumbprint() -> Result<String, OAuthCardError> {
+    let jwk = public_jwk(&key_pair()?).to_string();
+    Ok(URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.as_bytes())))
+}
+
+/// DPoP proof JWT (RFC 9449) for one `method` request to `url`, presenting
+/// `access_token` when given (token requests present none).
+pub(crate) fn proof(
+    method: &str,
+    url: &str,
+    access_token: Option<&str>,
+    now: u64,
+) -> Result<String, OAuthCardError> {
+    let key_pair = key_pair()?;
+    let header = json!({
+        "typ": "dpop+jwt",
+        "alg": "ES256",
+        "jwk": public_jwk(&key_pair),
+    });
+    // `htu` excludes the query and fragment.
+    let htu = url.split(['?', '#']).next().unwrap_or_default();
+    let mut claims = json!({
+        "jti": Uuid::new_v4().to_string(),
+        "htm": method.to_ascii_uppercase(),
+        "htu": htu,
+        "iat": now,
+    });
+    if let Some(access_token) = access_token {
+        claims["ath"] = json!(URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes())));
+    }
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let signature = key_pair
+        .sign(&SystemRandom::new(), signing_input.as_bytes())
+        .map_err(failed)?;
+    Ok(format!(
+        "{signing_input}.{}",
+        URL_SAFE_NO_PAD.encode(signature.as_ref())
+    ))
+}
@@ -0,0 +1,13 @@
+use serde_json::Value;
+
+/// Request/response pairs for every mode, recorded by `tests/examples.rs`
+/// against its fixture broker; `make examples` refreshes the responses.
+const CATALOG: &str = include_str!("../tests/fixtures/examples.json");
+
+/// The `examples` operation's payload: one canonical request and the
+/// response it produced for each mode, with generated state ids shown as
+/// `<state-id>`.
+pub(crate) fn catalog() -> Value {
+    let mut catalog: Value = serde_json::from_str(CATALOG).unwrap_or(Value::Null);
+    serde_json::json!({ "examples": catalog["examples"].take() })
+}
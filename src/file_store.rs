@@ -0,0 +1,291 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::claims::account_label;
+use crate::correlation::CallContext;
+use crate::model::{BackendCapabilities, ConnectionSummary, TokenSet};
+use crate::scopes::Scope;
+
+/// Environment variable holding the base64-encoded 32-byte AES-256-GCM key.
+pub const STORE_KEY_ENV: &str = "GREENTIC_OAUTH_STORE_KEY";
+
+const NONCE_LEN: usize = 12;
+const EXTENSION: &str = "token";
+
+/// What a token file holds once decrypted; the file name is only a digest, so
+/// `list_connections` reads the provider and subject from here.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    provider_id: String,
+    subject: String,
+    token: TokenSet,
+}
+
+/// Token store persisting one AES-256-GCM encrypted `TokenSet` per
+/// provider/subject under a directory, for CLI and local development use.
+/// Files are named by a SHA-256 digest of the provider and subject, and the
+/// directory and files are only accessible to the owner on Unix.
+///
+/// It only stores tokens: consent URLs and code exchanges report `Unsupported`,
+/// so pair it with a real broker (e.g. through a chained backend) and call
+/// [`FileTokenStore::save`] with the tokens that broker returns.
+pub struct FileTokenStore {
+    dir: PathBuf,
+    cipher: Aes256Gcm,
+}
+
+impl FileTokenStore {
+    pub fn new(dir: impl Into<PathBuf>, key: &[u8; 32]) -> Self {
+        Self {
+            dir: dir.into(),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    /// Open `dir` with the key from [`STORE_KEY_ENV`].
+    pub fn from_env(dir: impl Into<PathBuf>) -> Result<Self, OAuthCardError> {
+        let encoded = std::env::var(STORE_KEY_ENV)
+            .map_err(|_| OAuthCardError::Invalid(format!("{STORE_KEY_ENV} is not set")))?;
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|err| OAuthCardError::Parse(format!("{STORE_KEY_ENV}: {err}")))?;
+        let key: [u8; 32] = bytes.try_into().map_err(|_| {
+            OAuthCardError::Invalid(format!("{STORE_KEY_ENV} must decode to 32 bytes"))
+        })?;
+        Ok(Self::new(dir, &key))
+    }
+
+    /// Encrypt and persist the token for a provider/subject pair.
+    pub fn save(
+        &self,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        let entry = Entry {
+            provider_id: provider_id.to_string(),
+            subject: subject.to_string(),
+            token: token.clone(),
+        };
+        let plaintext = zeroize::Zeroizing::new(
+            serde_json::to_vec(&entry)
+                .map_err(|err| OAuthCardError::Parse(format!("token json: {err}")))?,
+        );
+        let aad = entry_name(provider_id, subject);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| OAuthCardError::Invalid("token encryption failed".into()))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+
+        self.create_dir()?;
+        let path = self.path(provider_id, subject);
+        // A fresh name per write, so concurrent saves never share a temp file.
+        let tmp = path.with_extension(format!("{:016x}.tmp", OsRng.next_u64()));
+        write_private(&tmp, STANDARD.encode(sealed).as_bytes())
+            .and_then(|()| fs::rename(&tmp, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tmp);
+            })
+            .map_err(io_error)
+    }
+
+    fn create_dir(&self) -> Result<(), OAuthCardError> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&self.dir).map_err(io_error)
+    }
+
+    fn load(&self, path: &Path, aad: &str) -> Result<Option<Entry>, OAuthCardError> {
+        let encoded = match fs::read_to_string(path) {
+            Ok(encoded) => encoded,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(io_error(err)),
+        };
+        let sealed = STANDARD
+            .decode(encoded.trim())
+            .map_err(|err| OAuthCardError::Parse(format!("token file: {err}")))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(OAuthCardError::Parse("token file is truncated".into()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                OAuthCardError::Invalid("token file could not be decrypted with this key".into())
+            })?;
+        let plaintext = zeroize::Zeroizing::new(plaintext);
+        serde_json::from_slice(&plaintext)
+            .map(Some)
+            .map_err(|err| OAuthCardError::Parse(format!("token json: {err}")))
+    }
+
+    fn path(&self, provider_id: &str, subject: &str) -> PathBuf {
+        self.dir
+            .join(entry_name(provider_id, subject))
+            .with_extension(EXTENSION)
+    }
+}
+
+/// File stem for an entry: base64url of the SHA-256 of `provider_id NUL
+/// subject`, so names have a fixed length and do not reveal who is connected.
+fn entry_name(provider_id: &str, subject: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(format!("{provider_id}\0{subject}")))
+}
+
+/// Create `path` (it must not exist yet) readable and writable by the owner
+/// only, and write `contents` to disk before returning.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn io_error(err: std::io::Error) -> OAuthCardError {
+    OAuthCardError::Backend(format!("token store: {err}"))
+}
+
+impl OAuthBackend for FileTokenStore {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(self
+            .load(
+                &self.path(provider_id, subject),
+                &entry_name(provider_id, subject),
+            )?
+            .map(|entry| entry.token))
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+        _extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "file token store cannot start sign-in".into(),
+        ))
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "file token store cannot exchange codes".into(),
+        ))
+    }
+
+    fn store_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        self.save(provider_id, subject, token)
+    }
+
+    fn revoke_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        match fs::remove_file(self.path(provider_id, subject)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(io_error(err)),
+            _ => Ok(()),
+        }
+    }
+
+    fn list_connections(
+        &self,
+        _ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(io_error(err)),
+        };
+        let mut connections = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(io_error)?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            // Names are digests, so the subject is only known once decrypted.
+            let Some(entry) = self.load(&path, stem)? else {
+                continue;
+            };
+            if entry.subject != subject || entry_name(&entry.provider_id, subject) != stem {
+                continue;
+            }
+            connections.push(ConnectionSummary {
+                label: account_label(&entry.token),
+                scopes: entry.token.scopes(),
+                expires_at: entry.token.expires_at,
+                provider_id: entry.provider_id,
+            });
+        }
+        connections.sort_by(|a, b| a.provider_id.cmp(&b.provider_id));
+        Ok(connections)
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            revoke: true,
+            list_connections: true,
+            ..Default::default()
+        }
+    }
+}
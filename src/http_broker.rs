@@ -0,0 +1,311 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::correlation::CallContext;
+use crate::model::{
+    BackendCapabilities, BrokerErrorCode, ConnectionSummary, OidcEndpoints, TokenSet,
+};
+use crate::scopes::Scope;
+
+/// Environment variable holding the broker REST base URL (e.g. `http://localhost:8085/oauth`).
+pub const BROKER_URL_ENV: &str = "GREENTIC_OAUTH_BROKER_URL";
+/// Optional bearer token sent to the broker on every request.
+pub const BROKER_TOKEN_ENV: &str = "GREENTIC_OAUTH_BROKER_TOKEN";
+/// Optional per-call timeout in milliseconds (defaults to 10 seconds).
+pub const BROKER_TIMEOUT_ENV: &str = "GREENTIC_OAUTH_BROKER_TIMEOUT_MS";
+
+/// Lets the broker recognise a replayed code exchange (see
+/// [`OAuthBackend::exchange_code`]).
+const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+/// Header carrying the per-call timeout so the broker can bound its own
+/// provider calls to the time the component is still waiting.
+const TIMEOUT_HEADER: &str = "X-Request-Timeout-Ms";
+/// Header carrying the invocation's correlation id, when the input set one.
+const CORRELATION_HEADER: &str = "X-Correlation-Id";
+/// Header carrying the invocation's tenant, when known.
+const TENANT_HEADER: &str = "X-Tenant-Id";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Native broker client speaking the broker REST API:
+///
+/// - `GET  {base}/token?provider_id&subject&scopes` → `TokenSet` (404 when not connected)
+/// - `POST {base}/consent-url` → `{ "url": "..." }`
+/// - `POST {base}/exchange` → `TokenSet`
+///
+/// It is never picked implicitly: hosts pass it to
+/// [`handle_message_with_backend`](crate::handle_message_with_backend) or
+/// install it with [`register_backend`](crate::register_backend).
+#[derive(Clone)]
+pub struct HttpBroker {
+    base_url: String,
+    bearer_token: Option<String>,
+    timeout: Duration,
+    agent: ureq::Agent,
+}
+
+#[derive(Deserialize)]
+struct ConsentUrlResponse {
+    url: String,
+}
+
+impl HttpBroker {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            bearer_token: None,
+            timeout: DEFAULT_TIMEOUT,
+            agent: agent(DEFAULT_TIMEOUT),
+        }
+    }
+
+    /// Configure from [`BROKER_URL_ENV`], [`BROKER_TOKEN_ENV`] and
+    /// [`BROKER_TIMEOUT_ENV`]; fails when no broker URL is set.
+    pub fn from_env() -> Result<Self, OAuthCardError> {
+        let mut broker = match std::env::var(BROKER_URL_ENV) {
+            Ok(url) if !url.trim().is_empty() => Self::new(url),
+            _ => {
+                return Err(OAuthCardError::validation(
+                    BROKER_URL_ENV,
+                    "the broker URL is not set",
+                ));
+            }
+        };
+        broker.bearer_token = std::env::var(BROKER_TOKEN_ENV).ok();
+        if let Some(millis) = std::env::var(BROKER_TIMEOUT_ENV)
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+        {
+            broker = broker.with_timeout(Duration::from_millis(millis));
+        }
+        Ok(broker)
+    }
+
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Overall timeout for each broker call; exceeding it reports
+    /// [`OAuthCardError::Timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.agent = agent(timeout);
+        self
+    }
+
+    fn request(&self, ctx: &CallContext, method: &str, path: &str) -> ureq::Request {
+        // Never wait past the host's deadline for the invocation.
+        let timeout = ctx
+            .remaining()
+            .map_or(self.timeout, |remaining| remaining.min(self.timeout));
+        let mut request = self
+            .agent
+            .request(method, &format!("{}{path}", self.base_url))
+            .timeout(timeout)
+            .set(TIMEOUT_HEADER, &timeout.as_millis().to_string());
+        if let Some(correlation_id) = &ctx.correlation_id {
+            request = request.set(CORRELATION_HEADER, correlation_id);
+        }
+        if let Some(tenant) = &ctx.tenant {
+            request = request.set(TENANT_HEADER, tenant);
+        }
+        match &self.bearer_token {
+            Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+            None => request,
+        }
+    }
+}
+
+fn agent(timeout: Duration) -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(timeout).build()
+}
+
+/// Timeouts map to `Timeout` and other transport failures to `Transient`.
+/// Error responses carrying an OAuth `error` value, 429s and 5xx become
+/// categorized `Broker` errors; remaining statuses are invalid requests.
+fn broker_error(err: ureq::Error) -> OAuthCardError {
+    match err {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let oauth_error = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body.get("error")?.as_str().map(str::to_string));
+            let message = format!("broker returned {status}: {body}");
+            match BrokerErrorCode::from_response(status, oauth_error.as_deref()) {
+                BrokerErrorCode::Unknown if oauth_error.is_none() => {
+                    OAuthCardError::Backend(message)
+                }
+                BrokerErrorCode::ConsentDenied => OAuthCardError::Denied(message),
+                code => OAuthCardError::Broker { code, message },
+            }
+        }
+        ureq::Error::Transport(transport) if timed_out(&transport) => {
+            OAuthCardError::Timeout(format!("broker did not answer: {transport}"))
+        }
+        ureq::Error::Transport(transport) => {
+            OAuthCardError::Transient(format!("broker unreachable: {transport}"))
+        }
+    }
+}
+
+fn timed_out(transport: &ureq::Transport) -> bool {
+    std::error::Error::source(transport)
+        .and_then(|source| source.downcast_ref::<std::io::Error>())
+        .is_some_and(|err| {
+            matches!(
+                err.kind(),
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+            )
+        })
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(
+    response: ureq::Response,
+    what: &str,
+) -> Result<T, OAuthCardError> {
+    response
+        .into_json()
+        .map_err(|err| OAuthCardError::Parse(format!("{what} json: {err}")))
+}
+
+impl OAuthBackend for HttpBroker {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        let response = self
+            .request(ctx, "GET", "/token")
+            .query("provider_id", provider_id)
+            .query("subject", subject)
+            .query("scopes", &scopes.join(" "))
+            .call();
+        match response {
+            Ok(response) => read_json(response, "token").map(Some),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(err) => Err(broker_error(err)),
+        }
+    }
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        let extra = extra_json
+            .as_deref()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .transpose()
+            .map_err(|err| OAuthCardError::Parse(format!("extra_json: {err}")))?;
+        let response = self
+            .request(ctx, "POST", "/consent-url")
+            .send_json(json!({
+                "provider_id": provider_id,
+                "subject": subject,
+                "scopes": scopes,
+                "redirect_path": redirect_path,
+                "extra_json": extra,
+            }))
+            .map_err(broker_error)?;
+        read_json::<ConsentUrlResponse>(response, "consent url").map(|body| body.url)
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        OAuthBackend::exchange_code_with_extra(
+            self,
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+            None,
+        )
+    }
+
+    /// Sends `extra_json` (e.g. `resource`) along in the exchange body.
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        let mut request = self.request(ctx, "POST", "/exchange");
+        if let Some(key) = idempotency_key {
+            request = request.set(IDEMPOTENCY_HEADER, key);
+        }
+        let mut body = json!({
+            "provider_id": provider_id,
+            "subject": subject,
+            "code": code,
+            "redirect_path": redirect_path,
+        });
+        if let Some(extra_json) = extra_json {
+            body["extra_json"] = serde_json::Value::String(extra_json);
+        }
+        let response = request.send_json(body).map_err(broker_error)?;
+        read_json(response, "exchange")
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        let response = self
+            .request(ctx, "GET", "/connections")
+            .query("subject", subject)
+            .call()
+            .map_err(broker_error)?;
+        read_json(response, "connections")
+    }
+
+    fn discover_oidc(
+        &self,
+        ctx: &CallContext,
+        issuer: &str,
+    ) -> Result<OidcEndpoints, OAuthCardError> {
+        let response = self
+            .request(ctx, "GET", "/discovery")
+            .query("issuer", issuer)
+            .call()
+            .map_err(broker_error)?;
+        read_json(response, "discovery")
+    }
+
+    fn health(&self, ctx: &CallContext) -> Result<(), OAuthCardError> {
+        self.request(ctx, "GET", "/health")
+            .call()
+            .map(|_| ())
+            .map_err(broker_error)
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            list_connections: true,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::signature::{self, RsaParameters, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::OAuthCardError;
+use crate::claims::{claim_str, decode_jwt_claims};
+use crate::clock::now_secs;
+use crate::model::{IdTokenVerification, Jwk};
+
+/// Leeway for `exp`, `nbf` and `iat` against our clock.
+const CLOCK_SKEW_SECS: u64 = 60;
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+fn invalid(reason: impl std::fmt::Display) -> OAuthCardError {
+    OAuthCardError::Invalid(format!("id_token {reason}"))
+}
+
+fn decode(segment: &str, what: &str) -> Result<Vec<u8>, OAuthCardError> {
+    URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .map_err(|err| invalid(format_args!("{what}: {err}")))
+}
+
+/// [`verify_jwt`] for a freshly issued id_token, which must also carry an
+/// unexpired `exp`, an `iat` not in the future and, if present, a passed `nbf`.
+pub(crate) fn verify_id_token(
+    jwt: &str,
+    verification: &IdTokenVerification,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    let claims = verify_jwt(jwt, verification)?;
+    let now = now_secs();
+    let time = |name: &str| claims.get(name).and_then(Value::as_u64);
+    match time("exp") {
+        None => return Err(invalid("has no exp")),
+        Some(exp) if exp.saturating_add(CLOCK_SKEW_SECS) <= now => {
+            return Err(invalid("has expired"));
+        }
+        Some(_) => {}
+    }
+    match time("iat") {
+        None => return Err(invalid("has no iat")),
+        Some(iat) if iat > now.saturating_add(CLOCK_SKEW_SECS) => {
+            return Err(invalid("was issued in the future"));
+        }
+        Some(_) => {}
+    }
+    if let Some(nbf) = time("nbf")
+        && nbf > now.saturating_add(CLOCK_SKEW_SECS)
+    {
+        return Err(invalid("is not valid yet"));
+    }
+    Ok(claims)
+}
+
+/// Check the signature of `jwt` against the configured JWKS (RS256/384/512,
+/// ES256/384), then its `iss` and `aud`, and return its claims. Every key
+/// matching the header's `kid` and `alg` is tried.
+pub(crate) fn verify_jwt(
+    jwt: &str,
+    verification: &IdTokenVerification,
+) -> Result<Map<String, Value>, OAuthCardError> {
+    let (signed, signature) = jwt
+        .rsplit_once('.')
+        .ok_or_else(|| invalid("is not a compact JWT"))?;
+    let header = signed
+        .split_once('.')
+        .map(|(header, _)| header)
+        .ok_or_else(|| invalid("is not a compact JWT"))?;
+    let header: Header = serde_json::from_slice(&decode(header, "header")?)
+        .map_err(|err| invalid(format_args!("header: {err}")))?;
+    let signature = decode(signature, "signature")?;
+    let kty = key_type(&header.alg)
+        .ok_or_else(|| invalid(format_args!("alg `{}` is not supported", header.alg)))?;
+    let mut keys = verification
+        .jwks
+        .keys
+        .iter()
+        .filter(|key| header.kid.is_none() || key.kid == header.kid)
+        .filter(|key| key.alg.as_ref().is_none_or(|alg| *alg == header.alg))
+        .filter(|key| key.kty == kty)
+        .peekable();
+    if keys.peek().is_none() {
+        return Err(invalid(
+            "is signed with a key missing from the provider JWKS",
+        ));
+    }
+    let mut result = Err(invalid("signature is invalid"));
+    for key in keys {
+        result = verify_signature(&header.alg, key, signed.as_bytes(), &signature);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result?;
+
+    let claims = decode_jwt_claims(jwt)?;
+    if let Some(issuer) = verification.issuer.as_deref()
+        && claim_str(&claims, "iss").as_deref() != Some(issuer)
+    {
+        return Err(invalid("issuer does not match"));
+    }
+    if let Some(audience) = verification.audience.as_deref() {
+        let matches = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !matches {
+            return Err(invalid("audience does not match"));
+        }
+    }
+    Ok(claims)
+}
+
+/// The JWK `kty` that can verify `alg`, when supported.
+fn key_type(alg: &str) -> Option<&'static str> {
+    match alg {
+        "RS256" | "RS384" | "RS512" => Some("RSA"),
+        "ES256" | "ES384" => Some("EC"),
+        _ => None,
+    }
+}
+
+fn verify_signature(
+    alg: &str,
+    key: &Jwk,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), OAuthCardError> {
+    let param = |value: &Option<String>, name: &str| match value.as_deref() {
+        Some(value) => decode(value, &format!("JWKS key `{name}`")),
+        None => Err(invalid(format_args!("JWKS key has no `{name}`"))),
+    };
+    let rsa = |params: &'static RsaParameters| -> Result<bool, OAuthCardError> {
+        let components = RsaPublicKeyComponents {
+            n: param(&key.n, "n")?,
+            e: param(&key.e, "e")?,
+        };
+        Ok(components.verify(params, message, signature).is_ok())
+    };
+    let ec = |params: &'static signature::EcdsaVerificationAlgorithm| {
+        // Uncompressed SEC1 point: 0x04 || x || y.
+        let mut point = vec![0x04];
+        point.extend(param(&key.x, "x")?);
+        point.extend(param(&key.y, "y")?);
+        Ok::<_, OAuthCardError>(
+            UnparsedPublicKey::new(params, point)
+                .verify(message, signature)
+                .is_ok(),
+        )
+    };
+    let valid = match (alg, key.kty.as_str()) {
+        ("RS256", "RSA") => rsa(&signature::RSA_PKCS1_2048_8192_SHA256)?,
+        ("RS384", "RSA") => rsa(&signature::RSA_PKCS1_2048_8192_SHA384)?,
+        ("RS512", "RSA") => rsa(&signature::RSA_PKCS1_2048_8192_SHA512)?,
+        ("ES256", "EC") => ec(&signature::ECDSA_P256_SHA256_FIXED)?,
+        ("ES384", "EC") => ec(&signature::ECDSA_P384_SHA384_FIXED)?,
+        _ => {
+            return Err(invalid(format_args!(
+                "alg `{alg}` is not supported for a `{}` key",
+                key.kty
+            )));
+        }
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid("signature is invalid"))
+    }
+}
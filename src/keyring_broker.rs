@@ -0,0 +1,141 @@
+use keyring::Entry;
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::correlation::CallContext;
+use crate::model::{BackendCapabilities, TokenSet};
+use crate::scopes::Scope;
+
+/// Keychain service name used unless the embedder picks its own.
+pub const DEFAULT_KEYRING_SERVICE: &str = "greentic-oauth-card";
+
+/// Token store backed by the OS credential store (macOS Keychain, Windows
+/// Credential Manager, Linux kernel keyutils), so desktop embedders never write
+/// refresh tokens to disk in plaintext.
+///
+/// Each provider/subject pair is one credential holding the `TokenSet` JSON.
+/// Like the file store it only stores tokens; sign-in itself needs a broker.
+/// Windows caps credentials at 2560 bytes, which large `id_token`s can exceed.
+///
+/// On Linux, keyutils is kernel memory: credentials are gone after a reboot
+/// and the persistent keyring expires a few days after the user's last
+/// session. A vanished credential reads as "not connected", so status-card and
+/// ensure-token ask the user to sign in again; treat it as a cache in front of
+/// a broker (e.g. with `ChainedBackend`) or use `FileTokenStore` where tokens
+/// must outlive the machine's uptime.
+#[derive(Debug, Clone)]
+pub struct KeyringBroker {
+    service: String,
+}
+
+impl Default for KeyringBroker {
+    fn default() -> Self {
+        Self::new(DEFAULT_KEYRING_SERVICE)
+    }
+}
+
+impl KeyringBroker {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+
+    /// Store (or replace) the token for a provider/subject pair.
+    pub fn save(
+        &self,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        let json = serde_json::to_string(token)
+            .map_err(|err| OAuthCardError::Parse(format!("token json: {err}")))?;
+        self.entry(provider_id, subject)?
+            .set_password(&json)
+            .map_err(keyring_error)
+    }
+
+    fn entry(&self, provider_id: &str, subject: &str) -> Result<Entry, OAuthCardError> {
+        Entry::new(&self.service, &format!("{provider_id}:{subject}")).map_err(keyring_error)
+    }
+}
+
+fn keyring_error(err: keyring::Error) -> OAuthCardError {
+    OAuthCardError::Backend(format!("keyring: {err}"))
+}
+
+impl OAuthBackend for KeyringBroker {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        match self.entry(provider_id, subject)?.get_password() {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|err| OAuthCardError::Parse(format!("token json: {err}"))),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(keyring_error(err)),
+        }
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+        _extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "keyring store cannot start sign-in".into(),
+        ))
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "keyring store cannot exchange codes".into(),
+        ))
+    }
+
+    fn store_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        self.save(provider_id, subject, token)
+    }
+
+    fn revoke_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        match self.entry(provider_id, subject)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(keyring_error(err)),
+        }
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            revoke: true,
+            ..Default::default()
+        }
+    }
+}
@@ -1,15 +1,108 @@
 #![warn(clippy::unwrap_used, clippy::expect_used)]
 
 mod broker;
+mod cached;
+mod chained;
+mod claims;
+mod clock;
+mod component_config;
+mod correlation;
+mod diagnostics;
+#[cfg(all(feature = "direct-oidc", not(target_arch = "wasm32")))]
+mod direct_oidc;
+mod discovery;
+#[cfg(feature = "dpop")]
+mod dpop;
+mod examples;
+#[cfg(all(feature = "file-store", not(target_arch = "wasm32")))]
+mod file_store;
+#[cfg(all(feature = "native-http", not(target_arch = "wasm32")))]
+mod http_broker;
+#[cfg(feature = "verify-jwt")]
+mod jwt;
+#[cfg(all(feature = "keyring", not(target_arch = "wasm32")))]
+mod keyring_broker;
+mod logging;
 mod logic;
+mod memory_broker;
+mod metrics;
 mod model;
+mod observed;
+mod output_policy;
+mod progress;
+mod provider_config;
+mod providers;
+mod rate_limit;
+mod render;
+mod retry;
+#[cfg(feature = "schema")]
+mod schema;
+mod scopes;
+mod signed_state;
+mod state_store;
+mod template;
+mod validation;
 
-pub use broker::{OAuthBackend, default_backend};
-pub use logic::handle;
+pub use broker::{
+    AsyncOAuthBackend, CURRENT_INPUT_VERSION, OAuthBackend, default_backend, encode_input,
+    parse_input, register_backend,
+};
+pub use cached::CachedBackend;
+pub use chained::{ChainedBackend, WritePolicy};
+pub use clock::{Clock, SystemClock, register_clock};
+pub use component_config::{ComponentConfig, component_config, load_component_config};
+pub use correlation::CallContext;
+#[cfg(all(feature = "direct-oidc", not(target_arch = "wasm32")))]
+pub use direct_oidc::{DirectOidcBackend, OidcClientConfig};
+#[cfg(feature = "dpop")]
+pub use dpop::DPOP_KEY_ENV;
+#[cfg(all(feature = "file-store", not(target_arch = "wasm32")))]
+pub use file_store::{FileTokenStore, STORE_KEY_ENV};
+#[cfg(all(feature = "native-http", not(target_arch = "wasm32")))]
+pub use http_broker::{BROKER_TIMEOUT_ENV, BROKER_TOKEN_ENV, BROKER_URL_ENV, HttpBroker};
+#[cfg(all(feature = "keyring", not(target_arch = "wasm32")))]
+pub use keyring_broker::{DEFAULT_KEYRING_SERVICE, KeyringBroker};
+#[cfg(target_arch = "wasm32")]
+pub use logging::HostLogger;
+#[cfg(not(target_arch = "wasm32"))]
+pub use logging::StderrLogger;
+pub use logging::{LogLevel, LogRecord, Logger, register_logger};
+pub use logic::{
+    handle, handle_async, handle_async_with_context, handle_with_context, health, health_async,
+};
+pub use memory_broker::{InMemoryBroker, TokenFixture};
+pub use metrics::{
+    LATENCY_BUCKETS_MS, LatencyHistogram, MeteredBackend, MetricsSnapshot, metrics_snapshot,
+    reset_metrics,
+};
 pub use model::{
-    Action, AuthContext, AuthHeader, OAuthCardInput, OAuthCardMode, OAuthCardOutput, OAuthStatus,
-    TokenSet,
+    AccountSummary, Action, AuthContext, AuthHeader, AuthInjection, AuthInjectionStyle,
+    BackChannelLogoutInput, BackChannelLogoutOutput, BackendCapabilities, Branding,
+    BrokerErrorCode, CardOverrides, ConnectionSummary, DeviceCodeGrant, Diagnostics, DpopRequest,
+    ErrorCode, HealthOutput, IdTokenVerification, ImageRef, Jwk, Jwks, MessageCard,
+    MessageCardBuilder, MessageCardKind, OAuthCardInput, OAuthCardMode, OAuthCardOutput,
+    OAuthStatus, OauthCard, OauthPrompt, OauthProvider, OidcEndpoints, PendingSignIn,
+    ProviderConfig, ProviderRequest, ProviderResult, REDACTED, SignInProgress, TOKEN_BINDING_KEY,
+    TokenSet, UserProfile, ValidationError, VersionOutput,
 };
+pub use output_policy::load_output_policy;
+pub use progress::Stage;
+pub use provider_config::load_provider_configs;
+pub use providers::{
+    MICROSOFT_CONSUMER_TENANT, OfflineAccess, PROVIDERS, ProviderInfo, microsoft_authority,
+    provider_info,
+};
+pub use rate_limit::load_sign_in_rate_limit;
+pub use render::resolve_post_back;
+pub use retry::RetryingBackend;
+#[cfg(feature = "schema")]
+pub use schema::schemas;
+pub use scopes::Scope;
+pub use signed_state::{STATE_KEY_ENV, load_state_signing_key};
+#[cfg(target_arch = "wasm32")]
+pub use state_store::HostStateStore;
+pub use state_store::{InMemoryStateStore, StateStore, register_state_store};
+pub use template::CardTemplate;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -20,6 +113,159 @@ pub enum OAuthCardError {
     Parse(String),
     #[error("unsupported: {0}")]
     Unsupported(String),
+    /// Temporary broker failure (network error, timeout, 5xx, rate limit);
+    /// the same call may succeed if retried.
+    #[error("temporarily unavailable: {0}")]
+    Transient(String),
+    /// The broker did not answer within the configured timeout.
+    #[error("timed out: {0}")]
+    Timeout(String),
+    /// The user or provider refused the authorization.
+    #[error("access denied: {0}")]
+    Denied(String),
+    /// The backend failed in a way that fits no other category.
+    #[error("backend error: {0}")]
+    Backend(String),
+    /// An input field is missing or does not match the flow it belongs to.
+    #[error("invalid {field}: {message}")]
+    Validation { field: String, message: String },
+    /// Several input fields failed validation at once.
+    #[error("invalid input: {}", summarize(.0))]
+    Validations(Vec<ValidationError>),
+    /// The `state_id` is not signed with the configured key, was issued for
+    /// another provider or subject, or has expired.
+    #[error("invalid state: {0}")]
+    InvalidState(String),
+    /// The callback's `state_id` or auth code was already used to complete a
+    /// sign-in.
+    #[error("replayed sign-in: {0}")]
+    Replay(String),
+    /// Too many start-sign-in requests for the same subject and provider.
+    #[error(
+        "too many sign-in attempts, try again in {}",
+        template::humanize_duration(*retry_after_secs)
+    )]
+    RateLimited { retry_after_secs: u64 },
+    /// The stored token is bound to another tenant or team than the input's.
+    #[error("tenant mismatch: {0}")]
+    TenantMismatch(String),
+    /// The id_token does not show the requested authentication level or
+    /// recency.
+    #[error("step-up required: {0}")]
+    StepUpRequired(String),
+    /// The broker reported a categorized failure.
+    #[error("{code}: {message}")]
+    Broker {
+        code: BrokerErrorCode,
+        message: String,
+    },
+}
+
+impl OAuthCardError {
+    /// Whether retrying the failed call may succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self.code(),
+            Some(BrokerErrorCode::Throttled | BrokerErrorCode::ProviderUnavailable)
+        )
+    }
+
+    /// Broker failure category the error card is chosen by; unreachable
+    /// brokers and timeouts count as `provider_unavailable`.
+    pub fn code(&self) -> Option<BrokerErrorCode> {
+        match self {
+            Self::Broker { code, .. } => Some(*code),
+            Self::Transient(_) | Self::Timeout(_) => Some(BrokerErrorCode::ProviderUnavailable),
+            Self::Denied(_) => Some(BrokerErrorCode::ConsentDenied),
+            Self::RateLimited { .. } => Some(BrokerErrorCode::Throttled),
+            Self::Invalid(_)
+            | Self::Parse(_)
+            | Self::Unsupported(_)
+            | Self::Backend(_)
+            | Self::InvalidState(_)
+            | Self::Replay(_)
+            | Self::TenantMismatch(_)
+            | Self::StepUpRequired(_)
+            | Self::Validation { .. }
+            | Self::Validations(_) => None,
+        }
+    }
+
+    /// Machine-readable code reported in `OAuthCardOutput::error_code`.
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Self::Invalid(_) => ErrorCode::InvalidInput,
+            Self::Parse(_) => ErrorCode::ParseError,
+            Self::Unsupported(_) => ErrorCode::Unsupported,
+            Self::Transient(_) => ErrorCode::ProviderUnavailable,
+            Self::Timeout(_) => ErrorCode::Timeout,
+            Self::Denied(_) => ErrorCode::ConsentDenied,
+            Self::Backend(_) => ErrorCode::BackendError,
+            Self::Validation { .. } | Self::Validations(_) => ErrorCode::ValidationFailed,
+            Self::InvalidState(_) => ErrorCode::InvalidState,
+            Self::Replay(_) => ErrorCode::Replay,
+            Self::RateLimited { .. } => ErrorCode::RateLimited,
+            Self::TenantMismatch(_) => ErrorCode::TenantMismatch,
+            Self::StepUpRequired(_) => ErrorCode::StepUpRequired,
+            Self::Broker { code, .. } => (*code).into(),
+        }
+    }
+
+    /// Structured context for `OAuthCardOutput::error_detail`: the unprefixed
+    /// message, plus the offending `field` for validation failures.
+    pub fn detail(&self) -> serde_json::Value {
+        match self {
+            Self::Validation { field, message } => {
+                serde_json::json!({ "field": field, "message": message })
+            }
+            Self::Validations(errors) => serde_json::json!({ "errors": errors }),
+            Self::Broker { code, message } => {
+                serde_json::json!({ "broker_code": code.as_str(), "message": message })
+            }
+            Self::RateLimited { retry_after_secs } => {
+                serde_json::json!({ "message": self.to_string(), "retry_after_secs": retry_after_secs })
+            }
+            Self::Invalid(message)
+            | Self::Parse(message)
+            | Self::Unsupported(message)
+            | Self::Transient(message)
+            | Self::Timeout(message)
+            | Self::Denied(message)
+            | Self::Backend(message)
+            | Self::InvalidState(message)
+            | Self::Replay(message)
+            | Self::TenantMismatch(message)
+            | Self::StepUpRequired(message) => serde_json::json!({ "message": message }),
+        }
+    }
+
+    /// Every rejected field of a validation failure; empty for other errors.
+    pub fn validation_errors(&self) -> Vec<ValidationError> {
+        match self {
+            Self::Validation { field, message } => vec![ValidationError {
+                field: field.clone(),
+                message: message.clone(),
+            }],
+            Self::Validations(errors) => errors.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Shorthand for a [`OAuthCardError::Validation`] failure.
+    pub fn validation(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::Validation {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+fn summarize(errors: &[ValidationError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("{}: {}", error.field, error.message))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -33,15 +279,31 @@ mod component {
         self, ExecCtx, InvokeResult, LifecycleStatus, StreamEvent,
     };
 
-    use super::{describe_payload, handle_message};
+    use super::{
+        CallContext, describe_payload, handle_message_stream_with_context,
+        handle_message_with_context,
+    };
 
     pub(super) struct Component;
 
+    /// Broker calls see the host's tenant, correlation id and deadline.
+    fn call_context(ctx: &ExecCtx) -> CallContext {
+        CallContext {
+            correlation_id: ctx.tenant.correlation_id.clone(),
+            tenant: Some(ctx.tenant.tenant.clone()),
+            deadline_unix_ms: ctx.tenant.deadline_unix_ms,
+            flow_id: Some(ctx.flow_id.clone()),
+            node_id: ctx.node_id.clone(),
+        }
+    }
+
     impl node::Guest for Component {
         fn get_manifest() -> String {
             describe_payload()
         }
 
+        // The start context carries no configuration; hosts send it with the
+        // `configure` operation.
         fn on_start(_ctx: ExecCtx) -> Result<LifecycleStatus, String> {
             Ok(LifecycleStatus::Ok)
         }
@@ -50,16 +312,25 @@ mod component {
             Ok(LifecycleStatus::Ok)
         }
 
-        fn invoke(_ctx: ExecCtx, op: String, input: String) -> InvokeResult {
-            InvokeResult::Ok(handle_message(&op, &input))
+        fn invoke(ctx: ExecCtx, op: String, input: String) -> InvokeResult {
+            InvokeResult::Ok(handle_message_with_context(
+                &call_context(&ctx),
+                &op,
+                &input,
+            ))
         }
 
-        fn invoke_stream(_ctx: ExecCtx, op: String, input: String) -> Vec<StreamEvent> {
-            vec![
-                StreamEvent::Progress(0),
-                StreamEvent::Data(handle_message(&op, &input)),
-                StreamEvent::Done,
-            ]
+        fn invoke_stream(ctx: ExecCtx, op: String, input: String) -> Vec<StreamEvent> {
+            // `invoke-stream` returns its events as one list once the invocation
+            // is done, so the progress events replay the stages reached rather
+            // than report live progress. They have no room for the stage label.
+            let (stages, data) =
+                handle_message_stream_with_context(&call_context(&ctx), &op, &input);
+            stages
+                .into_iter()
+                .map(|stage| StreamEvent::Progress(stage.percent()))
+                .chain([StreamEvent::Data(data), StreamEvent::Done])
+                .collect()
         }
     }
 }
@@ -120,7 +391,29 @@ mod exports {
     }
 }
 
+/// Component manifest returned by `get-manifest`. Besides the schema paths it
+/// embeds the schemas themselves (the committed files `tests/schemas.rs` keeps
+/// in sync with the models), since hosts may not have the repository, and
+/// lists the operations with an example input each. Its `capabilities` let
+/// flow designers feature-detect modes, providers, renderers and what the
+/// broker (registered or default) supports.
 pub fn describe_payload() -> String {
+    match broker::registered_backend() {
+        Some(backend) => describe_payload_with_backend(&backend),
+        None => describe_payload_with_backend(&broker::default_backend()),
+    }
+}
+
+/// [`describe_payload`] reporting the capabilities of a caller-supplied broker.
+pub fn describe_payload_with_backend<B: OAuthBackend>(backend: &B) -> String {
+    let renderers: std::collections::BTreeSet<&str> = ["adaptive", "text"]
+        .into_iter()
+        .chain(render::CHANNELS)
+        .collect();
+    let providers: Vec<&str> = PROVIDERS.iter().map(|info| info.id).collect();
+    let embedded = |schema: &str| {
+        serde_json::from_str::<serde_json::Value>(schema).unwrap_or(serde_json::Value::Null)
+    };
     serde_json::json!({
         "component": {
             "name": "component-oauth-card",
@@ -131,24 +424,192 @@ pub fn describe_payload() -> String {
                 "component": "schemas/component.schema.json",
                 "input": "schemas/io/input.schema.json",
                 "output": "schemas/io/output.schema.json"
-            }
+            },
+            "embedded_schemas": {
+                "component": embedded(include_str!("../schemas/component.schema.json")),
+                "input": embedded(include_str!("../schemas/io/input.schema.json")),
+                "output": embedded(include_str!("../schemas/io/output.schema.json"))
+            },
+            "capabilities": {
+                "modes": OAuthCardMode::SUPPORTED,
+                "providers": providers,
+                "configured_providers": provider_config::configured_provider_ids(),
+                "renderers": renderers,
+                "broker": backend.capabilities(),
+                "qr": cfg!(feature = "qr"),
+                "dpop": cfg!(feature = "dpop")
+            },
+            "operations": [
+                {
+                    "name": "invoke",
+                    "description": "Run the card flow selected by the input's mode.",
+                    "input_schema": "input",
+                    "output_schema": "output",
+                    "example": {
+                        "mode": "ensure-token",
+                        "provider_id": "msgraph",
+                        "subject": "user-1",
+                        "scopes": ["User.Read"]
+                    }
+                },
+                {
+                    "name": "configure",
+                    "description": "Load the component configuration, replacing the one loaded before; an invalid one changes nothing.",
+                    "input_schema": "component",
+                    "example": {
+                        "default_provider": "msgraph",
+                        "providers": { "msgraph": { "default_scopes": ["User.Read"] } }
+                    }
+                },
+                {
+                    "name": "backchannel-logout",
+                    "description": "Invalidate the sessions named by an OIDC back-channel logout token.",
+                    "example": {
+                        "provider_id": "msgraph",
+                        "logout_token": "<logout token JWT>"
+                    }
+                },
+                {
+                    "name": "health",
+                    "description": "Probe broker reachability; takes no input.",
+                    "example": null
+                },
+                {
+                    "name": "metrics",
+                    "description": "Invocation, error and broker latency counters; takes no input.",
+                    "example": null
+                },
+                {
+                    "name": "version",
+                    "description": "Crate version, git sha and supported modes; takes no input.",
+                    "example": null
+                },
+                {
+                    "name": "examples",
+                    "description": "A sample request and its response for every mode; takes no input.",
+                    "example": null
+                }
+            ]
         }
     })
     .to_string()
 }
 
 pub fn handle_message(operation: &str, input: &str) -> String {
-    let _ = operation;
-    let backend = broker::default_backend();
-    let response = broker::parse_input(input)
-        .and_then(|parsed| logic::handle(&backend, parsed))
-        .unwrap_or_else(|err| OAuthCardOutput {
+    handle_message_with_context(&CallContext::default(), operation, input)
+}
+
+/// [`handle_message`] for a host that supplies the invocation's
+/// [`CallContext`], e.g. from the node `ExecCtx`.
+pub fn handle_message_with_context(host: &CallContext, operation: &str, input: &str) -> String {
+    match broker::registered_backend() {
+        Some(backend) => {
+            handle_message_in_context(&MeteredBackend::new(backend), host, operation, input)
+        }
+        None => handle_message_in_context(
+            &MeteredBackend::new(broker::default_backend()),
+            host,
+            operation,
+            input,
+        ),
+    }
+}
+
+/// [`handle_message`], also returning the stages the invocation went through
+/// (parsing, broker lookup, consent URL, exchange, rendering) in order.
+///
+/// The stages are only known once the invocation has finished, so they tell
+/// a host how far a request got (e.g. where a failed one stopped), not how a
+/// running one is progressing.
+pub fn handle_message_stream(operation: &str, input: &str) -> (Vec<Stage>, String) {
+    handle_message_stream_with_context(&CallContext::default(), operation, input)
+}
+
+/// [`handle_message_stream`] for a host that supplies the invocation's
+/// [`CallContext`].
+pub fn handle_message_stream_with_context(
+    host: &CallContext,
+    operation: &str,
+    input: &str,
+) -> (Vec<Stage>, String) {
+    let (data, stages) = progress::collect(|| {
+        progress::mark(Stage::Parsing);
+        handle_message_with_context(host, operation, input)
+    });
+    (stages, data)
+}
+
+/// [`handle_message`] against a caller-supplied broker, for native hosts that
+/// embed the component with their own backend. Like [`handle_message`], its
+/// broker calls are recorded by a [`MeteredBackend`].
+pub fn handle_message_with_backend<B: OAuthBackend>(
+    backend: &B,
+    operation: &str,
+    input: &str,
+) -> String {
+    handle_message_in_context(
+        &MeteredBackend::new(backend),
+        &CallContext::default(),
+        operation,
+        input,
+    )
+}
+
+fn handle_message_in_context<B: OAuthBackend>(
+    backend: &B,
+    host: &CallContext,
+    operation: &str,
+    input: &str,
+) -> String {
+    if operation == "configure" {
+        return handle_configure(input);
+    }
+    if operation == "backchannel-logout" {
+        return handle_back_channel_logout(backend, host, input);
+    }
+    if operation == "health" {
+        return handle_health(backend, host);
+    }
+    if operation == "metrics" {
+        return handle_metrics();
+    }
+    if operation == "version" {
+        return handle_version();
+    }
+    if operation == "examples" {
+        return examples::catalog().to_string();
+    }
+    let mut response = broker::parse_input(input)
+        .and_then(|parsed| logic::handle_with_context(backend, host, parsed))
+        .unwrap_or_else(|err| {
+            let output = OAuthCardOutput::error(&err);
+            metrics::record_invocation(None, &output);
+            output
+        });
+
+    let json = serde_json::to_string(&response).unwrap_or_else(|err| {
+        serde_json::json!({
+            "status": "error",
+            "error": format!("serialization failure: {err}")
+        })
+        .to_string()
+    });
+    // Only the serialized copy handed back to the host keeps the token.
+    response.zeroize_secrets();
+    json
+}
+
+fn handle_back_channel_logout<B: OAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &str,
+) -> String {
+    let response = broker::parse_logout_input(input)
+        .and_then(|parsed| logic::back_channel_logout(backend, ctx, parsed))
+        .unwrap_or_else(|err| BackChannelLogoutOutput {
             status: OAuthStatus::Error,
-            card: None,
-            auth_context: None,
-            auth_header: None,
-            state_id: None,
             error: Some(err.to_string()),
+            ..Default::default()
         });
 
     serde_json::to_string(&response).unwrap_or_else(|err| {
@@ -160,18 +621,165 @@ pub fn handle_message(operation: &str, input: &str) -> String {
     })
 }
 
+fn handle_configure(input: &str) -> String {
+    match load_component_config(input) {
+        Ok(()) => serde_json::json!({ "status": "ok" }),
+        Err(err) => serde_json::json!({ "status": "error", "error": err.to_string() }),
+    }
+    .to_string()
+}
+
+fn handle_health<B: OAuthBackend>(backend: &B, ctx: &CallContext) -> String {
+    serde_json::to_string(&logic::health(backend, ctx)).unwrap_or_else(|err| {
+        serde_json::json!({
+            "status": "error",
+            "error": format!("serialization failure: {err}")
+        })
+        .to_string()
+    })
+}
+
+fn handle_metrics() -> String {
+    serde_json::to_string(&metrics_snapshot()).unwrap_or_else(|err| {
+        serde_json::json!({
+            "status": "error",
+            "error": format!("serialization failure: {err}")
+        })
+        .to_string()
+    })
+}
+
+fn handle_version() -> String {
+    let output = VersionOutput {
+        version: env!("CARGO_PKG_VERSION").into(),
+        git_sha: option_env!("GIT_SHA")
+            .filter(|sha| !sha.is_empty())
+            .map(str::to_string),
+        modes: OAuthCardMode::SUPPORTED.to_vec(),
+    };
+    serde_json::to_string(&output).unwrap_or_else(|err| {
+        serde_json::json!({
+            "status": "error",
+            "error": format!("serialization failure: {err}")
+        })
+        .to_string()
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::broker::MockBroker;
     use crate::model::{OAuthCardInput, OAuthCardMode, TokenSet};
 
+    fn scope(raw: &str) -> Scope {
+        Scope::parse(raw).unwrap_or_else(|err| panic!("scope {raw}: {err}"))
+    }
+
+    fn test_input(mode: OAuthCardMode) -> OAuthCardInput {
+        OAuthCardInput {
+            version: 1,
+            mode,
+            provider_id: "msgraph".into(),
+            subject: "user-1".into(),
+            tenant: None,
+            correlation_id: None,
+            team: None,
+            scopes: vec![],
+            state_id: None,
+            auth_code: None,
+            sso_token: None,
+            allow_auto_sign_in: false,
+            redirect_path: None,
+            extra_json: None,
+            refresh_margin_secs: None,
+            clock_skew_secs: None,
+            card_overrides: None,
+            require_confirmation: false,
+            include_qr: false,
+            channel: None,
+            account_id: None,
+            providers: vec![],
+            strict: false,
+            redact_secrets: false,
+            include_auth_header: true,
+            login_hint: None,
+            domain_hint: None,
+            prompt: None,
+            acr_values: None,
+            max_age: None,
+            resource: None,
+            audience: None,
+            subject_token: None,
+            subject_token_type: None,
+            dpop_request: None,
+            debug: false,
+        }
+    }
+
     #[test]
     fn describe_payload_is_json() {
         let payload = describe_payload();
         let json: serde_json::Value =
             serde_json::from_str(&payload).unwrap_or_else(|err| panic!("valid json: {err}"));
         assert_eq!(json["component"]["name"], "component-oauth-card");
+        let schemas = &json["component"]["embedded_schemas"];
+        for name in ["component", "input", "output"] {
+            assert!(schemas[name].is_object(), "{name} schema");
+        }
+        let invoke = &json["component"]["operations"][0];
+        assert_eq!(invoke["name"], "invoke");
+        let example = invoke["example"].to_string();
+        assert!(
+            broker::parse_input(&example).is_ok(),
+            "invoke example parses"
+        );
+        let operations: Vec<&str> = json["component"]["operations"]
+            .as_array()
+            .map(|operations| {
+                operations
+                    .iter()
+                    .filter_map(|op| op["name"].as_str())
+                    .collect()
+            })
+            .unwrap_or_default();
+        assert_eq!(
+            operations,
+            [
+                "invoke",
+                "configure",
+                "backchannel-logout",
+                "health",
+                "metrics",
+                "version",
+                "examples"
+            ]
+        );
+    }
+
+    #[test]
+    fn describe_payload_reports_capabilities_of_the_broker() {
+        let backend = MockBroker {
+            capabilities: Some(BackendCapabilities {
+                refresh: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&describe_payload_with_backend(&backend))
+                .unwrap_or_else(|err| panic!("valid json: {err}"));
+        let capabilities = &json["component"]["capabilities"];
+        assert_eq!(capabilities["broker"]["refresh"], true);
+        assert_eq!(capabilities["broker"]["device_code"], false);
+        assert_eq!(capabilities["broker"]["revoke"], false);
+        assert_eq!(
+            capabilities["modes"].as_array().map(Vec::len),
+            Some(OAuthCardMode::SUPPORTED.len())
+        );
+        assert_eq!(capabilities["providers"][0], "microsoft");
+        let renderers = capabilities["renderers"].to_string();
+        assert!(renderers.contains("\"slack\"") && renderers.contains("\"text\""));
     }
 
     #[test]
@@ -180,24 +788,22 @@ mod tests {
             token: Some(TokenSet {
                 access_token: "token123".into(),
                 refresh_token: None,
-                expires_at: Some(123),
+                expires_at: Some(clock::now_secs() + 3_600),
                 token_type: Some("Bearer".into()),
-                extra: Some(serde_json::json!({ "email": "user@example.com" })),
+                extra: Some(serde_json::json!({
+                    "email": "user@example.com",
+                    TOKEN_BINDING_KEY: { "tenant": "tenant-1", "team": "team-1" }
+                })),
+                ..Default::default()
             }),
             consent_url: "https://consent".into(),
+            ..Default::default()
         };
         let input = OAuthCardInput {
-            mode: OAuthCardMode::StatusCard,
-            provider_id: "msgraph".into(),
-            subject: "user-1".into(),
             tenant: Some("tenant-1".into()),
             team: Some("team-1".into()),
-            scopes: vec!["scope-a".into()],
-            state_id: None,
-            auth_code: None,
-            allow_auto_sign_in: false,
-            redirect_path: None,
-            extra_json: None,
+            scopes: vec![scope("scope-a")],
+            ..test_input(OAuthCardMode::StatusCard)
         };
 
         let output =
@@ -212,19 +818,11 @@ mod tests {
         let backend = MockBroker {
             token: None,
             consent_url: "https://consent/start".into(),
+            ..Default::default()
         };
         let input = OAuthCardInput {
-            mode: OAuthCardMode::EnsureToken,
-            provider_id: "msgraph".into(),
-            subject: "user-1".into(),
-            tenant: None,
-            team: None,
-            scopes: vec![],
-            state_id: None,
-            auth_code: None,
             allow_auto_sign_in: true,
-            redirect_path: None,
-            extra_json: None,
+            ..test_input(OAuthCardMode::EnsureToken)
         };
 
         let output =
@@ -238,19 +836,12 @@ mod tests {
         let backend = MockBroker {
             token: None,
             consent_url: "https://consent/start".into(),
+            ..Default::default()
         };
         let input = OAuthCardInput {
-            mode: OAuthCardMode::StartSignIn,
-            provider_id: "msgraph".into(),
-            subject: "user-1".into(),
-            tenant: None,
-            team: None,
-            scopes: vec!["openid".into()],
-            state_id: None,
-            auth_code: None,
-            allow_auto_sign_in: false,
+            scopes: vec![scope("openid")],
             redirect_path: Some("/oauth/callback/msgraph".into()),
-            extra_json: None,
+            ..test_input(OAuthCardMode::StartSignIn)
         };
 
         let output =
@@ -284,21 +875,18 @@ mod tests {
                 expires_at: Some(999),
                 token_type: Some("Bearer".into()),
                 extra: Some(serde_json::json!({ "email": "user@example.com" })),
+                ..Default::default()
             }),
             consent_url: "https://consent/start".into(),
+            ..Default::default()
         };
         let input = OAuthCardInput {
-            mode: OAuthCardMode::CompleteSignIn,
-            provider_id: "msgraph".into(),
-            subject: "user-1".into(),
             tenant: Some("t".into()),
             team: Some("team-1".into()),
-            scopes: vec!["openid".into()],
+            scopes: vec![scope("openid")],
             state_id: Some("state-1".into()),
             auth_code: Some("code-123".into()),
-            allow_auto_sign_in: false,
-            redirect_path: None,
-            extra_json: None,
+            ..test_input(OAuthCardMode::CompleteSignIn)
         };
 
         let output =
@@ -325,24 +913,16 @@ mod tests {
         let backend = MockBroker {
             token: None,
             consent_url: "https://consent/start".into(),
+            ..Default::default()
         };
         let input = OAuthCardInput {
-            mode: OAuthCardMode::Disconnect,
-            provider_id: "msgraph".into(),
-            subject: "user-1".into(),
-            tenant: None,
-            team: None,
-            scopes: vec!["openid".into()],
-            state_id: None,
-            auth_code: None,
-            allow_auto_sign_in: false,
-            redirect_path: None,
-            extra_json: None,
+            scopes: vec![scope("openid")],
+            ..test_input(OAuthCardMode::Disconnect)
         };
 
         let output =
             logic::handle(&backend, input).unwrap_or_else(|err| panic!("disconnect card: {err}"));
-        assert_eq!(output.status, OAuthStatus::Ok);
+        assert_eq!(output.status, OAuthStatus::Revoked);
         let card = if let Some(card) = output.card {
             card
         } else {
@@ -360,4 +940,3997 @@ mod tests {
                 if data.get("mode").and_then(|v| v.as_str()) == Some("start-sign-in")
         )));
     }
+
+    #[test]
+    fn device_code_start_then_poll() {
+        let mut backend = MockBroker {
+            token: None,
+            device_grant: Some(crate::model::DeviceCodeGrant {
+                device_code: "dev-1".into(),
+                user_code: "ABCD-EFGH".into(),
+                verification_uri: "https://login/device".into(),
+                verification_uri_complete: None,
+                expires_in: Some(900),
+                interval: Some(5),
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![scope("openid")],
+            ..test_input(OAuthCardMode::DeviceCode)
+        };
+
+        let output = logic::handle(&backend, input.clone())
+            .unwrap_or_else(|err| panic!("device code start: {err}"));
+        assert_eq!(output.status, OAuthStatus::Pending);
+        let state_id = output
+            .state_id
+            .unwrap_or_else(|| panic!("device flow state_id"));
+        assert_ne!(state_id, "dev-1");
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(
+            !serde_json::to_string(&card)
+                .unwrap_or_default()
+                .contains("dev-1")
+        );
+        assert!(card.text.unwrap_or_default().contains("ABCD-EFGH"));
+
+        let poll = OAuthCardInput {
+            state_id: Some(state_id.clone()),
+            ..input.clone()
+        };
+        let output = logic::handle(&backend, poll.clone())
+            .unwrap_or_else(|err| panic!("device code poll: {err}"));
+        assert_eq!(output.status, OAuthStatus::Pending);
+        assert_eq!(output.state_id, Some(state_id));
+        assert_eq!(
+            backend.calls_to("poll_device_code")[0]
+                .device_code
+                .as_deref(),
+            Some("dev-1")
+        );
+
+        let guessed = OAuthCardInput {
+            state_id: Some("dev-1".into()),
+            ..input
+        };
+        let output = logic::handle(&backend, guessed)
+            .unwrap_or_else(|err| panic!("device code guess: {err}"));
+        assert_eq!(output.status, OAuthStatus::InvalidState);
+        assert_eq!(backend.calls_to("poll_device_code").len(), 1);
+
+        backend.token = Some(TokenSet {
+            access_token: "token123".into(),
+            refresh_token: None,
+            expires_at: None,
+            token_type: None,
+            extra: None,
+            ..Default::default()
+        });
+        let output =
+            logic::handle(&backend, poll).unwrap_or_else(|err| panic!("device code done: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.auth_header.is_some());
+    }
+
+    #[test]
+    fn disconnect_reports_revocation_failure() {
+        let backend = MockBroker {
+            revoke_error: Some("provider unreachable".into()),
+            ..Default::default()
+        };
+        let input = test_input(OAuthCardMode::Disconnect);
+
+        let output =
+            logic::handle(&backend, input).unwrap_or_else(|err| panic!("disconnect: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert!(
+            output
+                .error
+                .unwrap_or_default()
+                .contains("provider unreachable")
+        );
+        let card = output.card.unwrap_or_else(|| panic!("error card present"));
+        assert!(card.actions.iter().any(|a| matches!(
+            a,
+            crate::model::Action::PostBack { data, .. }
+                if data.get("mode").and_then(|v| v.as_str()) == Some("disconnect")
+        )));
+    }
+
+    #[test]
+    fn client_credentials_uses_client_identity() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "app-token".into(),
+                refresh_token: None,
+                expires_at: Some(999),
+                token_type: Some("Bearer".into()),
+                extra: Some(serde_json::json!({ "client_id": "app-123" })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            subject: "service".into(),
+            tenant: Some("tenant-1".into()),
+            scopes: vec![scope("https://graph.microsoft.com/.default")],
+            ..test_input(OAuthCardMode::ClientCredentials)
+        };
+
+        let output = logic::handle(&backend, input)
+            .unwrap_or_else(|err| panic!("client credentials: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.card.is_none());
+        assert_eq!(
+            output.auth_context.map(|ctx| ctx.subject).as_deref(),
+            Some("app-123")
+        );
+        assert!(output.auth_header.is_some());
+    }
+
+    #[test]
+    fn scopes_are_trimmed_deduplicated_and_validated() {
+        assert_eq!(scope(" OpenID "), "openid");
+        assert_eq!(scope("User.Read"), "User.Read");
+        assert!(Scope::parse("").is_err());
+        assert!(Scope::parse("bad\\scope").is_err());
+        assert_eq!(
+            Scope::parse_list(["openid User.Read", "user.read", "OPENID"])
+                .unwrap_or_else(|err| panic!("list: {err}")),
+            vec![scope("openid"), scope("User.Read"), scope("user.read")]
+        );
+
+        let input = parse_input(
+            r#"{"mode":"start-sign-in","provider_id":"msgraph","subject":"u",
+                "scopes":[" openid","OpenID","Mail.Read Mail.Read"]}"#,
+        )
+        .unwrap_or_else(|err| panic!("parse: {err}"));
+        assert_eq!(input.scopes, vec![scope("openid"), scope("Mail.Read")]);
+        let rejected = parse_input(
+            r#"{"mode":"start-sign-in","provider_id":"msgraph","subject":"u","scopes":["a\"b"]}"#,
+        );
+        assert!(rejected.is_err());
+
+        let backend = MockBroker::default();
+        logic::handle(
+            &backend,
+            OAuthCardInput {
+                scopes: vec![scope("Mail.Read"), scope("Mail.Read")],
+                ..test_input(OAuthCardMode::StartSignIn)
+            },
+        )
+        .unwrap_or_else(|err| panic!("start: {err}"));
+        let consent = backend.calls_to("get_consent_url");
+        assert_eq!(consent[0].scopes, vec![scope("Mail.Read")]);
+    }
+
+    #[test]
+    fn input_payloads_round_trip_through_every_version() {
+        let input = OAuthCardInput {
+            scopes: vec![scope("read")],
+            state_id: Some("state-1".into()),
+            auth_code: Some("code-1".into()),
+            extra_json: Some(serde_json::json!({ "prompt": "consent" })),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        for version in 1..=CURRENT_INPUT_VERSION {
+            let payload =
+                encode_input(&input, version).unwrap_or_else(|err| panic!("encode: {err}"));
+            let parsed = parse_input(&payload).unwrap_or_else(|err| panic!("parse: {err}"));
+            assert_eq!(parsed.version, version);
+            let mut expected = serde_json::to_value(&input).unwrap_or_default();
+            expected["version"] = serde_json::json!(version);
+            assert_eq!(serde_json::to_value(&parsed).unwrap_or_default(), expected);
+        }
+
+        let unversioned =
+            parse_input(r#"{"mode":"status-card","provider_id":"demo","subject":"u"}"#)
+                .unwrap_or_else(|err| panic!("parse: {err}"));
+        assert_eq!(unversioned.version, 1);
+        let envelope = serde_json::json!({
+            "version": 2,
+            "input": { "mode": "status-card", "provider_id": "demo", "subject": "u" }
+        });
+        let response: serde_json::Value = serde_json::from_str(&handle_message_with_backend(
+            &MockBroker::default(),
+            "invoke",
+            &envelope.to_string(),
+        ))
+        .unwrap_or_default();
+        assert_eq!(response["status"], "needs-sign-in");
+
+        assert!(parse_input(r#"{"version":3,"mode":"status-card"}"#).is_err());
+        assert!(
+            parse_input(r#"{"version":2,"mode":"status-card","provider_id":"demo","subject":"u"}"#)
+                .is_err()
+        );
+        assert!(encode_input(&input, 0).is_err());
+    }
+
+    #[test]
+    fn camel_case_inputs_parse_and_strict_mode_rejects_unknown_fields() {
+        let camel = parse_input(
+            r#"{"mode":"complete-sign-in","providerId":"demo","subject":"u","stateId":"s-1",
+                "authCode":"c-1","allowAutoSignIn":true,"refreshMarginSecs":60,
+                "providers":[{"providerId":"github"}]}"#,
+        )
+        .unwrap_or_else(|err| panic!("camel: {err}"));
+        assert_eq!(camel.provider_id, "demo");
+        assert_eq!(camel.state_id.as_deref(), Some("s-1"));
+        assert_eq!(camel.auth_code.as_deref(), Some("c-1"));
+        assert!(camel.allow_auto_sign_in);
+        assert_eq!(camel.refresh_margin_secs, Some(60));
+        assert_eq!(camel.providers[0].provider_id, "github");
+
+        let lenient = r#"{"mode":"status-card","provider_id":"demo","subject":"u","colour":"red"}"#;
+        assert!(parse_input(lenient).is_ok());
+        let strict = r#"{"mode":"status-card","providerId":"demo","subject":"u","strict":true,
+            "colour":"red"}"#;
+        match parse_input(strict) {
+            Err(OAuthCardError::Parse(message)) => assert!(message.contains("`colour`")),
+            other => panic!("expected unknown field error, got {other:?}"),
+        }
+        let envelope = serde_json::json!({
+            "version": 2,
+            "input": {
+                "mode": "status-card", "providerId": "demo", "subject": "u",
+                "correlationId": "t-1", "strict": true
+            }
+        });
+        let parsed =
+            parse_input(&envelope.to_string()).unwrap_or_else(|err| panic!("strict: {err}"));
+        assert_eq!(parsed.correlation_id.as_deref(), Some("t-1"));
+    }
+
+    #[test]
+    fn id_token_claims_populate_auth_context() {
+        use base64::Engine;
+        let claims = serde_json::json!({
+            "sub": "abc-123",
+            "name": "Ada Lovelace",
+            "preferred_username": "ada@example.com",
+            "email": "ada@example.com",
+            "tid": "tenant-guid"
+        });
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string());
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                id_token: Some(format!("eyJhbGciOiJub25lIn0.{payload}.sig")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![scope("openid")],
+            ..test_input(OAuthCardMode::EnsureToken)
+        };
+
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("ensure: {err}"));
+        let ctx = output
+            .auth_context
+            .unwrap_or_else(|| panic!("auth context present"));
+        assert_eq!(ctx.sub.as_deref(), Some("abc-123"));
+        assert_eq!(ctx.name.as_deref(), Some("Ada Lovelace"));
+        assert_eq!(ctx.preferred_username.as_deref(), Some("ada@example.com"));
+        assert_eq!(ctx.email.as_deref(), Some("ada@example.com"));
+        assert_eq!(
+            ctx.claims
+                .and_then(|c| c.get("tid").cloned())
+                .and_then(|v| v.as_str().map(str::to_string))
+                .as_deref(),
+            Some("tenant-guid")
+        );
+    }
+
+    #[test]
+    fn auth_context_merges_extra_id_token_and_userinfo_claims() {
+        use base64::Engine;
+        let claims = serde_json::json!({
+            "sub": "abc-123",
+            "name": "Ada",
+            "picture": "https://id.example/ada.png",
+            "region": "from-id-token"
+        });
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string());
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                id_token: Some(format!("eyJhbGciOiJub25lIn0.{payload}.sig")),
+                extra: Some(serde_json::json!({ "locale": "en-GB", "region": "from-extra" })),
+                ..Default::default()
+            }),
+            userinfo: Some(UserProfile {
+                name: Some("Ada Lovelace".into()),
+                email: Some("ada@example.com".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let output = logic::handle(&backend, test_input(OAuthCardMode::StatusCard))
+            .unwrap_or_else(|err| panic!("status: {err}"));
+        let ctx = output
+            .auth_context
+            .unwrap_or_else(|| panic!("auth context present"));
+        assert_eq!(ctx.name.as_deref(), Some("Ada Lovelace"));
+        assert_eq!(ctx.email.as_deref(), Some("ada@example.com"));
+        assert_eq!(ctx.picture.as_deref(), Some("https://id.example/ada.png"));
+        assert_eq!(ctx.locale.as_deref(), Some("en-GB"));
+        let claims = ctx.claims.unwrap_or_else(|| panic!("claims present"));
+        assert_eq!(claims["region"], "from-id-token");
+        assert_eq!(claims["name"], "Ada Lovelace");
+    }
+
+    #[test]
+    fn ensure_token_refreshes_expiring_token() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "stale".into(),
+                expires_at: Some(1),
+                ..Default::default()
+            }),
+            refreshed_token: Some(TokenSet {
+                access_token: "fresh".into(),
+                expires_at: Some(u64::MAX),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = test_input(OAuthCardMode::EnsureToken);
+
+        let output = logic::handle(&backend, input.clone())
+            .unwrap_or_else(|err| panic!("ensure refresh: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        let header = output
+            .auth_header
+            .unwrap_or_else(|| panic!("auth header present"));
+        assert_eq!(header.headers[0].1, "Bearer fresh");
+
+        let backend = MockBroker {
+            refreshed_token: None,
+            ..backend
+        };
+        let output =
+            logic::handle(&backend, input).unwrap_or_else(|err| panic!("ensure expired: {err}"));
+        assert_eq!(output.status, OAuthStatus::Expired);
+        assert!(output.auth_header.is_none());
+    }
+
+    #[test]
+    fn complete_sign_in_with_sso_token() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "sso-token".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            sso_token: Some("teams-exchangeable".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        let output =
+            logic::handle(&backend, input.clone()).unwrap_or_else(|err| panic!("sso: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.auth_header.is_some());
+
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let output =
+            logic::handle(&backend, input).unwrap_or_else(|err| panic!("sso fallback: {err}"));
+        assert_eq!(output.status, OAuthStatus::NeedsSignIn);
+        assert!(output.state_id.is_some());
+        let card = output
+            .card
+            .unwrap_or_else(|| panic!("sign-in card present"));
+        assert!(
+            card.actions
+                .iter()
+                .any(|a| matches!(a, crate::model::Action::OpenUrl { .. }))
+        );
+    }
+
+    #[test]
+    fn batch_ensure_lists_missing_providers() {
+        struct PartialBroker;
+        impl OAuthBackend for PartialBroker {
+            fn get_token(
+                &self,
+                _ctx: &CallContext,
+                provider_id: &str,
+                _subject: &str,
+                _scopes: &[Scope],
+            ) -> Result<Option<TokenSet>, OAuthCardError> {
+                Ok((provider_id == "msgraph").then(|| TokenSet {
+                    access_token: "graph-token".into(),
+                    ..Default::default()
+                }))
+            }
+
+            fn get_consent_url(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _scopes: &[Scope],
+                _redirect_path: &str,
+                _extra_json: Option<String>,
+            ) -> Result<String, OAuthCardError> {
+                Ok(String::new())
+            }
+
+            fn exchange_code(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _code: &str,
+                _redirect_path: &str,
+                _idempotency_key: Option<&str>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                Err(OAuthCardError::Unsupported("not used".into()))
+            }
+        }
+
+        let input = OAuthCardInput {
+            providers: vec![
+                ProviderRequest {
+                    provider_id: "msgraph".into(),
+                    scopes: vec![],
+                },
+                ProviderRequest {
+                    provider_id: "github".into(),
+                    scopes: vec![scope("repo")],
+                },
+            ],
+            ..test_input(OAuthCardMode::BatchEnsure)
+        };
+        let output =
+            logic::handle(&PartialBroker, input).unwrap_or_else(|err| panic!("batch: {err}"));
+        assert_eq!(output.status, OAuthStatus::NeedsSignIn);
+        assert_eq!(output.provider_results.len(), 2);
+        assert_eq!(output.provider_results[0].status, OAuthStatus::Ok);
+        assert!(output.provider_results[0].auth_header.is_some());
+        assert_eq!(output.provider_results[1].status, OAuthStatus::NeedsSignIn);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.actions.len(), 1);
+        assert!(card.text.unwrap_or_default().contains("github"));
+    }
+
+    #[test]
+    fn list_connections_renders_actions_per_provider() {
+        let backend = MockBroker {
+            connections: vec![
+                ConnectionSummary {
+                    provider_id: "msgraph".into(),
+                    scopes: vec!["openid".into()],
+                    expires_at: Some(999),
+                    label: Some("ada@example.com".into()),
+                },
+                ConnectionSummary {
+                    provider_id: "github".into(),
+                    scopes: vec!["repo".into()],
+                    expires_at: None,
+                    label: None,
+                },
+            ],
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::ListConnections))
+            .unwrap_or_else(|err| panic!("list connections: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert_eq!(output.connections.len(), 2);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(
+            card.text
+                .as_deref()
+                .is_some_and(|text| text.contains("- msgraph (ada@example.com)"))
+        );
+        assert_eq!(card.actions.len(), 4);
+        assert!(card.actions.iter().any(|a| matches!(
+            a,
+            crate::model::Action::PostBack { data, .. }
+                if data.get("mode").and_then(|v| v.as_str()) == Some("disconnect")
+                    && data.get("provider_id").and_then(|v| v.as_str()) == Some("github")
+        )));
+    }
+
+    #[test]
+    fn switch_account_picker_and_selection() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "work-token".into(),
+                ..Default::default()
+            }),
+            accounts: vec![
+                AccountSummary {
+                    account_id: "acc-work".into(),
+                    label: Some("Work".into()),
+                    email: Some("ada@work.example".into()),
+                    active: true,
+                },
+                AccountSummary {
+                    account_id: "acc-home".into(),
+                    label: None,
+                    email: Some("ada@home.example".into()),
+                    active: false,
+                },
+            ],
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::SwitchAccount))
+            .unwrap_or_else(|err| panic!("picker: {err}"));
+        assert_eq!(output.accounts.len(), 2);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(card.actions.iter().any(|a| matches!(
+            a,
+            crate::model::Action::PostBack { title, data }
+                if title == "ada@home.example"
+                    && data.get("account_id").and_then(|v| v.as_str()) == Some("acc-home")
+        )));
+
+        let input = OAuthCardInput {
+            account_id: Some("acc-home".into()),
+            ..test_input(OAuthCardMode::SwitchAccount)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("select: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.auth_header.is_some());
+    }
+
+    #[test]
+    fn ensure_token_requests_missing_scopes_only() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                granted_scopes: vec!["openid".into(), "User.Read".into()],
+                ..Default::default()
+            }),
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![scope("openid"), scope("Calendars.Read")],
+            ..test_input(OAuthCardMode::EnsureToken)
+        };
+
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("consent: {err}"));
+        assert_eq!(output.status, OAuthStatus::NeedsConsent);
+        assert!(output.auth_header.is_none());
+        assert!(output.state_id.is_some());
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        let oauth = card.oauth.unwrap_or_else(|| panic!("oauth payload"));
+        assert_eq!(oauth.scopes, vec!["Calendars.Read".to_string()]);
+        assert!(!card.text.unwrap_or_default().contains("openid"));
+    }
+
+    #[test]
+    fn token_scope_string_counts_as_granted_scopes() {
+        let legacy: TokenSet = serde_json::from_str(r#"{"access_token":"abc"}"#)
+            .unwrap_or_else(|err| panic!("legacy token: {err}"));
+        assert!(legacy.scopes().is_empty());
+        assert!(legacy.id_token.is_none());
+
+        let token: TokenSet =
+            serde_json::from_str(r#"{"access_token":"abc","scope":"openid  User.Read"}"#)
+                .unwrap_or_else(|err| panic!("token: {err}"));
+        assert_eq!(token.scopes(), ["openid", "User.Read"]);
+
+        let backend = MockBroker {
+            token: Some(token),
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![scope("User.Read"), scope("Mail.Read")],
+            ..test_input(OAuthCardMode::EnsureToken)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(output.status, OAuthStatus::NeedsConsent);
+        let oauth = output
+            .card
+            .and_then(|card| card.oauth)
+            .unwrap_or_else(|| panic!("oauth payload"));
+        assert_eq!(oauth.scopes, ["Mail.Read"]);
+    }
+
+    #[test]
+    fn status_card_flags_uncovered_scopes() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                granted_scopes: vec!["openid".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![scope("openid"), scope("Mail.Send")],
+            ..test_input(OAuthCardMode::StatusCard)
+        };
+
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("status: {err}"));
+        assert_eq!(output.status, OAuthStatus::NeedsSignIn);
+        assert!(output.auth_header.is_none());
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(card.text.unwrap_or_default().contains("Mail.Send"));
+    }
+
+    #[test]
+    fn logout_links_end_session_and_revokes() {
+        let backend = MockBroker {
+            end_session_url: Some("https://login/logout?id_token_hint=x".into()),
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::Logout))
+            .unwrap_or_else(|err| panic!("logout: {err}"));
+        assert_eq!(output.status, OAuthStatus::Revoked);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(matches!(
+            card.actions.first(),
+            Some(crate::model::Action::OpenUrl { url, .. }) if url.starts_with("https://login/logout")
+        ));
+
+        let backend = MockBroker {
+            revoke_error: Some("revocation endpoint down".into()),
+            ..backend
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::Logout))
+            .unwrap_or_else(|err| panic!("logout failure: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+    }
+
+    #[test]
+    fn back_channel_logout_needs_a_verification_config() {
+        use base64::Engine;
+        let encode = |claims: serde_json::Value| {
+            let payload =
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string());
+            format!("eyJhbGciOiJub25lIn0.{payload}.sig")
+        };
+        let backend = MockBroker {
+            invalidated_subjects: vec!["user-1".into()],
+            ..Default::default()
+        };
+        let valid = BackChannelLogoutInput {
+            provider_id: "msgraph".into(),
+            logout_token: encode(serde_json::json!({
+                "iss": "https://login.example",
+                "sub": "abc-123",
+                "iat": clock::now_secs(),
+                "events": { "http://schemas.openid.net/event/backchannel-logout": {} }
+            })),
+        };
+        let json: serde_json::Value = serde_json::from_str(&handle_back_channel_logout(
+            &backend,
+            &CallContext::default(),
+            &serde_json::to_string(&valid).unwrap_or_default(),
+        ))
+        .unwrap_or_else(|err| panic!("valid json: {err}"));
+        assert_eq!(json["status"], "error");
+        assert!(
+            json["error"]
+                .as_str()
+                .is_some_and(|error| error.contains("id_token_verification")),
+            "{json}"
+        );
+        assert!(backend.calls_to("invalidate_sessions").is_empty());
+    }
+
+    #[test]
+    fn poll_sign_in_reports_progress() {
+        let input = OAuthCardInput {
+            state_id: Some("state-1".into()),
+            ..test_input(OAuthCardMode::PollSignIn)
+        };
+        let pending = MockBroker::default();
+        let output =
+            logic::handle(&pending, input.clone()).unwrap_or_else(|err| panic!("pending: {err}"));
+        assert_eq!(output.status, OAuthStatus::Pending);
+        assert_eq!(output.state_id.as_deref(), Some("state-1"));
+
+        let completed = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let output =
+            logic::handle(&completed, input.clone()).unwrap_or_else(|err| panic!("done: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.auth_header.is_some());
+
+        let denied = MockBroker {
+            sign_in_progress: Some(SignInProgress::Denied {
+                reason: Some("access_denied".into()),
+            }),
+            ..Default::default()
+        };
+        let output = logic::handle(&denied, input).unwrap_or_else(|err| panic!("denied: {err}"));
+        assert_eq!(output.status, OAuthStatus::Denied);
+        assert!(output.card.is_some());
+    }
+
+    #[test]
+    fn cancel_sign_in_offers_restart() {
+        let input = OAuthCardInput {
+            state_id: Some("state-1".into()),
+            ..test_input(OAuthCardMode::CancelSignIn)
+        };
+        let output = logic::handle(&MockBroker::default(), input)
+            .unwrap_or_else(|err| panic!("cancel: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.state_id.is_none());
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.title.as_deref(), Some("Sign-in cancelled"));
+        assert!(card.actions.iter().any(|a| matches!(
+            a,
+            crate::model::Action::PostBack { data, .. }
+                if data.get("mode").and_then(|v| v.as_str()) == Some("start-sign-in")
+        )));
+
+        let missing_state = test_input(OAuthCardMode::CancelSignIn);
+        let output = logic::handle(&MockBroker::default(), missing_state)
+            .unwrap_or_else(|err| panic!("error card: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert!(output.error.is_some());
+    }
+
+    #[test]
+    fn sign_in_card_carries_adaptive_card() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::StartSignIn))
+            .unwrap_or_else(|err| panic!("start sign-in: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        let adaptive = card.adaptive.unwrap_or_else(|| panic!("adaptive present"));
+        assert_eq!(adaptive["type"], "AdaptiveCard");
+        assert_eq!(adaptive["version"], "1.5");
+        assert_eq!(adaptive["body"][0]["text"], "Connect msgraph account");
+        assert_eq!(adaptive["actions"][0]["type"], "Action.OpenUrl");
+        assert_eq!(adaptive["actions"][0]["url"], "https://consent/start");
+        assert_eq!(adaptive["actions"][1]["type"], "Action.Submit");
+        assert_eq!(adaptive["actions"][1]["data"]["mode"], "complete-sign-in");
+    }
+
+    #[test]
+    fn slack_rendering_for_sign_in_card() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            channel: Some("slack".into()),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("slack: {err}"));
+        let slack = output
+            .rendered
+            .get("slack")
+            .unwrap_or_else(|| panic!("slack rendering present"));
+        let blocks = slack["blocks"]
+            .as_array()
+            .unwrap_or_else(|| panic!("blocks array"));
+        assert_eq!(blocks[0]["type"], "header");
+        let buttons = blocks
+            .iter()
+            .find(|b| b["type"] == "actions")
+            .unwrap_or_else(|| panic!("actions block"));
+        assert_eq!(buttons["elements"][0]["url"], "https://consent/start");
+        assert_eq!(
+            buttons["elements"][1]["action_id"],
+            "oauth_complete-sign-in_1"
+        );
+    }
+
+    #[test]
+    fn discord_rendering_uses_link_and_custom_id_buttons() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            channel: Some("discord".into()),
+            state_id: Some("state-1".into()),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("discord: {err}"));
+        let discord = output
+            .rendered
+            .get("discord")
+            .unwrap_or_else(|| panic!("discord rendering present"));
+        assert_eq!(discord["embeds"][0]["title"], "Connect msgraph account");
+        let buttons = &discord["components"][0]["components"];
+        assert_eq!(buttons[0]["style"], 5);
+        assert_eq!(buttons[0]["url"], "https://consent/start");
+        assert_eq!(
+            buttons[1]["custom_id"],
+            "oauth:complete-sign-in:msgraph:state-1"
+        );
+    }
+
+    #[test]
+    fn telegram_rendering_builds_inline_keyboard() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            channel: Some("telegram".into()),
+            ..test_input(OAuthCardMode::StatusCard)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("telegram: {err}"));
+        let telegram = output
+            .rendered
+            .get("telegram")
+            .unwrap_or_else(|| panic!("telegram rendering present"));
+        assert!(
+            telegram["text"]
+                .as_str()
+                .unwrap_or_default()
+                .starts_with("*Connected: msgraph*")
+        );
+        let keyboard = telegram["reply_markup"]["inline_keyboard"]
+            .as_array()
+            .unwrap_or_else(|| panic!("keyboard rows"));
+        assert_eq!(keyboard.len(), 3);
+        assert_eq!(keyboard[2][0]["callback_data"], "oauth:disconnect:msgraph:");
+        assert!(keyboard.iter().all(|row| {
+            row[0]["callback_data"]
+                .as_str()
+                .is_none_or(|data| data.len() <= 64)
+        }));
+    }
+
+    #[test]
+    fn telegram_rendering_escapes_markdown_v2() {
+        let card = MessageCard::builder()
+            .title("Connect my-app (v2.0)")
+            .text("Enter **AB-12** at example.com_login!")
+            .footer("Acme [beta]")
+            .build();
+        let telegram = render::telegram::render(&CallContext::default(), &card);
+        assert_eq!(telegram["parse_mode"], "MarkdownV2");
+        assert_eq!(
+            telegram["text"],
+            "*Connect my\\-app \\(v2\\.0\\)*\n\n\
+             Enter *AB\\-12* at example\\.com\\_login\\!\n\n\
+             _Acme \\[beta\\]_"
+        );
+
+        let unpaired = MessageCard::builder().text("2 ** 3").build();
+        assert_eq!(
+            render::telegram::render(&CallContext::default(), &unpaired)["text"],
+            "2 \\*\\* 3"
+        );
+    }
+
+    #[test]
+    fn whatsapp_rendering_splits_url_and_reply_buttons() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            channel: Some("whatsapp".into()),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("whatsapp: {err}"));
+        let whatsapp = output
+            .rendered
+            .get("whatsapp")
+            .unwrap_or_else(|| panic!("whatsapp rendering present"));
+        let messages = whatsapp["messages"]
+            .as_array()
+            .unwrap_or_else(|| panic!("messages array"));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["interactive"]["type"], "cta_url");
+        assert_eq!(
+            messages[0]["interactive"]["action"]["parameters"]["url"],
+            "https://consent/start"
+        );
+        assert_eq!(messages[1]["interactive"]["type"], "button");
+        assert_eq!(
+            messages[1]["interactive"]["action"]["buttons"][0]["reply"]["title"],
+            "Continue"
+        );
+    }
+
+    #[test]
+    fn html_rendering_escapes_and_uses_forms() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start?a=1&b=2".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            channel: Some("html".into()),
+            subject: "<script>alert(1)</script>".into(),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("html: {err}"));
+        let html = output
+            .rendered
+            .get("html")
+            .and_then(|v| v.as_str())
+            .unwrap_or_else(|| panic!("html rendering present"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains(r#"href="https://consent/start?a=1&amp;b=2""#));
+        assert!(html.contains(r#"<form class="oauth-card__form" method="post">"#));
+        assert!(!html.contains("onclick"));
+    }
+
+    #[test]
+    fn native_action_variants_render_per_channel() {
+        let nested = MessageCard::builder()
+            .title("More options")
+            .action(Action::Submit {
+                title: "Use work account".into(),
+                data: serde_json::json!({ "mode": "start-sign-in", "provider_id": "msgraph" }),
+            })
+            .build();
+        let card = MessageCard::builder()
+            .title("Connect")
+            .action(Action::SignIn {
+                title: "Sign in".into(),
+                url: "https://consent/start".into(),
+                connection_name: Some("graph".into()),
+            })
+            .action(Action::ShowCard {
+                title: "Other accounts".into(),
+                card: Box::new(nested),
+            })
+            .build();
+
+        let adaptive = render::adaptive::render(&card, None);
+        assert_eq!(adaptive["actions"][0]["type"], "Action.OpenUrl");
+        assert_eq!(adaptive["actions"][1]["type"], "Action.ShowCard");
+        assert_eq!(
+            adaptive["actions"][1]["card"]["actions"][0]["type"],
+            "Action.Submit"
+        );
+
+        let slack = render::slack::render(&card);
+        let elements = &slack["blocks"][1]["elements"];
+        assert_eq!(elements[0]["url"], "https://consent/start");
+        assert_eq!(elements[1]["text"]["text"], "Use work account");
+
+        let html = render::html::render(&card);
+        let html = html.as_str().unwrap_or_default();
+        assert!(html.contains(r#"<summary class="oauth-card__button">Other accounts</summary>"#));
+
+        let plain = render::text::render(&card);
+        assert!(plain.contains("Reply \"Use work account\""));
+
+        let json = serde_json::to_value(&card.actions[0]).unwrap_or_default();
+        assert_eq!(json["type"], "sign_in");
+        assert_eq!(json["connection_name"], "graph");
+    }
+
+    #[test]
+    fn text_fallback_inlines_consent_url() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::StartSignIn))
+            .unwrap_or_else(|err| panic!("fallback: {err}"));
+        let text = output
+            .text_fallback
+            .unwrap_or_else(|| panic!("text fallback present"));
+        assert!(text.starts_with("**Connect msgraph account**"));
+        assert!(text.contains("[Connect](https://consent/start)"));
+
+        let mut card = output.card.unwrap_or_else(|| panic!("card present"));
+        card.allow_markdown = false;
+        let plain = render::text::render(&card);
+        assert!(plain.contains("Connect: https://consent/start"));
+        assert!(!plain.contains("**"));
+    }
+
+    #[cfg(feature = "qr")]
+    #[test]
+    fn sign_in_card_embeds_qr_code() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            include_qr: true,
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("qr: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(
+            card.images
+                .iter()
+                .any(|image| image.url.starts_with("data:image/png;base64,"))
+        );
+    }
+
+    #[test]
+    fn card_templates_from_extra_json_override_copy() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                expires_at: Some(clock::now_secs() + 12 * 60 + 30),
+                extra: Some(serde_json::json!({ TOKEN_BINDING_KEY: { "team": "HR" } })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            team: Some("HR".into()),
+            extra_json: Some(serde_json::json!({
+                "card_templates": {
+                    "status-card": {
+                        "title": "{{provider}} is linked for {{ subject }}",
+                        "footer": "Team {{team}}, expires in {{expires_in}}{{unknown}}"
+                    }
+                }
+            })),
+            ..test_input(OAuthCardMode::StatusCard)
+        };
+        let output =
+            logic::handle(&backend, input).unwrap_or_else(|err| panic!("templates: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.title.as_deref(), Some("msgraph is linked for user-1"));
+        assert_eq!(
+            card.footer.as_deref(),
+            Some("Team HR, expires in 12 minutes")
+        );
+        assert!(card.text.is_some());
+    }
+
+    #[test]
+    fn branding_applies_logo_footer_and_accent() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let branding = Branding {
+            logo_url: Some("https://cdn.example/logo.png".into()),
+            accent_color: Some("#0A66C2".into()),
+            product_name: Some("Acme Assistant".into()),
+            footer_text: None,
+        };
+        let input = OAuthCardInput {
+            extra_json: Some(serde_json::json!({ "branding": branding })),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("branding: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.images[0].url, "https://cdn.example/logo.png");
+        assert_eq!(card.footer.as_deref(), Some("Secured by Acme Assistant"));
+        let adaptive = card.adaptive.unwrap_or_else(|| panic!("adaptive present"));
+        assert_eq!(adaptive["body"][0]["type"], "Container");
+        assert_eq!(adaptive["body"][0]["style"], "accent");
+    }
+
+    #[test]
+    fn card_overrides_replace_copy_and_labels() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            provider_id: "workday".into(),
+            card_overrides: Some(CardOverrides {
+                title: Some("Connect your HR system".into()),
+                body: None,
+                buttons: [
+                    ("open_url".to_string(), "Open HR portal".to_string()),
+                    ("complete-sign-in".to_string(), "I'm signed in".to_string()),
+                ]
+                .into_iter()
+                .collect(),
+            }),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output =
+            logic::handle(&backend, input).unwrap_or_else(|err| panic!("overrides: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.title.as_deref(), Some("Connect your HR system"));
+        assert!(card.text.unwrap_or_default().contains("Click Connect"));
+        let titles: Vec<&str> = card.actions.iter().map(|a| a.title()).collect();
+        assert_eq!(titles, vec!["Open HR portal", "I'm signed in"]);
+    }
+
+    #[test]
+    fn status_card_warns_when_expiring_soon() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                expires_at: Some(clock::now_secs() + 120),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::StatusCard))
+            .unwrap_or_else(|err| panic!("status ok: {err}"));
+        assert_eq!(output.status, OAuthStatus::ExpiringSoon);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.kind, crate::model::MessageCardKind::Warning);
+        assert!(card.text.unwrap_or_default().contains("Expires in"));
+        match &card.actions[0] {
+            crate::model::Action::PostBack { title, data } => {
+                assert_eq!(title, "Refresh now");
+                assert_eq!(data["mode"], "ensure-token");
+            }
+            other => panic!("unexpected primary action: {other:?}"),
+        }
+        assert_eq!(card.actions.len(), 3);
+    }
+
+    #[test]
+    fn connected_card_shows_profile() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "token123".into(),
+                ..Default::default()
+            }),
+            userinfo: Some(UserProfile {
+                name: Some("Ada Lovelace".into()),
+                email: Some("ada@example.com".into()),
+                picture: Some("https://cdn.example/ada.png".into()),
+                locale: None,
+            }),
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::StatusCard))
+            .unwrap_or_else(|err| panic!("status ok: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(
+            card.text
+                .unwrap_or_default()
+                .starts_with("Signed in as Ada Lovelace (ada@example.com)")
+        );
+        let urls: Vec<&str> = card.images.iter().map(|i| i.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/microsoft.svg",
+                "https://cdn.example/ada.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn sign_in_card_lists_scope_descriptions() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![
+                scope("Calendars.Read"),
+                scope("Sites.Manage.All"),
+                scope("custom.scope"),
+            ],
+            extra_json: Some(serde_json::json!({
+                "scope_descriptions": { "custom.scope": "Use the custom API" }
+            })),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("sign-in: {err}"));
+        let text = output.card.and_then(|card| card.text).unwrap_or_default();
+        assert!(text.contains("• Read your calendar"));
+        assert!(text.contains("• Sites.Manage.All"));
+        assert!(text.contains("• Use the custom API"));
+    }
+
+    #[test]
+    fn handler_failure_renders_retry_card() {
+        let output = logic::handle(&MockBroker::default(), test_input(OAuthCardMode::Logout))
+            .unwrap_or_else(|err| panic!("error card: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(card.text.unwrap_or_default().contains("unsupported"));
+        assert!(card.actions.is_empty());
+
+        let input = OAuthCardInput {
+            account_id: Some("missing".into()),
+            state_id: Some("state-9".into()),
+            ..test_input(OAuthCardMode::SwitchAccount)
+        };
+        let output = logic::handle(&MockBroker::default(), input)
+            .unwrap_or_else(|err| panic!("error card: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert!(output.text_fallback.is_some());
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        match &card.actions[..] {
+            [crate::model::Action::PostBack { title, data }] => {
+                assert_eq!(title, "Try again");
+                assert_eq!(data["mode"], "switch-account");
+                assert_eq!(data["state_id"], "state-9");
+            }
+            other => panic!("unexpected actions: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn disconnect_asks_for_confirmation_first() {
+        let backend = MockBroker {
+            revoke_error: Some("must not be called".into()),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            require_confirmation: true,
+            ..test_input(OAuthCardMode::Disconnect)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("confirm: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.kind, crate::model::MessageCardKind::Confirmation);
+        match &card.actions[0] {
+            crate::model::Action::PostBack { title, data } => {
+                assert_eq!(title, "Disconnect");
+                assert_eq!(data["mode"], "disconnect");
+                assert!(data.get("require_confirmation").is_none());
+            }
+            other => panic!("unexpected action: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn choose_provider_offers_each_candidate() {
+        let input = OAuthCardInput {
+            scopes: vec![scope("openid")],
+            providers: vec![
+                ProviderRequest {
+                    provider_id: "msgraph".into(),
+                    scopes: vec![scope("User.Read")],
+                },
+                ProviderRequest {
+                    provider_id: "google".into(),
+                    scopes: vec![],
+                },
+                ProviderRequest {
+                    provider_id: "okta".into(),
+                    scopes: vec![],
+                },
+            ],
+            ..test_input(OAuthCardMode::ChooseProvider)
+        };
+        let output = logic::handle(&MockBroker::default(), input)
+            .unwrap_or_else(|err| panic!("chooser: {err}"));
+        assert_eq!(output.status, OAuthStatus::NeedsSignIn);
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        let buttons: Vec<(String, serde_json::Value)> = card
+            .actions
+            .iter()
+            .filter_map(|a| match a {
+                crate::model::Action::PostBack { title, data } => {
+                    Some((title.clone(), data.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(buttons.len(), 3);
+        assert_eq!(buttons[0].0, "Sign in with Microsoft");
+        assert_eq!(buttons[0].1["scopes"], serde_json::json!(["User.Read"]));
+        assert_eq!(buttons[1].0, "Sign in with Google");
+        assert_eq!(buttons[1].1["scopes"], serde_json::json!(["openid"]));
+        assert_eq!(buttons[2].0, "Sign in with Okta");
+        assert_eq!(buttons[2].1["mode"], "start-sign-in");
+    }
+
+    #[test]
+    fn sign_in_card_uses_custom_provider_logo() {
+        let backend = MockBroker {
+            consent_url: "https://consent/start".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            provider_id: "acme-idp".into(),
+            extra_json: Some(serde_json::json!({
+                "provider_logos": { "acme-idp": "https://cdn.example/acme.png" }
+            })),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("sign-in: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert_eq!(card.images[0].url, "https://cdn.example/acme.png");
+        assert_eq!(card.images[0].alt.as_deref(), Some("acme-idp logo"));
+    }
+
+    struct AsyncBroker;
+
+    /// Pending on first poll, like a real network call.
+    async fn network_hop() {
+        let mut yielded = false;
+        std::future::poll_fn(|cx| {
+            if yielded {
+                std::task::Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                std::task::Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    impl AsyncOAuthBackend for AsyncBroker {
+        async fn get_token(
+            &self,
+            _ctx: &CallContext,
+            _provider_id: &str,
+            subject: &str,
+            _scopes: &[Scope],
+        ) -> Result<Option<TokenSet>, OAuthCardError> {
+            network_hop().await;
+            Ok(Some(TokenSet {
+                access_token: format!("async-{subject}"),
+                ..Default::default()
+            }))
+        }
+
+        async fn get_consent_url(
+            &self,
+            _ctx: &CallContext,
+            _provider_id: &str,
+            _subject: &str,
+            _scopes: &[Scope],
+            _redirect_path: &str,
+            _extra_json: Option<String>,
+        ) -> Result<String, OAuthCardError> {
+            Ok("https://consent".into())
+        }
+
+        async fn exchange_code(
+            &self,
+            _ctx: &CallContext,
+            _provider_id: &str,
+            _subject: &str,
+            _code: &str,
+            _redirect_path: &str,
+            _idempotency_key: Option<&str>,
+        ) -> Result<TokenSet, OAuthCardError> {
+            Err(OAuthCardError::Unsupported("not used".into()))
+        }
+    }
+
+    #[test]
+    fn handle_async_awaits_async_backend() {
+        let future = logic::handle_async(&AsyncBroker, test_input(OAuthCardMode::EnsureToken));
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&future);
+
+        let mut future = std::pin::pin!(future);
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        let output = loop {
+            if let std::task::Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+                break result.unwrap_or_else(|err| panic!("async ensure: {err}"));
+            }
+        };
+        assert_eq!(output.status, OAuthStatus::Ok);
+        let header = output
+            .auth_header
+            .unwrap_or_else(|| panic!("header present"));
+        assert_eq!(header.headers[0].1, "Bearer async-user-1");
+    }
+
+    #[cfg(feature = "native-http")]
+    #[test]
+    fn http_broker_fetches_token_from_rest_endpoint() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener =
+            std::net::TcpListener::bind("127.0.0.1:0").unwrap_or_else(|err| panic!("bind: {err}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|err| panic!("addr: {err}"));
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener
+                .accept()
+                .unwrap_or_else(|err| panic!("accept: {err}"));
+            let mut reader = BufReader::new(
+                stream
+                    .try_clone()
+                    .unwrap_or_else(|err| panic!("clone: {err}")),
+            );
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                    break;
+                }
+                head.push(line);
+            }
+            let body = r#"{"access_token":"http-token","token_type":"Bearer"}"#;
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            head
+        });
+
+        let broker = HttpBroker::new(format!("http://{addr}/oauth/")).with_bearer_token("secret");
+        let output = logic::handle(&broker, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("http ensure: {err}"));
+        let header = output
+            .auth_header
+            .unwrap_or_else(|| panic!("header present"));
+        assert_eq!(header.headers[0].1, "Bearer http-token");
+
+        let head = server.join().unwrap_or_else(|_| panic!("server thread"));
+        assert!(head[0].starts_with("GET /oauth/token?provider_id=msgraph&subject=user-1"));
+        assert!(
+            head.iter()
+                .any(|line| line.eq_ignore_ascii_case("authorization: Bearer secret\r\n"))
+        );
+        assert!(
+            head.iter()
+                .any(|line| line.eq_ignore_ascii_case("x-request-timeout-ms: 10000\r\n"))
+        );
+    }
+
+    #[cfg(feature = "native-http")]
+    #[test]
+    fn http_broker_needs_a_broker_url() {
+        if std::env::var(BROKER_URL_ENV).is_ok() {
+            return;
+        }
+        let err = HttpBroker::from_env()
+            .err()
+            .unwrap_or_else(|| panic!("no broker url"));
+        assert_eq!(err.error_code(), ErrorCode::ValidationFailed);
+        assert!(std::any::type_name_of_val(&default_backend()).ends_with("NoopBroker"));
+    }
+
+    #[cfg(feature = "native-http")]
+    #[test]
+    fn http_broker_timeout_reports_pending() {
+        use std::time::Duration;
+
+        let listener =
+            std::net::TcpListener::bind("127.0.0.1:0").unwrap_or_else(|err| panic!("bind: {err}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|err| panic!("addr: {err}"));
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener
+                .accept()
+                .unwrap_or_else(|err| panic!("accept: {err}"));
+            std::thread::sleep(Duration::from_millis(500));
+            drop(stream);
+        });
+
+        let broker =
+            HttpBroker::new(format!("http://{addr}")).with_timeout(Duration::from_millis(100));
+        let output = logic::handle(&broker, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("timeout: {err}"));
+        assert_eq!(output.status, OAuthStatus::Pending);
+        assert!(output.error.is_some_and(|err| err.starts_with("timed out")));
+        let card = output.card.unwrap_or_else(|| panic!("retry card"));
+        assert!(
+            card.actions
+                .iter()
+                .any(|a| matches!(a, Action::PostBack { title, .. } if title == "Try again"))
+        );
+        server.join().unwrap_or_else(|_| panic!("server thread"));
+    }
+
+    #[test]
+    fn in_memory_broker_keeps_tokens_per_subject() {
+        let fixtures = serde_json::json!([
+            {
+                "provider_id": "msgraph",
+                "subject": "alice",
+                "token": {
+                    "access_token": "alice-old",
+                    "refresh_token": "alice-refresh",
+                    "expires_at": 1
+                }
+            },
+            {
+                "provider_id": "msgraph",
+                "subject": "carol",
+                "token": { "access_token": "carol-expired", "expires_at": 1 }
+            }
+        ]);
+        let backend = InMemoryBroker::from_json(&fixtures.to_string())
+            .unwrap_or_else(|err| panic!("fixtures: {err}"));
+        let for_subject = |subject: &str, mode| OAuthCardInput {
+            subject: subject.into(),
+            ..test_input(mode)
+        };
+
+        // Expired with a refresh token: ensure-token refreshes silently.
+        let alice = logic::handle(&backend, for_subject("alice", OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("alice: {err}"));
+        assert_eq!(alice.status, OAuthStatus::Ok);
+        let stored = backend
+            .stored("msgraph", "alice")
+            .unwrap_or_else(|| panic!("alice stored"));
+        assert_ne!(stored.access_token, "alice-old");
+        assert_eq!(stored.refresh_token.as_deref(), Some("alice-refresh"));
+
+        // Expired without a refresh token, and a subject that never connected.
+        for subject in ["carol", "bob"] {
+            let output = logic::handle(&backend, for_subject(subject, OAuthCardMode::EnsureToken))
+                .unwrap_or_else(|err| panic!("{subject}: {err}"));
+            assert_eq!(output.status, OAuthStatus::NeedsSignIn);
+        }
+
+        let bob = OAuthCardInput {
+            scopes: vec![scope("User.Read")],
+            ..for_subject("bob", OAuthCardMode::StartSignIn)
+        };
+        logic::handle(&backend, bob.clone()).unwrap_or_else(|err| panic!("bob start: {err}"));
+        let complete = OAuthCardInput {
+            mode: OAuthCardMode::CompleteSignIn,
+            state_id: Some("state".into()),
+            auth_code: Some("code".into()),
+            ..bob
+        };
+        let output =
+            logic::handle(&backend, complete).unwrap_or_else(|err| panic!("bob complete: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        let bob_token = backend
+            .stored("msgraph", "bob")
+            .unwrap_or_else(|| panic!("bob stored"));
+        assert_eq!(bob_token.granted_scopes, vec!["User.Read".to_string()]);
+        assert_ne!(bob_token.access_token, stored.access_token);
+    }
+
+    #[cfg(feature = "file-store")]
+    #[test]
+    fn file_token_store_encrypts_tokens_at_rest() {
+        let dir = std::env::temp_dir().join(format!("oauth-card-store-{}", uuid::Uuid::new_v4()));
+        let store = FileTokenStore::new(&dir, &[7; 32]);
+        let token = TokenSet {
+            access_token: "plaintext-secret".into(),
+            refresh_token: Some("refresh-secret".into()),
+            granted_scopes: vec!["User.Read".into()],
+            ..Default::default()
+        };
+        store
+            .save("msgraph", "user-1", &token)
+            .unwrap_or_else(|err| panic!("save: {err}"));
+
+        let files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap_or_else(|err| panic!("read dir: {err}"))
+            .map(|entry| {
+                let path = entry.unwrap_or_else(|err| panic!("entry: {err}")).path();
+                std::fs::read_to_string(path).unwrap_or_else(|err| panic!("read: {err}"))
+            })
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert!(!files[0].contains("secret"));
+        let names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap_or_else(|err| panic!("read dir: {err}"))
+            .map(|entry| {
+                let entry = entry.unwrap_or_else(|err| panic!("entry: {err}"));
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    let mode = entry
+                        .metadata()
+                        .unwrap_or_else(|err| panic!("metadata: {err}"))
+                        .permissions()
+                        .mode();
+                    assert_eq!(mode & 0o777, 0o600);
+                }
+                entry.file_name().to_string_lossy().into_owned()
+            })
+            .collect();
+        assert!(!names[0].contains("msgraph"), "{names:?}");
+        let other_store = FileTokenStore::new(&dir, &[7; 32]);
+        other_store
+            .save("google", "user-2", &token)
+            .unwrap_or_else(|err| panic!("save: {err}"));
+
+        let output = logic::handle(&store, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        let header = output
+            .auth_header
+            .unwrap_or_else(|| panic!("header present"));
+        assert_eq!(header.headers[0].1, "Bearer plaintext-secret");
+        let connections = OAuthBackend::list_connections(&store, &CallContext::default(), "user-1")
+            .unwrap_or_else(|err| panic!("list: {err}"));
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].provider_id, "msgraph");
+
+        let wrong_key = FileTokenStore::new(&dir, &[8; 32]);
+        assert!(
+            OAuthBackend::get_token(
+                &wrong_key,
+                &CallContext::default(),
+                "msgraph",
+                "user-1",
+                &[]
+            )
+            .is_err()
+        );
+
+        OAuthBackend::revoke_token(&store, &CallContext::default(), "msgraph", "user-1", &[])
+            .unwrap_or_else(|err| panic!("revoke: {err}"));
+        assert!(
+            OAuthBackend::get_token(&store, &CallContext::default(), "msgraph", "user-1", &[])
+                .unwrap_or_else(|err| panic!("get: {err}"))
+                .is_none()
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    struct DownBroker;
+
+    impl OAuthBackend for DownBroker {
+        fn get_token(
+            &self,
+            _ctx: &CallContext,
+            _provider_id: &str,
+            _subject: &str,
+            _scopes: &[Scope],
+        ) -> Result<Option<TokenSet>, OAuthCardError> {
+            Err(OAuthCardError::Transient("broker unreachable".into()))
+        }
+
+        fn get_consent_url(
+            &self,
+            _ctx: &CallContext,
+            _provider_id: &str,
+            _subject: &str,
+            _scopes: &[Scope],
+            _redirect_path: &str,
+            _extra_json: Option<String>,
+        ) -> Result<String, OAuthCardError> {
+            Err(OAuthCardError::Transient("broker unreachable".into()))
+        }
+
+        fn exchange_code(
+            &self,
+            _ctx: &CallContext,
+            _provider_id: &str,
+            _subject: &str,
+            _code: &str,
+            _redirect_path: &str,
+            _idempotency_key: Option<&str>,
+        ) -> Result<TokenSet, OAuthCardError> {
+            Err(OAuthCardError::Transient("broker unreachable".into()))
+        }
+    }
+
+    #[test]
+    fn chained_backend_falls_back_and_writes_through() {
+        let cached = TokenSet {
+            access_token: "cached".into(),
+            ..Default::default()
+        };
+        let store = InMemoryBroker::new();
+        store.seed("msgraph", "user-1", cached);
+        let chained = ChainedBackend::new(DownBroker, store);
+        let output = logic::handle(&chained, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("fallback: {err}"));
+        let header = output
+            .auth_header
+            .unwrap_or_else(|| panic!("header present"));
+        assert_eq!(header.headers[0].1, "Bearer cached");
+
+        let primary = MockBroker {
+            token: Some(TokenSet {
+                access_token: "fresh".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let chained = ChainedBackend::new(primary, InMemoryBroker::new())
+            .with_write_policy(WritePolicy::WriteThrough);
+        logic::handle(&chained, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("primary: {err}"));
+        let mirrored = chained
+            .secondary()
+            .stored("msgraph", "user-1")
+            .unwrap_or_else(|| panic!("mirrored"));
+        assert_eq!(mirrored.access_token, "fresh");
+
+        logic::handle(&chained, test_input(OAuthCardMode::Disconnect))
+            .unwrap_or_else(|err| panic!("disconnect: {err}"));
+        assert!(chained.secondary().stored("msgraph", "user-1").is_none());
+    }
+
+    #[test]
+    fn cached_backend_memoizes_until_invalidated() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct CountingBroker {
+            fetches: AtomicUsize,
+        }
+
+        impl OAuthBackend for CountingBroker {
+            fn get_token(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _scopes: &[Scope],
+            ) -> Result<Option<TokenSet>, OAuthCardError> {
+                let fetch = self.fetches.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok(Some(TokenSet {
+                    access_token: format!("token-{fetch}"),
+                    ..Default::default()
+                }))
+            }
+
+            fn get_consent_url(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _scopes: &[Scope],
+                _redirect_path: &str,
+                _extra_json: Option<String>,
+            ) -> Result<String, OAuthCardError> {
+                Ok("https://consent".into())
+            }
+
+            fn exchange_code(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _code: &str,
+                _redirect_path: &str,
+                _idempotency_key: Option<&str>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                Err(OAuthCardError::Unsupported("no exchange".into()))
+            }
+
+            fn revoke_token(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _scopes: &[Scope],
+            ) -> Result<(), OAuthCardError> {
+                Ok(())
+            }
+        }
+
+        let cached = CachedBackend::new(CountingBroker::default());
+        let scopes = vec![scope("User.Read"), scope("Mail.Read")];
+        let reversed: Vec<Scope> = scopes.iter().rev().cloned().collect();
+        let fetch = |scopes: &[Scope]| {
+            OAuthBackend::get_token(
+                &cached,
+                &CallContext::default(),
+                "msgraph",
+                "user-1",
+                scopes,
+            )
+            .unwrap_or_else(|err| panic!("get_token: {err}"))
+            .map(|token| token.access_token)
+        };
+        assert_eq!(fetch(&scopes).as_deref(), Some("token-1"));
+        assert_eq!(fetch(&reversed).as_deref(), Some("token-1"));
+        assert_eq!(cached.inner().fetches.load(Ordering::SeqCst), 1);
+
+        OAuthBackend::revoke_token(
+            &cached,
+            &CallContext::default(),
+            "msgraph",
+            "user-1",
+            &scopes,
+        )
+        .unwrap_or_else(|err| panic!("revoke: {err}"));
+        assert_eq!(fetch(&scopes).as_deref(), Some("token-2"));
+    }
+
+    #[test]
+    fn retrying_backend_retries_only_transient_failures() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::time::Duration;
+
+        struct FlakyBroker {
+            failures: u32,
+            error: fn() -> OAuthCardError,
+            calls: AtomicU32,
+        }
+
+        impl OAuthBackend for FlakyBroker {
+            fn get_token(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _scopes: &[Scope],
+            ) -> Result<Option<TokenSet>, OAuthCardError> {
+                if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    return Err((self.error)());
+                }
+                Ok(Some(TokenSet {
+                    access_token: "recovered".into(),
+                    ..Default::default()
+                }))
+            }
+
+            fn get_consent_url(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _scopes: &[Scope],
+                _redirect_path: &str,
+                _extra_json: Option<String>,
+            ) -> Result<String, OAuthCardError> {
+                Ok("https://consent".into())
+            }
+
+            fn exchange_code(
+                &self,
+                _ctx: &CallContext,
+                _provider_id: &str,
+                _subject: &str,
+                _code: &str,
+                _redirect_path: &str,
+                _idempotency_key: Option<&str>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                Err(OAuthCardError::Unsupported("no exchange".into()))
+            }
+        }
+
+        let flaky = |failures, error| {
+            RetryingBackend::new(FlakyBroker {
+                failures,
+                error,
+                calls: AtomicU32::new(0),
+            })
+            .with_backoff(Duration::ZERO, Duration::ZERO)
+        };
+
+        let backend = flaky(2, || OAuthCardError::Transient("timeout".into()));
+        let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("retry: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert_eq!(backend.inner().calls.load(Ordering::SeqCst), 3);
+
+        let backend = flaky(5, || OAuthCardError::Transient("timeout".into()));
+        let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("exhausted: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert_eq!(backend.inner().calls.load(Ordering::SeqCst), 3);
+        let card = output.card.unwrap_or_else(|| panic!("error card"));
+        assert!(
+            card.title
+                .is_some_and(|t| t.contains("temporarily unavailable"))
+        );
+        assert!(
+            card.actions
+                .iter()
+                .any(|a| matches!(a, Action::PostBack { title, .. } if title == "Try again"))
+        );
+
+        let backend = flaky(1, || OAuthCardError::Invalid("denied".into()));
+        let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("fatal: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert_eq!(backend.inner().calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn health_reports_reachability() {
+        let json: serde_json::Value = serde_json::from_str(&handle_health(
+            &MockBroker::default(),
+            &CallContext::default(),
+        ))
+        .unwrap_or_else(|err| panic!("valid json: {err}"));
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["reachable"], true);
+        assert!(json["latency_ms"].is_u64());
+
+        let down = MockBroker {
+            health_error: Some("connection refused".into()),
+            ..Default::default()
+        };
+        let output = logic::health(&down, &CallContext::default());
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert!(!output.reachable);
+        assert!(
+            output
+                .error
+                .is_some_and(|err| err.contains("connection refused"))
+        );
+    }
+
+    #[test]
+    fn version_operation_lists_every_supported_mode() {
+        let response = handle_message_with_backend(&MockBroker::default(), "version", "");
+        let output: VersionOutput =
+            serde_json::from_str(&response).unwrap_or_else(|err| panic!("valid json: {err}"));
+        assert_eq!(output.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(output.modes, OAuthCardMode::SUPPORTED);
+        for mode in output.modes {
+            let output = logic::handle(&MockBroker::default(), test_input(mode.clone()))
+                .unwrap_or_else(|err| panic!("{mode:?}: {err}"));
+            assert!(
+                !output
+                    .error
+                    .is_some_and(|err| err.contains("not supported by this component version")),
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn mock_broker_scripts_play_in_order() {
+        use crate::broker::Script;
+        use std::time::Duration;
+
+        let token = |access_token: &str, expires_at| TokenSet {
+            access_token: access_token.into(),
+            refresh_token: Some("refresh".into()),
+            expires_at: Some(expires_at),
+            ..Default::default()
+        };
+        let valid_until = clock::now_secs() + 3_600;
+
+        let backend = MockBroker {
+            get_token_script: Script::new([Ok(None), Ok(Some(token("second", valid_until)))]),
+            ..Default::default()
+        };
+        let status = |backend: &MockBroker| {
+            logic::handle(backend, test_input(OAuthCardMode::StatusCard))
+                .unwrap_or_else(|err| panic!("status: {err}"))
+                .status
+        };
+        assert_eq!(status(&backend), OAuthStatus::NeedsSignIn);
+        assert_eq!(status(&backend), OAuthStatus::Ok);
+        assert_eq!(backend.get_token_script.remaining(), 0);
+        assert_eq!(status(&backend), OAuthStatus::NeedsSignIn);
+
+        let backend = MockBroker {
+            token: Some(token("exchanged", valid_until)),
+            exchange_code_script: Script::new([Err(OAuthCardError::Invalid(
+                "code already used".into(),
+            ))]),
+            ..Default::default()
+        };
+        let complete = OAuthCardInput {
+            auth_code: Some("code-123".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        let first = logic::handle(&backend, complete.clone())
+            .unwrap_or_else(|err| panic!("first exchange: {err}"));
+        assert_eq!(first.status, OAuthStatus::Error);
+        let second = logic::handle(&backend, complete)
+            .unwrap_or_else(|err| panic!("second exchange: {err}"));
+        assert_eq!(second.status, OAuthStatus::Ok);
+
+        // A refresh may have rotated the refresh token before timing out, so
+        // it is never replayed.
+        let backend = RetryingBackend::new(MockBroker {
+            token: Some(token("expiring", clock::now_secs() + 10)),
+            refresh_token_script: Script::new([
+                Err(OAuthCardError::Transient("timeout".into())),
+                Ok(token("refreshed", valid_until)),
+            ]),
+            ..Default::default()
+        })
+        .with_backoff(Duration::ZERO, Duration::ZERO);
+        logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(backend.inner().calls_to("refresh_token").len(), 1);
+        assert_eq!(backend.inner().refresh_token_script.remaining(), 1);
+
+        // Exchanges are replayed only under an idempotency key.
+        let backend = RetryingBackend::new(MockBroker {
+            exchange_code_script: Script::new([
+                Err(OAuthCardError::Transient("timeout".into())),
+                Ok(token("exchanged", valid_until)),
+            ]),
+            ..Default::default()
+        })
+        .with_backoff(Duration::ZERO, Duration::ZERO);
+        let keyed = OAuthBackend::exchange_code(
+            &backend,
+            &CallContext::default(),
+            "msgraph",
+            "user-1",
+            "code",
+            "/cb",
+            Some("key"),
+        )
+        .unwrap_or_else(|err| panic!("keyed exchange: {err}"));
+        assert_eq!(keyed.access_token, "exchanged");
+        let backend = RetryingBackend::new(MockBroker {
+            exchange_code_script: Script::new([
+                Err(OAuthCardError::Transient("timeout".into())),
+                Ok(token("exchanged", valid_until)),
+            ]),
+            ..Default::default()
+        })
+        .with_backoff(Duration::ZERO, Duration::ZERO);
+        assert!(
+            OAuthBackend::exchange_code(
+                &backend,
+                &CallContext::default(),
+                "msgraph",
+                "user-1",
+                "code",
+                "/cb",
+                None
+            )
+            .is_err()
+        );
+        assert_eq!(backend.inner().calls_to("exchange_code").len(), 1);
+    }
+
+    #[test]
+    fn mock_broker_records_call_arguments() {
+        let backend = MockBroker {
+            consent_url: "https://login.example/authorize".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![scope("User.Read")],
+            extra_json: Some(serde_json::json!({ "prompt": "consent" })),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        logic::handle(&backend, input).unwrap_or_else(|err| panic!("start: {err}"));
+        let consent = backend.calls_to("get_consent_url");
+        assert_eq!(consent.len(), 1);
+        assert_eq!(consent[0].provider_id, "msgraph");
+        assert_eq!(consent[0].subject, "user-1");
+        assert_eq!(consent[0].scopes, vec!["User.Read".to_string()]);
+        assert_eq!(
+            consent[0].redirect_path.as_deref(),
+            Some("/oauth/callback/msgraph")
+        );
+        let extra: serde_json::Value =
+            serde_json::from_str(consent[0].extra_json.as_deref().unwrap_or_default())
+                .unwrap_or_else(|err| panic!("extra json: {err}"));
+        assert_eq!(extra["prompt"], "consent");
+
+        let input = OAuthCardInput {
+            auth_code: Some("code-123".into()),
+            redirect_path: Some("/oauth/callback/custom".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        logic::handle(&backend, input).unwrap_or_else(|err| panic!("complete: {err}"));
+        let exchange = backend.calls_to("exchange_code");
+        assert_eq!(
+            exchange[0].redirect_path.as_deref(),
+            Some("/oauth/callback/custom")
+        );
+        assert_eq!(backend.calls()[0].method, "get_consent_url");
+    }
+
+    #[test]
+    fn broker_error_codes_choose_card_and_status() {
+        use crate::broker::Script;
+
+        let failing = |code| MockBroker {
+            get_token_script: Script::new([Err(OAuthCardError::Broker {
+                code,
+                message: "provider said no".into(),
+            })]),
+            ..Default::default()
+        };
+        let ensure = |backend: &MockBroker| {
+            logic::handle(backend, test_input(OAuthCardMode::EnsureToken))
+                .unwrap_or_else(|err| panic!("ensure: {err}"))
+        };
+        let retry_title = |output: &OAuthCardOutput| {
+            output
+                .card
+                .as_ref()
+                .and_then(|card| {
+                    card.actions.iter().find_map(|action| match action {
+                        Action::PostBack { title, data } => Some((
+                            title.clone(),
+                            data.get("mode")
+                                .and_then(|mode| mode.as_str())
+                                .map(str::to_string),
+                        )),
+                        _ => None,
+                    })
+                })
+                .unwrap_or_else(|| panic!("retry action missing"))
+        };
+
+        let expired = ensure(&failing(BrokerErrorCode::InvalidGrant));
+        assert_eq!(expired.status, OAuthStatus::Expired);
+        assert_eq!(expired.error_code, Some(ErrorCode::InvalidGrant));
+        assert_eq!(
+            retry_title(&expired),
+            ("Reconnect".to_string(), Some("start-sign-in".to_string()))
+        );
+
+        let denied = ensure(&failing(BrokerErrorCode::ConsentDenied));
+        assert_eq!(denied.status, OAuthStatus::Denied);
+        assert_eq!(denied.error_code, Some(ErrorCode::ConsentDenied));
+        assert_eq!(retry_title(&denied).0, "Try again");
+
+        let throttled = ensure(&failing(BrokerErrorCode::Throttled));
+        assert_eq!(throttled.status, OAuthStatus::Error);
+        assert_eq!(throttled.error_code, Some(ErrorCode::Throttled));
+        assert!(
+            OAuthCardError::Broker {
+                code: BrokerErrorCode::Throttled,
+                message: String::new(),
+            }
+            .is_transient()
+        );
+
+        let plain = ensure(&failing(BrokerErrorCode::Unknown));
+        assert_eq!(plain.error_code, Some(ErrorCode::Unknown));
+        let invalid = logic::handle(
+            &MockBroker {
+                get_token_script: Script::new([Err(OAuthCardError::Invalid("nope".into()))]),
+                ..Default::default()
+            },
+            test_input(OAuthCardMode::EnsureToken),
+        )
+        .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(invalid.error_code, Some(ErrorCode::InvalidInput));
+        assert_eq!(invalid.retryable, Some(false));
+
+        assert_eq!(
+            BrokerErrorCode::from_response(400, Some("invalid_grant")),
+            BrokerErrorCode::InvalidGrant
+        );
+        assert_eq!(
+            BrokerErrorCode::from_response(503, None),
+            BrokerErrorCode::ProviderUnavailable
+        );
+    }
+
+    #[test]
+    fn error_outputs_carry_structured_details() {
+        use crate::broker::Script;
+
+        let missing_code = logic::handle(
+            &MockBroker::default(),
+            test_input(OAuthCardMode::CompleteSignIn),
+        )
+        .unwrap_or_else(|err| panic!("complete: {err}"));
+        assert_eq!(missing_code.status, OAuthStatus::Error);
+        assert_eq!(missing_code.error_code, Some(ErrorCode::ValidationFailed));
+        assert_eq!(missing_code.retryable, Some(false));
+        assert_eq!(
+            missing_code
+                .error_detail
+                .as_ref()
+                .and_then(|detail| detail.get("field")),
+            Some(&serde_json::json!("auth_code"))
+        );
+
+        let failing = |err| MockBroker {
+            get_token_script: Script::new([Err(err)]),
+            ..Default::default()
+        };
+        let ensure = |backend: &MockBroker| {
+            logic::handle(backend, test_input(OAuthCardMode::EnsureToken))
+                .unwrap_or_else(|err| panic!("ensure: {err}"))
+        };
+        let timed_out = ensure(&failing(OAuthCardError::Timeout("slow".into())));
+        assert_eq!(timed_out.error_code, Some(ErrorCode::Timeout));
+        assert_eq!(timed_out.retryable, Some(true));
+
+        let denied = ensure(&failing(OAuthCardError::Denied("no".into())));
+        assert_eq!(denied.status, OAuthStatus::Denied);
+        assert_eq!(denied.error_code, Some(ErrorCode::ConsentDenied));
+
+        let backend = ensure(&failing(OAuthCardError::Backend("disk full".into())));
+        assert_eq!(backend.error_code, Some(ErrorCode::BackendError));
+        assert_eq!(
+            backend.error_detail,
+            Some(serde_json::json!({ "message": "disk full" }))
+        );
+    }
+
+    #[test]
+    fn cards_hide_actions_the_backend_cannot_fulfil() {
+        let token = TokenSet {
+            access_token: "abc".into(),
+            expires_at: Some(clock::now_secs() + 60),
+            ..Default::default()
+        };
+        let titles = |capabilities| {
+            let backend = MockBroker {
+                token: Some(token.clone()),
+                capabilities,
+                ..Default::default()
+            };
+            let output = logic::handle(&backend, test_input(OAuthCardMode::StatusCard))
+                .unwrap_or_else(|err| panic!("status: {err}"));
+            assert_eq!(output.status, OAuthStatus::ExpiringSoon);
+            output
+                .card
+                .unwrap_or_else(|| panic!("card"))
+                .actions
+                .into_iter()
+                .map(|action| action.title().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            titles(None),
+            ["Refresh now", "Use different account", "Disconnect"]
+        );
+        assert_eq!(
+            titles(Some(BackendCapabilities::default())),
+            ["Use different account"]
+        );
+        assert_eq!(
+            titles(Some(BackendCapabilities {
+                revoke: true,
+                ..Default::default()
+            })),
+            ["Use different account", "Disconnect"]
+        );
+        let chained = ChainedBackend::new(
+            MockBroker {
+                capabilities: Some(BackendCapabilities::default()),
+                ..Default::default()
+            },
+            InMemoryBroker::new(),
+        );
+        let capabilities = OAuthBackend::capabilities(&chained);
+        assert!(capabilities.refresh && capabilities.revoke && !capabilities.device_code);
+    }
+
+    #[test]
+    fn oauth_provider_keeps_custom_ids() {
+        use crate::model::OauthProvider;
+
+        assert_eq!(OauthProvider::from_id("Jira"), OauthProvider::Atlassian);
+        assert_eq!(OauthProvider::from_id("auth0").display_name(), "Auth0");
+        let custom = OauthProvider::from_id("acme-sso");
+        assert_eq!(custom.display_name(), "acme-sso");
+
+        let backend = MockBroker {
+            consent_url: "https://acme.example/authorize".into(),
+            ..Default::default()
+        };
+        let provider_json = |provider_id: &str| {
+            let output = logic::handle(
+                &backend,
+                OAuthCardInput {
+                    provider_id: provider_id.into(),
+                    ..test_input(OAuthCardMode::StartSignIn)
+                },
+            )
+            .unwrap_or_else(|err| panic!("start: {err}"));
+            serde_json::to_value(&output).unwrap_or_default()["card"]["oauth"]["provider"].clone()
+        };
+        assert_eq!(provider_json("slack"), serde_json::json!("slack"));
+        assert_eq!(
+            provider_json("acme-sso"),
+            serde_json::json!({ "custom": { "id": "acme-sso" } })
+        );
+    }
+
+    #[test]
+    fn provider_config_fills_missing_input_fields() {
+        let config = ProviderConfig {
+            display_name: Some("Acme ID".into()),
+            icon: Some("https://cdn.example/acme.png".into()),
+            default_scopes: vec![scope("openid"), scope("acme.read")],
+            prompt: Some("consent".into()),
+            audience: Some("api://acme".into()),
+            redirect_template: Some("/auth/{provider_id}/done".into()),
+            ..Default::default()
+        };
+        let input = provider_config::apply(
+            OAuthCardInput {
+                provider_id: "acme".into(),
+                extra_json: Some(serde_json::json!({ "prompt": "login" })),
+                ..test_input(OAuthCardMode::StartSignIn)
+            },
+            &config,
+        );
+        assert_eq!(input.scopes, ["openid", "acme.read"]);
+        assert_eq!(input.redirect_path.as_deref(), Some("/auth/acme/done"));
+        let extra = input.extra_json.unwrap_or_default();
+        assert_eq!(extra["prompt"], "login");
+        assert_eq!(extra["audience"], "api://acme");
+        assert!(extra.get("provider_logos").is_none());
+
+        let explicit = provider_config::apply(
+            OAuthCardInput {
+                scopes: vec![scope("acme.write")],
+                redirect_path: Some("/custom".into()),
+                ..test_input(OAuthCardMode::StartSignIn)
+            },
+            &config,
+        );
+        assert_eq!(explicit.scopes, ["acme.write"]);
+        assert_eq!(explicit.redirect_path.as_deref(), Some("/custom"));
+
+        load_provider_configs(
+            &serde_json::json!({
+                "providers": {
+                    "acme-configured": {
+                        "display_name": "Acme ID",
+                        "icon": "https://cdn.example/acme.png",
+                        "default_scopes": ["acme.read"]
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .unwrap_or_else(|err| panic!("load: {err}"));
+        let backend = MockBroker {
+            consent_url: "https://acme.example/authorize".into(),
+            ..Default::default()
+        };
+        let output = logic::handle(
+            &backend,
+            OAuthCardInput {
+                providers: vec![ProviderRequest {
+                    provider_id: "acme-configured".into(),
+                    scopes: vec![],
+                }],
+                ..test_input(OAuthCardMode::ChooseProvider)
+            },
+        )
+        .unwrap_or_else(|err| panic!("choose: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card"));
+        assert!(matches!(
+            &card.actions[0],
+            Action::PostBack { title, data }
+                if title == "Sign in with Acme ID" && data["scopes"] == serde_json::json!(["acme.read"])
+        ));
+
+        // The configured icon reaches the card but never the broker, and
+        // card-only extra_json entries are kept out of broker calls.
+        let output = logic::handle(
+            &backend,
+            OAuthCardInput {
+                provider_id: "acme-configured".into(),
+                extra_json: Some(serde_json::json!({
+                    "prompt": "login",
+                    "branding": { "product_name": "Acme Bot" },
+                    "scope_descriptions": { "acme.read": "Read Acme data" }
+                })),
+                ..test_input(OAuthCardMode::StartSignIn)
+            },
+        )
+        .unwrap_or_else(|err| panic!("start: {err}"));
+        let card = output.card.unwrap_or_else(|| panic!("card"));
+        assert!(
+            card.images
+                .iter()
+                .any(|image| image.url == "https://cdn.example/acme.png")
+        );
+        let consent = backend.calls_to("get_consent_url");
+        let sent: serde_json::Value = serde_json::from_str(
+            consent[0]
+                .extra_json
+                .as_deref()
+                .unwrap_or_else(|| panic!("extra_json sent")),
+        )
+        .unwrap_or_else(|err| panic!("extra json: {err}"));
+        assert_eq!(sent["prompt"], "login");
+        for key in ["provider_logos", "branding", "scope_descriptions"] {
+            assert!(sent.get(key).is_none(), "{key}: {sent}");
+        }
+        assert!(load_provider_configs("not json").is_err());
+    }
+
+    #[test]
+    fn in_memory_state_store_expires_entries() {
+        let store = InMemoryStateStore::new();
+        let pending = PendingSignIn {
+            provider_id: "msgraph".into(),
+            subject: "user-1".into(),
+            redirect_path: "/oauth/callback/msgraph".into(),
+            scopes: vec![scope("User.Read")],
+            nonce: Some("n-1".into()),
+            tenant: None,
+        };
+        store
+            .save("live", &pending, 600)
+            .unwrap_or_else(|err| panic!("save: {err}"));
+        store
+            .save("stale", &pending, 0)
+            .unwrap_or_else(|err| panic!("save: {err}"));
+        let load = |state_id| {
+            store
+                .load(state_id)
+                .unwrap_or_else(|err| panic!("load: {err}"))
+        };
+        assert_eq!(load("live"), Some(pending));
+        assert_eq!(load("stale"), None);
+        store
+            .delete("live")
+            .unwrap_or_else(|err| panic!("delete: {err}"));
+        assert_eq!(load("live"), None);
+
+        let claim = |owner: &str| {
+            store
+                .claim("code:abc", owner, 600)
+                .unwrap_or_else(|err| panic!("claim: {err}"))
+        };
+        assert_eq!(claim("attempt-1"), None);
+        assert_eq!(claim("attempt-2").as_deref(), Some("attempt-1"));
+        store
+            .release("code:abc")
+            .unwrap_or_else(|err| panic!("release: {err}"));
+        assert_eq!(claim("attempt-2"), None);
+    }
+
+    #[test]
+    fn complete_sign_in_treats_replayed_exchange_as_success() {
+        use crate::broker::Script;
+
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "first-exchange".into(),
+                ..Default::default()
+            }),
+            exchange_code_script: Script::new([Err(OAuthCardError::Broker {
+                code: BrokerErrorCode::AlreadyExchanged,
+                message: "code already redeemed".into(),
+            })]),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            auth_code: Some("code-123".into()),
+            state_id: Some("state-1".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        let output =
+            logic::handle(&backend, input.clone()).unwrap_or_else(|err| panic!("replay: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert_eq!(output.error_code, None);
+        let header = output
+            .auth_header
+            .unwrap_or_else(|| panic!("header present"));
+        assert_eq!(header.headers[0].1, "Bearer first-exchange");
+        assert_eq!(
+            backend.calls_to("exchange_code")[0]
+                .idempotency_key
+                .as_deref(),
+            Some("msgraph:user-1:state-1")
+        );
+
+        // Without a stored token the replay is still reported.
+        let empty = MockBroker {
+            exchange_code_script: Script::new([Err(OAuthCardError::Broker {
+                code: BrokerErrorCode::AlreadyExchanged,
+                message: "code already redeemed".into(),
+            })]),
+            ..Default::default()
+        };
+        let output = logic::handle(&empty, input).unwrap_or_else(|err| panic!("empty: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert_eq!(output.error_code, Some(ErrorCode::AlreadyExchanged));
+    }
+
+    #[cfg(feature = "direct-oidc")]
+    #[test]
+    fn direct_oidc_backend_discovers_and_exchanges_codes() {
+        use base64::Engine;
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        use sha2::Digest;
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener =
+            std::net::TcpListener::bind("127.0.0.1:0").unwrap_or_else(|err| panic!("bind: {err}"));
+        let addr = listener
+            .local_addr()
+            .unwrap_or_else(|err| panic!("addr: {err}"));
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener
+                    .accept()
+                    .unwrap_or_else(|err| panic!("accept: {err}"));
+                let mut reader = BufReader::new(
+                    stream
+                        .try_clone()
+                        .unwrap_or_else(|err| panic!("clone: {err}")),
+                );
+                let mut head = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head.push(line);
+                }
+                let length = head
+                    .iter()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                let _ = reader.read_exact(&mut body);
+                let response = if head[0].starts_with("GET /.well-known/openid-configuration") {
+                    serde_json::json!({
+                        "authorization_endpoint": format!("http://{addr}/authorize"),
+                        "token_endpoint": format!("http://{addr}/token"),
+                    })
+                } else {
+                    serde_json::json!({
+                        "access_token": "direct-token",
+                        "token_type": "Bearer",
+                        "expires_in": 3600,
+                        "scope": "openid User.Read",
+                    })
+                }
+                .to_string();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                    response.len()
+                );
+                requests.push((head[0].clone(), String::from_utf8_lossy(&body).to_string()));
+            }
+            requests
+        });
+
+        let backend = DirectOidcBackend::from_json(
+            &serde_json::json!({
+                "msgraph": {
+                    "issuer": format!("http://{addr}/"),
+                    "client_id": "client-1",
+                    "client_secret": "s3cret",
+                    "redirect_base_url": "https://bot.example"
+                }
+            })
+            .to_string(),
+        )
+        .unwrap_or_else(|err| panic!("config: {err}"));
+
+        let consent = OAuthBackend::get_consent_url(
+            &backend,
+            &CallContext::default(),
+            "msgraph",
+            "user-1",
+            &[scope("openid"), scope("User.Read")],
+            "/oauth/callback/msgraph",
+            Some(r#"{"prompt":"consent","state_id":"state-1"}"#.into()),
+        )
+        .unwrap_or_else(|err| panic!("consent: {err}"));
+        assert!(consent.starts_with(&format!("http://{addr}/authorize?response_type=code")));
+        assert!(consent.contains("client_id=client-1"));
+        assert!(
+            consent.contains("redirect_uri=https%3A%2F%2Fbot.example%2Foauth%2Fcallback%2Fmsgraph")
+        );
+        assert!(consent.contains("scope=openid+User.Read"));
+        assert!(consent.contains("prompt=consent"));
+        assert!(consent.contains("state=state-1"));
+        assert!(consent.contains("code_challenge_method=S256"));
+        assert!(!consent.contains("state_id"));
+        let challenge = consent
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("code_challenge="))
+            .unwrap_or_else(|| panic!("challenge in {consent}"));
+        let reserved = OAuthBackend::get_consent_url(
+            &backend,
+            &CallContext::default(),
+            "msgraph",
+            "user-1",
+            &[scope("openid")],
+            "/oauth/callback/msgraph",
+            Some(r#"{"redirect_uri":"https://evil.example"}"#.into()),
+        );
+        assert!(matches!(reserved, Err(OAuthCardError::Validation { .. })));
+
+        // A callback for an authorization this backend never issued is refused
+        // before the provider is contacted.
+        let forged = OAuthCardInput {
+            auth_code: Some("code-123".into()),
+            state_id: Some("state-2".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        let output = logic::handle(&backend, forged).unwrap_or_else(|err| panic!("forged: {err}"));
+        assert_eq!(output.status, OAuthStatus::InvalidState);
+        assert_eq!(output.error_code, Some(ErrorCode::InvalidState));
+
+        let input = OAuthCardInput {
+            auth_code: Some("code-123".into()),
+            state_id: Some("state-1".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        let output =
+            logic::handle(&backend, input.clone()).unwrap_or_else(|err| panic!("complete: {err}"));
+        let header = output
+            .auth_header
+            .unwrap_or_else(|| panic!("header present"));
+        assert_eq!(header.headers[0].1, "Bearer direct-token");
+        // A replayed callback is answered from the store without spending the
+        // code again.
+        let replay = logic::handle(&backend, input).unwrap_or_else(|err| panic!("replay: {err}"));
+        assert_eq!(replay.status, OAuthStatus::Ok);
+        let stored =
+            OAuthBackend::get_token(&backend, &CallContext::default(), "msgraph", "user-1", &[])
+                .unwrap_or_else(|err| panic!("stored: {err}"))
+                .unwrap_or_else(|| panic!("token stored"));
+        assert_eq!(stored.granted_scopes, vec!["openid", "User.Read"]);
+
+        let requests = server.join().unwrap_or_else(|_| panic!("server thread"));
+        assert!(requests[1].0.starts_with("POST /token"));
+        assert!(requests[1].1.contains("grant_type=authorization_code"));
+        assert!(requests[1].1.contains("code=code-123"));
+        assert!(requests[1].1.contains("client_secret=s3cret"));
+        let verifier = requests[1]
+            .1
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("code_verifier="))
+            .unwrap_or_else(|| panic!("verifier in {}", requests[1].1));
+        assert_eq!(
+            URL_SAFE_NO_PAD.encode(sha2::Sha256::digest(verifier.as_bytes())),
+            challenge
+        );
+    }
+
+    #[test]
+    fn correlation_id_is_echoed_and_forwarded_to_broker_calls() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "tok".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            correlation_id: Some("trace-42".into()),
+            ..test_input(OAuthCardMode::EnsureToken)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(output.correlation_id.as_deref(), Some("trace-42"));
+        let calls = backend.calls();
+        assert!(!calls.is_empty());
+        assert!(
+            calls
+                .iter()
+                .all(|call| call.correlation_id.as_deref() == Some("trace-42"))
+        );
+
+        // Errors carry it too, and it is omitted when the caller sent none.
+        let input = OAuthCardInput {
+            correlation_id: Some("trace-43".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("error: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert_eq!(output.correlation_id.as_deref(), Some("trace-43"));
+        let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("plain: {err}"));
+        let json = serde_json::to_value(&output).unwrap_or_else(|err| panic!("json: {err}"));
+        assert!(json.get("correlation_id").is_none());
+    }
+
+    #[test]
+    fn secrets_are_redacted_from_debug_and_on_request_from_outputs() {
+        let token = TokenSet {
+            access_token: "secret-access".into(),
+            refresh_token: Some("secret-refresh".into()),
+            id_token: Some("secret-id".into()),
+            token_type: Some("Bearer".into()),
+            ..Default::default()
+        };
+        let debug = format!("{token:?}");
+        assert!(!debug.contains("secret-"));
+        assert!(debug.contains(REDACTED) && debug.contains("Bearer"));
+        let json = serde_json::to_value(token.redacted()).unwrap_or_default();
+        assert_eq!(json["access_token"], REDACTED);
+        assert_eq!(json["refresh_token"], REDACTED);
+
+        let backend = MockBroker {
+            token: Some(token),
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        let header = output
+            .auth_header
+            .as_ref()
+            .unwrap_or_else(|| panic!("header present"));
+        assert_eq!(header.headers[0].1, "Bearer secret-access");
+        let debug = format!("{output:?}");
+        assert!(!debug.contains("secret-access"));
+
+        let input = OAuthCardInput {
+            redact_secrets: true,
+            ..test_input(OAuthCardMode::EnsureToken)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("redacted: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.auth_header.is_none() && output.auth_injection.is_none());
+        assert!(output.auth_context.is_some());
+    }
+
+    #[test]
+    fn unknown_enum_values_deserialize_to_other() {
+        let input = parse_input(r#"{"mode":"rotate-keys","provider_id":"demo","subject":"u"}"#)
+            .unwrap_or_else(|err| panic!("parse: {err}"));
+        assert_eq!(input.mode, OAuthCardMode::Other("rotate-keys".into()));
+        let output = logic::handle(&MockBroker::default(), input)
+            .unwrap_or_else(|err| panic!("handle: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert_eq!(output.error_code, Some(ErrorCode::Unsupported));
+        assert!(output.error.unwrap_or_default().contains("rotate-keys"));
+
+        let status: OAuthStatus =
+            serde_json::from_str(r#""throttled""#).unwrap_or_else(|err| panic!("status: {err}"));
+        assert_eq!(status, OAuthStatus::Other("throttled".into()));
+        assert_eq!(
+            serde_json::to_string(&status).unwrap_or_default(),
+            r#""throttled""#
+        );
+        let known: OAuthStatus = serde_json::from_str(r#""needs-sign-in""#)
+            .unwrap_or_else(|err| panic!("status: {err}"));
+        assert_eq!(known, OAuthStatus::NeedsSignIn);
+
+        let provider: OauthProvider =
+            serde_json::from_str(r#""gitlab""#).unwrap_or_else(|err| panic!("provider: {err}"));
+        assert_eq!(provider.display_name(), "gitlab");
+    }
+
+    #[test]
+    fn redirect_paths_outside_the_policy_are_rejected_before_consent() {
+        let start = |redirect_path: &str| {
+            let backend = MockBroker {
+                consent_url: "https://login.example/authorize".into(),
+                ..Default::default()
+            };
+            let input = OAuthCardInput {
+                redirect_path: Some(redirect_path.into()),
+                ..test_input(OAuthCardMode::StartSignIn)
+            };
+            let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("{err}"));
+            (output, backend.calls_to("get_consent_url").len())
+        };
+        let (output, consent_calls) = start("/oauth/callback/msgraph?tenant=a");
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert_eq!(consent_calls, 1);
+        for path in [
+            "https://evil.example/cb",
+            "//evil.example/cb",
+            "/oauth/callback/../../admin",
+            "/oauth/callback/%2E%2E/admin",
+            "/oauth/callback\\..\\admin",
+            "/elsewhere/cb",
+        ] {
+            let (output, consent_calls) = start(path);
+            assert_eq!(output.status, OAuthStatus::Error, "{path}");
+            assert_eq!(output.error_code, Some(ErrorCode::ValidationFailed));
+            assert_eq!(
+                output.error_detail.unwrap_or_default()["field"],
+                "redirect_path"
+            );
+            assert_eq!(consent_calls, 0, "{path}");
+        }
+
+        let config = ProviderConfig {
+            redirect_template: Some("/auth/{provider_id}/done".into()),
+            redirect_prefixes: vec!["/hooks/".into()],
+            ..Default::default()
+        };
+        let check = |path: &str| {
+            provider_config::check_redirect_path(Some(&config), "msgraph", path).is_ok()
+        };
+        assert!(check("/hooks/msgraph"));
+        assert!(check("/auth/msgraph/done"));
+        assert!(!check("/auth/other/done"));
+        assert!(!check("/oauth/callback/msgraph"));
+        assert!(!check("/hooks/../admin"));
+    }
+
+    #[cfg(feature = "verify-jwt")]
+    #[test]
+    fn id_tokens_verify_against_the_provider_jwks() {
+        use base64::Engine;
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        use ring::rand::SystemRandom;
+        use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .unwrap_or_else(|err| panic!("generate key: {err}"));
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap_or_else(|err| panic!("load key: {err}"));
+        let point = key_pair.public_key().as_ref();
+        let verification = IdTokenVerification {
+            jwks: Jwks {
+                // A key of another type under the same kid is skipped.
+                keys: vec![
+                    Jwk {
+                        kty: "RSA".into(),
+                        kid: Some("k1".into()),
+                        n: Some("AQAB".into()),
+                        e: Some("AQAB".into()),
+                        ..Default::default()
+                    },
+                    Jwk {
+                        kty: "EC".into(),
+                        kid: Some("k1".into()),
+                        crv: Some("P-256".into()),
+                        x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+                        y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+                        ..Default::default()
+                    },
+                ],
+            },
+            issuer: Some("https://login.example".into()),
+            audience: Some("client-1".into()),
+        };
+        let sign = |kid: &str, claims: serde_json::Value| {
+            let header = URL_SAFE_NO_PAD
+                .encode(serde_json::json!({ "alg": "ES256", "kid": kid }).to_string());
+            let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+            let signed = format!("{header}.{payload}");
+            let signature = key_pair
+                .sign(&rng, signed.as_bytes())
+                .unwrap_or_else(|err| panic!("sign: {err}"));
+            format!("{signed}.{}", URL_SAFE_NO_PAD.encode(signature.as_ref()))
+        };
+        let now = clock::now_secs();
+        let claims = serde_json::json!({
+            "iss": "https://login.example",
+            "aud": ["client-1", "other"],
+            "email": "ada@example.com",
+            "iat": now,
+            "exp": now + 3600
+        });
+        let with = |overrides: serde_json::Value| {
+            let mut claims = claims.clone();
+            for (key, value) in overrides.as_object().into_iter().flatten() {
+                claims[key] = value.clone();
+            }
+            sign("k1", claims)
+        };
+
+        let verified = jwt::verify_id_token(&sign("k1", claims.clone()), &verification)
+            .unwrap_or_else(|err| panic!("verify: {err}"));
+        assert_eq!(verified["email"], "ada@example.com");
+
+        let token = sign("k1", claims.clone());
+        let (signed, signature) = token.rsplit_once('.').unwrap_or_else(|| panic!("jwt"));
+        let (header, _) = signed.split_once('.').unwrap_or_else(|| panic!("jwt"));
+        let forged = URL_SAFE_NO_PAD.encode(
+            serde_json::json!({ "iss": "https://login.example", "aud": "client-1", "email": "eve@example.com" })
+                .to_string(),
+        );
+        let rejected = [
+            format!("{header}.{forged}.{signature}"),
+            sign("k2", claims.clone()),
+            with(serde_json::json!({ "iss": "https://evil.example" })),
+            with(serde_json::json!({ "aud": "client-2" })),
+            with(serde_json::json!({ "exp": now - 3600 })),
+            with(serde_json::json!({ "exp": null })),
+            with(serde_json::json!({ "iat": now + 3600 })),
+            with(serde_json::json!({ "iat": null })),
+            with(serde_json::json!({ "nbf": now + 3600 })),
+        ];
+        for token in rejected {
+            let err = jwt::verify_id_token(&token, &verification)
+                .err()
+                .unwrap_or_else(|| panic!("{token} should be rejected"));
+            assert_eq!(err.error_code(), ErrorCode::InvalidInput, "{err}");
+        }
+    }
+
+    #[test]
+    fn unsafe_consent_urls_become_error_cards() {
+        let start = |consent_url: &str| {
+            let backend = MockBroker {
+                consent_url: consent_url.into(),
+                ..Default::default()
+            };
+            logic::handle(&backend, test_input(OAuthCardMode::StartSignIn))
+                .unwrap_or_else(|err| panic!("{err}"))
+        };
+        for url in [
+            "https://login.example/authorize?x=1",
+            "http://localhost:8080/authorize",
+        ] {
+            assert_eq!(start(url).status, OAuthStatus::Ok, "{url}");
+        }
+        for url in [
+            "javascript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "http://login.example/authorize",
+            "https://user:pw@login.example/authorize",
+            "https://login.example/a b",
+            "//login.example/authorize",
+        ] {
+            let output = start(url);
+            assert_eq!(output.status, OAuthStatus::Error, "{url}");
+            assert_eq!(output.error_code, Some(ErrorCode::BackendError));
+            let card = output.card.unwrap_or_else(|| panic!("error card"));
+            assert!(
+                !card
+                    .actions
+                    .iter()
+                    .any(|action| matches!(action, Action::OpenUrl { .. })),
+                "{url}"
+            );
+        }
+
+        let config = ProviderConfig {
+            consent_hosts: vec!["login.example".into(), "*.okta.example".into()],
+            ..Default::default()
+        };
+        let check = |url: &str| provider_config::check_consent_url(Some(&config), url).is_ok();
+        assert!(check("https://LOGIN.example/authorize"));
+        assert!(check("https://acme.okta.example:443/authorize"));
+        assert!(!check("https://okta.example/authorize"));
+        assert!(!check("https://evil-okta.example/authorize"));
+        assert!(!check("https://login.example.evil/authorize"));
+    }
+
+    #[test]
+    fn tokens_are_only_handed_out_under_the_tenant_they_were_issued_for() {
+        let backend = InMemoryBroker::new();
+        let in_tenant = |mode: OAuthCardMode, tenant: Option<&str>| OAuthCardInput {
+            tenant: tenant.map(str::to_string),
+            team: Some("team-1".into()),
+            ..test_input(mode)
+        };
+        let started = logic::handle(
+            &backend,
+            in_tenant(OAuthCardMode::StartSignIn, Some("tenant-1")),
+        )
+        .unwrap_or_else(|err| panic!("start: {err}"));
+        let completed = logic::handle(
+            &backend,
+            OAuthCardInput {
+                state_id: started.state_id,
+                auth_code: Some("code".into()),
+                ..in_tenant(OAuthCardMode::CompleteSignIn, Some("tenant-1"))
+            },
+        )
+        .unwrap_or_else(|err| panic!("complete: {err}"));
+        assert_eq!(completed.status, OAuthStatus::Ok);
+        let stored = backend
+            .stored("msgraph", "user-1")
+            .unwrap_or_else(|| panic!("stored token"));
+        assert_eq!(
+            stored.extra.unwrap_or_default()[TOKEN_BINDING_KEY],
+            serde_json::json!({ "tenant": "tenant-1", "team": "team-1" })
+        );
+
+        let same = logic::handle(
+            &backend,
+            in_tenant(OAuthCardMode::EnsureToken, Some("tenant-1")),
+        )
+        .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(same.status, OAuthStatus::Ok);
+        assert!(same.auth_header.is_some());
+
+        for mode in [OAuthCardMode::EnsureToken, OAuthCardMode::StatusCard] {
+            for tenant in [Some("tenant-2"), None] {
+                let other = logic::handle(&backend, in_tenant(mode.clone(), tenant))
+                    .unwrap_or_else(|err| panic!("{mode:?}: {err}"));
+                assert_eq!(other.status, OAuthStatus::TenantMismatch, "{mode:?}");
+                assert_eq!(other.error_code, Some(ErrorCode::TenantMismatch));
+                assert!(other.auth_header.is_none());
+                assert!(other.auth_injection.is_none());
+            }
+        }
+
+        // Tokens stored unbound (earlier versions, brokers that drop the
+        // key) are bound to the first tenant using them. On wasm every input
+        // has the host's tenant.
+        let unbound = InMemoryBroker::new();
+        unbound.seed(
+            "msgraph",
+            "user-1",
+            TokenSet {
+                access_token: "unbound".into(),
+                ..Default::default()
+            },
+        );
+        let host = |tenant: &str| CallContext {
+            tenant: Some(tenant.into()),
+            ..Default::default()
+        };
+        for mode in [
+            OAuthCardMode::StatusCard,
+            OAuthCardMode::EnsureToken,
+            OAuthCardMode::TokenExchange,
+        ] {
+            let first =
+                logic::handle_with_context(&unbound, &host("tenant-1"), test_input(mode.clone()))
+                    .unwrap_or_else(|err| panic!("{mode:?}: {err}"));
+            assert_ne!(first.status, OAuthStatus::TenantMismatch, "{mode:?}");
+        }
+        let bound = unbound
+            .stored("msgraph", "user-1")
+            .unwrap_or_else(|| panic!("stored token"));
+        assert_eq!(
+            bound.extra.unwrap_or_default()[TOKEN_BINDING_KEY],
+            serde_json::json!({ "tenant": "tenant-1", "team": null })
+        );
+        let elsewhere = logic::handle_with_context(
+            &unbound,
+            &host("tenant-2"),
+            test_input(OAuthCardMode::EnsureToken),
+        )
+        .unwrap_or_else(|err| panic!("elsewhere: {err}"));
+        assert_eq!(elsewhere.status, OAuthStatus::TenantMismatch);
+        assert!(elsewhere.auth_header.is_none());
+
+        let unscoped = MockBroker {
+            token: Some(TokenSet {
+                access_token: "unbound".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let output = logic::handle(&unscoped, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("unscoped: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(unscoped.calls_to("store_token").is_empty());
+    }
+
+    #[test]
+    fn token_material_is_wiped_on_zeroize() {
+        use zeroize::Zeroize;
+
+        let mut token = TokenSet {
+            access_token: "access".into(),
+            refresh_token: Some("refresh".into()),
+            id_token: Some("id".into()),
+            expires_at: Some(1),
+            ..Default::default()
+        };
+        token.zeroize();
+        assert!(token.access_token.is_empty());
+        assert_eq!(
+            (token.refresh_token, token.id_token, token.expires_at),
+            (None, None, None)
+        );
+
+        let mut output = logic::handle(
+            &MockBroker {
+                token: Some(TokenSet {
+                    access_token: "secret".into(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            test_input(OAuthCardMode::EnsureToken),
+        )
+        .unwrap_or_else(|err| panic!("ensure: {err}"));
+        output.provider_results.push(ProviderResult {
+            provider_id: "msgraph".into(),
+            status: OAuthStatus::Ok,
+            auth_context: None,
+            auth_header: output.auth_header.clone(),
+            auth_injection: output.auth_injection.clone(),
+            error: None,
+            dropped_scopes: Vec::new(),
+        });
+        assert!(output.auth_header.is_some());
+        output.zeroize_secrets();
+        assert!(output.auth_header.is_none());
+        assert!(output.auth_injection.is_none());
+        assert!(output.provider_results[0].auth_header.is_none());
+    }
+
+    #[test]
+    fn dpop_token_types_get_the_dpop_authorization_scheme() {
+        for (token_type, expected) in [
+            ("DPoP", "DPoP tok"),
+            ("dpop", "DPoP tok"),
+            ("bearer", "Bearer tok"),
+        ] {
+            let backend = InMemoryBroker::new();
+            backend.seed(
+                "msgraph",
+                "user-1",
+                TokenSet {
+                    access_token: "tok".into(),
+                    token_type: Some(token_type.into()),
+                    ..Default::default()
+                },
+            );
+            let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+                .unwrap_or_else(|err| panic!("ensure: {err}"));
+            let header = output
+                .auth_header
+                .unwrap_or_else(|| panic!("{token_type}: auth header"));
+            assert_eq!(
+                header.headers,
+                vec![("Authorization".to_string(), expected.to_string())],
+                "{token_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn sign_in_hints_reach_the_broker_and_the_card() {
+        let backend = MockBroker {
+            consent_url: "https://login.example/authorize".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            login_hint: Some("ada@example.com".into()),
+            domain_hint: Some("example.com".into()),
+            prompt: Some(OauthPrompt::Login),
+            extra_json: Some(serde_json::json!({ "prompt": "consent", "login_hint": "old" })),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("start: {err}"));
+
+        let consent = backend.calls_to("get_consent_url");
+        let extra: serde_json::Value =
+            serde_json::from_str(consent[0].extra_json.as_deref().unwrap_or_default())
+                .unwrap_or_else(|err| panic!("extra json: {err}"));
+        assert_eq!(extra["login_hint"], "ada@example.com");
+        assert_eq!(extra["domain_hint"], "example.com");
+        assert_eq!(extra["prompt"], "login");
+
+        let card = output.card.unwrap_or_else(|| panic!("card"));
+        let oauth = card.oauth.unwrap_or_else(|| panic!("oauth"));
+        assert_eq!(oauth.prompt, Some(OauthPrompt::Login));
+        let metadata = oauth.metadata.unwrap_or_default();
+        assert_eq!(metadata["login_hint"], "ada@example.com");
+        assert_eq!(metadata["domain_hint"], "example.com");
+        assert_eq!(metadata["prompt"], "login");
+    }
+
+    #[test]
+    fn admin_consent_is_requested_then_checked_before_members_sign_in() {
+        let backend = InMemoryBroker::new();
+        let input = |mode: OAuthCardMode| OAuthCardInput {
+            extra_json: Some(serde_json::json!({ "tenant": "contoso.com" })),
+            scopes: vec![scope("User.Read.All")],
+            ..test_input(mode)
+        };
+
+        let requested = logic::handle(&backend, input(OAuthCardMode::AdminConsent))
+            .unwrap_or_else(|err| panic!("admin consent: {err}"));
+        assert_eq!(requested.status, OAuthStatus::NeedsConsent);
+        assert!(requested.state_id.is_some());
+        let card = requested.card.unwrap_or_else(|| panic!("card"));
+        assert!(matches!(
+            &card.actions[0],
+            Action::OpenUrl { url, .. } if url.starts_with("https://msgraph.memory.invalid/contoso.com/adminconsent")
+        ));
+        assert!(matches!(
+            &card.actions[1],
+            Action::PostBack { data, .. } if data["mode"] == "check-admin-consent"
+        ));
+        let metadata = card
+            .oauth
+            .and_then(|oauth| oauth.metadata)
+            .unwrap_or_default();
+        assert_eq!(metadata["audience"], "tenant_admins");
+        assert_eq!(metadata["tenant"], "contoso.com");
+
+        let pending = logic::handle(&backend, input(OAuthCardMode::CheckAdminConsent))
+            .unwrap_or_else(|err| panic!("check: {err}"));
+        assert_eq!(pending.status, OAuthStatus::NeedsConsent);
+        assert!(
+            pending
+                .card
+                .and_then(|card| card.text)
+                .unwrap_or_default()
+                .starts_with("Admin consent has not been granted yet.")
+        );
+
+        backend.grant_admin_consent("msgraph", "contoso.com");
+        let granted = logic::handle(&backend, input(OAuthCardMode::CheckAdminConsent))
+            .unwrap_or_else(|err| panic!("check: {err}"));
+        assert_eq!(granted.status, OAuthStatus::Ok);
+        let card = granted.card.unwrap_or_else(|| panic!("card"));
+        assert!(matches!(
+            &card.actions[0],
+            Action::PostBack { data, .. } if data["mode"] == "start-sign-in"
+        ));
+    }
+
+    #[test]
+    fn admin_consent_templates_encode_their_values() {
+        let url = provider_config::admin_consent_url(
+            "https://login.microsoftonline.com/{tenant}/v2.0/adminconsent?client_id=app&scope={scopes}&redirect_uri=https://bot.example{redirect_path}&state={state}",
+            "contoso.com/../x",
+            &[
+                scope("https://graph.microsoft.com/User.Read"),
+                scope("offline_access"),
+            ],
+            "/oauth/callback/msgraph",
+            "s1",
+        );
+        assert_eq!(
+            url,
+            "https://login.microsoftonline.com/contoso.com%2F..%2Fx/v2.0/adminconsent?client_id=app&scope=https%3A%2F%2Fgraph.microsoft.com%2FUser.Read%20offline_access&redirect_uri=https://bot.example%2Foauth%2Fcallback%2Fmsgraph&state=s1"
+        );
+    }
+
+    #[test]
+    fn sign_ins_below_the_requested_assurance_require_step_up() {
+        use base64::Engine;
+        let now = crate::clock::now_secs();
+        let complete = |claims: serde_json::Value| {
+            let payload =
+                base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string());
+            let backend = MockBroker {
+                token: Some(TokenSet {
+                    access_token: "tok".into(),
+                    id_token: Some(format!("eyJhbGciOiJub25lIn0.{payload}.sig")),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let input = OAuthCardInput {
+                auth_code: Some("code".into()),
+                state_id: Some("state-1".into()),
+                acr_values: Some("mfa phr".into()),
+                max_age: Some(600),
+                ..test_input(OAuthCardMode::CompleteSignIn)
+            };
+            let output =
+                logic::handle(&backend, input).unwrap_or_else(|err| panic!("complete: {err}"));
+            (output, backend.calls_to("revoke_token").len())
+        };
+
+        let (strong, revoked) = complete(serde_json::json!({ "acr": "mfa", "auth_time": now }));
+        assert_eq!(strong.status, OAuthStatus::Ok);
+        assert_eq!(revoked, 0);
+
+        for claims in [
+            serde_json::json!({ "acr": "pwd", "auth_time": now }),
+            serde_json::json!({ "acr": "mfa", "auth_time": now - 3_600 }),
+            serde_json::json!({ "auth_time": now }),
+        ] {
+            let (weak, revoked) = complete(claims.clone());
+            assert_eq!(weak.status, OAuthStatus::StepUpRequired, "{claims}");
+            // The broker already stored the rejected token; it must not linger.
+            assert_eq!(revoked, 1);
+            assert_eq!(weak.error_code, Some(ErrorCode::StepUpRequired));
+            assert!(weak.auth_header.is_none());
+            let card = weak.card.unwrap_or_else(|| panic!("card"));
+            assert!(matches!(
+                &card.actions[0],
+                Action::PostBack { data, .. }
+                    if data["mode"] == "start-sign-in"
+                        && data["acr_values"] == "mfa phr"
+                        && data["max_age"] == 600
+            ));
+        }
+    }
+
+    #[test]
+    fn step_up_requirements_are_sent_with_consent() {
+        let backend = MockBroker {
+            consent_url: "https://login.example/authorize".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            acr_values: Some("mfa".into()),
+            max_age: Some(0),
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        logic::handle(&backend, input).unwrap_or_else(|err| panic!("start: {err}"));
+        let consent = backend.calls_to("get_consent_url");
+        let extra: serde_json::Value =
+            serde_json::from_str(consent[0].extra_json.as_deref().unwrap_or_default())
+                .unwrap_or_else(|err| panic!("extra json: {err}"));
+        assert_eq!(extra["acr_values"], "mfa");
+        assert_eq!(extra["max_age"], 0);
+    }
+
+    #[test]
+    fn resource_and_audience_reach_consent_exchange_and_card() {
+        let backend = MockBroker {
+            consent_url: "https://login.example/authorize".into(),
+            token: Some(TokenSet {
+                access_token: "tok".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = |mode: OAuthCardMode| OAuthCardInput {
+            resource: Some("https://api.example".into()),
+            audience: Some("api://example".into()),
+            extra_json: Some(serde_json::json!({ "resource": "https://other.example" })),
+            ..test_input(mode)
+        };
+
+        let started = logic::handle(&backend, input(OAuthCardMode::StartSignIn))
+            .unwrap_or_else(|err| panic!("start: {err}"));
+        let oauth = started
+            .card
+            .and_then(|card| card.oauth)
+            .unwrap_or_else(|| panic!("oauth card"));
+        assert_eq!(oauth.resource.as_deref(), Some("https://api.example"));
+        let consent = backend.calls_to("get_consent_url");
+        let extra: serde_json::Value =
+            serde_json::from_str(consent[0].extra_json.as_deref().unwrap_or_default())
+                .unwrap_or_else(|err| panic!("extra json: {err}"));
+        assert_eq!(extra["resource"], "https://api.example");
+        assert_eq!(extra["audience"], "api://example");
+
+        logic::handle(
+            &backend,
+            OAuthCardInput {
+                auth_code: Some("code".into()),
+                ..input(OAuthCardMode::CompleteSignIn)
+            },
+        )
+        .unwrap_or_else(|err| panic!("complete: {err}"));
+        let exchange = backend.calls_to("exchange_code");
+        let extra: serde_json::Value =
+            serde_json::from_str(exchange[0].extra_json.as_deref().unwrap_or_default())
+                .unwrap_or_else(|err| panic!("extra json: {err}"));
+        assert_eq!(
+            extra,
+            serde_json::json!({ "resource": "https://api.example", "audience": "api://example" })
+        );
+    }
+
+    #[test]
+    fn token_exchange_returns_the_derived_token() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "user-token".into(),
+                expires_at: Some(clock::now_secs() + 30),
+                ..Default::default()
+            }),
+            refreshed_token: Some(TokenSet {
+                access_token: "refreshed-token".into(),
+                expires_at: Some(clock::now_secs() + 3_600),
+                ..Default::default()
+            }),
+            exchanged_token: Some(TokenSet {
+                access_token: "internal-token".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![scope("orders.read")],
+            audience: Some("orders-service".into()),
+            ..test_input(OAuthCardMode::TokenExchange)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("exchange: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(output.card.is_none());
+        let header = output.auth_header.unwrap_or_else(|| panic!("auth header"));
+        assert_eq!(header.headers[0].1, "Bearer internal-token");
+        // The stored subject token is looked up and refreshed for the scopes.
+        assert_eq!(
+            backend.calls_to("get_token")[0].scopes,
+            vec![scope("orders.read")]
+        );
+        assert_eq!(backend.calls_to("refresh_token").len(), 1);
+        let calls = backend.calls_to("exchange_token");
+        assert_eq!(calls[0].scopes, vec![scope("orders.read")]);
+        assert_eq!(
+            calls[0].extra_json.as_deref(),
+            Some(r#"{"audience":"orders-service"}"#)
+        );
+
+        let unconnected = MockBroker::default();
+        let output = logic::handle(&unconnected, test_input(OAuthCardMode::TokenExchange))
+            .unwrap_or_else(|err| panic!("exchange: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        assert_eq!(output.error_code, Some(ErrorCode::ValidationFailed));
+    }
+
+    #[test]
+    fn log_records_mask_tokens_in_fields_and_text() {
+        let fields = logging::redact_fields(serde_json::json!({
+            "provider_id": "msgraph",
+            "refresh_token": "rt-1",
+            "nested": { "Code": "auth-code", "note": "header was DPoP proof-1 ok" },
+            "jwt": ["eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJ1In0.sig"],
+        }));
+        assert_eq!(fields["provider_id"], "msgraph");
+        assert_eq!(fields["refresh_token"], REDACTED);
+        assert_eq!(fields["nested"]["Code"], REDACTED);
+        assert_eq!(fields["nested"]["note"], "header was DPoP [redacted] ok");
+        assert_eq!(fields["jwt"][0], REDACTED);
+        assert_eq!(
+            logging::redact_text("rejected (eyJa.eyJb.c) for Bearer"),
+            "rejected [redacted] for Bearer"
+        );
+    }
+
+    #[test]
+    fn debug_inputs_get_diagnostics_without_secrets() {
+        let backend = MockBroker {
+            consent_url: "https://login.example/authorize".into(),
+            ..Default::default()
+        };
+        let input = OAuthCardInput {
+            scopes: vec![
+                scope("Mail.Read"),
+                scope("Mail.Read"),
+                scope("offline_access"),
+            ],
+            auth_code: Some("secret-code".into()),
+            debug: true,
+            ..test_input(OAuthCardMode::StartSignIn)
+        };
+        let output = logic::handle(&backend, input).unwrap_or_else(|err| panic!("start: {err}"));
+        let diagnostics = output.diagnostics.unwrap_or_else(|| panic!("diagnostics"));
+        assert_eq!(diagnostics.provider, OauthProvider::Microsoft);
+        assert!(!diagnostics.provider_configured);
+        assert_eq!(
+            diagnostics.redirect_path.as_deref(),
+            Some("/oauth/callback/msgraph")
+        );
+        assert_eq!(diagnostics.requested_scopes.len(), 3);
+        assert_eq!(
+            diagnostics.normalized_scopes,
+            vec![scope("Mail.Read"), scope("offline_access")]
+        );
+        assert_eq!(diagnostics.broker_calls, ["get_consent_url"]);
+        let json = serde_json::to_string(&diagnostics).unwrap_or_default();
+        assert!(!json.contains("secret-code"));
+
+        let plain = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert!(plain.diagnostics.is_none());
+    }
+
+    #[test]
+    fn host_call_context_reaches_broker_calls_and_its_tenant_wins() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "tok".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let host = CallContext {
+            correlation_id: Some("host-trace".into()),
+            tenant: Some("host-tenant".into()),
+            deadline_unix_ms: Some(u64::MAX),
+            ..CallContext::default()
+        };
+        let output =
+            logic::handle_with_context(&backend, &host, test_input(OAuthCardMode::EnsureToken))
+                .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(output.correlation_id.as_deref(), Some("host-trace"));
+        let call = &backend.calls()[0];
+        assert_eq!(call.correlation_id.as_deref(), Some("host-trace"));
+        assert_eq!(call.tenant.as_deref(), Some("host-tenant"));
+
+        // The input may name the host's tenant and its own correlation id...
+        let input = OAuthCardInput {
+            correlation_id: Some("input-trace".into()),
+            tenant: Some("host-tenant".into()),
+            ..test_input(OAuthCardMode::EnsureToken)
+        };
+        logic::handle_with_context(&backend, &host, input)
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        let call = backend.calls().pop().unwrap_or_else(|| panic!("call"));
+        assert_eq!(call.correlation_id.as_deref(), Some("input-trace"));
+        assert_eq!(call.tenant.as_deref(), Some("host-tenant"));
+
+        // ...but not another tenant.
+        let calls = backend.calls().len();
+        let input = OAuthCardInput {
+            tenant: Some("acme".into()),
+            ..test_input(OAuthCardMode::EnsureToken)
+        };
+        let output = logic::handle_with_context(&backend, &host, input)
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(output.status, OAuthStatus::TenantMismatch);
+        assert_eq!(output.error_code, Some(ErrorCode::TenantMismatch));
+        assert_eq!(backend.calls().len(), calls);
+    }
+
+    #[test]
+    fn validation_reports_every_violation_at_once() {
+        let response = handle_message_with_backend(
+            &MockBroker::default(),
+            "invoke",
+            r#"{"mode":"status-card","provider_id":"demo","scopes":["bad scope\u0001"],"allow_auto_sign_in":"yes"}"#,
+        );
+        let output: OAuthCardOutput =
+            serde_json::from_str(&response).unwrap_or_else(|err| panic!("valid json: {err}"));
+        assert_eq!(output.error_code, Some(ErrorCode::ValidationFailed));
+        let fields: Vec<&str> = output
+            .validation_errors
+            .iter()
+            .map(|error| error.field.as_str())
+            .collect();
+        assert_eq!(fields, ["subject", "allow_auto_sign_in", "scopes"]);
+
+        let input = OAuthCardInput {
+            provider_id: " ".into(),
+            redirect_path: Some("https://evil.example/cb".into()),
+            ..test_input(OAuthCardMode::CompleteSignIn)
+        };
+        let output = logic::handle(&MockBroker::default(), input)
+            .unwrap_or_else(|err| panic!("complete: {err}"));
+        assert_eq!(output.status, OAuthStatus::Error);
+        let fields: Vec<&str> = output
+            .validation_errors
+            .iter()
+            .map(|error| error.field.as_str())
+            .collect();
+        assert_eq!(fields, ["provider_id", "auth_code", "redirect_path"]);
+        assert_eq!(
+            output
+                .error_detail
+                .as_ref()
+                .map(|detail| detail["errors"].as_array().map(Vec::len)),
+            Some(Some(3))
+        );
+
+        // A single violation keeps the plain field error.
+        let output = logic::handle(
+            &MockBroker::default(),
+            test_input(OAuthCardMode::PollSignIn),
+        )
+        .unwrap_or_else(|err| panic!("poll: {err}"));
+        assert_eq!(
+            output.error_detail.as_ref().map(|d| &d["field"]),
+            Some(&serde_json::json!("state_id"))
+        );
+        assert_eq!(output.validation_errors.len(), 1);
+    }
+
+    #[test]
+    fn provider_registry_feeds_cards_and_consent_requests() {
+        let info = provider_info("M365").unwrap_or_else(|| panic!("alias known"));
+        assert_eq!(info.id, "microsoft");
+        assert!(provider_info("acme-sso").is_none());
+        assert!(PROVIDERS.iter().all(|info| {
+            info.icon_url.starts_with("https://")
+                && (info.icon_url.ends_with(".svg") || info.icon_url.ends_with(".png"))
+        }));
+
+        let backend = MockBroker {
+            consent_url: "https://accounts.example/authorize".into(),
+            ..Default::default()
+        };
+        let start = |provider_id: &str, tenant: Option<&str>| {
+            let output = logic::handle(
+                &backend,
+                OAuthCardInput {
+                    provider_id: provider_id.into(),
+                    tenant: tenant.map(str::to_string),
+                    ..test_input(OAuthCardMode::StartSignIn)
+                },
+            )
+            .unwrap_or_else(|err| panic!("start {provider_id}: {err}"));
+            let call = backend
+                .calls_to("get_consent_url")
+                .pop()
+                .unwrap_or_else(|| panic!("consent requested"));
+            let extra: serde_json::Value =
+                serde_json::from_str(call.extra_json.as_deref().unwrap_or("{}"))
+                    .unwrap_or_default();
+            (
+                output.card.unwrap_or_else(|| panic!("card")),
+                call.scopes,
+                extra,
+            )
+        };
+
+        let (card, scopes, extra) = start("google", None);
+        assert_eq!(scopes, ["openid", "email", "profile"]);
+        assert_eq!(extra["access_type"], "offline");
+        assert_eq!(
+            card.images[0].url,
+            "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/google.svg"
+        );
+
+        // The input's tenant is the Greentic one; only the provider config
+        // pins the identity provider's.
+        let (_, scopes, extra) = start("msgraph", Some("contoso.onmicrosoft.com"));
+        assert!(scopes.iter().any(|scope| scope == "offline_access"));
+        assert!(extra.get("tenant").is_none(), "{extra}");
+        let (_, _, extra) = start("slack", Some("acme"));
+        assert!(extra.get("team").is_none(), "{extra}");
+
+        // Only new grants ask for offline access.
+        let lookup = providers::with_defaults(OAuthCardInput {
+            provider_id: "google".into(),
+            ..test_input(OAuthCardMode::EnsureToken)
+        });
+        assert_eq!(lookup.extra_json, None);
+
+        let (card, _, _) = start("gitlab", None);
+        assert_eq!(card.images[0].alt.as_deref(), Some("GitLab logo"));
+        assert_eq!(OauthProvider::from_id("gitlab").display_name(), "gitlab");
+        for known in OauthProvider::KNOWN {
+            let info = provider_info(known.id()).unwrap_or_else(|| panic!("{known:?} registered"));
+            assert_eq!(known.display_name(), info.display_name);
+        }
+    }
+
+    #[test]
+    fn custom_providers_use_discovered_oidc_endpoints() {
+        let endpoints = OidcEndpoints {
+            issuer: "https://idp.acme.example".into(),
+            authorization_endpoint: "https://idp.acme.example/authorize".into(),
+            token_endpoint: "https://idp.acme.example/token".into(),
+            end_session_endpoint: Some("https://idp.acme.example/logout".into()),
+            ..Default::default()
+        };
+        let backend = MockBroker {
+            consent_url: "https://idp.acme.example/authorize?client_id=bot".into(),
+            oidc_endpoints: Some(endpoints.clone()),
+            ..Default::default()
+        };
+        let input = |mode: OAuthCardMode, issuer: &str| OAuthCardInput {
+            provider_id: "acme-sso".into(),
+            extra_json: Some(serde_json::json!({ "issuer": issuer })),
+            ..test_input(mode)
+        };
+
+        let output = logic::handle(
+            &backend,
+            input(OAuthCardMode::StartSignIn, "https://idp.acme.example/"),
+        )
+        .unwrap_or_else(|err| panic!("start: {err}"));
+        let consent = backend.calls_to("get_consent_url");
+        let extra: serde_json::Value =
+            serde_json::from_str(consent[0].extra_json.as_deref().unwrap_or("{}"))
+                .unwrap_or_default();
+        assert_eq!(
+            extra["oidc_endpoints"]["authorization_endpoint"],
+            "https://idp.acme.example/authorize"
+        );
+        let metadata = output
+            .card
+            .and_then(|card| card.oauth)
+            .and_then(|oauth| oauth.metadata)
+            .unwrap_or_default();
+        assert_eq!(
+            metadata["oidc_endpoints"]["token_endpoint"],
+            "https://idp.acme.example/token"
+        );
+
+        // Cached per issuer: logout does not fetch the document again and
+        // falls back to its end-session endpoint.
+        let cached = MockBroker::default();
+        let output = logic::handle(
+            &cached,
+            input(OAuthCardMode::Logout, "https://idp.acme.example"),
+        )
+        .unwrap_or_else(|err| panic!("logout: {err}"));
+        assert!(cached.calls_to("discover_oidc").is_empty());
+        let card = output.card.unwrap_or_else(|| panic!("card present"));
+        assert!(matches!(
+            card.actions.first(),
+            Some(crate::model::Action::OpenUrl { url, .. }) if url == "https://idp.acme.example/logout"
+        ));
+
+        let output = logic::handle(
+            &backend,
+            input(OAuthCardMode::StartSignIn, "https://idp.other.example"),
+        )
+        .unwrap_or_else(|err| panic!("mismatched issuer: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        let consent = backend.calls_to("get_consent_url");
+        assert!(
+            !consent[1]
+                .extra_json
+                .as_deref()
+                .unwrap_or_default()
+                .contains("oidc_endpoints")
+        );
+
+        // Modes without endpoint needs skip discovery.
+        let status = MockBroker::default();
+        logic::handle(
+            &status,
+            input(OAuthCardMode::StatusCard, "https://idp.status.example"),
+        )
+        .unwrap_or_else(|err| panic!("status: {err}"));
+        assert!(status.calls_to("discover_oidc").is_empty());
+
+        // A document naming no issuer is refused, and the failure is cached.
+        let anonymous = MockBroker {
+            oidc_endpoints: Some(OidcEndpoints {
+                issuer: String::new(),
+                ..endpoints
+            }),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            let output = logic::handle(
+                &anonymous,
+                input(OAuthCardMode::StartSignIn, "https://idp.anonymous.example"),
+            )
+            .unwrap_or_else(|err| panic!("anonymous issuer: {err}"));
+            assert_eq!(output.status, OAuthStatus::Ok);
+        }
+        assert_eq!(anonymous.calls_to("discover_oidc").len(), 1);
+        assert!(anonymous.calls_to("get_consent_url").iter().all(|call| {
+            !call
+                .extra_json
+                .as_deref()
+                .unwrap_or_default()
+                .contains("oidc_endpoints")
+        }));
+    }
+
+    #[test]
+    fn microsoft_sign_ins_target_and_check_the_tenant_authority() {
+        use base64::Engine;
+        const CONTOSO: &str = "72f988bf-86f1-41af-91ab-2d7cd011db47";
+        let authority = |tenant: Option<&str>| microsoft_authority(tenant).ok();
+        assert_eq!(
+            authority(Some("Organizations")).as_deref(),
+            Some("organizations")
+        );
+        assert_eq!(
+            authority(Some(&CONTOSO.to_uppercase())).as_deref(),
+            Some(CONTOSO)
+        );
+        assert_eq!(authority(None).as_deref(), Some("common"));
+        assert!(matches!(
+            microsoft_authority(Some("tenant-1")),
+            Err(OAuthCardError::Validation { field, .. }) if field == "extra_json.tenant"
+        ));
+        let unknown = logic::handle(
+            &MockBroker::default(),
+            OAuthCardInput {
+                extra_json: Some(serde_json::json!({ "tenant": "tenant-1" })),
+                ..test_input(OAuthCardMode::StartSignIn)
+            },
+        )
+        .unwrap_or_else(|err| panic!("unknown tenant: {err}"));
+        assert_eq!(unknown.status, OAuthStatus::Error);
+
+        let backend = MockBroker::default();
+        let output = logic::handle(
+            &backend,
+            OAuthCardInput {
+                extra_json: Some(serde_json::json!({ "tenant": "organizations" })),
+                ..test_input(OAuthCardMode::StartSignIn)
+            },
+        )
+        .unwrap_or_else(|err| panic!("start: {err}"));
+        let call = backend
+            .calls_to("get_consent_url")
+            .pop()
+            .unwrap_or_else(|| panic!("consent requested"));
+        let extra: serde_json::Value =
+            serde_json::from_str(call.extra_json.as_deref().unwrap_or("{}")).unwrap_or_default();
+        assert_eq!(extra["tenant"], "organizations");
+        let metadata = output
+            .card
+            .and_then(|card| card.oauth)
+            .and_then(|oauth| oauth.metadata)
+            .unwrap_or_default();
+        assert_eq!(metadata["tenant"], "organizations");
+        assert_eq!(
+            metadata["authorize_url"],
+            "https://login.microsoftonline.com/organizations/oauth2/v2.0/authorize"
+        );
+
+        // Domains resolve through discovery of their issuer, which names
+        // the tenant id.
+        let contoso_issuer = OidcEndpoints {
+            issuer: format!("https://login.microsoftonline.com/{CONTOSO}/v2.0"),
+            ..Default::default()
+        };
+        let complete_with =
+            |tenant: &str, tid: Option<&str>, discovered: Option<&OidcEndpoints>| {
+                let claims = serde_json::json!({ "sub": "abc-123", "tid": tid });
+                let payload =
+                    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string());
+                let backend = MockBroker {
+                    token: Some(TokenSet {
+                        access_token: "tok".into(),
+                        id_token: Some(format!("eyJhbGciOiJub25lIn0.{payload}.sig")),
+                        ..Default::default()
+                    }),
+                    oidc_endpoints: discovered.cloned(),
+                    ..Default::default()
+                };
+                let input = OAuthCardInput {
+                    auth_code: Some("code".into()),
+                    state_id: Some("state-1".into()),
+                    extra_json: Some(serde_json::json!({ "tenant": tenant })),
+                    ..test_input(OAuthCardMode::CompleteSignIn)
+                };
+                logic::handle(&backend, input)
+                    .unwrap_or_else(|err| panic!("complete: {err}"))
+                    .status
+            };
+        let complete = |tenant: &str, tid: &str| complete_with(tenant, Some(tid), None);
+        assert_eq!(complete(CONTOSO, CONTOSO), OAuthStatus::Ok);
+        assert_eq!(complete("organizations", CONTOSO), OAuthStatus::Ok);
+        assert_eq!(
+            complete("consumers", MICROSOFT_CONSUMER_TENANT),
+            OAuthStatus::Ok
+        );
+        for (tenant, tid) in [
+            (CONTOSO, "00000000-0000-0000-0000-000000000001"),
+            ("organizations", MICROSOFT_CONSUMER_TENANT),
+            ("consumers", CONTOSO),
+        ] {
+            assert_eq!(
+                complete(tenant, tid),
+                OAuthStatus::TenantMismatch,
+                "{tenant}"
+            );
+        }
+
+        assert_eq!(
+            complete_with("contoso.com", Some(CONTOSO), Some(&contoso_issuer)),
+            OAuthStatus::Ok
+        );
+        assert_eq!(
+            complete_with(
+                "fabrikam.com",
+                Some(MICROSOFT_CONSUMER_TENANT),
+                Some(&contoso_issuer)
+            ),
+            OAuthStatus::TenantMismatch
+        );
+        assert_eq!(
+            complete_with("contoso.com", Some(CONTOSO), None),
+            OAuthStatus::TenantMismatch
+        );
+        assert_eq!(complete_with("common", None, None), OAuthStatus::Ok);
+        assert_eq!(
+            complete_with("organizations", None, None),
+            OAuthStatus::TenantMismatch
+        );
+    }
+
+    #[test]
+    fn sync_entry_points_take_non_sync_backends_and_wait_for_pending_futures() {
+        /// Counts calls in a `Cell`, so it is neither `Sync` nor `Send`.
+        struct Counting {
+            inner: MockBroker,
+            calls: std::cell::Cell<u32>,
+        }
+
+        impl OAuthBackend for Counting {
+            fn get_token(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+            ) -> Result<Option<TokenSet>, OAuthCardError> {
+                self.calls.set(self.calls.get() + 1);
+                OAuthBackend::get_token(&self.inner, ctx, provider_id, subject, scopes)
+            }
+
+            fn get_consent_url(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+                redirect_path: &str,
+                extra_json: Option<String>,
+            ) -> Result<String, OAuthCardError> {
+                OAuthBackend::get_consent_url(
+                    &self.inner,
+                    ctx,
+                    provider_id,
+                    subject,
+                    scopes,
+                    redirect_path,
+                    extra_json,
+                )
+            }
+
+            fn exchange_code(
+                &self,
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                code: &str,
+                redirect_path: &str,
+                idempotency_key: Option<&str>,
+            ) -> Result<TokenSet, OAuthCardError> {
+                OAuthBackend::exchange_code(
+                    &self.inner,
+                    ctx,
+                    provider_id,
+                    subject,
+                    code,
+                    redirect_path,
+                    idempotency_key,
+                )
+            }
+        }
+
+        let backend = Counting {
+            inner: MockBroker {
+                token: Some(TokenSet {
+                    access_token: "token123".into(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            calls: std::cell::Cell::new(0),
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        assert!(backend.calls.get() > 0);
+
+        // A future that completes only after another thread wakes it.
+        let mut woken = false;
+        let later = std::future::poll_fn(move |cx| {
+            if woken {
+                return std::task::Poll::Ready(42);
+            }
+            woken = true;
+            let waker = cx.waker().clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                waker.wake();
+            });
+            std::task::Poll::Pending
+        });
+        assert_eq!(logic::run_sync(later), 42);
+    }
+
+    #[test]
+    fn error_fields_are_typed_and_omitted_on_success() {
+        let backend = MockBroker {
+            token: Some(TokenSet {
+                access_token: "tok".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let output = logic::handle(&backend, test_input(OAuthCardMode::EnsureToken))
+            .unwrap_or_else(|err| panic!("ensure: {err}"));
+        assert_eq!(output.status, OAuthStatus::Ok);
+        let json = serde_json::to_value(&output).unwrap_or_else(|err| panic!("json: {err}"));
+        for field in ["error_code", "retryable", "error_detail"] {
+            assert!(json.get(field).is_none(), "{field}: {json}");
+        }
+
+        let failed = OAuthCardOutput::error(&OAuthCardError::Broker {
+            code: BrokerErrorCode::InvalidGrant,
+            message: "expired".into(),
+        });
+        assert_eq!(failed.error_code, Some(ErrorCode::InvalidGrant));
+        let json = serde_json::to_value(&failed).unwrap_or_else(|err| panic!("json: {err}"));
+        assert_eq!(json["error_code"], "invalid_grant");
+        let parsed: OAuthCardOutput =
+            serde_json::from_value(json).unwrap_or_else(|err| panic!("parse: {err}"));
+        assert_eq!(parsed.error_code, Some(ErrorCode::InvalidGrant));
+    }
+
+    #[test]
+    fn default_code_exchanges_refuse_to_drop_token_request_parameters() {
+        let backend = InMemoryBroker::new();
+        let exchange = |extra: serde_json::Value| {
+            OAuthBackend::exchange_code_with_extra(
+                &backend,
+                &CallContext::default(),
+                "msgraph",
+                "user-1",
+                "code",
+                "/oauth/callback/msgraph",
+                None,
+                Some(extra.to_string()),
+            )
+        };
+        assert!(exchange(serde_json::json!({ "state_id": "s1" })).is_ok());
+        for key in ["resource", "audience", "dpop_proof"] {
+            let extra = serde_json::json!({ "state_id": "s1", key: "value" });
+            assert!(
+                matches!(exchange(extra), Err(OAuthCardError::Unsupported(_))),
+                "{key}"
+            );
+        }
+    }
 }
@@ -0,0 +1,245 @@
+use std::sync::{Arc, OnceLock};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::OAuthCardError;
+use crate::correlation::CallContext;
+use crate::model::REDACTED;
+
+/// Severity of a [`LogRecord`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// One component-internal event. Token-like values are already redacted from
+/// `message` and `fields`.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub message: String,
+    pub fields: Value,
+    /// Correlation id of the invocation that logged it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Tenant, flow and node of the invocation that logged it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<String>,
+}
+
+/// Destination for component-internal warnings that do not fail the request
+/// (an unusable consent URL, malformed token extras, missing scopes, ...).
+pub trait Logger {
+    fn log(&self, record: &LogRecord);
+}
+
+impl<F: Fn(&LogRecord)> Logger for F {
+    fn log(&self, record: &LogRecord) {
+        self(record)
+    }
+}
+
+/// Writes records at or above `min_level` as JSON lines to stderr, for
+/// native embedders without a logging stack of their own.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy)]
+pub struct StderrLogger {
+    pub min_level: LogLevel,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for StderrLogger {
+    fn default() -> Self {
+        Self {
+            min_level: LogLevel::Warn,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Logger for StderrLogger {
+    fn log(&self, record: &LogRecord) {
+        if record.level >= self.min_level
+            && let Ok(line) = serde_json::to_string(record)
+        {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// Sends records at or above `min_level` to the host's
+/// `greentic:telemetry/logger-api`, under a span for the record's tenant, flow
+/// and node.
+///
+/// Each record becomes `level`, `message` and `correlation_id` fields plus
+/// one field per top-level key of its `fields` (strings as is, other values
+/// as JSON).
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Clone, Copy)]
+pub struct HostLogger {
+    pub min_level: LogLevel,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for HostLogger {
+    fn default() -> Self {
+        Self {
+            min_level: LogLevel::Warn,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Logger for HostLogger {
+    fn log(&self, record: &LogRecord) {
+        use greentic_interfaces_guest::telemetry_logger::{self, SpanContext};
+
+        if record.level < self.min_level {
+            return;
+        }
+        let span = SpanContext {
+            tenant: record.tenant.clone().unwrap_or_default(),
+            session_id: None,
+            flow_id: record.flow_id.clone().unwrap_or_default(),
+            node_id: record.node_id.clone(),
+            provider: env!("CARGO_PKG_NAME").to_string(),
+            start_ms: None,
+            end_ms: None,
+        };
+        // A host that refuses the record leaves nowhere else to report it.
+        let _ = telemetry_logger::log(&span, &host_fields(record), None);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn host_fields(record: &LogRecord) -> Vec<(String, String)> {
+    let level = match record.level {
+        LogLevel::Debug => "debug",
+        LogLevel::Info => "info",
+        LogLevel::Warn => "warn",
+        LogLevel::Error => "error",
+    };
+    let mut fields = vec![
+        ("level".to_string(), level.to_string()),
+        ("message".to_string(), record.message.clone()),
+    ];
+    if let Some(correlation_id) = &record.correlation_id {
+        fields.push(("correlation_id".to_string(), correlation_id.clone()));
+    }
+    if let Value::Object(map) = &record.fields {
+        fields.extend(map.iter().map(|(key, value)| {
+            let value = match value {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            (key.clone(), value)
+        }));
+    }
+    fields
+}
+
+static REGISTERED_LOGGER: OnceLock<Arc<dyn Logger + Send + Sync>> = OnceLock::new();
+
+/// Install the process-wide logger. Without one, wasm builds send warnings to
+/// the host's telemetry logger (`HostLogger`) and native builds stay silent;
+/// only the first registration takes effect.
+pub fn register_logger(logger: impl Logger + Send + Sync + 'static) -> Result<(), OAuthCardError> {
+    REGISTERED_LOGGER
+        .set(Arc::new(logger))
+        .map_err(|_| OAuthCardError::Invalid("a logger is already registered".into()))
+}
+
+pub(crate) fn log(ctx: &CallContext, level: LogLevel, message: &str, fields: Value) {
+    let record = || LogRecord {
+        level,
+        message: redact_text(message),
+        fields: redact_fields(fields),
+        correlation_id: ctx.correlation_id.clone(),
+        tenant: ctx.tenant.clone(),
+        flow_id: ctx.flow_id.clone(),
+        node_id: ctx.node_id.clone(),
+    };
+    match REGISTERED_LOGGER.get() {
+        Some(logger) => logger.log(&record()),
+        #[cfg(target_arch = "wasm32")]
+        None => HostLogger::default().log(&record()),
+        #[cfg(not(target_arch = "wasm32"))]
+        None => {}
+    }
+}
+
+pub(crate) fn warn(ctx: &CallContext, message: &str, fields: Value) {
+    log(ctx, LogLevel::Warn, message, fields);
+}
+
+pub(crate) fn info(ctx: &CallContext, message: &str, fields: Value) {
+    log(ctx, LogLevel::Info, message, fields);
+}
+
+/// Field names whose string values are always replaced.
+const SECRET_KEYS: [&str; 12] = [
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "token",
+    "auth_code",
+    "code",
+    "sso_token",
+    "subject_token",
+    "client_secret",
+    "authorization",
+    "password",
+    "secret",
+];
+
+/// `fields` with secret-named values and token-like strings masked.
+pub(crate) fn redact_fields(fields: Value) -> Value {
+    match fields {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(_) if SECRET_KEYS.contains(&key.to_lowercase().as_str()) => {
+                            Value::String(REDACTED.into())
+                        }
+                        other => redact_fields(other),
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(redact_fields).collect()),
+        Value::String(text) => Value::String(redact_text(&text)),
+        other => other,
+    }
+}
+
+/// `text` with JWTs and the credential after `Bearer`/`DPoP` masked.
+pub(crate) fn redact_text(text: &str) -> String {
+    let mut masked = Vec::new();
+    let mut after_scheme = false;
+    for word in text.split(' ') {
+        let scheme = word.eq_ignore_ascii_case("bearer") || word.eq_ignore_ascii_case("dpop");
+        if (after_scheme && !word.is_empty()) || looks_like_jwt(word) {
+            masked.push(REDACTED);
+        } else {
+            masked.push(word);
+        }
+        after_scheme = scheme;
+    }
+    masked.join(" ")
+}
+
+fn looks_like_jwt(word: &str) -> bool {
+    let word = word.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    word.starts_with("eyJ") && word.matches('.').count() >= 2
+}
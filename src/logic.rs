@@ -1,194 +1,2412 @@
+use std::collections::BTreeMap;
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
 use uuid::Uuid;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::OAuthCardError;
+use crate::broker::{AsyncOAuthBackend, OAuthBackend};
+use crate::claims::{
+    claim_str, decode_jwt_claims, signed_id_token_claims, validate_logout_token,
+    verified_id_token_claims,
+};
+use crate::clock::now_secs;
+use crate::component_config;
+use crate::correlation::CallContext;
+use crate::diagnostics;
+use crate::discovery;
+use crate::logging;
+use crate::metrics;
+use crate::model::{
+    Action, AuthContext, AuthHeader, AuthInjection, AuthInjectionStyle, BackChannelLogoutInput,
+    BackChannelLogoutOutput, Branding, BrokerErrorCode, DeviceCodeGrant, Diagnostics, HealthOutput,
+    ImageRef, MessageCard, MessageCardKind, OAuthCardInput, OAuthCardMode, OAuthCardOutput,
+    OAuthStatus, OauthCard, OauthPrompt, OauthProvider, PendingSignIn, ProviderResult,
+    SignInProgress, TOKEN_BINDING_KEY, TokenSet, UserProfile,
+};
+use crate::output_policy;
+use crate::progress::{self, Stage};
+use crate::provider_config;
+use crate::providers;
+use crate::rate_limit;
+use crate::render;
+use crate::scopes::{self, Scope};
+use crate::signed_state;
+use crate::state_store::{self, SIGN_IN_STATE_TTL_SECS, StateStore};
+use crate::template::{CardTemplate, humanize_duration};
+use crate::validation;
+use serde_json::{Value, json};
+
+const DEFAULT_REFRESH_MARGIN_SECS: u64 = 300;
+const DEFAULT_CLOCK_SKEW_SECS: u64 = 30;
+/// How long a connection disconnected without broker revocation stays hidden
+/// unless the subject signs in again.
+const FORGOTTEN_CONNECTION_TTL_SECS: u64 = 365 * 24 * 3_600;
+/// How long a started device flow can be polled; caps the grant's `expires_in`.
+const DEVICE_CODE_TTL_SECS: u64 = 30 * 60;
+
+/// Device codes by `state_id` (with their expiry) for when no state store
+/// keeps values.
+static DEVICE_CODES: Mutex<BTreeMap<String, (Zeroizing<String>, u64)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Synchronous entry point used by the wasm host-broker path and other blocking
+/// backends; see [`handle_async`] for async brokers.
+pub fn handle<B: OAuthBackend>(
+    backend: &B,
+    input: OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    handle_with_context(backend, &CallContext::default(), input)
+}
+
+/// [`handle`] for a host that supplies the invocation's [`CallContext`].
+pub fn handle_with_context<B: OAuthBackend>(
+    backend: &B,
+    host: &CallContext,
+    input: OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    run_sync(handle_async_with_context(backend, host, input))
+}
+
+pub async fn handle_async<B: AsyncOAuthBackend>(
+    backend: &B,
+    input: OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    handle_async_with_context(backend, &CallContext::default(), input).await
+}
+
+/// [`handle_async`] for a host that supplies the invocation's
+/// [`CallContext`]. The host's tenant is the invocation's tenant: an input
+/// without one gets it, and an input naming another fails with
+/// `TenantMismatch`. The input's `correlation_id` takes precedence over the
+/// host's.
+pub async fn handle_async_with_context<B: AsyncOAuthBackend>(
+    backend: &B,
+    host: &CallContext,
+    mut input: OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let host_tenant = adopt_host_tenant(host, &mut input);
+    let ctx = &CallContext {
+        correlation_id: input.correlation_id.clone().or(host.correlation_id.clone()),
+        tenant: input.tenant.clone(),
+        ..host.clone()
+    };
+    let requested_scopes = input.scopes.clone();
+    let mut input = provider_config::with_defaults(component_config::with_defaults(input));
+    // Batch-ensure and choose-provider entries get their provider's defaults
+    // one by one.
+    if !matches!(
+        input.mode,
+        OAuthCardMode::BatchEnsure | OAuthCardMode::ChooseProvider
+    ) {
+        input = providers::with_defaults(input);
+    }
+    input.scopes = scopes::dedup(input.scopes);
+    let traced = input.debug.then(|| diagnostics::Traced::new(backend));
+    let mut dropped_scopes = Vec::new();
+    let checked = host_tenant
+        .and_then(|()| validation::validate(&input))
+        .and_then(|()| provider_config::restrict_scopes(&mut input));
+    let result = match checked {
+        Ok(dropped) => {
+            dropped_scopes = dropped;
+            match traced.as_ref() {
+                Some(traced) => dispatch_discovered(traced, ctx, &input).await,
+                None => dispatch_discovered(backend, ctx, &input).await,
+            }
+        }
+        Err(err) => Err(err),
+    };
+    let mut output = result.unwrap_or_else(|err| error_output(&input, &err));
+    output.correlation_id = ctx.correlation_id.clone();
+    output.dropped_scopes = dropped_scopes;
+    if let Some(traced) = traced {
+        output.diagnostics = Some(Diagnostics {
+            provider: OauthProvider::from_id(&input.provider_id),
+            provider_configured: provider_config::provider_config(&input.provider_id).is_some(),
+            redirect_path: redirect_path(&input).ok(),
+            requested_scopes,
+            normalized_scopes: input.scopes.clone(),
+            broker_calls: traced.calls(),
+        });
+    }
+    metrics::record_invocation(Some(&input.mode), &output);
+    if !output_policy::include_auth_header(&input) {
+        output.zeroize_secrets();
+    }
+    if let Some(card) = output.card.as_mut() {
+        progress::mark(Stage::Rendering);
+        apply_card_template(ctx, &input, output.auth_context.as_ref(), card);
+        let branding = branding(ctx, &input);
+        if let Some(branding) = branding.as_ref() {
+            apply_branding(branding, card);
+        }
+        card.adaptive = Some(render::adaptive::render(card, branding.as_ref()));
+        output.text_fallback = Some(render::text::render(card));
+        if let Some(channel) = input.channel.as_deref()
+            && let Some(rendered) = render::render_for_channel(ctx, channel, card)
+        {
+            output.rendered.insert(channel.to_string(), rendered);
+        }
+    }
+    input.auth_code.zeroize();
+    input.sso_token.zeroize();
+    input.subject_token.zeroize();
+    Ok(output)
+}
+
+/// Give the input the host's tenant, refusing an input that names another.
+fn adopt_host_tenant(host: &CallContext, input: &mut OAuthCardInput) -> Result<(), OAuthCardError> {
+    let Some(tenant) = host.tenant.as_deref() else {
+        return Ok(());
+    };
+    match input.tenant.as_deref() {
+        Some(requested) if requested != tenant => Err(OAuthCardError::TenantMismatch(format!(
+            "input tenant `{requested}` is not the host's tenant `{tenant}`"
+        ))),
+        _ => {
+            input.tenant = Some(tenant.to_string());
+            Ok(())
+        }
+    }
+}
+
+/// [`dispatch`] with the OIDC endpoints of the provider's issuer (provider
+/// config or `extra_json.issuer`) added to `extra_json` for modes that use
+/// them, so brokers and cards get the authorize, token and end-session URLs
+/// of custom providers.
+async fn dispatch_discovered<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    if !discovery::needed_by(&input.mode) {
+        return dispatch(backend, ctx, input).await;
+    }
+    match discovery::with_endpoints(backend, ctx, input).await {
+        Some(discovered) => dispatch(backend, ctx, &discovered).await,
+        None => dispatch(backend, ctx, input).await,
+    }
+}
+
+/// Run the handler for the input's mode; broker calls made here get `ctx`.
+async fn dispatch<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    match &input.mode {
+        OAuthCardMode::StatusCard => status_card(backend, ctx, input).await,
+        OAuthCardMode::StartSignIn => start_sign_in(backend, ctx, input).await,
+        OAuthCardMode::CompleteSignIn => complete_sign_in(backend, ctx, input).await,
+        OAuthCardMode::EnsureToken => ensure_token(backend, ctx, input).await,
+        OAuthCardMode::Disconnect => disconnect(backend, ctx, input).await,
+        OAuthCardMode::DeviceCode => device_code(backend, ctx, input).await,
+        OAuthCardMode::ClientCredentials => client_credentials(backend, ctx, input).await,
+        OAuthCardMode::BatchEnsure => batch_ensure(backend, ctx, input).await,
+        OAuthCardMode::ListConnections => list_connections(backend, ctx, input).await,
+        OAuthCardMode::SwitchAccount => switch_account(backend, ctx, input).await,
+        OAuthCardMode::Logout => logout(backend, ctx, input).await,
+        OAuthCardMode::PollSignIn => poll_sign_in(backend, ctx, input).await,
+        OAuthCardMode::CancelSignIn => cancel_sign_in(backend, ctx, input).await,
+        OAuthCardMode::ChooseProvider => choose_provider(input),
+        OAuthCardMode::AdminConsent => {
+            admin_consent(backend, ctx, input, &admin_consent_tenant(input)?, false).await
+        }
+        OAuthCardMode::CheckAdminConsent => check_admin_consent(backend, ctx, input).await,
+        OAuthCardMode::TokenExchange => token_exchange(backend, ctx, input).await,
+        OAuthCardMode::Other(mode) => Err(OAuthCardError::Unsupported(format!(
+            "mode `{mode}` is not supported by this component version"
+        ))),
+    }
+}
+
+/// Drive a handler future to completion on the current thread. The blanket
+/// [`AsyncOAuthBackend`] impl returns ready futures, so synchronous backends
+/// finish in one poll; a future that does return `Pending` parks the thread
+/// until its waker fires.
+pub(crate) fn run_sync<T>(future: impl Future<Output = T>) -> T {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(result) => return result,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Map a handler failure to an error card explaining the reason, with a
+/// "Try again" PostBack that replays the original mode and `state_id`.
+///
+/// Timeouts while ensuring or completing sign-in report `pending` rather than
+/// `error`: the broker may still finish, so the flow should simply retry.
+/// Broker error codes pick the card: a denied consent or an invalid grant
+/// offers a fresh sign-in instead of replaying the failed call.
+fn error_output(input: &OAuthCardInput, err: &OAuthCardError) -> OAuthCardOutput {
+    let provider = &input.provider_id;
+    let code = err.code();
+    let (status, title, retry) = match (err, code) {
+        (OAuthCardError::Timeout(_), _)
+            if matches!(
+                input.mode,
+                OAuthCardMode::EnsureToken | OAuthCardMode::CompleteSignIn
+            ) =>
+        {
+            (
+                OAuthStatus::Pending,
+                format!("{provider} is taking longer than expected"),
+                Some(("Try again", input.mode.clone())),
+            )
+        }
+        (OAuthCardError::InvalidState(_), _) => (
+            OAuthStatus::InvalidState,
+            format!("This {provider} sign-in link is no longer valid"),
+            Some(("Start again", OAuthCardMode::StartSignIn)),
+        ),
+        (OAuthCardError::Replay(_), _) => (
+            OAuthStatus::InvalidState,
+            format!("This {provider} sign-in link has already been used"),
+            Some(("Start again", OAuthCardMode::StartSignIn)),
+        ),
+        (OAuthCardError::TenantMismatch(_), _) => (
+            OAuthStatus::TenantMismatch,
+            format!("This {provider} connection belongs to another workspace"),
+            Some(("Connect here", OAuthCardMode::StartSignIn)),
+        ),
+        (OAuthCardError::StepUpRequired(_), _) => (
+            OAuthStatus::StepUpRequired,
+            format!("{provider} needs you to sign in again"),
+            Some(("Sign in again", OAuthCardMode::StartSignIn)),
+        ),
+        (OAuthCardError::RateLimited { .. }, _) => (
+            OAuthStatus::Error,
+            format!("Too many {provider} sign-in attempts"),
+            None,
+        ),
+        (_, Some(BrokerErrorCode::ConsentDenied)) => (
+            OAuthStatus::Denied,
+            format!("{provider} access was not granted"),
+            Some(("Try again", OAuthCardMode::StartSignIn)),
+        ),
+        (_, Some(BrokerErrorCode::InvalidGrant)) => (
+            OAuthStatus::Expired,
+            format!("Your {provider} connection has expired"),
+            Some(("Reconnect", OAuthCardMode::StartSignIn)),
+        ),
+        (_, Some(BrokerErrorCode::Throttled)) => (
+            OAuthStatus::Error,
+            format!("Too many requests to {provider}"),
+            Some(("Try again", input.mode.clone())),
+        ),
+        (OAuthCardError::Timeout(_), _) => (
+            OAuthStatus::Error,
+            format!("{provider} is taking longer than expected"),
+            Some(("Try again", input.mode.clone())),
+        ),
+        (_, Some(BrokerErrorCode::ProviderUnavailable)) => (
+            OAuthStatus::Error,
+            format!("{provider} is temporarily unavailable"),
+            Some(("Try again", input.mode.clone())),
+        ),
+        (OAuthCardError::Parse(_), _) => (
+            OAuthStatus::Error,
+            format!("Unexpected response from {provider}"),
+            Some(("Try again", input.mode.clone())),
+        ),
+        (OAuthCardError::Unsupported(_), _) => (
+            OAuthStatus::Error,
+            format!("{provider} does not support this action"),
+            None,
+        ),
+        _ => (
+            OAuthStatus::Error,
+            format!("Could not complete {provider} request"),
+            Some(("Try again", input.mode.clone())),
+        ),
+    };
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(title)
+        .text(err.to_string())
+        .build();
+    if let Some((label, mode)) = retry {
+        let state_id = (mode == input.mode)
+            .then(|| input.state_id.clone())
+            .flatten();
+        card.actions.push(action(label, mode, input, state_id));
+    }
+    OAuthCardOutput {
+        status,
+        card: Some(card),
+        state_id: input.state_id.clone(),
+        ..OAuthCardOutput::error(err)
+    }
+}
+
+/// Handle an OIDC back-channel logout token by invalidating matching connections.
+pub fn back_channel_logout<B: OAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: BackChannelLogoutInput,
+) -> Result<BackChannelLogoutOutput, OAuthCardError> {
+    run_sync(back_channel_logout_async(backend, ctx, input))
+}
+
+pub async fn back_channel_logout_async<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: BackChannelLogoutInput,
+) -> Result<BackChannelLogoutOutput, OAuthCardError> {
+    let claims = validate_logout_token(&input.provider_id, &input.logout_token)?;
+    let subjects = backend
+        .invalidate_sessions(
+            ctx,
+            &input.provider_id,
+            claims.sub.as_deref(),
+            claims.sid.as_deref(),
+        )
+        .await?;
+    Ok(BackChannelLogoutOutput {
+        status: OAuthStatus::Ok,
+        provider_id: input.provider_id,
+        sub: claims.sub,
+        sid: claims.sid,
+        subjects,
+        error: None,
+    })
+}
+
+/// Probe the broker and report reachability and probe latency.
+pub fn health<B: OAuthBackend>(backend: &B, ctx: &CallContext) -> HealthOutput {
+    let started = Instant::now();
+    health_output(started, backend.health(ctx))
+}
+
+pub async fn health_async<B: AsyncOAuthBackend>(backend: &B, ctx: &CallContext) -> HealthOutput {
+    let started = Instant::now();
+    health_output(started, backend.health(ctx).await)
+}
+
+fn health_output(started: Instant, result: Result<(), OAuthCardError>) -> HealthOutput {
+    let latency_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    match result {
+        Ok(()) => HealthOutput {
+            status: OAuthStatus::Ok,
+            reachable: true,
+            latency_ms,
+            error: None,
+        },
+        Err(err) => HealthOutput {
+            status: OAuthStatus::Error,
+            reachable: false,
+            latency_ms,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+async fn status_card<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let token = stored_token(backend, ctx, input).await?.map(Zeroizing::new);
+
+    if let Some(token) = token.as_ref() {
+        check_token_binding(backend, ctx, input, token).await?;
+        let missing = missing_scopes(&input.scopes, &token.scopes());
+        if !missing.is_empty() {
+            log_missing_scopes(ctx, input, &missing);
+            let mut card = connect_prompt_card(input, None);
+            card.text = Some(format!(
+                "Your {} connection does not cover all requested permissions. Sign in again to grant: {}.",
+                input.provider_id,
+                missing.join(", ")
+            ));
+            return Ok(OAuthCardOutput::needs_sign_in(card, None));
+        }
+    }
+
+    if let Some(token) = token {
+        let (mut card, context) = connected_card(backend, ctx, input, &token, "Connected").await;
+        let can_refresh = backend.capabilities().refresh;
+        let status = annotate_expiry(input, &token, can_refresh, &mut card);
+        // A dead token must not be handed out for downstream calls.
+        let usable = status != OAuthStatus::Expired;
+        Ok(OAuthCardOutput {
+            status,
+            card: Some(card),
+            auth_context: Some(context),
+            auth_header: auth_header(input, &token)?.filter(|_| usable),
+            auth_injection: usable.then(|| auth_injection(input, &token)),
+            ..Default::default()
+        })
+    } else {
+        let card = connect_prompt_card(input, None);
+        Ok(OAuthCardOutput::needs_sign_in(card, None))
+    }
+}
+
+async fn start_sign_in<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let (state_id, consent_url) = consent_request(backend, ctx, input).await?;
+    let card = sign_in_card(input, &state_id, &consent_url);
+
+    Ok(OAuthCardOutput {
+        state_id: Some(state_id),
+        ..OAuthCardOutput::ok(card)
+    })
+}
+
+/// Mint (or reuse) the `state_id` and consent URL for a sign-in, saving the
+/// flow state when a state store is active. Every sign-in started here counts
+/// against the subject's sign-in rate limit.
+async fn consent_request<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<(String, String), OAuthCardError> {
+    rate_limit::check_sign_in(&input.provider_id, &input.subject)?;
+    let now = now_secs();
+    // A replayed start keeps its state unless signing is on and it fails the check.
+    let state_id = input
+        .state_id
+        .clone()
+        .filter(|state_id| {
+            signed_state::verify(
+                state_id,
+                &input.provider_id,
+                &input.subject,
+                now,
+                SIGN_IN_STATE_TTL_SECS,
+            )
+            .is_ok()
+        })
+        .unwrap_or_else(|| signed_state::issue(&input.provider_id, &input.subject, now));
+    let redirect_path = redirect_path(input)?;
+    let mut extra_json = input.extra_json.clone();
+    if let Some(store) = state_store::active_state_store() {
+        let nonce = Uuid::new_v4().simple().to_string();
+        let pending = PendingSignIn {
+            provider_id: input.provider_id.clone(),
+            subject: input.subject.clone(),
+            redirect_path: redirect_path.clone(),
+            scopes: input.scopes.clone(),
+            nonce: Some(nonce.clone()),
+            tenant: None,
+        };
+        store.save(&state_id, &pending, SIGN_IN_STATE_TTL_SECS)?;
+        extra_json = with_extra(extra_json, "nonce", Value::String(nonce));
+    }
+    if input.tenant.is_some() || input.team.is_some() {
+        let binding = json!({ "tenant": input.tenant, "team": input.team });
+        extra_json = with_extra(extra_json, TOKEN_BINDING_KEY, binding);
+    }
+    if let Some(jkt) = dpop_jkt(input)? {
+        extra_json = with_extra(extra_json, "dpop_jkt", Value::String(jkt));
+    }
+    for (key, value) in sign_in_hints(input) {
+        extra_json = with_extra(extra_json, key, value);
+    }
+    // Backends running the redirect themselves send it as the OAuth `state`.
+    extra_json = with_extra(extra_json, "state_id", Value::String(state_id.clone()));
+    let consent_url = backend
+        .get_consent_url(
+            ctx,
+            &input.provider_id,
+            &input.subject,
+            &input.scopes,
+            &redirect_path,
+            broker_extra(extra_json.as_ref()),
+        )
+        .await
+        .unwrap_or_else(|err| {
+            logging::warn(
+                ctx,
+                "consent url unavailable",
+                json!({ "provider_id": input.provider_id, "error": err.to_string() }),
+            );
+            String::new()
+        });
+    if consent_url.is_empty() {
+        logging::warn(
+            ctx,
+            "sign-in card has no consent url",
+            json!({ "provider_id": input.provider_id }),
+        );
+    } else {
+        let config = provider_config::provider_config(&input.provider_id);
+        provider_config::check_consent_url(config.as_ref(), &consent_url)?;
+    }
+    Ok((state_id, consent_url))
+}
+
+/// The input's `login_hint`, `domain_hint`, `prompt`, `acr_values`,
+/// `max_age`, `resource` and `audience`, under the keys brokers forward to the
+/// authorization endpoint.
+fn sign_in_hints(input: &OAuthCardInput) -> Vec<(&'static str, Value)> {
+    let mut hints = Vec::new();
+    if let Some(hint) = &input.login_hint {
+        hints.push(("login_hint", Value::String(hint.clone())));
+    }
+    if let Some(hint) = &input.domain_hint {
+        hints.push(("domain_hint", Value::String(hint.clone())));
+    }
+    if let Some(prompt) = &input.prompt {
+        hints.push(("prompt", json!(prompt)));
+    }
+    if let Some(acr_values) = &input.acr_values {
+        hints.push(("acr_values", Value::String(acr_values.clone())));
+    }
+    if let Some(max_age) = input.max_age {
+        hints.push(("max_age", json!(max_age)));
+    }
+    if let Some(resource) = &input.resource {
+        hints.push(("resource", Value::String(resource.clone())));
+    }
+    if let Some(audience) = &input.audience {
+        hints.push(("audience", Value::String(audience.clone())));
+    }
+    hints
+}
+
+/// Microsoft authority and the authorization endpoint it resolves to, for
+/// card metadata.
+fn microsoft_endpoint(input: &OAuthCardInput) -> Vec<(&'static str, Value)> {
+    let (Ok(Some(authority)), Some(info)) = (
+        providers::requested_microsoft_authority(input),
+        providers::provider_info(&input.provider_id),
+    ) else {
+        return Vec::new();
+    };
+    vec![
+        ("authorize_url", json!(info.authorize_url_for(&authority))),
+        ("tenant", json!(authority)),
+    ]
+}
+
+/// Discovered OIDC endpoints of a custom provider, for card metadata.
+fn discovered_endpoints(input: &OAuthCardInput) -> Option<(&'static str, Value)> {
+    let endpoints = input
+        .extra_json
+        .as_ref()?
+        .get(discovery::OIDC_ENDPOINTS_KEY)?;
+    Some((discovery::OIDC_ENDPOINTS_KEY, endpoints.clone()))
+}
+
+/// `resource`/`audience` the token is requested for: the input's, else the
+/// `extra_json` entries (where provider config defaults land).
+fn token_target(input: &OAuthCardInput, key: &str) -> Option<String> {
+    let explicit = match key {
+        "resource" => input.resource.as_ref(),
+        _ => input.audience.as_ref(),
+    };
+    explicit.cloned().or_else(|| {
+        input
+            .extra_json
+            .as_ref()
+            .and_then(|extra| extra.get(key))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    })
+}
+
+/// Token-request parameters and the sign-in's `state_id` for the code
+/// exchange, as `extra_json`. DPoP providers also get a `dpop_proof` for the
+/// token endpoint, which brokers send as the token request's `DPoP` header.
+fn exchange_extra(input: &OAuthCardInput) -> Result<Option<String>, OAuthCardError> {
+    let mut extra: serde_json::Map<String, Value> = ["resource", "audience"]
+        .into_iter()
+        .filter_map(|key| Some((key.to_string(), Value::String(token_target(input, key)?))))
+        .collect();
+    if let Some(state_id) = &input.state_id {
+        extra.insert("state_id".into(), Value::String(state_id.clone()));
+    }
+    if let Some(proof) = dpop_exchange_proof(input)? {
+        extra.insert("dpop_proof".into(), Value::String(proof));
+    }
+    Ok((!extra.is_empty()).then(|| Value::Object(extra).to_string()))
+}
+
+/// Token endpoint of the input's provider: the discovered one, else the
+/// registry's with the requested tenant filled in.
+#[cfg(feature = "dpop")]
+fn token_endpoint(input: &OAuthCardInput) -> Option<String> {
+    let discovered = input
+        .extra_json
+        .as_ref()
+        .and_then(|extra| extra.get(discovery::OIDC_ENDPOINTS_KEY))
+        .and_then(|endpoints| endpoints.get("token_endpoint"))
+        .and_then(Value::as_str)
+        .filter(|endpoint| !endpoint.is_empty());
+    if let Some(endpoint) = discovered {
+        return Some(endpoint.to_string());
+    }
+    let info = providers::provider_info(&input.provider_id)?;
+    if !info.token_url.contains("{tenant}") {
+        return Some(info.token_url.to_string());
+    }
+    let tenant = match providers::requested_microsoft_authority(input) {
+        Ok(Some(authority)) => authority,
+        _ => providers::requested_tenant(input)?.to_string(),
+    };
+    Some(info.token_url_for(&tenant))
+}
+
+/// `extra_json` entries only cards read; they are not sent to brokers.
+const PRESENTATION_KEYS: [&str; 4] = [
+    "provider_logos",
+    "branding",
+    "card_templates",
+    "scope_descriptions",
+];
+
+/// `extra_json` as sent to broker calls, without the card-only entries.
+fn broker_extra(extra_json: Option<&Value>) -> Option<String> {
+    match extra_json? {
+        Value::Object(extra) => {
+            let extra: serde_json::Map<String, Value> = extra
+                .iter()
+                .filter(|(key, _)| !PRESENTATION_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            (!extra.is_empty()).then(|| Value::Object(extra).to_string())
+        }
+        other => Some(other.to_string()),
+    }
+}
+
+/// `extra_json` with `key` set, starting a fresh object when it is not one.
+fn with_extra(extra_json: Option<Value>, key: &str, value: Value) -> Option<Value> {
+    let mut extra = match extra_json {
+        Some(Value::Object(extra)) => extra,
+        _ => serde_json::Map::new(),
+    };
+    extra.insert(key.into(), value);
+    Some(Value::Object(extra))
+}
+
+/// Refuse tokens bound (via [`TOKEN_BINDING_KEY`]) to another tenant or team
+/// than the input's. Unbound tokens (stored before sign-ins bound them, or
+/// by a broker that does not keep the key) are accepted and bound to the
+/// input's tenant and team on first use through `store_token`, so uses from
+/// elsewhere are refused afterwards when the broker can store tokens.
+async fn check_token_binding<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    token: &TokenSet,
+) -> Result<(), OAuthCardError> {
+    let binding = token
+        .extra
+        .as_ref()
+        .and_then(|extra| extra.get(TOKEN_BINDING_KEY));
+    if binding.is_none() {
+        if input.tenant.is_some() || input.team.is_some() {
+            bind_token(backend, ctx, input, token).await;
+        }
+        return Ok(());
+    }
+    for (name, expected) in [("tenant", &input.tenant), ("team", &input.team)] {
+        let bound = binding
+            .and_then(|binding| binding.get(name))
+            .and_then(Value::as_str);
+        if bound == expected.as_deref() {
+            continue;
+        }
+        return Err(OAuthCardError::TenantMismatch(match bound {
+            Some(bound) => format!(
+                "the {} connection was issued for {name} `{bound}`",
+                input.provider_id
+            ),
+            None => format!(
+                "the {} connection was not issued for a {name}",
+                input.provider_id
+            ),
+        }));
+    }
+    Ok(())
+}
+
+/// Store `token` bound to the input's tenant and team. Brokers that cannot
+/// store tokens leave it unbound.
+async fn bind_token<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    token: &TokenSet,
+) {
+    if token.extra.as_ref().is_some_and(|extra| !extra.is_object()) {
+        return;
+    }
+    let mut bound = Zeroizing::new(token.clone());
+    let binding = json!({ "tenant": input.tenant, "team": input.team });
+    bound.extra = with_extra(bound.extra.take(), TOKEN_BINDING_KEY, binding);
+    match backend
+        .store_token(ctx, &input.provider_id, &input.subject, &bound)
+        .await
+    {
+        Ok(()) | Err(OAuthCardError::Unsupported(_)) => {}
+        Err(err) => logging::warn(
+            ctx,
+            "could not bind a token to its tenant",
+            json!({ "provider_id": input.provider_id, "error": err.to_string() }),
+        ),
+    }
+}
+
+/// The saved flow state for the callback's `state_id`, or `None` when no
+/// state store is active (the re-submitted input is trusted then).
+fn pending_sign_in(input: &OAuthCardInput) -> Result<Option<PendingSignIn>, OAuthCardError> {
+    let Some(store) = state_store::active_state_store() else {
+        return Ok(None);
+    };
+    let state_id = input
+        .state_id
+        .as_deref()
+        .ok_or_else(|| OAuthCardError::validation("state_id", "required to complete sign-in"))?;
+    store
+        .load(state_id)?
+        .filter(|pending| {
+            pending.provider_id == input.provider_id && pending.subject == input.subject
+        })
+        .map(Some)
+        .ok_or_else(|| {
+            OAuthCardError::validation(
+                "state_id",
+                format!("sign-in {state_id} is unknown, expired, or belongs to another user"),
+            )
+        })
+}
+
+/// Reject tokens whose id_token does not carry the nonce sent with the
+/// consent request, and OpenID sign-ins (`openid` scope) that returned no
+/// id_token to check it in.
+fn check_nonce(pending: &PendingSignIn, token: &TokenSet) -> Result<(), OAuthCardError> {
+    let Some(expected) = pending.nonce.as_deref() else {
+        return Ok(());
+    };
+    let Some(id_token) = token.id_token.as_deref() else {
+        if pending
+            .scopes
+            .iter()
+            .any(|scope| scope.as_str() == "openid")
+        {
+            return Err(OAuthCardError::Invalid(
+                "OpenID sign-in returned no id_token to check the nonce in".into(),
+            ));
+        }
+        return Ok(());
+    };
+    match claim_str(&decode_jwt_claims(id_token)?, "nonce") {
+        Some(nonce) if nonce == expected => Ok(()),
+        Some(_) => Err(OAuthCardError::Invalid(
+            "id_token nonce does not match the sign-in request".into(),
+        )),
+        None => Err(OAuthCardError::Invalid(
+            "id_token carries no nonce although the sign-in request sent one".into(),
+        )),
+    }
+}
+
+/// Reject a freshly issued token whose id_token fails the provider's
+/// configured verification.
+fn verify_id_token(input: &OAuthCardInput, token: &TokenSet) -> Result<(), OAuthCardError> {
+    match token.id_token.as_deref() {
+        Some(id_token) => verified_id_token_claims(&input.provider_id, id_token).map(drop),
+        None => Ok(()),
+    }
+}
+
+/// Refuse a fresh Microsoft sign-in whose id_token `tid` is outside the
+/// requested authority, or that has no `tid` to check when the authority is
+/// not `common`. A domain authority is first resolved to its tenant id
+/// through the broker's discovery of the domain's issuer.
+async fn check_tenant<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    token: &TokenSet,
+) -> Result<(), OAuthCardError> {
+    let Some(mut authority) = providers::requested_microsoft_authority(input)? else {
+        return Ok(());
+    };
+    if providers::is_microsoft_domain(&authority) {
+        authority = backend
+            .discover_oidc(ctx, &providers::microsoft_issuer(&authority))
+            .await
+            .ok()
+            .and_then(|endpoints| providers::microsoft_tenant_id(&endpoints.issuer))
+            .ok_or_else(|| {
+                OAuthCardError::TenantMismatch(format!(
+                    "could not resolve Microsoft domain `{authority}` to a tenant id to check the sign-in against"
+                ))
+            })?;
+    }
+    let tid = match token.id_token.as_deref() {
+        Some(id_token) => claim_str(
+            &verified_id_token_claims(&input.provider_id, id_token)?,
+            "tid",
+        ),
+        None => None,
+    };
+    providers::check_microsoft_tenant(&authority, tid.as_deref())
+}
+
+/// Refuse a fresh sign-in whose id_token misses the input's `acr_values` or
+/// authenticated longer than `max_age` (plus clock skew) ago.
+fn check_step_up(input: &OAuthCardInput, token: &TokenSet) -> Result<(), OAuthCardError> {
+    let acr_values: Vec<&str> = input
+        .acr_values
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    if acr_values.is_empty() && input.max_age.is_none() {
+        return Ok(());
+    }
+    let id_token = token.id_token.as_deref().ok_or_else(|| {
+        OAuthCardError::StepUpRequired("no id_token to confirm how the user signed in".into())
+    })?;
+    let claims = verified_id_token_claims(&input.provider_id, id_token)?;
+    if !acr_values.is_empty() {
+        match claim_str(&claims, "acr") {
+            Some(acr) if acr_values.contains(&acr.as_str()) => {}
+            Some(acr) => {
+                return Err(OAuthCardError::StepUpRequired(format!(
+                    "authentication level `{acr}` is not one of {}",
+                    acr_values.join(", ")
+                )));
+            }
+            None => {
+                return Err(OAuthCardError::StepUpRequired(
+                    "id_token does not state the authentication level".into(),
+                ));
+            }
+        }
+    }
+    if let Some(max_age) = input.max_age {
+        let auth_time = claims
+            .get("auth_time")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| {
+                OAuthCardError::StepUpRequired(
+                    "id_token does not state when the user authenticated".into(),
+                )
+            })?;
+        if now_secs().saturating_sub(auth_time) > max_age.saturating_add(clock_skew(input)) {
+            return Err(OAuthCardError::StepUpRequired(format!(
+                "authentication is older than {}",
+                humanize_duration(max_age)
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn complete_sign_in<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    if input.auth_code.is_none()
+        && let Some(sso_token) = input.sso_token.as_deref()
+    {
+        return complete_sso(backend, ctx, input, sso_token).await;
+    }
+    let code = input.auth_code.as_ref().ok_or_else(|| {
+        OAuthCardError::validation(
+            "auth_code",
+            "required to complete sign-in without sso_token",
+        )
+    })?;
+    match input.state_id.as_deref() {
+        Some(state_id) => signed_state::verify(
+            state_id,
+            &input.provider_id,
+            &input.subject,
+            now_secs(),
+            SIGN_IN_STATE_TTL_SECS,
+        )?,
+        None if signed_state::enabled() => {
+            return Err(OAuthCardError::InvalidState("state_id is missing".into()));
+        }
+        None => {}
+    }
+    let idempotency_key = exchange_idempotency_key(input);
+    let token = match state_store::active_state_store() {
+        Some(store) => {
+            // Claim the callback before exchanging, so concurrent or repeated
+            // deliveries cannot spend the code twice. The claim is held by the
+            // exchange's idempotency key: the same sign-in attempt delivered
+            // again while its first delivery is still exchanging is asked to
+            // retry; once that exchange finished, or for any other attempt,
+            // it is a replay and gets no token.
+            let owner = idempotency_key
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+            let keys =
+                state_store::consumption_keys(&input.provider_id, input.state_id.as_deref(), code);
+            let mut claimed = Vec::new();
+            let mut retried = false;
+            for key in &keys {
+                match store.claim(key, &owner, SIGN_IN_STATE_TTL_SECS) {
+                    Ok(None) => claimed.push(key.as_str()),
+                    Ok(Some(holder)) if holder == owner => retried = true,
+                    Ok(Some(_)) => {
+                        release_claims(ctx, store.as_ref(), &claimed);
+                        return Err(OAuthCardError::Replay(
+                            "this callback was already used to complete a sign-in".into(),
+                        ));
+                    }
+                    Err(err) => {
+                        release_claims(ctx, store.as_ref(), &claimed);
+                        return Err(err);
+                    }
+                }
+            }
+            if retried {
+                release_claims(ctx, store.as_ref(), &claimed);
+                return Err(if store.load(&completion_marker(&owner))?.is_some() {
+                    OAuthCardError::Replay(
+                        "this callback was already used to complete a sign-in".into(),
+                    )
+                } else {
+                    OAuthCardError::Timeout("this sign-in is still being completed".into())
+                });
+            }
+            match exchange_and_accept(backend, ctx, input, code, idempotency_key.as_deref()).await {
+                Ok(token) => {
+                    let completed = store.save(
+                        &completion_marker(&owner),
+                        &PendingSignIn::default(),
+                        SIGN_IN_STATE_TTL_SECS,
+                    );
+                    let deleted = input
+                        .state_id
+                        .as_deref()
+                        .map_or(Ok(()), |state_id| store.delete(state_id));
+                    if let Err(err) = completed.and(deleted) {
+                        logging::warn(
+                            ctx,
+                            "could not record completed sign-in state",
+                            json!({ "provider_id": input.provider_id, "error": err.to_string() }),
+                        );
+                    }
+                    token
+                }
+                Err(err) => {
+                    release_claims(ctx, store.as_ref(), &claimed);
+                    return Err(err);
+                }
+            }
+        }
+        None => exchange_and_accept(backend, ctx, input, code, idempotency_key.as_deref()).await?,
+    };
+    remember_connection(ctx, input);
+    let (card, context) = connected_card(backend, ctx, input, &token, "Connected").await;
+
+    Ok(OAuthCardOutput {
+        auth_context: Some(context),
+        auth_header: auth_header(input, &token)?,
+        auth_injection: Some(auth_injection(input, &token)),
+        ..OAuthCardOutput::ok(card)
+    })
+}
+
+/// Drop claims taken by a callback that did not complete, so it can be
+/// delivered again.
+fn release_claims(ctx: &CallContext, store: &dyn StateStore, keys: &[&str]) {
+    for key in keys {
+        if let Err(err) = store.release(key) {
+            logging::warn(
+                ctx,
+                "could not release sign-in claim",
+                json!({ "error": err.to_string() }),
+            );
+        }
+    }
+}
+
+/// State-store key marking that the sign-in attempt claimed by `owner`
+/// finished its exchange.
+fn completion_marker(owner: &str) -> String {
+    format!("completed:{owner}")
+}
+
+/// Exchange the code (with the saved flow state, when a store keeps it) and
+/// check the issued token.
+async fn exchange_and_accept<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    code: &str,
+    idempotency_key: Option<&str>,
+) -> Result<Zeroizing<TokenSet>, OAuthCardError> {
+    let pending = pending_sign_in(input)?.map(Zeroizing::new);
+    let redirect_path = match pending.as_ref() {
+        Some(pending) => pending.redirect_path.clone(),
+        None => redirect_path(input)?,
+    };
+    let scopes = pending.as_ref().map_or(&input.scopes, |p| &p.scopes);
+    let token = Zeroizing::new(
+        match backend
+            .exchange_code_with_extra(
+                ctx,
+                &input.provider_id,
+                &input.subject,
+                code,
+                &redirect_path,
+                idempotency_key,
+                exchange_extra(input)?,
+            )
+            .await
+        {
+            Ok(token) => token,
+            // A retried Continue click or duplicate callback delivery: the first
+            // exchange already stored the token.
+            Err(err) if err.code() == Some(BrokerErrorCode::AlreadyExchanged) => backend
+                .get_token(ctx, &input.provider_id, &input.subject, scopes)
+                .await?
+                .ok_or(err)?,
+            Err(err) => return Err(err),
+        },
+    );
+    accept_fresh_token(backend, ctx, input, &token, pending.as_deref()).await?;
+    Ok(token)
+}
+
+/// Check a token the broker just issued (and stored): its id_token
+/// verification, step-up, tenant and nonce. A rejected token is revoked, or
+/// forgotten here when the broker cannot revoke, so status-card and
+/// ensure-token never hand it out.
+async fn accept_fresh_token<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    token: &TokenSet,
+    pending: Option<&PendingSignIn>,
+) -> Result<(), OAuthCardError> {
+    let checked = match verify_id_token(input, token).and_then(|()| check_step_up(input, token)) {
+        Ok(()) => check_tenant(backend, ctx, input, token).await,
+        Err(err) => Err(err),
+    }
+    .and_then(|()| pending.map_or(Ok(()), |pending| check_nonce(pending, token)));
+    let Err(err) = checked else {
+        return Ok(());
+    };
+    match backend
+        .revoke_token(ctx, &input.provider_id, &input.subject, &input.scopes)
+        .await
+    {
+        Ok(()) => {}
+        Err(OAuthCardError::Unsupported(_)) if forget_connection(ctx, input) => {}
+        Err(revoke_err) => logging::warn(
+            ctx,
+            "could not discard a rejected token",
+            json!({ "provider_id": input.provider_id, "error": revoke_err.to_string() }),
+        ),
+    }
+    Err(err)
+}
+
+/// Key identifying the code exchange of one sign-in attempt, so replays of the
+/// same `state_id` are recognised by the broker.
+fn exchange_idempotency_key(input: &OAuthCardInput) -> Option<String> {
+    input
+        .state_id
+        .as_deref()
+        .map(|state_id| format!("{}:{}:{state_id}", input.provider_id, input.subject))
+}
+
+/// Silent SSO: exchange the channel token; if the provider still needs
+/// interactive consent, fall back to a regular sign-in card.
+async fn complete_sso<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    sso_token: &str,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    match backend
+        .exchange_sso_token(
+            ctx,
+            &input.provider_id,
+            &input.subject,
+            sso_token,
+            &input.scopes,
+        )
+        .await
+    {
+        Ok(token) => {
+            let token = Zeroizing::new(token);
+            accept_fresh_token(backend, ctx, input, &token, None).await?;
+            remember_connection(ctx, input);
+            let (card, context) = connected_card(backend, ctx, input, &token, "Connected").await;
+            Ok(OAuthCardOutput {
+                auth_context: Some(context),
+                auth_header: auth_header(input, &token)?,
+                auth_injection: Some(auth_injection(input, &token)),
+                ..OAuthCardOutput::ok(card)
+            })
+        }
+        Err(_) => {
+            let mut output = start_sign_in(backend, ctx, input).await?;
+            output.status = OAuthStatus::NeedsSignIn;
+            Ok(output)
+        }
+    }
+}
+
+async fn poll_sign_in<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let state_id = input
+        .state_id
+        .as_deref()
+        .ok_or_else(|| OAuthCardError::validation("state_id", "required to poll sign-in"))?;
+
+    match backend
+        .poll_sign_in(ctx, &input.provider_id, &input.subject, state_id)
+        .await?
+    {
+        SignInProgress::Pending => Ok(OAuthCardOutput {
+            status: OAuthStatus::Pending,
+            state_id: Some(state_id.to_string()),
+            ..Default::default()
+        }),
+        SignInProgress::Completed { token } => {
+            let token = Zeroizing::new(token);
+            remember_connection(ctx, input);
+            let (card, context) = connected_card(backend, ctx, input, &token, "Connected").await;
+            Ok(OAuthCardOutput {
+                auth_context: Some(context),
+                auth_header: auth_header(input, &token)?,
+                auth_injection: Some(auth_injection(input, &token)),
+                ..OAuthCardOutput::ok(card)
+            })
+        }
+        SignInProgress::Denied { reason } => {
+            let card = MessageCard::builder()
+                .kind(MessageCardKind::Oauth)
+                .title(format!("{} sign-in was declined", input.provider_id))
+                .text(
+                    reason
+                        .clone()
+                        .unwrap_or_else(|| "The authorization request was not approved.".into()),
+                )
+                .action(action("Try again", OAuthCardMode::StartSignIn, input, None))
+                .build();
+            Ok(OAuthCardOutput {
+                status: OAuthStatus::Denied,
+                card: Some(card),
+                error: reason,
+                ..Default::default()
+            })
+        }
+    }
+}
+
+async fn cancel_sign_in<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let state_id = input
+        .state_id
+        .as_deref()
+        .ok_or_else(|| OAuthCardError::validation("state_id", "required to cancel sign-in"))?;
+    // With a state store the component can invalidate the flow itself, so a
+    // broker without cancellation support is not an error.
+    let store = state_store::active_state_store();
+    if let Some(store) = store.as_ref() {
+        store.delete(state_id)?;
+    }
+    match backend
+        .cancel_sign_in(ctx, &input.provider_id, &input.subject, state_id)
+        .await
+    {
+        Err(OAuthCardError::Unsupported(_)) if store.is_some() => {}
+        result => result?,
+    }
+
+    let card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title("Sign-in cancelled")
+        .text(format!(
+            "The {} sign-in request was cancelled. You can start over at any time.",
+            input.provider_id
+        ))
+        .action(action("Restart", OAuthCardMode::StartSignIn, input, None))
+        .build();
+
+    Ok(OAuthCardOutput::ok(card))
+}
+
+/// How long a Check approval button stays valid; admins may take a while to
+/// approve.
+const ADMIN_CONSENT_STATE_TTL_SECS: u64 = 86_400;
+
+/// State-store key of the admin-consent request issued as `state_id`, apart
+/// from sign-in states so one cannot complete the other.
+fn admin_consent_key(state_id: &str) -> String {
+    format!("admin-consent:{state_id}")
+}
+
+/// Tenant an admin-consent request is for: the one sign-ins use
+/// ([`providers::requested_tenant`], as a Microsoft authority for
+/// Microsoft), else Microsoft's `organizations` (whichever work tenant the
+/// admin signs in to).
+fn admin_consent_tenant(input: &OAuthCardInput) -> Result<String, OAuthCardError> {
+    let tenant = match providers::requested_microsoft_authority(input)? {
+        Some(authority) => Some(authority).filter(|authority| authority != "common"),
+        None => providers::requested_tenant(input).map(str::to_string),
+    };
+    Ok(tenant.unwrap_or_else(|| "organizations".into()))
+}
+
+/// Card asking a tenant administrator to approve the scopes for everyone,
+/// with a button to check the approval afterwards. `still_pending` notes
+/// that a previous check found no consent yet.
+async fn admin_consent<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    tenant: &str,
+    still_pending: bool,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let redirect_path = redirect_path(input)?;
+    let state_id = signed_state::issue(&input.provider_id, &input.subject, now_secs());
+    if let Some(store) = state_store::active_state_store() {
+        let pending = PendingSignIn {
+            provider_id: input.provider_id.clone(),
+            subject: input.subject.clone(),
+            redirect_path: redirect_path.clone(),
+            scopes: input.scopes.clone(),
+            nonce: None,
+            tenant: Some(tenant.to_string()),
+        };
+        store.save(
+            &admin_consent_key(&state_id),
+            &pending,
+            ADMIN_CONSENT_STATE_TTL_SECS,
+        )?;
+    }
+    let config = provider_config::provider_config(&input.provider_id);
+    let url = match config
+        .as_ref()
+        .and_then(|config| config.admin_consent_url.as_deref())
+    {
+        Some(template) => provider_config::admin_consent_url(
+            template,
+            tenant,
+            &input.scopes,
+            &redirect_path,
+            &state_id,
+        ),
+        None => {
+            backend
+                .get_admin_consent_url(
+                    ctx,
+                    &input.provider_id,
+                    tenant,
+                    &input.scopes,
+                    &redirect_path,
+                )
+                .await?
+        }
+    };
+    provider_config::check_consent_url(config.as_ref(), &url)?;
+
+    let label = provider_label(&input.provider_id);
+    let mut text = format!(
+        "An administrator of {tenant} needs to approve {label} access for everyone in the organization before members can sign in."
+    );
+    if still_pending {
+        text.insert_str(0, "Admin consent has not been granted yet. ");
+    }
+    if let Some(list) = scope_list(input) {
+        text.push_str("\n\n");
+        text.push_str(&list);
+    }
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("Admin approval needed for {label}"))
+        .text(text)
+        .action(Action::OpenUrl {
+            title: "Grant admin consent".into(),
+            url: url.clone(),
+        })
+        .action(action(
+            "Check approval",
+            OAuthCardMode::CheckAdminConsent,
+            input,
+            Some(state_id.clone()),
+        ))
+        .oauth(OauthCard {
+            provider: OauthProvider::from_id(&input.provider_id),
+            scopes: input.scopes.clone(),
+            resource: token_target(input, "resource"),
+            prompt: Some(OauthPrompt::Consent),
+            start_url: Some(url),
+            connection_name: None,
+            metadata: Some(json!({
+                "audience": "tenant_admins",
+                "tenant": tenant,
+                "state_id": state_id,
+                "provider_id": input.provider_id,
+                "subject": input.subject,
+            })),
+        })
+        .build();
+    attach_provider_logo(input, &mut card);
+    apply_overrides(input, &mut card);
+
+    Ok(OAuthCardOutput {
+        status: OAuthStatus::NeedsConsent,
+        state_id: Some(state_id),
+        card: Some(card),
+        ..Default::default()
+    })
+}
+
+/// Ask the broker whether the tenant's admin approved the scopes; members get
+/// a Connect card once it has, otherwise the admin card is shown again. The
+/// `state_id` must be one admin-consent issued for the same provider and
+/// subject; with a state store its saved tenant and scopes are checked.
+async fn check_admin_consent<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    match input.state_id.as_deref() {
+        Some(state_id) => signed_state::verify(
+            state_id,
+            &input.provider_id,
+            &input.subject,
+            now_secs(),
+            ADMIN_CONSENT_STATE_TTL_SECS,
+        )?,
+        None if signed_state::enabled() => {
+            return Err(OAuthCardError::InvalidState("state_id is missing".into()));
+        }
+        None => {}
+    }
+    let store = state_store::active_state_store();
+    let pending = match store.as_ref() {
+        Some(store) => {
+            let key = admin_consent_key(input.state_id.as_deref().ok_or_else(|| {
+                OAuthCardError::validation("state_id", "required to check admin consent")
+            })?);
+            let pending = store
+                .load(&key)?
+                .filter(|pending| {
+                    pending.provider_id == input.provider_id && pending.subject == input.subject
+                })
+                .ok_or_else(|| {
+                    OAuthCardError::InvalidState(
+                        "admin consent request is unknown, expired, or belongs to another user"
+                            .into(),
+                    )
+                })?;
+            store.delete(&key)?;
+            Some(pending)
+        }
+        None => None,
+    };
+    let (input, tenant) = &match pending {
+        Some(pending) => {
+            let tenant = match pending.tenant {
+                Some(tenant) => tenant,
+                None => admin_consent_tenant(input)?,
+            };
+            let input = OAuthCardInput {
+                scopes: pending.scopes,
+                ..input.clone()
+            };
+            (input, tenant)
+        }
+        None => (input.clone(), admin_consent_tenant(input)?),
+    };
+    if !backend
+        .admin_consent_granted(ctx, &input.provider_id, tenant, &input.scopes)
+        .await?
+    {
+        return admin_consent(backend, ctx, input, tenant, true).await;
+    }
+    let label = provider_label(&input.provider_id);
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("{label} approved for {tenant}"))
+        .text("An administrator granted consent. Members can now connect their accounts.")
+        .action(action("Connect", OAuthCardMode::StartSignIn, input, None))
+        .build();
+    apply_overrides(input, &mut card);
+    Ok(OAuthCardOutput::ok(card))
+}
+
+async fn ensure_token<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let stored = stored_token(backend, ctx, input).await?.map(Zeroizing::new);
+    let had_token = stored.is_some();
+    let token = match stored {
+        Some(token) => refresh_if_expiring(backend, ctx, input, token).await?,
+        None => None,
+    };
+    // A stored token that could not be kept alive reads as expired rather
+    // than never connected.
+    let signed_out = if had_token {
+        OAuthStatus::Expired
+    } else {
+        OAuthStatus::NeedsSignIn
+    };
+    if let Some(token) = token {
+        check_token_binding(backend, ctx, input, &token).await?;
+        let missing = missing_scopes(&input.scopes, &token.scopes());
+        if !missing.is_empty() {
+            log_missing_scopes(ctx, input, &missing);
+            return consent_upgrade(backend, ctx, input, missing).await;
+        }
+        return Ok(OAuthCardOutput {
+            status: OAuthStatus::Ok,
+            auth_context: Some(auth_context(ctx, input, &token)),
+            auth_header: auth_header(input, &token)?,
+            auth_injection: Some(auth_injection(input, &token)),
+            ..Default::default()
+        });
+    }
+
+    if input.allow_auto_sign_in {
+        let (state_id, consent_url) = consent_request(backend, ctx, input).await?;
+        let card = sign_in_card(input, &state_id, &consent_url);
+
+        Ok(OAuthCardOutput {
+            status: signed_out,
+            card: Some(card),
+            state_id: Some(state_id),
+            ..Default::default()
+        })
+    } else {
+        Ok(OAuthCardOutput {
+            status: signed_out,
+            ..Default::default()
+        })
+    }
+}
+
+async fn device_code<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    // A state_id names a flow that was already started; its device code
+    // never leaves the component.
+    if let Some(state_id) = input.state_id.as_deref() {
+        signed_state::verify(
+            state_id,
+            &input.provider_id,
+            &input.subject,
+            now_secs(),
+            DEVICE_CODE_TTL_SECS,
+        )?;
+        let device_code = load_device_code(state_id)?.ok_or_else(|| {
+            OAuthCardError::InvalidState("device sign-in expired or was never started".into())
+        })?;
+        if let Some(token) = backend
+            .poll_device_code(ctx, &input.provider_id, &input.subject, &device_code)
+            .await?
+            .map(Zeroizing::new)
+        {
+            delete_device_code(ctx, state_id);
+            remember_connection(ctx, input);
+            let (card, context) = connected_card(backend, ctx, input, &token, "Connected").await;
+            return Ok(OAuthCardOutput {
+                auth_context: Some(context),
+                auth_header: auth_header(input, &token)?,
+                auth_injection: Some(auth_injection(input, &token)),
+                ..OAuthCardOutput::ok(card)
+            });
+        }
+        return Ok(OAuthCardOutput {
+            status: OAuthStatus::Pending,
+            state_id: Some(state_id.to_string()),
+            ..Default::default()
+        });
+    }
+
+    let grant = backend
+        .start_device_code(
+            ctx,
+            &input.provider_id,
+            &input.subject,
+            &input.scopes,
+            broker_extra(input.extra_json.as_ref()),
+        )
+        .await?;
+    let state_id = signed_state::issue(&input.provider_id, &input.subject, now_secs());
+    let ttl_secs = grant.expires_in.map_or(DEVICE_CODE_TTL_SECS, |expires_in| {
+        expires_in.min(DEVICE_CODE_TTL_SECS)
+    });
+    save_device_code(&state_id, &grant.device_code, ttl_secs)?;
+    let card = device_code_card(input, &grant, &state_id);
+
+    Ok(OAuthCardOutput {
+        status: OAuthStatus::Pending,
+        card: Some(card),
+        state_id: Some(state_id),
+        ..Default::default()
+    })
+}
+
+fn device_code_key(state_id: &str) -> String {
+    format!("device:{state_id}")
+}
+
+fn device_codes() -> MutexGuard<'static, BTreeMap<String, (Zeroizing<String>, u64)>> {
+    DEVICE_CODES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Keep a started flow's device code under its `state_id` for `ttl_secs`, in
+/// the active state store or else in process memory.
+fn save_device_code(
+    state_id: &str,
+    device_code: &str,
+    ttl_secs: u64,
+) -> Result<(), OAuthCardError> {
+    if let Some(store) = state_store::active_state_store() {
+        match store.save_value(&device_code_key(state_id), device_code, ttl_secs) {
+            Err(OAuthCardError::Unsupported(_)) => {}
+            saved => return saved,
+        }
+    }
+    let now = now_secs();
+    let mut codes = device_codes();
+    codes.retain(|_, (_, expires_at)| now < *expires_at);
+    codes.insert(
+        state_id.to_string(),
+        (
+            Zeroizing::new(device_code.to_string()),
+            now.saturating_add(ttl_secs),
+        ),
+    );
+    Ok(())
+}
+
+/// The device code saved by [`save_device_code`], unless it expired.
+fn load_device_code(state_id: &str) -> Result<Option<Zeroizing<String>>, OAuthCardError> {
+    if let Some(store) = state_store::active_state_store() {
+        match store.load_value(&device_code_key(state_id)) {
+            Err(OAuthCardError::Unsupported(_)) => {}
+            loaded => return Ok(loaded?.map(Zeroizing::new)),
+        }
+    }
+    let now = now_secs();
+    Ok(device_codes()
+        .get(state_id)
+        .filter(|(_, expires_at)| now < *expires_at)
+        .map(|(device_code, _)| device_code.clone()))
+}
+
+/// Forget a device code once its flow completed.
+fn delete_device_code(ctx: &CallContext, state_id: &str) {
+    device_codes().remove(state_id);
+    if let Some(store) = state_store::active_state_store() {
+        match store.delete_value(&device_code_key(state_id)) {
+            Ok(()) | Err(OAuthCardError::Unsupported(_)) => {}
+            Err(err) => logging::warn(
+                ctx,
+                "could not delete device code",
+                json!({ "error": err.to_string() }),
+            ),
+        }
+    }
+}
+
+async fn client_credentials<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let token = Zeroizing::new(
+        backend
+            .get_app_token(
+                ctx,
+                &input.provider_id,
+                &input.scopes,
+                broker_extra(input.extra_json.as_ref()),
+            )
+            .await?,
+    );
+    // App tokens act as the client itself, so prefer the identity the broker reports.
+    let mut context = auth_context(ctx, input, &token);
+    if let Some(client_id) = token
+        .extra
+        .as_ref()
+        .and_then(|extra| extra.get("client_id"))
+        .and_then(|v| v.as_str())
+    {
+        context.subject = client_id.to_string();
+    }
+
+    Ok(OAuthCardOutput {
+        status: OAuthStatus::Ok,
+        auth_context: Some(context),
+        auth_header: auth_header(input, &token)?,
+        auth_injection: Some(auth_injection(input, &token)),
+        ..Default::default()
+    })
+}
+
+/// `subject_token_type` assumed when the input names none.
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Exchange the input's `subject_token` (or the subject's stored token, looked
+/// up and refreshed like ensure-token does) for a token with the input's
+/// scopes and `resource`/`audience`; no card.
+async fn token_exchange<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let stored;
+    let subject_token = match input.subject_token.as_deref() {
+        Some(subject_token) => subject_token,
+        None => {
+            let usable = match stored_token(backend, ctx, input).await? {
+                Some(token) => {
+                    refresh_if_expiring(backend, ctx, input, Zeroizing::new(token)).await?
+                }
+                None => None,
+            };
+            stored = usable.ok_or_else(|| {
+                OAuthCardError::validation(
+                    "subject_token",
+                    "required when the subject has no usable stored token",
+                )
+            })?;
+            check_token_binding(backend, ctx, input, &stored).await?;
+            stored.access_token.as_str()
+        }
+    };
+    let token = Zeroizing::new(
+        backend
+            .exchange_token(
+                ctx,
+                &input.provider_id,
+                &input.subject,
+                subject_token,
+                input
+                    .subject_token_type
+                    .as_deref()
+                    .unwrap_or(ACCESS_TOKEN_TYPE),
+                &input.scopes,
+                exchange_extra(input)?,
+            )
+            .await?,
+    );
+
+    Ok(OAuthCardOutput {
+        status: OAuthStatus::Ok,
+        auth_context: Some(auth_context(ctx, input, &token)),
+        auth_header: auth_header(input, &token)?,
+        auth_injection: Some(auth_injection(input, &token)),
+        ..Default::default()
+    })
+}
+
+async fn batch_ensure<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    if input.providers.is_empty() {
+        return Err(OAuthCardError::validation(
+            "providers",
+            "required for batch-ensure",
+        ));
+    }
+
+    let mut results = Vec::with_capacity(input.providers.len());
+    let mut missing = Vec::new();
+    for request in &input.providers {
+        let mut provider_input =
+            providers::with_defaults(provider_config::with_defaults(OAuthCardInput {
+                mode: OAuthCardMode::EnsureToken,
+                provider_id: request.provider_id.clone(),
+                scopes: if request.scopes.is_empty() {
+                    input.scopes.clone()
+                } else {
+                    request.scopes.clone()
+                },
+                allow_auto_sign_in: false,
+                providers: Vec::new(),
+                ..input.clone()
+            }));
+        let outcome = match provider_config::restrict_scopes(&mut provider_input) {
+            Ok(dropped) => ensure_token(backend, ctx, &provider_input)
+                .await
+                .map(|output| (output, dropped)),
+            Err(err) => Err(err),
+        };
+        let result = match outcome {
+            Ok((output, dropped_scopes)) => ProviderResult {
+                provider_id: request.provider_id.clone(),
+                status: output.status,
+                auth_context: output.auth_context,
+                auth_header: output.auth_header,
+                auth_injection: output.auth_injection,
+                error: output.error,
+                dropped_scopes,
+            },
+            Err(err) => ProviderResult {
+                provider_id: request.provider_id.clone(),
+                status: OAuthStatus::Error,
+                auth_context: None,
+                auth_header: None,
+                auth_injection: None,
+                error: Some(err.to_string()),
+                dropped_scopes: Vec::new(),
+            },
+        };
+        if result.status != OAuthStatus::Ok {
+            missing.push(provider_input);
+        }
+        results.push(result);
+    }
+
+    let card = if missing.is_empty() {
+        None
+    } else {
+        let mut card = MessageCard::builder()
+            .kind(MessageCardKind::Oauth)
+            .title("Connect your accounts")
+            .text(format!(
+                "Sign in to continue:\n{}",
+                missing
+                    .iter()
+                    .map(|p| format!("- {}", p.provider_id))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ))
+            .build();
+        for provider_input in &missing {
+            card.actions.push(action(
+                &format!("Connect {}", provider_input.provider_id),
+                OAuthCardMode::StartSignIn,
+                provider_input,
+                None,
+            ));
+        }
+        Some(card)
+    };
+
+    Ok(OAuthCardOutput {
+        status: if missing.is_empty() {
+            OAuthStatus::Ok
+        } else {
+            OAuthStatus::NeedsSignIn
+        },
+        card,
+        provider_results: results,
+        ..Default::default()
+    })
+}
+
+/// One card with a "Sign in with ..." StartSignIn button per candidate provider.
+fn choose_provider(input: &OAuthCardInput) -> Result<OAuthCardOutput, OAuthCardError> {
+    if input.providers.is_empty() {
+        return Err(OAuthCardError::validation(
+            "providers",
+            "required for choose-provider",
+        ));
+    }
+
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title("Choose how to sign in")
+        .text(format!("Pick the account {} should use.", input.subject))
+        .build();
+    for request in &input.providers {
+        let provider_input =
+            providers::with_defaults(provider_config::with_defaults(OAuthCardInput {
+                provider_id: request.provider_id.clone(),
+                scopes: if request.scopes.is_empty() {
+                    input.scopes.clone()
+                } else {
+                    request.scopes.clone()
+                },
+                ..input.clone()
+            }));
+        card.actions.push(action(
+            &format!("Sign in with {}", provider_label(&request.provider_id)),
+            OAuthCardMode::StartSignIn,
+            &provider_input,
+            None,
+        ));
+    }
+    apply_overrides(input, &mut card);
+
+    Ok(OAuthCardOutput::needs_sign_in(card, None))
+}
+
+async fn list_connections<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    let connections = backend.list_connections(ctx, &input.subject).await?;
+    let text = if connections.is_empty() {
+        "No accounts are connected yet.".to_string()
+    } else {
+        connections
+            .iter()
+            .map(|c| match &c.label {
+                Some(label) => format!("- {} ({label})", c.provider_id),
+                None => format!("- {}", c.provider_id),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("Connected accounts for {}", input.subject))
+        .text(text)
+        .build();
+    let capabilities = backend.capabilities();
+    for connection in &connections {
+        let provider_input = OAuthCardInput {
+            provider_id: connection.provider_id.clone(),
+            scopes: Scope::parse_list(&connection.scopes)?,
+            ..input.clone()
+        };
+        if capabilities.refresh {
+            card.actions.push(action(
+                &format!("Refresh {}", connection.provider_id),
+                OAuthCardMode::EnsureToken,
+                &provider_input,
+                None,
+            ));
+        }
+        if capabilities.revoke {
+            card.actions.push(action(
+                &format!("Disconnect {}", connection.provider_id),
+                OAuthCardMode::Disconnect,
+                &provider_input,
+                None,
+            ));
+        }
+    }
+
+    Ok(OAuthCardOutput {
+        connections,
+        ..OAuthCardOutput::ok(card)
+    })
+}
+
+async fn switch_account<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+) -> Result<OAuthCardOutput, OAuthCardError> {
+    if let Some(account_id) = input.account_id.as_deref() {
+        backend
+            .select_account(ctx, &input.provider_id, &input.subject, account_id)
+            .await?;
+        return status_card(backend, ctx, input).await;
+    }
+
+    let accounts = backend
+        .list_accounts(ctx, &input.provider_id, &input.subject)
+        .await?;
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("Choose a {} account", input.provider_id))
+        .text(if accounts.is_empty() {
+            "No accounts are connected yet.".to_string()
+        } else {
+            "Select the account to use from now on.".to_string()
+        })
+        .build();
+    for account in &accounts {
+        let label = account
+            .label
+            .as_deref()
+            .or(account.email.as_deref())
+            .unwrap_or(&account.account_id);
+        card.actions.push(Action::PostBack {
+            title: if account.active {
+                format!("{label} (current)")
+            } else {
+                label.to_string()
+            },
+            data: json!({
+                "mode": OAuthCardMode::SwitchAccount,
+                "provider_id": input.provider_id,
+                "subject": input.subject,
+                "account_id": account.account_id,
+                "scopes": input.scopes,
+            }),
+        });
+    }
+    card.actions.push(action(
+        "Add another account",
+        OAuthCardMode::StartSignIn,
+        input,
+        None,
+    ));
 
-use crate::OAuthCardError;
-use crate::broker::OAuthBackend;
-use crate::model::{
-    Action, AuthContext, AuthHeader, MessageCard, MessageCardKind, OAuthCardInput, OAuthCardMode,
-    OAuthCardOutput, OAuthStatus, OauthCard, OauthPrompt, OauthProvider, TokenSet,
-};
-use serde_json::json;
+    Ok(OAuthCardOutput {
+        accounts,
+        ..OAuthCardOutput::ok(card)
+    })
+}
 
-pub fn handle<B: OAuthBackend>(
+/// Ask only for the scopes the current grant is missing (incremental consent).
+async fn consent_upgrade<B: AsyncOAuthBackend>(
     backend: &B,
-    input: OAuthCardInput,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    missing: Vec<Scope>,
 ) -> Result<OAuthCardOutput, OAuthCardError> {
-    match input.mode {
-        OAuthCardMode::StatusCard => status_card(backend, &input),
-        OAuthCardMode::StartSignIn => start_sign_in(backend, &input),
-        OAuthCardMode::CompleteSignIn => complete_sign_in(backend, &input),
-        OAuthCardMode::EnsureToken => ensure_token(backend, &input),
-        OAuthCardMode::Disconnect => disconnect_card(&input),
+    let consent_input = OAuthCardInput {
+        scopes: missing,
+        ..input.clone()
+    };
+    let mut output = start_sign_in(backend, ctx, &consent_input).await?;
+    output.status = OAuthStatus::NeedsConsent;
+    if let Some(card) = output.card.as_mut() {
+        card.title = Some(format!(
+            "Additional {} permissions needed",
+            input.provider_id
+        ));
+        card.text = Some(format!(
+            "Grant these additional permissions to continue:\n{}",
+            consent_input
+                .scopes
+                .iter()
+                .map(|scope| format!("- {scope}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ));
     }
+    Ok(output)
 }
 
-fn status_card<B: OAuthBackend>(
+/// Note that a stored token was passed over for lacking `missing` scopes.
+fn log_missing_scopes(ctx: &CallContext, input: &OAuthCardInput, missing: &[Scope]) {
+    logging::info(
+        ctx,
+        "stored token lacks requested scopes",
+        json!({ "provider_id": input.provider_id, "missing_scopes": missing }),
+    );
+}
+
+/// Requested scopes not present in the granted set. An empty granted set means
+/// the broker does not report grants, so coverage is assumed.
+fn missing_scopes(requested: &[Scope], granted: &[String]) -> Vec<Scope> {
+    if granted.is_empty() {
+        return Vec::new();
+    }
+    requested
+        .iter()
+        .filter(|scope| !granted.iter().any(|g| g.eq_ignore_ascii_case(scope)))
+        .cloned()
+        .collect()
+}
+
+/// Refresh tokens that expire within the configured margin. A failed refresh
+/// keeps a still-valid token; an already expired one is dropped so the caller
+/// falls back to sign-in, unless the failure was transient, in which case the
+/// error is surfaced instead of asking the user to sign in again.
+async fn refresh_if_expiring<B: AsyncOAuthBackend>(
     backend: &B,
+    ctx: &CallContext,
     input: &OAuthCardInput,
-) -> Result<OAuthCardOutput, OAuthCardError> {
-    let token = backend.get_token(&input.provider_id, &input.subject, &input.scopes)?;
+    token: Zeroizing<TokenSet>,
+) -> Result<Option<Zeroizing<TokenSet>>, OAuthCardError> {
+    let Some(expires_at) = token.expires_at else {
+        return Ok(Some(token));
+    };
+    let now = now_secs();
+    let margin = input
+        .refresh_margin_secs
+        .unwrap_or(DEFAULT_REFRESH_MARGIN_SECS)
+        .max(clock_skew(input));
+    if expires_at > now.saturating_add(margin) {
+        return Ok(Some(token));
+    }
+    match backend
+        .refresh_token(ctx, &input.provider_id, &input.subject, &input.scopes)
+        .await
+    {
+        Ok(refreshed) if !is_expired(input, &refreshed, now) => Ok(Some(Zeroizing::new(refreshed))),
+        Ok(_) => Ok(None),
+        Err(_) if !is_expired(input, &token, now) => Ok(Some(token)),
+        Err(err) if err.is_transient() => Err(err),
+        Err(_) => Ok(None),
+    }
+}
 
-    if let Some(token) = token {
-        let card = connected_card(input, &token, "Connected");
-        Ok(OAuthCardOutput {
-            status: OAuthStatus::Ok,
-            card: Some(card),
-            auth_context: Some(auth_context(input, &token)),
-            auth_header: Some(auth_header(&token)),
-            state_id: None,
-            error: None,
-        })
-    } else {
-        let card = connect_prompt_card(input, None);
-        Ok(OAuthCardOutput {
-            status: OAuthStatus::NeedsSignIn,
-            card: Some(card),
-            auth_context: None,
-            auth_header: None,
-            state_id: None,
-            error: None,
-        })
+fn clock_skew(input: &OAuthCardInput) -> u64 {
+    input.clock_skew_secs.unwrap_or(DEFAULT_CLOCK_SKEW_SECS)
+}
+
+/// Whether `token` has expired at `now`, counting tokens that expire within
+/// the tolerated clock skew as already gone.
+fn is_expired(input: &OAuthCardInput, token: &TokenSet, now: u64) -> bool {
+    token
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now.saturating_add(clock_skew(input)))
+}
+
+/// Apply the card template for the input's mode: the component config's
+/// `card_templates.<mode>`, overridden field by field by the provider config's
+/// and then by the flow-supplied `extra_json.card_templates.<mode>`.
+fn apply_card_template(
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    context: Option<&AuthContext>,
+    card: &mut MessageCard,
+) {
+    let mode = mode_name(&input.mode);
+    let flow_template = input
+        .extra_json
+        .as_ref()
+        .and_then(|extra| extra.get("card_templates"))
+        .and_then(|templates| templates.get(&mode))
+        .and_then(|value| {
+            serde_json::from_value::<CardTemplate>(value.clone())
+                .inspect_err(|err| {
+                    logging::warn(
+                        ctx,
+                        "ignoring invalid card template",
+                        json!({ "mode": mode, "error": err.to_string() }),
+                    )
+                })
+                .ok()
+        });
+    let layers = [
+        component_config::component_config()
+            .card_templates
+            .remove(&mode),
+        provider_config::provider_config(&input.provider_id)
+            .and_then(|mut config| config.card_templates.remove(&mode)),
+        flow_template,
+    ];
+    let Some(template) = layers
+        .into_iter()
+        .flatten()
+        .reduce(|base, over| over.over(base))
+    else {
+        return;
+    };
+
+    let mut vars = BTreeMap::new();
+    vars.insert("provider", input.provider_id.clone());
+    vars.insert("provider_name", provider_label(&input.provider_id));
+    vars.insert("subject", input.subject.clone());
+    vars.insert("team", input.team.clone().unwrap_or_default());
+    vars.insert("tenant", input.tenant.clone().unwrap_or_default());
+    vars.insert(
+        "expires_in",
+        context
+            .and_then(|ctx| ctx.expires_at)
+            .map(|expires_at| humanize_duration(expires_at.saturating_sub(now_secs())))
+            .unwrap_or_default(),
+    );
+    template.apply(card, &vars);
+}
+
+/// The component config's branding, overridden field by field by the
+/// provider config's and then by the flow-supplied `extra_json.branding`.
+fn branding(ctx: &CallContext, input: &OAuthCardInput) -> Option<Branding> {
+    let flow_branding = input
+        .extra_json
+        .as_ref()
+        .and_then(|extra| extra.get("branding"))
+        .and_then(|value| {
+            serde_json::from_value(value.clone())
+                .inspect_err(|err| {
+                    logging::warn(
+                        ctx,
+                        "ignoring invalid branding",
+                        json!({ "error": err.to_string() }),
+                    )
+                })
+                .ok()
+        });
+    let layers = [
+        component_config::component_config().branding,
+        provider_config::provider_config(&input.provider_id).and_then(|config| config.branding),
+        flow_branding,
+    ];
+    layers
+        .into_iter()
+        .flatten()
+        .reduce(|base, over| over.over(base))
+}
+
+fn apply_branding(branding: &Branding, card: &mut MessageCard) {
+    if let Some(logo_url) = &branding.logo_url {
+        card.images.insert(
+            0,
+            ImageRef {
+                url: logo_url.clone(),
+                alt: branding.product_name.clone(),
+            },
+        );
+    }
+    if card.footer.is_none() {
+        card.footer = branding.footer_text.clone().or_else(|| {
+            branding
+                .product_name
+                .as_ref()
+                .map(|name| format!("Secured by {name}"))
+        });
     }
 }
 
-fn start_sign_in<B: OAuthBackend>(
+fn mode_name(mode: &OAuthCardMode) -> String {
+    serde_json::to_value(mode)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+async fn disconnect<B: AsyncOAuthBackend>(
     backend: &B,
+    ctx: &CallContext,
     input: &OAuthCardInput,
 ) -> Result<OAuthCardOutput, OAuthCardError> {
-    let state_id = input
-        .state_id
-        .clone()
-        .unwrap_or_else(|| Uuid::new_v4().to_string());
-    let redirect_path = redirect_path(input);
-    let consent_url = backend
-        .get_consent_url(
-            &input.provider_id,
-            &input.subject,
-            &input.scopes,
-            &redirect_path,
-            input.extra_json.as_ref().map(|v| v.to_string()),
-        )
-        .unwrap_or_default();
-    let card = sign_in_card(input, &state_id, &consent_url);
+    if input.require_confirmation {
+        return Ok(confirmation_output(input));
+    }
+    match backend
+        .revoke_token(ctx, &input.provider_id, &input.subject, &input.scopes)
+        .await
+    {
+        Ok(()) => disconnect_card(input),
+        // Brokers that cannot revoke (the host broker) leave the grant in
+        // place: forget it here and tell the user to unlink at the provider.
+        Err(OAuthCardError::Unsupported(_)) if forget_connection(ctx, input) => {
+            let mut output = disconnect_card(input)?;
+            if let Some(card) = output.card.as_mut() {
+                card.text = Some(format!(
+                    "The connection was removed here, but {provider} access could not be revoked. \
+                     To remove it completely, unlink this app in your {provider} account settings.",
+                    provider = input.provider_id
+                ));
+            }
+            Ok(output)
+        }
+        Err(err) => Ok(revoke_failed_output(input, &err)),
+    }
+}
 
-    Ok(OAuthCardOutput {
-        status: OAuthStatus::Ok,
+fn revoke_failed_output(input: &OAuthCardInput, err: &OAuthCardError) -> OAuthCardOutput {
+    let card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("Could not disconnect {}", input.provider_id))
+        .text(format!(
+            "Revoking the {} token failed: {err}",
+            input.provider_id
+        ))
+        .action(action("Try again", OAuthCardMode::Disconnect, input, None))
+        .build();
+    OAuthCardOutput {
+        status: OAuthStatus::Error,
         card: Some(card),
-        auth_context: None,
-        auth_header: None,
-        state_id: Some(state_id),
-        error: None,
-    })
+        error: Some(err.to_string()),
+        ..Default::default()
+    }
+}
+
+/// State-store key marking the subject's connection as disconnected here.
+fn forgotten_key(input: &OAuthCardInput) -> String {
+    format!("forgotten:{}:{}", input.provider_id, input.subject)
+}
+
+/// Hide the subject's connection until it signs in again; `false` when no
+/// state store can record that.
+fn forget_connection(ctx: &CallContext, input: &OAuthCardInput) -> bool {
+    let Some(store) = state_store::active_state_store() else {
+        return false;
+    };
+    match store.save_value(&forgotten_key(input), "1", FORGOTTEN_CONNECTION_TTL_SECS) {
+        Ok(()) => true,
+        Err(err) => {
+            logging::warn(
+                ctx,
+                "could not forget connection",
+                json!({ "provider_id": input.provider_id, "error": err.to_string() }),
+            );
+            false
+        }
+    }
+}
+
+/// Undo [`forget_connection`] after a fresh sign-in.
+fn remember_connection(ctx: &CallContext, input: &OAuthCardInput) {
+    if let Some(store) = state_store::active_state_store()
+        && let Err(err) = store.delete_value(&forgotten_key(input))
+    {
+        logging::warn(
+            ctx,
+            "could not restore connection",
+            json!({ "provider_id": input.provider_id, "error": err.to_string() }),
+        );
+    }
 }
 
-fn complete_sign_in<B: OAuthBackend>(
+/// The broker's token for the input, unless the subject disconnected it while
+/// the broker could not revoke it.
+async fn stored_token<B: AsyncOAuthBackend>(
     backend: &B,
+    ctx: &CallContext,
     input: &OAuthCardInput,
-) -> Result<OAuthCardOutput, OAuthCardError> {
-    let code = input.auth_code.as_ref().ok_or_else(|| {
-        OAuthCardError::Invalid("auth_code is required to complete sign-in".into())
-    })?;
-    let redirect_path = redirect_path(input);
-    let token = backend.exchange_code(&input.provider_id, &input.subject, code, &redirect_path)?;
-    let card = connected_card(input, &token, "Connected");
-
-    Ok(OAuthCardOutput {
-        status: OAuthStatus::Ok,
-        card: Some(card),
-        auth_context: Some(auth_context(input, &token)),
-        auth_header: Some(auth_header(&token)),
-        state_id: None,
-        error: None,
-    })
+) -> Result<Option<TokenSet>, OAuthCardError> {
+    if let Some(store) = state_store::active_state_store()
+        && store.load_value(&forgotten_key(input))?.is_some()
+    {
+        return Ok(None);
+    }
+    backend
+        .get_token(ctx, &input.provider_id, &input.subject, &input.scopes)
+        .await
 }
 
-fn ensure_token<B: OAuthBackend>(
+/// Disconnect plus a link that terminates the provider-side session.
+async fn logout<B: AsyncOAuthBackend>(
     backend: &B,
+    ctx: &CallContext,
     input: &OAuthCardInput,
 ) -> Result<OAuthCardOutput, OAuthCardError> {
-    if let Some(token) = backend.get_token(&input.provider_id, &input.subject, &input.scopes)? {
-        return Ok(OAuthCardOutput {
-            status: OAuthStatus::Ok,
-            card: None,
-            auth_context: Some(auth_context(input, &token)),
-            auth_header: Some(auth_header(&token)),
-            state_id: None,
-            error: None,
-        });
+    if input.require_confirmation {
+        return Ok(confirmation_output(input));
     }
+    let end_session_url = match backend
+        .get_end_session_url(ctx, &input.provider_id, &input.subject)
+        .await
+    {
+        Err(OAuthCardError::Unsupported(message)) => {
+            discovery::end_session_endpoint(input).ok_or(OAuthCardError::Unsupported(message))?
+        }
+        result => result?,
+    };
+    let mut output = disconnect(backend, ctx, input).await?;
+    if output.status != OAuthStatus::Revoked {
+        return Ok(output);
+    }
+    if let Some(card) = output.card.as_mut() {
+        card.title = Some(format!("Signed out of {}", input.provider_id));
+        card.text = Some(format!(
+            "Your token was removed. Open the link below to also end your {} session on this device.",
+            input.provider_id
+        ));
+        card.actions.insert(
+            0,
+            Action::OpenUrl {
+                title: format!("Sign out of {}", input.provider_id),
+                url: end_session_url.clone(),
+            },
+        );
+        if let Some(oauth) = card.oauth.as_mut() {
+            oauth.start_url = Some(end_session_url);
+        }
+    }
+    Ok(output)
+}
 
-    if input.allow_auto_sign_in {
-        let state_id = input
-            .state_id
-            .clone()
-            .unwrap_or_else(|| Uuid::new_v4().to_string());
-        let redirect_path = redirect_path(input);
-        let consent_url = backend
-            .get_consent_url(
-                &input.provider_id,
-                &input.subject,
-                &input.scopes,
-                &redirect_path,
-                input.extra_json.as_ref().map(|v| v.to_string()),
-            )
-            .unwrap_or_default();
-        let card = sign_in_card(input, &state_id, &consent_url);
-
-        Ok(OAuthCardOutput {
-            status: OAuthStatus::NeedsSignIn,
-            card: Some(card),
-            auth_context: None,
-            auth_header: None,
-            state_id: Some(state_id),
-            error: None,
-        })
+/// "Are you sure?" card for disconnect/logout; its confirm button replays the
+/// mode without `require_confirmation`.
+fn confirmation_output(input: &OAuthCardInput) -> OAuthCardOutput {
+    let (title, text, confirm) = if input.mode == OAuthCardMode::Logout {
+        (
+            format!("Sign out of {}?", input.provider_id),
+            format!(
+                "This removes your {} token and ends the provider session.",
+                input.provider_id
+            ),
+            "Sign out",
+        )
     } else {
-        Ok(OAuthCardOutput {
-            status: OAuthStatus::NeedsSignIn,
-            card: None,
-            auth_context: None,
-            auth_header: None,
-            state_id: None,
-            error: None,
-        })
-    }
+        (
+            format!("Disconnect {}?", input.provider_id),
+            format!(
+                "Flows will have to ask you to sign in to {} again.",
+                input.provider_id
+            ),
+            "Disconnect",
+        )
+    };
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Confirmation)
+        .title(title)
+        .text(text)
+        .action(action(confirm, input.mode.clone(), input, None))
+        .action(action("Cancel", OAuthCardMode::StatusCard, input, None))
+        .build();
+    apply_overrides(input, &mut card);
+    OAuthCardOutput::ok(card)
 }
 
 fn disconnect_card(input: &OAuthCardInput) -> Result<OAuthCardOutput, OAuthCardError> {
-    let mut card = base_card(
-        MessageCardKind::Oauth,
-        Some(format!("Disconnected from {}", input.provider_id)),
-        Some("You can reconnect this account at any time.".into()),
-    );
-    card.actions
-        .push(action("Reconnect", OAuthCardMode::StartSignIn, input, None));
-    card.oauth = Some(OauthCard {
-        provider: provider_from_id(&input.provider_id),
-        scopes: input.scopes.clone(),
-        resource: None,
-        prompt: None,
-        start_url: None,
-        connection_name: None,
-        metadata: Some(json!({
-            "provider_id": input.provider_id,
-            "subject": input.subject,
-        })),
-    });
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("Disconnected from {}", input.provider_id))
+        .text("You can reconnect this account at any time.")
+        .action(action("Reconnect", OAuthCardMode::StartSignIn, input, None))
+        .oauth(OauthCard {
+            provider: OauthProvider::from_id(&input.provider_id),
+            scopes: input.scopes.clone(),
+            resource: token_target(input, "resource"),
+            prompt: None,
+            start_url: None,
+            connection_name: None,
+            metadata: Some(json!({
+                "provider_id": input.provider_id,
+                "subject": input.subject,
+            })),
+        })
+        .build();
+
+    apply_overrides(input, &mut card);
 
     Ok(OAuthCardOutput {
-        status: OAuthStatus::Ok,
+        status: OAuthStatus::Revoked,
         card: Some(card),
-        auth_context: None,
-        auth_header: None,
-        state_id: None,
-        error: None,
+        ..Default::default()
     })
 }
 
 fn sign_in_card(input: &OAuthCardInput, state_id: &str, url: &str) -> MessageCard {
-    let mut card = base_card(
-        MessageCardKind::Oauth,
-        Some(format!("Connect {} account", input.provider_id)),
-        Some(format!(
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("Connect {} account", input.provider_id))
+        .text(format!(
             "Click Connect to sign in as {}{}.",
             input.subject,
             input
@@ -196,13 +2414,21 @@ fn sign_in_card(input: &OAuthCardInput, state_id: &str, url: &str) -> MessageCar
                 .as_ref()
                 .map(|team| format!(" (team {team})"))
                 .unwrap_or_default()
-        )),
-    );
+        ))
+        .build();
+    attach_provider_logo(input, &mut card);
+    if let Some(list) = scope_list(input)
+        && let Some(text) = card.text.as_mut()
+    {
+        text.push_str("\n\n");
+        text.push_str(&list);
+    }
     if !url.is_empty() {
         card.actions.push(Action::OpenUrl {
             title: "Connect".into(),
             url: url.into(),
         });
+        attach_qr(&mut card, input, url);
     }
     card.actions.push(action(
         "Continue",
@@ -211,62 +2437,222 @@ fn sign_in_card(input: &OAuthCardInput, state_id: &str, url: &str) -> MessageCar
         Some(state_id.to_string()),
     ));
     card.oauth = Some(OauthCard {
-        provider: provider_from_id(&input.provider_id),
+        provider: OauthProvider::from_id(&input.provider_id),
         scopes: input.scopes.clone(),
-        resource: None,
-        prompt: Some(OauthPrompt::Consent),
+        resource: token_target(input, "resource"),
+        prompt: Some(input.prompt.clone().unwrap_or(OauthPrompt::Consent)),
         start_url: if url.is_empty() {
             None
         } else {
             Some(url.to_string())
         },
         connection_name: None,
+        metadata: Some(Value::Object(
+            [
+                ("state_id", json!(state_id)),
+                ("provider_id", json!(input.provider_id)),
+                ("subject", json!(input.subject)),
+            ]
+            .into_iter()
+            .chain(sign_in_hints(input))
+            .chain(microsoft_endpoint(input))
+            .chain(discovered_endpoints(input))
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+        )),
+    });
+    apply_overrides(input, &mut card);
+    card
+}
+
+/// Bulleted list of the requested scopes with friendly descriptions, taken from
+/// `extra_json.scope_descriptions` first and the built-in catalog second.
+fn scope_list(input: &OAuthCardInput) -> Option<String> {
+    if input.scopes.is_empty() {
+        return None;
+    }
+    let custom = input
+        .extra_json
+        .as_ref()
+        .and_then(|extra| extra.get("scope_descriptions"));
+    let lines: Vec<String> = input
+        .scopes
+        .iter()
+        .map(|scope| {
+            let description = custom
+                .and_then(|map| map.get(scope.as_str()))
+                .and_then(|value| value.as_str())
+                .or_else(|| scopes::describe(&input.provider_id, scope));
+            match description {
+                Some(description) => format!("• {description}"),
+                None => format!("• {scope}"),
+            }
+        })
+        .collect();
+    Some(format!("Requested permissions:\n{}", lines.join("\n")))
+}
+
+fn device_code_card(
+    input: &OAuthCardInput,
+    grant: &DeviceCodeGrant,
+    state_id: &str,
+) -> MessageCard {
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("Connect {} account", input.provider_id))
+        .text(format!(
+            "Go to {} and enter the code **{}** to sign in as {}.",
+            grant.verification_uri, grant.user_code, input.subject
+        ))
+        .build();
+    if let Some(expires_in) = grant.expires_in {
+        card.footer = Some(format!("The code expires in {} minutes.", expires_in / 60));
+    }
+    let url = grant
+        .verification_uri_complete
+        .as_deref()
+        .unwrap_or(&grant.verification_uri);
+    card.actions.push(Action::OpenUrl {
+        title: "Open sign-in page".into(),
+        url: url.to_string(),
+    });
+    attach_qr(&mut card, input, url);
+    card.actions.push(action(
+        "I've entered the code",
+        OAuthCardMode::DeviceCode,
+        input,
+        Some(state_id.to_string()),
+    ));
+    card.oauth = Some(OauthCard {
+        provider: OauthProvider::from_id(&input.provider_id),
+        scopes: input.scopes.clone(),
+        resource: token_target(input, "resource"),
+        prompt: None,
+        start_url: Some(url.to_string()),
+        connection_name: None,
         metadata: Some(json!({
-            "state_id": state_id,
+            "user_code": grant.user_code,
+            "verification_uri": grant.verification_uri,
+            "interval": grant.interval,
             "provider_id": input.provider_id,
             "subject": input.subject,
         })),
     });
+    apply_overrides(input, &mut card);
     card
 }
 
+/// Add a QR code of `url` when requested; without the `qr` feature the flag is ignored.
+#[cfg(feature = "qr")]
+fn attach_qr(card: &mut MessageCard, input: &OAuthCardInput, url: &str) {
+    if input.include_qr
+        && let Ok(image) = render::qr::image_for_url(url)
+    {
+        card.images.push(image);
+    }
+}
+
+#[cfg(not(feature = "qr"))]
+fn attach_qr(_card: &mut MessageCard, _input: &OAuthCardInput, _url: &str) {}
+
+/// Apply per-invocation `card_overrides`: literal title/body and button labels
+/// keyed by the PostBack target mode (or `open_url` for link buttons).
+fn apply_overrides(input: &OAuthCardInput, card: &mut MessageCard) {
+    let Some(overrides) = input.card_overrides.as_ref() else {
+        return;
+    };
+    if let Some(title) = &overrides.title {
+        card.title = Some(title.clone());
+    }
+    if let Some(body) = &overrides.body {
+        card.text = Some(body.clone());
+    }
+    for action in &mut card.actions {
+        let (title, key) = match action {
+            Action::OpenUrl { title, .. } => (title, "open_url"),
+            Action::SignIn { title, .. } => (title, "sign_in"),
+            Action::ShowCard { title, .. } => (title, "show_card"),
+            Action::PostBack { title, data } | Action::Submit { title, data } => (
+                title,
+                data.get("mode")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default(),
+            ),
+        };
+        if let Some(label) = overrides.buttons.get(key) {
+            *title = label.clone();
+        }
+    }
+}
+
 fn connect_prompt_card(input: &OAuthCardInput, existing_state: Option<String>) -> MessageCard {
     let state_id = existing_state.unwrap_or_else(|| Uuid::new_v4().to_string());
     sign_in_card(input, &state_id, "")
 }
 
-fn connected_card(input: &OAuthCardInput, token: &TokenSet, headline: &str) -> MessageCard {
-    let mut card = base_card(
-        MessageCardKind::Oauth,
-        Some(format!("{headline}: {}", input.provider_id)),
-        Some(format!(
-            "Signed in as {}{}.",
-            input.subject,
+async fn connected_card<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    token: &TokenSet,
+    headline: &str,
+) -> (MessageCard, AuthContext) {
+    let context = connected_context(backend, ctx, input, token).await;
+    let display_name = context
+        .name
+        .as_ref()
+        .or(context.preferred_username.as_ref());
+    let account = match (display_name, &context.email) {
+        (Some(name), Some(email)) => format!("{name} ({email})"),
+        (Some(name), None) => name.clone(),
+        (None, Some(email)) => email.clone(),
+        (None, None) => input.subject.clone(),
+    };
+    let mut card = MessageCard::builder()
+        .kind(MessageCardKind::Oauth)
+        .title(format!("{headline}: {}", input.provider_id))
+        .text(format!(
+            "Signed in as {account}{}.",
             input
                 .team
                 .as_ref()
                 .map(|team| format!(" (team {team})"))
                 .unwrap_or_default()
-        )),
-    );
-    card.actions.push(action(
-        "Refresh token",
-        OAuthCardMode::EnsureToken,
-        input,
-        None,
-    ));
+        ))
+        .build();
+    attach_provider_logo(input, &mut card);
+    if let Some(picture) = context.picture.clone() {
+        card.images.push(ImageRef {
+            url: picture,
+            alt: Some(format!("{account} avatar")),
+        });
+    }
+    let capabilities = backend.capabilities();
+    if capabilities.refresh {
+        card.actions.push(action(
+            "Refresh token",
+            OAuthCardMode::EnsureToken,
+            input,
+            None,
+        ));
+    }
     card.actions.push(action(
         "Use different account",
         OAuthCardMode::StartSignIn,
         input,
         None,
     ));
-    card.actions
-        .push(action("Disconnect", OAuthCardMode::Disconnect, input, None));
+    if capabilities.revoke {
+        let mut disconnect = action("Disconnect", OAuthCardMode::Disconnect, input, None);
+        if let Action::PostBack { data, .. } = &mut disconnect {
+            data["require_confirmation"] = json!(true);
+        }
+        card.actions.push(disconnect);
+    }
     card.oauth = Some(OauthCard {
-        provider: provider_from_id(&input.provider_id),
+        provider: OauthProvider::from_id(&input.provider_id),
         scopes: input.scopes.clone(),
-        resource: None,
+        resource: token_target(input, "resource"),
         prompt: None,
         start_url: None,
         connection_name: None,
@@ -276,40 +2662,280 @@ fn connected_card(input: &OAuthCardInput, token: &TokenSet, headline: &str) -> M
             "subject": input.subject,
         })),
     });
-    card
+    apply_overrides(input, &mut card);
+    (card, context)
 }
 
-fn redirect_path(input: &OAuthCardInput) -> String {
-    input
-        .redirect_path
-        .clone()
-        .unwrap_or_else(|| format!("/oauth/callback/{}", input.provider_id))
+/// Append "Expires in ..." to the card text and return the status to report:
+/// [`OAuthStatus::Expired`] once the token has expired or expires within the
+/// clock skew (offering "Sign in again" when the backend cannot refresh),
+/// [`OAuthStatus::ExpiringSoon`] inside the refresh margin (where the refresh
+/// action becomes a leading "Refresh now" button if the backend can refresh),
+/// [`OAuthStatus::Ok`] otherwise.
+fn annotate_expiry(
+    input: &OAuthCardInput,
+    token: &TokenSet,
+    can_refresh: bool,
+    card: &mut MessageCard,
+) -> OAuthStatus {
+    let Some(expires_at) = token.expires_at else {
+        return OAuthStatus::Ok;
+    };
+    let now = now_secs();
+    let expired = is_expired(input, token, now);
+    let remaining = expires_at.saturating_sub(now);
+    let notice = if expired {
+        "The token has expired.".to_string()
+    } else {
+        format!("Expires in {}.", humanize_duration(remaining))
+    };
+    card.text = Some(match card.text.take() {
+        Some(text) => format!("{text} {notice}"),
+        None => notice,
+    });
+
+    let margin = input
+        .refresh_margin_secs
+        .unwrap_or(DEFAULT_REFRESH_MARGIN_SECS);
+    if !expired && remaining >= margin {
+        return OAuthStatus::Ok;
+    }
+    card.kind = MessageCardKind::Warning;
+    let refresh = mode_name(&OAuthCardMode::EnsureToken);
+    card.actions.retain(|action| {
+        !matches!(action, Action::PostBack { data, .. } if data["mode"] == refresh.as_str())
+    });
+    if can_refresh {
+        card.actions.insert(
+            0,
+            action("Refresh now", OAuthCardMode::EnsureToken, input, None),
+        );
+    } else if expired {
+        card.actions.insert(
+            0,
+            action("Sign in again", OAuthCardMode::StartSignIn, input, None),
+        );
+    }
+    apply_overrides(input, card);
+    if expired {
+        OAuthStatus::Expired
+    } else {
+        OAuthStatus::ExpiringSoon
+    }
+}
+
+/// Auth context for a connected token, enriched with the broker's userinfo
+/// when it has any.
+async fn connected_context<B: AsyncOAuthBackend>(
+    backend: &B,
+    ctx: &CallContext,
+    input: &OAuthCardInput,
+    token: &TokenSet,
+) -> AuthContext {
+    let mut context = auth_context(ctx, input, token);
+    match backend
+        .get_userinfo(ctx, &input.provider_id, &input.subject)
+        .await
+    {
+        Ok(profile) => apply_userinfo(&mut context, profile),
+        Err(OAuthCardError::Unsupported(_)) => {}
+        Err(err) => logging::warn(
+            ctx,
+            "userinfo unavailable",
+            json!({ "provider_id": input.provider_id, "error": err.to_string() }),
+        ),
+    }
+    context
 }
 
-fn auth_context(input: &OAuthCardInput, token: &TokenSet) -> AuthContext {
+/// The input's redirect path (default `/oauth/callback/{provider_id}`, or
+/// under the configured `redirect_base`), checked against the provider's
+/// redirect policy.
+fn redirect_path(input: &OAuthCardInput) -> Result<String, OAuthCardError> {
+    let path = input.redirect_path.clone().unwrap_or_else(|| {
+        format!(
+            "{}{}",
+            component_config::redirect_prefix(),
+            input.provider_id
+        )
+    });
+    let config = provider_config::provider_config(&input.provider_id);
+    provider_config::check_redirect_path(config.as_ref(), &input.provider_id, &path)?;
+    Ok(path)
+}
+
+fn auth_context(ctx: &CallContext, input: &OAuthCardInput, token: &TokenSet) -> AuthContext {
+    let mut claims = match &token.extra {
+        Some(Value::Object(extra)) => extra.clone(),
+        None => serde_json::Map::new(),
+        Some(other) => {
+            logging::warn(
+                ctx,
+                "ignoring token extra that is not an object",
+                json!({ "provider_id": input.provider_id, "extra": other }),
+            );
+            serde_json::Map::new()
+        }
+    };
+    if let Some(id_claims) = token.id_token.as_deref().and_then(|jwt| {
+        signed_id_token_claims(&input.provider_id, jwt)
+            .inspect_err(|err| {
+                logging::warn(
+                    ctx,
+                    "ignoring id_token claims",
+                    json!({ "provider_id": input.provider_id, "error": err.to_string() }),
+                )
+            })
+            .ok()
+    }) {
+        claims.extend(id_claims);
+    }
+    let claim = |name: &str| claim_str(&claims, name);
     AuthContext {
         provider_id: input.provider_id.clone(),
         subject: input.subject.clone(),
-        email: token
-            .extra
-            .as_ref()
-            .and_then(|extra| extra.get("email"))
-            .and_then(|v| v.as_str().map(|s| s.to_string())),
+        email: claim("email"),
+        name: claim("name"),
+        preferred_username: claim("preferred_username"),
+        picture: claim("picture"),
+        locale: claim("locale"),
+        sub: claim("sub"),
         tenant: input.tenant.clone(),
         team: input.team.clone(),
         scopes: input.scopes.clone(),
         expires_at: token.expires_at,
+        claims: (!claims.is_empty()).then_some(claims),
     }
 }
 
-fn auth_header(token: &TokenSet) -> AuthHeader {
-    let mut headers = Vec::new();
-    let prefix = token.token_type.as_deref().unwrap_or("Bearer");
-    headers.push((
-        "Authorization".into(),
-        format!("{prefix} {}", token.access_token),
-    ));
-    AuthHeader { headers }
+/// Overlay the broker's userinfo on the token-derived context.
+fn apply_userinfo(context: &mut AuthContext, profile: UserProfile) {
+    let claims = context.claims.get_or_insert_with(serde_json::Map::new);
+    for (name, value, field) in [
+        ("name", profile.name, &mut context.name),
+        ("email", profile.email, &mut context.email),
+        ("picture", profile.picture, &mut context.picture),
+        ("locale", profile.locale, &mut context.locale),
+    ] {
+        if let Some(value) = value {
+            claims.insert(name.to_string(), Value::String(value.clone()));
+            *field = Some(value);
+        }
+    }
+}
+
+/// Token placement from the provider config, defaulting to an
+/// `Authorization: <token_type> <token>` header.
+fn auth_injection(input: &OAuthCardInput, token: &TokenSet) -> AuthInjection {
+    let access_token = token.access_token.clone();
+    match provider_config::provider_config(&input.provider_id).and_then(|c| c.auth_injection) {
+        None => AuthInjection::Header {
+            name: "Authorization".into(),
+            value: format!(
+                "{} {access_token}",
+                if is_dpop(token) { "DPoP" } else { "Bearer" }
+            ),
+        },
+        Some(AuthInjectionStyle::Header { name, prefix }) => AuthInjection::Header {
+            name,
+            value: match prefix.as_deref() {
+                Some(prefix) if !prefix.is_empty() => format!("{prefix} {access_token}"),
+                _ => access_token,
+            },
+        },
+        Some(AuthInjectionStyle::Query { param }) => AuthInjection::Query {
+            param,
+            value: access_token,
+        },
+        Some(AuthInjectionStyle::Cookie { name }) => AuthInjection::Cookie {
+            name,
+            value: access_token,
+        },
+    }
+}
+
+/// `None` when the provider takes the token as a query parameter. DPoP-bound
+/// tokens also get a `DPoP` proof header for the input's `dpop_request`.
+fn auth_header(
+    input: &OAuthCardInput,
+    token: &TokenSet,
+) -> Result<Option<AuthHeader>, OAuthCardError> {
+    let Some(header) = auth_injection(input, token).header() else {
+        return Ok(None);
+    };
+    let mut headers = vec![header];
+    if let Some(proof) = dpop_proof(input, token)? {
+        headers.push(("DPoP".into(), proof));
+    }
+    Ok(Some(AuthHeader { headers }))
+}
+
+/// Whether the broker reported a DPoP-bound token (`token_type` is matched
+/// case-insensitively; anything else is presented as a bearer token).
+fn is_dpop(token: &TokenSet) -> bool {
+    token
+        .token_type
+        .as_deref()
+        .is_some_and(|token_type| token_type.eq_ignore_ascii_case("dpop"))
+}
+
+#[cfg(feature = "dpop")]
+fn dpop_proof(input: &OAuthCardInput, token: &TokenSet) -> Result<Option<String>, OAuthCardError> {
+    let Some(request) = input.dpop_request.as_ref().filter(|_| is_dpop(token)) else {
+        return Ok(None);
+    };
+    crate::dpop::proof(
+        &request.method,
+        &request.url,
+        Some(&token.access_token),
+        now_secs(),
+    )
+    .map(Some)
+}
+
+#[cfg(not(feature = "dpop"))]
+fn dpop_proof(
+    _input: &OAuthCardInput,
+    _token: &TokenSet,
+) -> Result<Option<String>, OAuthCardError> {
+    Ok(None)
+}
+
+/// Proof for the token requests (code and token exchange) of a provider
+/// configured with `dpop`, so a code bound to the `dpop_jkt` sent on consent
+/// can be redeemed.
+#[cfg(feature = "dpop")]
+fn dpop_exchange_proof(input: &OAuthCardInput) -> Result<Option<String>, OAuthCardError> {
+    if dpop_jkt(input)?.is_none() {
+        return Ok(None);
+    }
+    let endpoint = token_endpoint(input).ok_or_else(|| {
+        OAuthCardError::Invalid(format!(
+            "{} is configured for DPoP but its token endpoint is unknown; set its issuer",
+            input.provider_id
+        ))
+    })?;
+    crate::dpop::proof("POST", &endpoint, None, now_secs()).map(Some)
+}
+
+#[cfg(not(feature = "dpop"))]
+fn dpop_exchange_proof(input: &OAuthCardInput) -> Result<Option<String>, OAuthCardError> {
+    dpop_jkt(input).map(|_| None)
+}
+
+/// Thumbprint of the component's DPoP key for providers configured with
+/// `dpop`, sent as `dpop_jkt` with the consent request.
+fn dpop_jkt(input: &OAuthCardInput) -> Result<Option<String>, OAuthCardError> {
+    if !provider_config::provider_config(&input.provider_id).is_some_and(|config| config.dpop) {
+        return Ok(None);
+    }
+    #[cfg(feature = "dpop")]
+    return crate::dpop::thumbprint().map(Some);
+    #[cfg(not(feature = "dpop"))]
+    Err(OAuthCardError::Unsupported(format!(
+        "{} is configured for DPoP, which needs the `dpop` feature",
+        input.provider_id
+    )))
 }
 
 fn action(
@@ -318,34 +2944,53 @@ fn action(
     input: &OAuthCardInput,
     state_id: Option<String>,
 ) -> Action {
+    let mut data = json!({
+        "mode": mode,
+        "provider_id": input.provider_id,
+        "subject": input.subject,
+        "state_id": state_id,
+        "scopes": input.scopes,
+    });
+    // Hints ride along so a re-started sign-in asks the provider the same way.
+    for (key, value) in sign_in_hints(input) {
+        data[key] = value;
+    }
     Action::PostBack {
         title: title.to_string(),
-        data: json!({
-            "mode": mode,
-            "provider_id": input.provider_id,
-            "subject": input.subject,
-            "state_id": state_id,
-            "scopes": input.scopes,
-        }),
+        data,
     }
 }
 
-fn provider_from_id(id: &str) -> OauthProvider {
-    match id.to_ascii_lowercase().as_str() {
-        "microsoft" | "msgraph" | "m365" => OauthProvider::Microsoft,
-        "google" => OauthProvider::Google,
-        "github" => OauthProvider::Github,
-        _ => OauthProvider::Custom,
+/// Provider icon from `extra_json.provider_logos.<provider_id>`, else the
+/// provider config's `icon`, else the provider registry.
+fn attach_provider_logo(input: &OAuthCardInput, card: &mut MessageCard) {
+    let custom = input
+        .extra_json
+        .as_ref()
+        .and_then(|extra| extra.get("provider_logos"))
+        .and_then(|logos| logos.get(&input.provider_id))
+        .and_then(|url| url.as_str())
+        .map(str::to_string);
+    if let Some(url) = custom
+        .or_else(|| provider_config::provider_config(&input.provider_id)?.icon)
+        .or_else(|| {
+            providers::provider_info(&input.provider_id).map(|info| info.icon_url.to_string())
+        })
+    {
+        card.images.push(ImageRef {
+            url,
+            alt: Some(format!("{} logo", provider_label(&input.provider_id))),
+        });
     }
 }
 
-fn base_card(kind: MessageCardKind, title: Option<String>, text: Option<String>) -> MessageCard {
-    let mut card = MessageCard {
-        kind,
-        title,
-        text,
-        ..Default::default()
-    };
-    card.allow_markdown = true;
-    card
+/// Display name for a provider id: the configured `display_name`, else the
+/// registry's ("Microsoft" for `msgraph` and the like), else the id itself.
+fn provider_label(id: &str) -> String {
+    if let Some(name) = provider_config::provider_config(id).and_then(|config| config.display_name)
+    {
+        return name;
+    }
+    providers::provider_info(id)
+        .map_or_else(|| id.to_string(), |info| info.display_name.to_string())
 }
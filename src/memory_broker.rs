@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::claims::account_label;
+use crate::clock::now_secs;
+use crate::correlation::CallContext;
+use crate::model::{BackendCapabilities, ConnectionSummary, TOKEN_BINDING_KEY, TokenSet};
+use crate::scopes::Scope;
+
+/// Lifetime of tokens issued by `exchange_code`/`refresh_token` unless overridden.
+const DEFAULT_TTL_SECS: u64 = 3_600;
+
+type Key = (String, String);
+
+/// One stored connection in a JSON fixture.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenFixture {
+    pub provider_id: String,
+    pub subject: String,
+    pub token: TokenSet,
+}
+
+/// Broker that keeps tokens per `(provider_id, subject)` in memory.
+///
+/// Expired tokens without a refresh token are reported as missing; refreshes
+/// and code exchanges issue new access tokens valid for the configured TTL,
+/// keeping the tenant/team binding sent with the consent URL.
+/// Suited to multi-user tests and local demos, not to production storage.
+#[derive(Debug, Default)]
+pub struct InMemoryBroker {
+    tokens: Mutex<BTreeMap<Key, TokenSet>>,
+    /// Scopes requested by the last consent URL, granted on code exchange.
+    pending_scopes: Mutex<BTreeMap<Key, Vec<Scope>>>,
+    /// `token_binding` sent with the last consent URL, kept on issued tokens.
+    pending_binding: Mutex<BTreeMap<Key, Value>>,
+    /// `(provider_id, tenant)` pairs an administrator approved.
+    admin_consents: Mutex<BTreeSet<Key>>,
+    issued: Mutex<u64>,
+    ttl_secs: Option<u64>,
+}
+
+impl InMemoryBroker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lifetime of newly issued tokens (defaults to one hour).
+    pub fn with_ttl(mut self, ttl_secs: u64) -> Self {
+        self.ttl_secs = Some(ttl_secs);
+        self
+    }
+
+    /// Load connections from a JSON array of [`TokenFixture`]s.
+    pub fn from_json(fixtures: &str) -> Result<Self, OAuthCardError> {
+        let fixtures: Vec<TokenFixture> = serde_json::from_str(fixtures)
+            .map_err(|err| OAuthCardError::Parse(format!("token fixtures: {err}")))?;
+        let broker = Self::new();
+        for fixture in fixtures {
+            broker.seed(&fixture.provider_id, &fixture.subject, fixture.token);
+        }
+        Ok(broker)
+    }
+
+    /// Store (or replace) the token for a provider/subject pair.
+    pub fn seed(&self, provider_id: &str, subject: &str, token: TokenSet) {
+        lock(&self.tokens).insert(key(provider_id, subject), token);
+    }
+
+    /// Record that an administrator of `tenant` approved the provider's scopes.
+    pub fn grant_admin_consent(&self, provider_id: &str, tenant: &str) {
+        lock(&self.admin_consents).insert(key(provider_id, tenant));
+    }
+
+    /// Current stored token, regardless of expiry.
+    pub fn stored(&self, provider_id: &str, subject: &str) -> Option<TokenSet> {
+        lock(&self.tokens).get(&key(provider_id, subject)).cloned()
+    }
+
+    fn issue(
+        &self,
+        provider_id: &str,
+        subject: &str,
+        scopes: Vec<String>,
+        refresh_token: Option<String>,
+        extra: Option<Value>,
+    ) -> TokenSet {
+        let serial = {
+            let mut issued = lock(&self.issued);
+            *issued += 1;
+            *issued
+        };
+        let token = TokenSet {
+            access_token: format!("{provider_id}-{subject}-{serial}"),
+            refresh_token: refresh_token
+                .or_else(|| Some(format!("refresh-{provider_id}-{subject}-{serial}"))),
+            expires_at: Some(now_secs() + self.ttl_secs.unwrap_or(DEFAULT_TTL_SECS)),
+            token_type: Some("Bearer".into()),
+            granted_scopes: scopes,
+            extra,
+            ..Default::default()
+        };
+        self.seed(provider_id, subject, token.clone());
+        token
+    }
+}
+
+fn key(provider_id: &str, subject: &str) -> Key {
+    (provider_id.to_string(), subject.to_string())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn is_expired(token: &TokenSet) -> bool {
+    token.expires_at.is_some_and(|at| at <= now_secs())
+}
+
+impl OAuthBackend for InMemoryBroker {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(self
+            .stored(provider_id, subject)
+            .filter(|token| !is_expired(token) || token.refresh_token.is_some()))
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        lock(&self.pending_scopes).insert(key(provider_id, subject), scopes.to_vec());
+        let binding = extra_json
+            .and_then(|extra| serde_json::from_str::<Value>(&extra).ok())
+            .and_then(|mut extra| extra.get_mut(TOKEN_BINDING_KEY).map(Value::take));
+        let mut pending_binding = lock(&self.pending_binding);
+        match binding {
+            Some(binding) => pending_binding.insert(key(provider_id, subject), binding),
+            None => pending_binding.remove(&key(provider_id, subject)),
+        };
+        Ok(format!(
+            "https://{provider_id}.memory.invalid/authorize?subject={subject}&redirect={redirect_path}"
+        ))
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        let scopes = lock(&self.pending_scopes)
+            .remove(&key(provider_id, subject))
+            .unwrap_or_default()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let extra = lock(&self.pending_binding)
+            .remove(&key(provider_id, subject))
+            .map(|binding| json!({ TOKEN_BINDING_KEY: binding }));
+        Ok(self.issue(provider_id, subject, scopes, None, extra))
+    }
+
+    fn refresh_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        let current = self.stored(provider_id, subject).ok_or_else(|| {
+            OAuthCardError::Invalid(format!("no {provider_id} connection for {subject}"))
+        })?;
+        let Some(refresh_token) = current.refresh_token else {
+            return Err(OAuthCardError::Invalid(format!(
+                "{provider_id} connection for {subject} has no refresh token"
+            )));
+        };
+        Ok(self.issue(
+            provider_id,
+            subject,
+            current.granted_scopes,
+            Some(refresh_token),
+            current.extra,
+        ))
+    }
+
+    fn store_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        self.seed(provider_id, subject, token.clone());
+        Ok(())
+    }
+
+    fn revoke_token(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        lock(&self.tokens).remove(&key(provider_id, subject));
+        Ok(())
+    }
+
+    fn list_connections(
+        &self,
+        _ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        Ok(lock(&self.tokens)
+            .iter()
+            .filter(|((_, owner), _)| owner == subject)
+            .map(|((provider_id, _), token)| ConnectionSummary {
+                provider_id: provider_id.clone(),
+                scopes: token.scopes(),
+                expires_at: token.expires_at,
+                label: account_label(token),
+            })
+            .collect())
+    }
+
+    fn get_admin_consent_url(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        _scopes: &[Scope],
+        redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        Ok(format!(
+            "https://{provider_id}.memory.invalid/{tenant}/adminconsent?redirect={redirect_path}"
+        ))
+    }
+
+    fn admin_consent_granted(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        _scopes: &[Scope],
+    ) -> Result<bool, OAuthCardError> {
+        Ok(lock(&self.admin_consents).contains(&key(provider_id, tenant)))
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            refresh: true,
+            revoke: true,
+            list_connections: true,
+            ..Default::default()
+        }
+    }
+}
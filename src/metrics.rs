@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::correlation::CallContext;
+use crate::model::{
+    AccountSummary, BackendCapabilities, ConnectionSummary, DeviceCodeGrant, OAuthCardMode,
+    OAuthCardOutput, OidcEndpoints, SignInProgress, TokenSet, UserProfile,
+};
+use crate::observed::{CallHook, observed_backend};
+use crate::progress;
+use crate::scopes::Scope;
+
+/// Upper bounds (in milliseconds) of the broker latency buckets; one more
+/// bucket counts slower calls.
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000];
+
+static METRICS: Mutex<MetricsSnapshot> = Mutex::new(MetricsSnapshot {
+    invocations: BTreeMap::new(),
+    statuses: BTreeMap::new(),
+    errors: BTreeMap::new(),
+    broker_calls: BTreeMap::new(),
+});
+
+/// Counters collected in this process since start or the last
+/// [`reset_metrics`]; the `metrics` operation returns them as JSON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Invocations per mode (`unknown` for inputs that did not parse).
+    pub invocations: BTreeMap<String, u64>,
+    /// Outputs per status.
+    pub statuses: BTreeMap<String, u64>,
+    /// Error outputs per `error_code`.
+    pub errors: BTreeMap<String, u64>,
+    /// Calls made through a [`MeteredBackend`], per broker method.
+    pub broker_calls: BTreeMap<String, LatencyHistogram>,
+}
+
+/// Latency distribution of one broker method.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub count: u64,
+    /// Calls among `count` that returned an error.
+    pub errors: u64,
+    pub sum_ms: u64,
+    /// Calls per [`LATENCY_BUCKETS_MS`] bucket (each counting calls above the
+    /// previous bound), followed by the calls slower than the last bound.
+    pub buckets: Vec<u64>,
+}
+
+/// Copy of the current counters.
+pub fn metrics_snapshot() -> MetricsSnapshot {
+    metrics().clone()
+}
+
+/// Clear every counter, e.g. after the host has scraped them.
+pub fn reset_metrics() {
+    *metrics() = MetricsSnapshot::default();
+}
+
+fn metrics() -> MutexGuard<'static, MetricsSnapshot> {
+    METRICS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Count one handled invocation by mode, output status and error code.
+pub(crate) fn record_invocation(mode: Option<&OAuthCardMode>, output: &OAuthCardOutput) {
+    let mode = mode.map_or_else(|| "unknown".to_string(), label);
+    let status = label(&output.status);
+    let mut metrics = metrics();
+    *metrics.invocations.entry(mode).or_default() += 1;
+    *metrics.statuses.entry(status).or_default() += 1;
+    if let Some(code) = &output.error_code {
+        *metrics.errors.entry(code.to_string()).or_default() += 1;
+    }
+}
+
+/// Kebab-case name a mode or status serializes to.
+fn label(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => "unknown".into(),
+    }
+}
+
+fn record_broker_call(method: &str, elapsed: Duration, failed: bool) {
+    let elapsed_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+    let bucket = LATENCY_BUCKETS_MS
+        .iter()
+        .position(|bound| elapsed_ms <= *bound)
+        .unwrap_or(LATENCY_BUCKETS_MS.len());
+    let mut metrics = metrics();
+    let histogram = metrics
+        .broker_calls
+        .entry(method.to_string())
+        .or_insert_with(|| LatencyHistogram {
+            buckets: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+            ..Default::default()
+        });
+    histogram.count += 1;
+    histogram.errors += u64::from(failed);
+    histogram.sum_ms = histogram.sum_ms.saturating_add(elapsed_ms);
+    histogram.buckets[bucket] += 1;
+}
+
+/// Records the latency and outcome of every broker call into the process
+/// metrics (see [`metrics_snapshot`]) and reports its progress stage to
+/// [`crate::handle_message_stream`]. [`crate::handle_message`] and
+/// [`crate::handle_message_with_backend`] wrap their backend in one; embedders
+/// calling `handle` directly can wrap theirs.
+#[derive(Debug, Clone)]
+pub struct MeteredBackend<B> {
+    inner: B,
+}
+
+impl<B: OAuthBackend> MeteredBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B> CallHook for MeteredBackend<B> {
+    fn started(&self, method: &'static str) {
+        progress::mark_broker_call(method);
+    }
+
+    fn finished(method: &'static str, elapsed: Duration, failed: bool) {
+        record_broker_call(method, elapsed, failed);
+    }
+}
+
+observed_backend!(sync impl[B: OAuthBackend] MeteredBackend<B>);
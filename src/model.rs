@@ -1,11 +1,24 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use zeroize::Zeroize;
+
+use crate::OAuthCardError;
+use crate::providers;
+use crate::scopes::Scope;
+use crate::template::CardTemplate;
 
 fn default_true() -> bool {
     true
 }
 
+fn default_input_version() -> u32 {
+    1
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MessageCard {
     #[serde(default)]
     pub kind: MessageCardKind,
@@ -43,38 +56,246 @@ impl Default for MessageCard {
     }
 }
 
+impl MessageCard {
+    /// Start building a card; unset fields keep their `Default` values.
+    pub fn builder() -> MessageCardBuilder {
+        MessageCardBuilder::default()
+    }
+}
+
+/// Fluent constructor for [`MessageCard`], for components that assemble
+/// cards compatible with this one's output.
+#[derive(Debug, Clone, Default)]
+pub struct MessageCardBuilder {
+    card: MessageCard,
+}
+
+impl MessageCardBuilder {
+    pub fn kind(mut self, kind: MessageCardKind) -> Self {
+        self.card.kind = kind;
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.card.title = Some(title.into());
+        self
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.card.text = Some(text.into());
+        self
+    }
+
+    pub fn footer(mut self, footer: impl Into<String>) -> Self {
+        self.card.footer = Some(footer.into());
+        self
+    }
+
+    /// Append an image; call repeatedly for several.
+    pub fn image(mut self, image: ImageRef) -> Self {
+        self.card.images.push(image);
+        self
+    }
+
+    /// Append an action; call repeatedly for several.
+    pub fn action(mut self, action: Action) -> Self {
+        self.card.actions.push(action);
+        self
+    }
+
+    pub fn allow_markdown(mut self, allow_markdown: bool) -> Self {
+        self.card.allow_markdown = allow_markdown;
+        self
+    }
+
+    pub fn oauth(mut self, oauth: OauthCard) -> Self {
+        self.card.oauth = Some(oauth);
+        self
+    }
+
+    pub fn build(self) -> MessageCard {
+        self.card
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ImageRef {
     pub url: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub alt: Option<String>,
 }
 
+/// Per-invocation card copy, e.g. "Connect your HR system" instead of the default title.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CardOverrides {
+    pub title: Option<String>,
+    pub body: Option<String>,
+    /// Button labels keyed by the PostBack target mode (e.g. `complete-sign-in`)
+    /// or `open_url` for link buttons.
+    #[serde(default)]
+    pub buttons: BTreeMap<String, String>,
+}
+
+/// White-label settings applied to every card.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct Branding {
+    pub logo_url: Option<String>,
+    /// Hex color such as `#0A66C2`; enables the accent container in Adaptive Cards.
+    pub accent_color: Option<String>,
+    pub product_name: Option<String>,
+    pub footer_text: Option<String>,
+}
+
+impl Branding {
+    /// These settings, falling back to `base` for the ones left unset.
+    pub fn over(self, base: Branding) -> Branding {
+        Branding {
+            logo_url: self.logo_url.or(base.logo_url),
+            accent_color: self.accent_color.or(base.accent_color),
+            product_name: self.product_name.or(base.product_name),
+            footer_text: self.footer_text.or(base.footer_text),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Action {
-    OpenUrl { title: String, url: String },
-    PostBack { title: String, data: Value },
+    OpenUrl {
+        title: String,
+        url: String,
+    },
+    PostBack {
+        title: String,
+        data: Value,
+    },
+    /// Bot Framework style sign-in button; channels without one open `url`.
+    SignIn {
+        title: String,
+        url: String,
+        #[serde(default)]
+        connection_name: Option<String>,
+    },
+    /// Form submission carrying `data`, without echoing a chat message.
+    Submit {
+        title: String,
+        data: Value,
+    },
+    /// Reveals a nested card in place; channels without one list its actions.
+    ShowCard {
+        title: String,
+        card: Box<MessageCard>,
+    },
+}
+
+impl Action {
+    /// Button label, whatever the variant.
+    pub fn title(&self) -> &str {
+        match self {
+            Self::OpenUrl { title, .. }
+            | Self::PostBack { title, .. }
+            | Self::SignIn { title, .. }
+            | Self::Submit { title, .. }
+            | Self::ShowCard { title, .. } => title,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum MessageCardKind {
     #[default]
     Standard,
     Oauth,
+    /// Asks the user to confirm a destructive action (e.g. disconnect).
+    Confirmation,
+    /// Highlights a condition needing attention (e.g. a token about to expire).
+    Warning,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum OauthProvider {
     Microsoft,
     Google,
     Github,
-    Custom,
+    Slack,
+    Discord,
+    Salesforce,
+    Okta,
+    Auth0,
+    Atlassian,
+    Zoom,
+    Dropbox,
+    /// Any other provider, keeping its original id.
+    Custom {
+        id: String,
+    },
+    /// Provider name this version does not know, e.g. from a newer host.
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl OauthProvider {
+    /// Providers with built-in branding and id aliases.
+    pub const KNOWN: [OauthProvider; 11] = [
+        OauthProvider::Microsoft,
+        OauthProvider::Google,
+        OauthProvider::Github,
+        OauthProvider::Slack,
+        OauthProvider::Discord,
+        OauthProvider::Salesforce,
+        OauthProvider::Okta,
+        OauthProvider::Auth0,
+        OauthProvider::Atlassian,
+        OauthProvider::Zoom,
+        OauthProvider::Dropbox,
+    ];
+
+    /// Map a provider id (case-insensitive, including registry aliases such
+    /// as `msgraph` or `jira`) to a known provider.
+    pub fn from_id(id: &str) -> Self {
+        providers::provider_info(id)
+            .and_then(|info| info.provider.clone())
+            .unwrap_or_else(|| Self::Custom { id: id.to_string() })
+    }
+
+    /// Canonical provider id (the registry's for known providers).
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Microsoft => "microsoft",
+            Self::Google => "google",
+            Self::Github => "github",
+            Self::Slack => "slack",
+            Self::Discord => "discord",
+            Self::Salesforce => "salesforce",
+            Self::Okta => "okta",
+            Self::Auth0 => "auth0",
+            Self::Atlassian => "atlassian",
+            Self::Zoom => "zoom",
+            Self::Dropbox => "dropbox",
+            Self::Custom { id } | Self::Other(id) => id,
+        }
+    }
+
+    /// Human-readable provider name; custom providers show their id.
+    pub fn display_name(&self) -> &str {
+        match self {
+            Self::Custom { id } | Self::Other(id) => id,
+            known => providers::provider_info(known.id())
+                .map_or_else(|| known.id(), |info| info.display_name),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum OauthPrompt {
     None,
@@ -83,10 +304,11 @@ pub enum OauthPrompt {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct OauthCard {
     pub provider: OauthProvider,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    pub scopes: Vec<String>,
+    pub scopes: Vec<Scope>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub resource: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -100,78 +322,998 @@ pub struct OauthCard {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
 pub enum OAuthCardMode {
     StatusCard,
     StartSignIn,
     CompleteSignIn,
     EnsureToken,
     Disconnect,
+    /// Device authorization grant (RFC 8628) for channels that cannot open a redirect.
+    DeviceCode,
+    /// App-only token for service-to-service calls; no end user or consent card.
+    ClientCredentials,
+    /// Ensure tokens for every entry in `providers` and aggregate the results.
+    BatchEnsure,
+    /// Show every provider the subject has connected.
+    ListConnections,
+    /// Pick the active account when a subject has several for one provider.
+    SwitchAccount,
+    /// RP-initiated logout: end the provider session and clear the stored token.
+    Logout,
+    /// Check whether the sign-in identified by `state_id` has completed.
+    PollSignIn,
+    /// Abandon the pending sign-in identified by `state_id`.
+    CancelSignIn,
+    /// Offer a "Sign in with ..." choice between the `providers` entries.
+    ChooseProvider,
+    /// Ask a tenant administrator to approve the scopes for the whole
+    /// organization (Microsoft admin consent).
+    AdminConsent,
+    /// Check whether admin consent was granted before users sign in.
+    CheckAdminConsent,
+    /// Swap a token for one aimed at another audience or scope set (RFC 8693).
+    TokenExchange,
+    /// Mode this version does not know (e.g. sent by a newer host); handling
+    /// it fails with `OAuthCardError::Unsupported`.
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl OAuthCardMode {
+    /// Every mode this version handles.
+    pub const SUPPORTED: [OAuthCardMode; 17] = [
+        OAuthCardMode::StatusCard,
+        OAuthCardMode::StartSignIn,
+        OAuthCardMode::CompleteSignIn,
+        OAuthCardMode::EnsureToken,
+        OAuthCardMode::Disconnect,
+        OAuthCardMode::DeviceCode,
+        OAuthCardMode::ClientCredentials,
+        OAuthCardMode::BatchEnsure,
+        OAuthCardMode::ListConnections,
+        OAuthCardMode::SwitchAccount,
+        OAuthCardMode::Logout,
+        OAuthCardMode::PollSignIn,
+        OAuthCardMode::CancelSignIn,
+        OAuthCardMode::ChooseProvider,
+        OAuthCardMode::AdminConsent,
+        OAuthCardMode::CheckAdminConsent,
+        OAuthCardMode::TokenExchange,
+    ];
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct OAuthCardInput {
+    /// Payload version the input arrived in; `parse_input` upgrades older
+    /// payloads, so this only records where it came from.
+    #[serde(default = "default_input_version")]
+    pub version: u32,
     pub mode: OAuthCardMode,
+    /// Provider to act on; may be left empty when the component config sets
+    /// a `default_provider`.
+    #[serde(default, alias = "providerId")]
     pub provider_id: String,
     /// Logical subject identifier (user/service) this card operates on.
     pub subject: String,
-    /// Optional tenant context for routing; not enforced locally but echoed back.
+    /// Tenant the card operates for, used for routing and token binding. The
+    /// host's tenant fills it in; an input naming another tenant is rejected.
     pub tenant: Option<String>,
+    /// Caller-supplied trace id, echoed on the output and forwarded to every
+    /// broker call made for this invocation.
+    #[serde(default, alias = "correlationId")]
+    pub correlation_id: Option<String>,
     pub team: Option<String>,
-    #[serde(default)]
-    pub scopes: Vec<String>,
+    #[serde(default, deserialize_with = "crate::scopes::deserialize_list")]
+    pub scopes: Vec<Scope>,
     /// Correlation handle used by sign-in flows.
+    #[serde(alias = "stateId")]
     pub state_id: Option<String>,
     /// Authorization code returned by the provider (for complete-sign-in).
+    #[serde(alias = "authCode")]
     pub auth_code: Option<String>,
-    #[serde(default)]
+    /// Exchangeable SSO token (e.g. Teams `signin/tokenExchange`) used instead of `auth_code`.
+    #[serde(alias = "ssoToken")]
+    pub sso_token: Option<String>,
+    /// Token token-exchange swaps (defaults to the subject's stored token).
+    #[serde(default, alias = "subjectToken")]
+    pub subject_token: Option<String>,
+    /// RFC 8693 type of `subject_token` (defaults to an access token).
+    #[serde(default, alias = "subjectTokenType")]
+    pub subject_token_type: Option<String>,
+    #[serde(default, alias = "allowAutoSignIn")]
     pub allow_auto_sign_in: bool,
-    /// Optional redirect path (defaults to "/oauth/callback/{provider_id}").
+    /// Optional redirect path (defaults to "/oauth/callback/{provider_id}", or
+    /// under the component config's `redirect_base`).
+    #[serde(alias = "redirectPath")]
     pub redirect_path: Option<String>,
     /// Provider-specific options forwarded to the broker.
+    #[serde(alias = "extraJson")]
     pub extra_json: Option<serde_json::Value>,
+    /// Account to pre-fill on the provider's sign-in page (`login_hint`).
+    #[serde(default, alias = "loginHint")]
+    pub login_hint: Option<String>,
+    /// Tenant or domain to route sign-in to (`domain_hint`, Microsoft).
+    #[serde(default, alias = "domainHint")]
+    pub domain_hint: Option<String>,
+    /// Consent prompt for this sign-in; overrides the provider config's `prompt`.
+    #[serde(default)]
+    pub prompt: Option<OauthPrompt>,
+    /// Space-separated authentication context classes the sign-in must
+    /// satisfy (e.g. an MFA level); complete-sign-in checks the id_token's
+    /// `acr` against them.
+    #[serde(default, alias = "acrValues")]
+    pub acr_values: Option<String>,
+    /// Maximum seconds since the user last authenticated; complete-sign-in
+    /// checks the id_token's `auth_time` against it.
+    #[serde(default, alias = "maxAge")]
+    pub max_age: Option<u64>,
+    /// Resource indicator (RFC 8707) of the API the token is for; overrides
+    /// the provider config's `resource`.
+    #[serde(default)]
+    pub resource: Option<String>,
+    /// Audience of the API the token is for; overrides the provider config's
+    /// `audience`.
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// Seconds before expiry at which ensure-token refreshes silently (defaults to 300).
+    #[serde(alias = "refreshMarginSecs")]
+    pub refresh_margin_secs: Option<u64>,
+    /// Seconds of host clock drift tolerated when judging expiry: tokens
+    /// expiring within this window are treated as expired (defaults to 30).
+    #[serde(alias = "clockSkewSecs")]
+    pub clock_skew_secs: Option<u64>,
+    /// Literal copy overrides for the card produced by this invocation.
+    #[serde(alias = "cardOverrides")]
+    pub card_overrides: Option<CardOverrides>,
+    /// Disconnect/logout render a confirmation card instead of revoking right away.
+    #[serde(default, alias = "requireConfirmation")]
+    pub require_confirmation: bool,
+    /// Attach a QR code of the sign-in/verification URL (requires the `qr` feature).
+    #[serde(default, alias = "includeQr")]
+    pub include_qr: bool,
+    /// Target channel (e.g. "slack", "teams") whose native rendering is added to `rendered`.
+    pub channel: Option<String>,
+    /// Account to activate in switch-account mode.
+    #[serde(alias = "accountId")]
+    pub account_id: Option<String>,
+    /// Providers checked by batch-ensure or offered by choose-provider; `provider_id` is
+    /// only used as the card context.
+    #[serde(default)]
+    pub providers: Vec<ProviderRequest>,
+    /// Reject fields `parse_input` does not recognise instead of ignoring them.
+    #[serde(default)]
+    pub strict: bool,
+    /// Leave `auth_header`/`auth_injection` out of the output, for flows that
+    /// only need the card.
+    #[serde(default, alias = "redactSecrets")]
+    pub redact_secrets: bool,
+    /// Whether the output carries `auth_header`/`auth_injection` (defaults to
+    /// true); the component config's `include_auth_header: false` turns this
+    /// off for every invocation.
+    #[serde(default = "default_true", alias = "includeAuthHeader")]
+    pub include_auth_header: bool,
+    /// Downstream request to add a `DPoP` proof header for when the token is
+    /// DPoP-bound (requires the `dpop` feature).
+    #[serde(default, alias = "dpopRequest")]
+    pub dpop_request: Option<DpopRequest>,
+    /// Attach `diagnostics` to the output, for troubleshooting provider setup.
+    #[serde(default)]
+    pub debug: bool,
+}
+
+/// HTTP method and URL a DPoP proof is generated for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DpopRequest {
+    pub method: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProviderRequest {
+    #[serde(alias = "providerId")]
+    pub provider_id: String,
+    /// Scopes for this provider; falls back to the top-level `scopes` when empty.
+    #[serde(default, deserialize_with = "crate::scopes::deserialize_list")]
+    pub scopes: Vec<Scope>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct OAuthCardOutput {
     pub status: OAuthStatus,
     pub card: Option<MessageCard>,
     pub auth_context: Option<AuthContext>,
     pub auth_header: Option<AuthHeader>,
+    /// Token placement for API calls, per the provider's configured style.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_injection: Option<AuthInjection>,
     pub state_id: Option<String>,
     pub error: Option<String>,
+    /// Per-provider outcomes for batch-ensure.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub provider_results: Vec<ProviderResult>,
+    /// Connections reported by list-connections.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub connections: Vec<ConnectionSummary>,
+    /// Accounts offered by switch-account.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<AccountSummary>,
+    /// Channel-native renderings of `card`, keyed by channel name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rendered: BTreeMap<String, Value>,
+    /// Markdown (or plain text) version of `card` for channels without card support.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_fallback: Option<String>,
+    /// Machine-readable category of the failure behind `error`
+    /// (`OAuthCardError::error_code`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ErrorCode>,
+    /// Whether repeating the same request may succeed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retryable: Option<bool>,
+    /// Structured context for `error`, such as the offending input field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_detail: Option<Value>,
+    /// The input's `correlation_id`, echoed back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Requested scopes removed because the provider's `allowed_scopes` do
+    /// not include them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_scopes: Vec<Scope>,
+    /// Every rejected input field when the input failed validation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub validation_errors: Vec<ValidationError>,
+    /// How the invocation was resolved; only set for `debug` inputs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Diagnostics>,
+}
+
+/// One rejected input field.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ValidationError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Troubleshooting details for a `debug` invocation. Carries no tokens,
+/// codes or secrets, so it is kept when the output's secrets are zeroized.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Diagnostics {
+    /// Card provider the `provider_id` maps to.
+    pub provider: OauthProvider,
+    /// Whether the component config has an entry for the provider.
+    pub provider_configured: bool,
+    /// Redirect path sign-in would use, or `None` when the provider's
+    /// redirect policy rejects it.
+    pub redirect_path: Option<String>,
+    /// Scopes as the input gave them.
+    pub requested_scopes: Vec<Scope>,
+    /// Scopes after provider defaults, de-duplication and the allow-list.
+    pub normalized_scopes: Vec<Scope>,
+    /// Broker methods called, in order.
+    pub broker_calls: Vec<String>,
+}
+
+impl OAuthCardOutput {
+    /// `ok` output showing `card`; other fields start empty.
+    pub fn ok(card: MessageCard) -> Self {
+        Self {
+            status: OAuthStatus::Ok,
+            card: Some(card),
+            ..Default::default()
+        }
+    }
+
+    /// `needs-sign-in` output showing `card`, with the `state_id` of the
+    /// sign-in it started, if any.
+    pub fn needs_sign_in(card: MessageCard, state_id: Option<String>) -> Self {
+        Self {
+            status: OAuthStatus::NeedsSignIn,
+            card: Some(card),
+            state_id,
+            ..Default::default()
+        }
+    }
+
+    /// `error` output describing `err` (message, code, retryability and
+    /// detail), without a card.
+    pub fn error(err: &OAuthCardError) -> Self {
+        Self {
+            status: OAuthStatus::Error,
+            error: Some(err.to_string()),
+            error_code: Some(err.error_code()),
+            retryable: Some(err.is_transient()),
+            error_detail: Some(err.detail()),
+            validation_errors: err.validation_errors(),
+            ..Default::default()
+        }
+    }
+
+    /// Wipe and remove `auth_header`/`auth_injection`, including those of
+    /// `provider_results`.
+    pub fn zeroize_secrets(&mut self) {
+        let results = self
+            .provider_results
+            .iter_mut()
+            .map(|result| (&mut result.auth_header, &mut result.auth_injection));
+        for (header, injection) in
+            std::iter::once((&mut self.auth_header, &mut self.auth_injection)).chain(results)
+        {
+            header.zeroize();
+            *header = None;
+            injection.zeroize();
+            *injection = None;
+        }
+    }
+}
+
+/// Broker failure categories flows can branch on without parsing messages.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrokerErrorCode {
+    /// The user or provider refused the authorization (`access_denied`).
+    ConsentDenied,
+    /// The refresh token or code is no longer valid; the user must sign in again.
+    InvalidGrant,
+    /// The broker or provider is rate limiting requests.
+    Throttled,
+    /// The broker or provider could not be reached or failed internally.
+    ProviderUnavailable,
+    /// The code was already redeemed under the same idempotency key; the
+    /// token from the first exchange is available via `get_token`.
+    AlreadyExchanged,
+    Unknown,
+}
+
+impl BrokerErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ConsentDenied => "consent_denied",
+            Self::InvalidGrant => "invalid_grant",
+            Self::Throttled => "throttled",
+            Self::ProviderUnavailable => "provider_unavailable",
+            Self::AlreadyExchanged => "already_exchanged",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// Classify an HTTP status and OAuth `error` value (RFC 6749 §5.2) from a
+    /// broker or provider response.
+    pub fn from_response(status: u16, oauth_error: Option<&str>) -> Self {
+        match oauth_error {
+            Some("access_denied" | "consent_required" | "interaction_required") => {
+                Self::ConsentDenied
+            }
+            Some("invalid_grant") => Self::InvalidGrant,
+            Some("slow_down") => Self::Throttled,
+            Some("temporarily_unavailable" | "server_error") => Self::ProviderUnavailable,
+            Some("already_exchanged") => Self::AlreadyExchanged,
+            _ if status == 409 => Self::AlreadyExchanged,
+            _ if status == 429 => Self::Throttled,
+            _ if status >= 500 => Self::ProviderUnavailable,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for BrokerErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Category of a failed invocation, reported as `OAuthCardOutput::error_code`;
+/// the broker categories of [`BrokerErrorCode`] plus the component's own.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidInput,
+    ParseError,
+    Unsupported,
+    Timeout,
+    BackendError,
+    ValidationFailed,
+    InvalidState,
+    Replay,
+    RateLimited,
+    TenantMismatch,
+    StepUpRequired,
+    ConsentDenied,
+    InvalidGrant,
+    Throttled,
+    ProviderUnavailable,
+    AlreadyExchanged,
+    Unknown,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidInput => "invalid_input",
+            Self::ParseError => "parse_error",
+            Self::Unsupported => "unsupported",
+            Self::Timeout => "timeout",
+            Self::BackendError => "backend_error",
+            Self::ValidationFailed => "validation_failed",
+            Self::InvalidState => "invalid_state",
+            Self::Replay => "replay",
+            Self::RateLimited => "rate_limited",
+            Self::TenantMismatch => "tenant_mismatch",
+            Self::StepUpRequired => "step_up_required",
+            Self::ConsentDenied => "consent_denied",
+            Self::InvalidGrant => "invalid_grant",
+            Self::Throttled => "throttled",
+            Self::ProviderUnavailable => "provider_unavailable",
+            Self::AlreadyExchanged => "already_exchanged",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<BrokerErrorCode> for ErrorCode {
+    fn from(code: BrokerErrorCode) -> Self {
+        match code {
+            BrokerErrorCode::ConsentDenied => Self::ConsentDenied,
+            BrokerErrorCode::InvalidGrant => Self::InvalidGrant,
+            BrokerErrorCode::Throttled => Self::Throttled,
+            BrokerErrorCode::ProviderUnavailable => Self::ProviderUnavailable,
+            BrokerErrorCode::AlreadyExchanged => Self::AlreadyExchanged,
+            BrokerErrorCode::Unknown => Self::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProviderResult {
+    pub provider_id: String,
+    pub status: OAuthStatus,
+    pub auth_context: Option<AuthContext>,
+    pub auth_header: Option<AuthHeader>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_injection: Option<AuthInjection>,
+    pub error: Option<String>,
+    /// Scopes removed by the provider's `allowed_scopes`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_scopes: Vec<Scope>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AuthContext {
     pub provider_id: String,
     pub subject: String,
     pub email: Option<String>,
+    /// Display name from the id_token `name` claim.
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+    /// Avatar image URL (`picture` claim).
+    #[serde(default)]
+    pub picture: Option<String>,
+    /// Preferred language tag (`locale` claim).
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Provider-side subject identifier (`sub` claim).
+    pub sub: Option<String>,
     pub tenant: Option<String>,
     pub team: Option<String>,
-    pub scopes: Vec<String>,
+    pub scopes: Vec<Scope>,
     pub expires_at: Option<u64>,
+    /// Identity claims merged from the token's `extra` fields, the id_token
+    /// and the broker's userinfo (later sources win), for downstream nodes.
+    pub claims: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Stand-in for secret values in `Debug` output and `redacted()` copies.
+pub const REDACTED: &str = "[redacted]";
+
+/// Key of the `{tenant, team}` object sent in the consent `extra_json` of
+/// tenant- or team-scoped sign-ins. Brokers keep it in the issued token's
+/// `extra` under the same key, and tokens bound this way are only handed out
+/// to inputs with the same tenant and team.
+pub const TOKEN_BINDING_KEY: &str = "token_binding";
+
+/// `Debug` masks the header values, which carry the token.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AuthHeader {
     pub headers: Vec<(String, String)>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Zeroize for AuthHeader {
+    fn zeroize(&mut self) {
+        for (_, value) in &mut self.headers {
+            value.zeroize();
+        }
+        self.headers.clear();
+    }
+}
+
+impl AuthHeader {
+    /// Copy with every header value replaced by [`REDACTED`], safe to log or
+    /// serialize.
+    pub fn redacted(&self) -> Self {
+        Self {
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, _)| (name.clone(), REDACTED.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Debug for AuthHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthHeader")
+            .field("headers", &self.redacted().headers)
+            .finish()
+    }
+}
+
+/// Where downstream API calls should put the access token. `auth_header`
+/// carries the same credential for the header and cookie styles; `Debug`
+/// masks the value.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "style", rename_all = "snake_case")]
+pub enum AuthInjection {
+    Header { name: String, value: String },
+    Query { param: String, value: String },
+    Cookie { name: String, value: String },
+}
+
+impl Zeroize for AuthInjection {
+    fn zeroize(&mut self) {
+        match self {
+            Self::Header { value, .. } | Self::Query { value, .. } | Self::Cookie { value, .. } => {
+                value.zeroize()
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for AuthInjection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (variant, key, name) = match self {
+            Self::Header { name, .. } => ("Header", "name", name),
+            Self::Query { param, .. } => ("Query", "param", param),
+            Self::Cookie { name, .. } => ("Cookie", "name", name),
+        };
+        f.debug_struct(variant)
+            .field(key, name)
+            .field("value", &REDACTED)
+            .finish()
+    }
+}
+
+impl AuthInjection {
+    /// The request header that carries the token, if this style uses one.
+    pub fn header(&self) -> Option<(String, String)> {
+        match self {
+            Self::Header { name, value } => Some((name.clone(), value.clone())),
+            Self::Cookie { name, value } => Some(("Cookie".into(), format!("{name}={value}"))),
+            Self::Query { .. } => None,
+        }
+    }
+}
+
+/// Configured token placement for a provider (`ProviderConfig::auth_injection`).
+/// Without one, tokens go in `Authorization: <token_type> <token>`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "style", rename_all = "snake_case")]
+pub enum AuthInjectionStyle {
+    /// Custom header such as `X-Api-Key`; `prefix` (e.g. `Bearer`) is
+    /// prepended with a space when set.
+    Header {
+        name: String,
+        #[serde(default)]
+        prefix: Option<String>,
+    },
+    /// Query parameter such as `access_token`.
+    Query {
+        param: String,
+    },
+    Cookie {
+        name: String,
+    },
+}
+
+/// `Debug` masks the access, refresh and id tokens; use [`TokenSet::redacted`]
+/// to serialize one for logs.
+#[derive(Clone, Serialize, Deserialize, Default)]
 pub struct TokenSet {
     pub access_token: String,
     pub refresh_token: Option<String>,
     pub expires_at: Option<u64>,
     pub token_type: Option<String>,
     pub extra: Option<serde_json::Value>,
+    /// OIDC id_token (compact JWT) when the provider issued one.
+    #[serde(default)]
+    pub id_token: Option<String>,
+    /// Space-delimited `scope` value of the token response (RFC 6749 §5.1).
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// Scopes the provider actually granted; empty when the broker does not report them.
+    #[serde(default)]
+    pub granted_scopes: Vec<String>,
+}
+
+impl TokenSet {
+    /// Granted scopes: `granted_scopes` when reported, otherwise the split
+    /// `scope` string, otherwise empty (unknown).
+    pub fn scopes(&self) -> Vec<String> {
+        if !self.granted_scopes.is_empty() {
+            return self.granted_scopes.clone();
+        }
+        self.scope
+            .as_deref()
+            .map(|scope| scope.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    /// Copy with the access, refresh and id tokens replaced by [`REDACTED`].
+    pub fn redacted(&self) -> Self {
+        Self {
+            access_token: REDACTED.into(),
+            refresh_token: self.refresh_token.as_ref().map(|_| REDACTED.into()),
+            id_token: self.id_token.as_ref().map(|_| REDACTED.into()),
+            ..self.clone()
+        }
+    }
+}
+
+/// Overwrites the access, refresh and id tokens before clearing the rest;
+/// handlers hold tokens in [`zeroize::Zeroizing`] so they are wiped on drop.
+impl Zeroize for TokenSet {
+    fn zeroize(&mut self) {
+        self.access_token.zeroize();
+        self.refresh_token.zeroize();
+        self.id_token.zeroize();
+        self.expires_at = None;
+        self.token_type = None;
+        self.extra = None;
+        self.scope = None;
+        self.granted_scopes.clear();
+    }
+}
+
+impl std::fmt::Debug for TokenSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = self.redacted();
+        f.debug_struct("TokenSet")
+            .field("access_token", &redacted.access_token)
+            .field("refresh_token", &redacted.refresh_token)
+            .field("expires_at", &self.expires_at)
+            .field("token_type", &self.token_type)
+            .field("extra", &self.extra)
+            .field("id_token", &redacted.id_token)
+            .field("scope", &self.scope)
+            .field("granted_scopes", &self.granted_scopes)
+            .finish()
+    }
+}
+
+/// A provider connection stored by the broker for a subject.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ConnectionSummary {
+    pub provider_id: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub expires_at: Option<u64>,
+    /// Human-readable account name (e.g. the connected email address).
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// One of possibly several accounts a subject has connected for a provider.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AccountSummary {
+    pub account_id: String,
+    pub label: Option<String>,
+    pub email: Option<String>,
+    /// Whether the broker currently hands out this account's token.
+    #[serde(default)]
+    pub active: bool,
+}
+
+/// Provider endpoints from an OIDC discovery document
+/// (`{issuer}/.well-known/openid-configuration`).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct OidcEndpoints {
+    #[serde(default)]
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_session_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userinfo_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revocation_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
 }
 
+/// Display details of a connected account, from the broker's userinfo or ID token claims.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct UserProfile {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// Avatar image URL (`picture` claim).
+    pub picture: Option<String>,
+    /// Preferred language tag (`locale` claim).
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+/// Flow state saved by start-sign-in (and admin-consent) and checked when
+/// the callback completes (or the approval is checked).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct PendingSignIn {
+    pub provider_id: String,
+    pub subject: String,
+    pub redirect_path: String,
+    pub scopes: Vec<Scope>,
+    /// Sent with the consent request and expected in the returned id_token.
+    #[serde(default)]
+    pub nonce: Option<String>,
+    /// Tenant an admin-consent request was issued for.
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl Zeroize for PendingSignIn {
+    fn zeroize(&mut self) {
+        self.nonce.zeroize();
+    }
+}
+
+/// Progress of an authorization started with a `state_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum SignInProgress {
+    Pending,
+    Completed { token: TokenSet },
+    Denied { reason: Option<String> },
+}
+
+/// Device authorization response returned by the broker when starting a device-code flow.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeviceCodeGrant {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    /// Lifetime of the device code in seconds.
+    pub expires_in: Option<u64>,
+    /// Minimum polling interval in seconds.
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
 pub enum OAuthStatus {
     #[default]
     Ok,
+    /// A token is available but expires within the refresh margin.
+    ExpiringSoon,
     NeedsSignIn,
+    /// A connection existed but its token expired and could not be refreshed;
+    /// the user must sign in again.
+    Expired,
+    /// The connection was disconnected and its token revoked.
+    Revoked,
+    /// Authorization is in progress (e.g. waiting for the user to enter a device code).
+    Pending,
+    /// A token exists but lacks some requested scopes; the user must grant them.
+    NeedsConsent,
+    /// The user or provider declined the authorization.
+    Denied,
+    /// The callback's `state_id` failed signature or freshness checks; the
+    /// sign-in has to start over.
+    InvalidState,
+    /// The token was issued under another tenant or team than the input's.
+    TenantMismatch,
+    /// The sign-in did not meet the requested `acr_values`/`max_age`; the user
+    /// must authenticate again more strongly.
+    StepUpRequired,
     Error,
+    /// Status this version does not know, e.g. in an output from a newer component.
+    #[serde(untagged)]
+    Other(String),
+}
+
+/// Input for the `backchannel-logout` operation (OIDC Back-Channel Logout 1.0).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackChannelLogoutInput {
+    pub provider_id: String,
+    /// Logout token (JWT) posted by the provider.
+    pub logout_token: String,
+}
+
+/// Optional operations a backend can fulfil, so cards only offer actions that
+/// will work. `Default` is the bare minimum (token lookup and code exchange).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct BackendCapabilities {
+    pub refresh: bool,
+    pub revoke: bool,
+    pub device_code: bool,
+    pub client_credentials: bool,
+    /// `poll_sign_in` and `cancel_sign_in`.
+    pub sign_in_polling: bool,
+    /// `list_accounts` and `select_account`.
+    pub account_switching: bool,
+    pub end_session: bool,
+    pub list_connections: bool,
+}
+
+impl BackendCapabilities {
+    /// Everything; assumed for backends that do not report capabilities.
+    pub fn all() -> Self {
+        Self {
+            refresh: true,
+            revoke: true,
+            device_code: true,
+            client_credentials: true,
+            sign_in_polling: true,
+            account_switching: true,
+            end_session: true,
+            list_connections: true,
+        }
+    }
+
+    /// Capabilities offered by either backend.
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            refresh: self.refresh || other.refresh,
+            revoke: self.revoke || other.revoke,
+            device_code: self.device_code || other.device_code,
+            client_credentials: self.client_credentials || other.client_credentials,
+            sign_in_polling: self.sign_in_polling || other.sign_in_polling,
+            account_switching: self.account_switching || other.account_switching,
+            end_session: self.end_session || other.end_session,
+            list_connections: self.list_connections || other.list_connections,
+        }
+    }
+}
+
+/// Per-provider defaults from the component configuration, applied to inputs
+/// that leave the corresponding fields empty.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct ProviderConfig {
+    /// Shown instead of the provider id, e.g. on "Sign in with ..." buttons.
+    pub display_name: Option<String>,
+    /// Logo URL used on cards for this provider.
+    pub icon: Option<String>,
+    #[serde(deserialize_with = "crate::scopes::deserialize_list")]
+    pub default_scopes: Vec<Scope>,
+    /// Consent prompt sent with sign-in requests (`consent`, `select_account`, ...).
+    pub prompt: Option<String>,
+    pub resource: Option<String>,
+    pub audience: Option<String>,
+    /// Redirect path with a `{provider_id}` placeholder.
+    pub redirect_template: Option<String>,
+    /// Path prefixes an input `redirect_path` must start with (default
+    /// `/oauth/callback/`); the `redirect_template` path is always allowed.
+    pub redirect_prefixes: Vec<String>,
+    /// Hosts consent URLs from the broker may point to (`*.example.com`
+    /// also matches subdomains); empty allows any https host.
+    pub consent_hosts: Vec<String>,
+    /// Maximum scope set requests for this provider may ask for; empty means
+    /// no limit. Other scopes are dropped, or rejected with
+    /// `reject_disallowed_scopes`.
+    #[serde(deserialize_with = "crate::scopes::deserialize_list")]
+    pub allowed_scopes: Vec<Scope>,
+    pub reject_disallowed_scopes: bool,
+    /// How the access token is attached to API calls for this provider.
+    pub auth_injection: Option<AuthInjectionStyle>,
+    /// Verify this provider's id_tokens before their claims reach the auth
+    /// context (cargo feature `verify-jwt`).
+    pub id_token_verification: Option<IdTokenVerification>,
+    /// Ask the broker for DPoP-bound tokens by sending the component key's
+    /// thumbprint as `dpop_jkt` with consent requests (cargo feature `dpop`).
+    pub dpop: bool,
+    /// Identity-provider tenant sign-ins are pinned to, sent under the
+    /// registry's tenant parameter (Microsoft `tenant`, Slack `team`, Discord
+    /// `guild_id`, Auth0 `organization`).
+    pub tenant: Option<String>,
+    /// OIDC issuer of a provider the registry does not know; its discovery
+    /// document supplies the authorize, token and end-session endpoints.
+    pub issuer: Option<String>,
+    /// Admin-consent URL template with `{tenant}`, `{scopes}`,
+    /// `{redirect_path}` and `{state}` placeholders; without it the broker
+    /// builds the URL.
+    pub admin_consent_url: Option<String>,
+    /// Card copy keyed by mode name for this provider, over the component's
+    /// `card_templates`.
+    pub card_templates: BTreeMap<String, CardTemplate>,
+    /// Branding for this provider's cards, over the component's `branding`.
+    pub branding: Option<Branding>,
+}
+
+/// Keys and expected values an id_token must match to be trusted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct IdTokenVerification {
+    /// The provider's JSON Web Key Set, as served from its `jwks_uri`.
+    pub jwks: Jwks,
+    /// Expected `iss` claim.
+    pub issuer: Option<String>,
+    /// Expected `aud` claim (usually the client id).
+    pub audience: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// Public key in JWK form; RSA keys carry `n`/`e`, EC keys `crv`/`x`/`y`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: Option<String>,
+    pub alg: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+    pub crv: Option<String>,
+    pub x: Option<String>,
+    pub y: Option<String>,
+}
+
+/// Result of the `health` operation.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct HealthOutput {
+    pub status: OAuthStatus,
+    /// Whether the broker answered the probe.
+    pub reachable: bool,
+    /// Time taken by the probe in milliseconds.
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Result of the `version` operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionOutput {
+    /// Crate version of the component.
+    pub version: String,
+    /// Commit the component was built from (`GIT_SHA` at build time).
+    pub git_sha: Option<String>,
+    pub modes: Vec<OAuthCardMode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BackChannelLogoutOutput {
+    pub status: OAuthStatus,
+    pub provider_id: String,
+    pub sub: Option<String>,
+    pub sid: Option<String>,
+    /// Subjects whose connections were invalidated, so the flow can notify them.
+    #[serde(default)]
+    pub subjects: Vec<String>,
+    pub error: Option<String>,
 }
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+/// Per-call hook of a backend decorator whose forwarding is generated by
+/// [`observed_backend!`]: `started` runs before each broker call is made,
+/// `finished` once it returned (for async backends, once its future
+/// completed) with how long it took and whether it failed.
+pub(crate) trait CallHook {
+    fn started(&self, method: &'static str);
+
+    /// Takes no `self`, so async decorators need not hold on to themselves
+    /// while a call is pending.
+    fn finished(_method: &'static str, _elapsed: Duration, _failed: bool) {}
+}
+
+/// Implement `OAuthBackend` (`sync`) or `AsyncOAuthBackend` (`async`) for a
+/// decorator with an `inner` backend and a [`CallHook`], forwarding every
+/// broker call to `inner` between the hook's `started` and `finished`;
+/// `capabilities` is forwarded as is.
+///
+/// ```ignore
+/// observed_backend!(sync impl[B: OAuthBackend] MeteredBackend<B>);
+/// ```
+macro_rules! observed_backend {
+    ($flavor:ident impl[$($generics:tt)*] $decorator:ty) => {
+        $crate::observed::observed_backend!(@impl $flavor [$($generics)*] $decorator {
+            get_token(ctx: &CallContext, provider_id: &str, subject: &str, scopes: &[Scope])
+                -> Option<TokenSet>;
+            get_consent_url(
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+                redirect_path: &str,
+                extra_json: Option<String>
+            ) -> String;
+            exchange_code(
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                code: &str,
+                redirect_path: &str,
+                idempotency_key: Option<&str>
+            ) -> TokenSet;
+            exchange_code_with_extra(
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                code: &str,
+                redirect_path: &str,
+                idempotency_key: Option<&str>,
+                extra_json: Option<String>
+            ) -> TokenSet;
+            poll_sign_in(ctx: &CallContext, provider_id: &str, subject: &str, state_id: &str)
+                -> SignInProgress;
+            cancel_sign_in(ctx: &CallContext, provider_id: &str, subject: &str, state_id: &str)
+                -> ();
+            exchange_sso_token(
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                sso_token: &str,
+                scopes: &[Scope]
+            ) -> TokenSet;
+            refresh_token(ctx: &CallContext, provider_id: &str, subject: &str, scopes: &[Scope])
+                -> TokenSet;
+            get_app_token(
+                ctx: &CallContext,
+                provider_id: &str,
+                scopes: &[Scope],
+                extra_json: Option<String>
+            ) -> TokenSet;
+            exchange_token(
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                subject_token: &str,
+                subject_token_type: &str,
+                scopes: &[Scope],
+                extra_json: Option<String>
+            ) -> TokenSet;
+            revoke_token(ctx: &CallContext, provider_id: &str, subject: &str, scopes: &[Scope])
+                -> ();
+            get_end_session_url(ctx: &CallContext, provider_id: &str, subject: &str) -> String;
+            get_admin_consent_url(
+                ctx: &CallContext,
+                provider_id: &str,
+                tenant: &str,
+                scopes: &[Scope],
+                redirect_path: &str
+            ) -> String;
+            admin_consent_granted(
+                ctx: &CallContext,
+                provider_id: &str,
+                tenant: &str,
+                scopes: &[Scope]
+            ) -> bool;
+            invalidate_sessions(
+                ctx: &CallContext,
+                provider_id: &str,
+                sub: Option<&str>,
+                sid: Option<&str>
+            ) -> Vec<String>;
+            list_connections(ctx: &CallContext, subject: &str) -> Vec<ConnectionSummary>;
+            list_accounts(ctx: &CallContext, provider_id: &str, subject: &str)
+                -> Vec<AccountSummary>;
+            select_account(ctx: &CallContext, provider_id: &str, subject: &str, account_id: &str)
+                -> ();
+            store_token(ctx: &CallContext, provider_id: &str, subject: &str, token: &TokenSet)
+                -> ();
+            get_userinfo(ctx: &CallContext, provider_id: &str, subject: &str) -> UserProfile;
+            discover_oidc(ctx: &CallContext, issuer: &str) -> OidcEndpoints;
+            start_device_code(
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                scopes: &[Scope],
+                extra_json: Option<String>
+            ) -> DeviceCodeGrant;
+            poll_device_code(
+                ctx: &CallContext,
+                provider_id: &str,
+                subject: &str,
+                device_code: &str
+            ) -> Option<TokenSet>;
+            health(ctx: &CallContext) -> ();
+        });
+    };
+    (@impl sync [$($generics:tt)*] $decorator:ty {
+        $($method:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*
+    }) => {
+        impl<$($generics)*> OAuthBackend for $decorator {
+            $(
+                fn $method(&self, $($arg: $ty),*) -> Result<$ret, OAuthCardError> {
+                    let method = stringify!($method);
+                    $crate::observed::CallHook::started(self, method);
+                    let started = std::time::Instant::now();
+                    let result = self.inner.$method($($arg),*);
+                    <Self as $crate::observed::CallHook>::finished(
+                        method,
+                        started.elapsed(),
+                        result.is_err(),
+                    );
+                    result
+                }
+            )*
+
+            fn capabilities(&self) -> BackendCapabilities {
+                self.inner.capabilities()
+            }
+        }
+    };
+    (@impl async [$($generics:tt)*] $decorator:ty {
+        $($method:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;)*
+    }) => {
+        impl<$($generics)*> AsyncOAuthBackend for $decorator {
+            $(
+                fn $method(
+                    &self,
+                    $($arg: $ty),*
+                ) -> impl Future<Output = Result<$ret, OAuthCardError>> + Send {
+                    let method = stringify!($method);
+                    $crate::observed::CallHook::started(self, method);
+                    let started = std::time::Instant::now();
+                    let call = self.inner.$method($($arg),*);
+                    async move {
+                        let result = call.await;
+                        <Self as $crate::observed::CallHook>::finished(
+                            method,
+                            started.elapsed(),
+                            result.is_err(),
+                        );
+                        result
+                    }
+                }
+            )*
+
+            fn capabilities(&self) -> BackendCapabilities {
+                self.inner.capabilities()
+            }
+        }
+    };
+}
+
+pub(crate) use observed_backend;
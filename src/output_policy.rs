@@ -0,0 +1,35 @@
+use serde::Deserialize;
+
+use crate::model::OAuthCardInput;
+use crate::{OAuthCardError, component_config};
+
+#[derive(Deserialize)]
+struct ComponentOutputPolicy {
+    #[serde(default)]
+    include_auth_header: Option<bool>,
+}
+
+/// Load `include_auth_header` from the component configuration, replacing any
+/// previously loaded value (a missing key clears it). Other configuration keys
+/// are ignored.
+///
+/// `false` overrides the input's `include_auth_header`, so a deployment whose
+/// channels only display cards keeps bearer tokens out of every output
+/// whatever the flow asks for; `true` (or no value) leaves it to the input.
+pub fn load_output_policy(config_json: &str) -> Result<(), OAuthCardError> {
+    let include_auth_header = parse(config_json)?;
+    component_config::update(|loaded| loaded.include_auth_header = include_auth_header);
+    Ok(())
+}
+
+pub(crate) fn parse(config_json: &str) -> Result<Option<bool>, OAuthCardError> {
+    let config: ComponentOutputPolicy = serde_json::from_str(config_json)
+        .map_err(|err| OAuthCardError::Parse(format!("output policy config json: {err}")))?;
+    Ok(config.include_auth_header)
+}
+
+/// Whether the output for `input` may carry `auth_header`/`auth_injection`.
+pub(crate) fn include_auth_header(input: &OAuthCardInput) -> bool {
+    let configured = component_config::loaded().include_auth_header;
+    configured != Some(false) && input.include_auth_header && !input.redact_secrets
+}
@@ -0,0 +1,89 @@
+use std::cell::RefCell;
+
+use serde::Serialize;
+
+thread_local! {
+    static STAGES: RefCell<Option<Vec<Stage>>> = const { RefCell::new(None) };
+}
+
+/// Step of an invocation reported by [`crate::handle_message_stream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    Parsing,
+    BrokerLookup,
+    ConsentUrl,
+    Exchange,
+    Rendering,
+}
+
+impl Stage {
+    /// Share of the invocation done once this stage starts.
+    pub fn percent(self) -> u8 {
+        match self {
+            Stage::Parsing => 10,
+            Stage::BrokerLookup => 30,
+            Stage::ConsentUrl => 50,
+            Stage::Exchange => 70,
+            Stage::Rendering => 90,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Stage::Parsing => "Reading request",
+            Stage::BrokerLookup => "Checking connection",
+            Stage::ConsentUrl => "Preparing sign-in link",
+            Stage::Exchange => "Completing sign-in",
+            Stage::Rendering => "Rendering card",
+        }
+    }
+
+    /// Stage a broker method belongs to, if it is worth reporting.
+    fn of_broker_call(method: &str) -> Option<Self> {
+        match method {
+            "get_token" | "list_connections" | "list_accounts" | "get_userinfo" => {
+                Some(Stage::BrokerLookup)
+            }
+            "get_consent_url" | "get_admin_consent_url" | "start_device_code" => {
+                Some(Stage::ConsentUrl)
+            }
+            "exchange_code"
+            | "exchange_code_with_extra"
+            | "exchange_sso_token"
+            | "exchange_token"
+            | "refresh_token"
+            | "get_app_token"
+            | "poll_device_code"
+            | "poll_sign_in" => Some(Stage::Exchange),
+            _ => None,
+        }
+    }
+}
+
+/// Run `f`, collecting the stages it reaches in order. Stages never go
+/// backwards, so a lookup after an exchange is not reported twice.
+pub(crate) fn collect<T>(f: impl FnOnce() -> T) -> (T, Vec<Stage>) {
+    let previous = STAGES.with(|stages| stages.replace(Some(Vec::new())));
+    let value = f();
+    let stages = STAGES.with(|stages| stages.replace(previous));
+    (value, stages.unwrap_or_default())
+}
+
+/// Note that the current invocation reached `stage`; a no-op outside
+/// [`collect`].
+pub(crate) fn mark(stage: Stage) {
+    STAGES.with(|stages| {
+        if let Some(stages) = stages.borrow_mut().as_mut()
+            && stages.last().is_none_or(|last| *last < stage)
+        {
+            stages.push(stage);
+        }
+    });
+}
+
+pub(crate) fn mark_broker_call(method: &str) {
+    if let Some(stage) = Stage::of_broker_call(method) {
+        mark(stage);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::OAuthCardError;
+use crate::component_config;
+use crate::model::{OAuthCardInput, ProviderConfig};
+use crate::scopes::Scope;
+
+#[derive(Deserialize)]
+struct ComponentProviders {
+    #[serde(default)]
+    providers: BTreeMap<String, ProviderConfig>,
+}
+
+/// Load the `providers` map of the component configuration, replacing any
+/// previously loaded provider defaults. Other configuration keys are ignored.
+pub fn load_provider_configs(config_json: &str) -> Result<(), OAuthCardError> {
+    let providers = parse(config_json)?;
+    component_config::update(|loaded| loaded.providers = providers);
+    Ok(())
+}
+
+pub(crate) fn parse(config_json: &str) -> Result<BTreeMap<String, ProviderConfig>, OAuthCardError> {
+    let config: ComponentProviders = serde_json::from_str(config_json)
+        .map_err(|err| OAuthCardError::Parse(format!("provider config json: {err}")))?;
+    Ok(config.providers)
+}
+
+/// Ids of the providers the component config has an entry for.
+pub(crate) fn configured_provider_ids() -> Vec<String> {
+    component_config::loaded()
+        .providers
+        .keys()
+        .cloned()
+        .collect()
+}
+
+pub(crate) fn provider_config(provider_id: &str) -> Option<ProviderConfig> {
+    component_config::loaded()
+        .providers
+        .get(provider_id)
+        .cloned()
+}
+
+/// Fill fields the input left empty from the loaded config of its provider.
+pub(crate) fn with_defaults(input: OAuthCardInput) -> OAuthCardInput {
+    match provider_config(&input.provider_id) {
+        Some(config) => apply(input, &config),
+        None => input,
+    }
+}
+
+/// Scopes, redirect path, icon and consent parameters (`prompt`, `resource`,
+/// `audience` in `extra_json`) are only set when the input does not carry them.
+pub(crate) fn apply(mut input: OAuthCardInput, config: &ProviderConfig) -> OAuthCardInput {
+    if input.scopes.is_empty() {
+        input.scopes = config.default_scopes.clone();
+    }
+    if input.redirect_path.is_none()
+        && let Some(template) = config.redirect_template.as_deref()
+    {
+        input.redirect_path = Some(template.replace("{provider_id}", &input.provider_id));
+    }
+
+    let consent = [
+        ("prompt", &config.prompt),
+        ("resource", &config.resource),
+        ("audience", &config.audience),
+    ];
+    if consent.iter().all(|(_, value)| value.is_none()) {
+        return input;
+    }
+    let mut extra = match input.extra_json.take() {
+        Some(Value::Object(extra)) => extra,
+        Some(other) => {
+            input.extra_json = Some(other);
+            return input;
+        }
+        None => serde_json::Map::new(),
+    };
+    for (key, value) in consent {
+        if let Some(value) = value {
+            extra
+                .entry(key)
+                .or_insert_with(|| Value::String(value.clone()));
+        }
+    }
+    input.extra_json = Some(Value::Object(extra));
+    input
+}
+
+/// Redirect prefix allowed when the provider config sets no `redirect_prefixes`.
+pub(crate) const DEFAULT_REDIRECT_PREFIX: &str = "/oauth/callback/";
+
+/// Reject redirect paths the provider's policy does not allow: absolute or
+/// protocol-relative URLs, `.`/`..` segments (also percent-encoded),
+/// backslashes and control characters, and paths outside the configured
+/// `redirect_prefixes` (default `/oauth/callback/` or the component config's
+/// `redirect_base`). The path rendered from the provider's own
+/// `redirect_template` is always allowed.
+pub(crate) fn check_redirect_path(
+    config: Option<&ProviderConfig>,
+    provider_id: &str,
+    path: &str,
+) -> Result<(), OAuthCardError> {
+    let reject = |reason: &str| {
+        Err(OAuthCardError::validation(
+            "redirect_path",
+            format!("{path:?} {reason}"),
+        ))
+    };
+    if !path.starts_with('/') || path.starts_with("//") || path.contains("://") {
+        return reject("must be a path on this host, not a URL");
+    }
+    if path.chars().any(|c| c == '\\' || c.is_control()) {
+        return reject("contains a backslash or control character");
+    }
+    let decoded = path
+        .to_ascii_lowercase()
+        .replace("%2e", ".")
+        .replace("%2f", "/")
+        .replace("%5c", "/");
+    if decoded
+        .split(['/', '?', '#'])
+        .any(|segment| segment == "." || segment == "..")
+    {
+        return reject("contains a relative path segment");
+    }
+    let config = config.cloned().unwrap_or_default();
+    if config
+        .redirect_template
+        .is_some_and(|template| template.replace("{provider_id}", provider_id) == path)
+    {
+        return Ok(());
+    }
+    let allowed = if config.redirect_prefixes.is_empty() {
+        path.starts_with(&component_config::redirect_prefix())
+    } else {
+        config
+            .redirect_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    };
+    if allowed {
+        Ok(())
+    } else {
+        reject("is outside the allowed redirect prefixes")
+    }
+}
+
+/// Render an `admin_consent_url` template, percent-encoding each value.
+pub(crate) fn admin_consent_url(
+    template: &str,
+    tenant: &str,
+    scopes: &[Scope],
+    redirect_path: &str,
+    state: &str,
+) -> String {
+    let scopes = scopes
+        .iter()
+        .map(Scope::as_str)
+        .collect::<Vec<_>>()
+        .join(" ");
+    template
+        .replace("{tenant}", &percent_encode(tenant))
+        .replace("{scopes}", &percent_encode(&scopes))
+        .replace("{redirect_path}", &percent_encode(redirect_path))
+        .replace("{state}", &percent_encode(state))
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Reject consent URLs that are unsafe to open: anything but `https` (plain
+/// `http` only for loopback hosts), credentials, backslashes or control
+/// characters in the URL, and hosts missing from the provider's
+/// `consent_hosts` when set (a `*.` entry also matches subdomains).
+pub(crate) fn check_consent_url(
+    config: Option<&ProviderConfig>,
+    url: &str,
+) -> Result<(), OAuthCardError> {
+    let reject = |reason: &str| {
+        Err(OAuthCardError::Backend(format!(
+            "consent URL from the broker {reason}"
+        )))
+    };
+    if url
+        .chars()
+        .any(|c| c == '\\' || c.is_whitespace() || c.is_control())
+    {
+        return reject("contains a backslash, whitespace or control characters");
+    }
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return reject("has no https scheme");
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if authority.contains('@') {
+        return reject("carries credentials in its host");
+    }
+    let host = match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    }
+    .to_ascii_lowercase();
+    if host.is_empty() {
+        return reject("has no host");
+    }
+    let loopback = matches!(host.as_str(), "localhost" | "127.0.0.1" | "::1");
+    match scheme.to_ascii_lowercase().as_str() {
+        "https" => {}
+        "http" if loopback => {}
+        _ => return reject("must use https"),
+    }
+    let allowed_hosts = config.map_or(&[][..], |config| config.consent_hosts.as_slice());
+    let allowed = allowed_hosts.is_empty()
+        || allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.ends_with('.')),
+                None => host == allowed,
+            }
+        });
+    if allowed {
+        Ok(())
+    } else {
+        reject(&format!(
+            "points to {host}, which is not an allowed consent host"
+        ))
+    }
+}
+
+/// Limit `input.scopes` to its provider's `allowed_scopes` (or the component
+/// config's when the provider sets none), returning the scopes removed; with
+/// `reject_disallowed_scopes` any such scope fails the request instead.
+pub(crate) fn restrict_scopes(input: &mut OAuthCardInput) -> Result<Vec<Scope>, OAuthCardError> {
+    let config = provider_config(&input.provider_id).unwrap_or_default();
+    let allowed = if config.allowed_scopes.is_empty() {
+        component_config::component_config().allowed_scopes
+    } else {
+        config.allowed_scopes
+    };
+    if allowed.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (kept, dropped): (Vec<Scope>, Vec<Scope>) = input
+        .scopes
+        .iter()
+        .cloned()
+        .partition(|scope| allowed.contains(scope));
+    if config.reject_disallowed_scopes && !dropped.is_empty() {
+        return Err(OAuthCardError::validation(
+            "scopes",
+            format!(
+                "not allowed for {}: {}",
+                input.provider_id,
+                dropped
+                    .iter()
+                    .map(Scope::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ));
+    }
+    input.scopes = kept;
+    Ok(dropped)
+}
@@ -0,0 +1,404 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::OAuthCardError;
+use crate::model::{OAuthCardInput, OAuthCardMode, OauthProvider};
+use crate::provider_config;
+use crate::scopes::Scope;
+
+/// How a provider hands out refresh tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum OfflineAccess {
+    /// Only when this scope is requested (`offline_access`, `refresh_token`).
+    Scope { scope: &'static str },
+    /// Only when the consent URL carries this parameter
+    /// (`access_type=offline`).
+    Param {
+        name: &'static str,
+        value: &'static str,
+    },
+    /// With every grant.
+    Always,
+    /// Never; tokens are long-lived or sign-in is repeated.
+    Unavailable,
+}
+
+/// Built-in facts about a well-known identity provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProviderInfo {
+    /// Canonical provider id.
+    pub id: &'static str,
+    /// Other ids meaning the same provider (`msgraph`, `jira`, ...).
+    pub aliases: &'static [&'static str],
+    /// Matching [`OauthProvider`] variant; `None` for providers the card
+    /// model has no variant for.
+    pub provider: Option<OauthProvider>,
+    pub display_name: &'static str,
+    /// SVG logo (Simple Icons, served from a CDN), usable as a card image.
+    pub icon_url: &'static str,
+    /// Authorization endpoint; `{tenant}` stands for the tenant id or the
+    /// organisation's domain.
+    pub authorize_url: &'static str,
+    /// Token endpoint, with the same `{tenant}` placeholder; DPoP proofs for
+    /// code exchanges are bound to it when discovery supplies none.
+    pub token_url: &'static str,
+    /// Scopes a new sign-in asks for when neither the input nor the
+    /// provider config names any.
+    pub default_scopes: &'static [&'static str],
+    pub offline_access: OfflineAccess,
+    /// Consent URL parameter that pins sign-in to the provider config's
+    /// `tenant` (for Microsoft, checked by [`microsoft_authority`]).
+    pub tenant_param: Option<&'static str>,
+}
+
+impl ProviderInfo {
+    /// `authorize_url` with `{tenant}` filled in.
+    pub fn authorize_url_for(&self, tenant: &str) -> String {
+        self.authorize_url.replace("{tenant}", tenant)
+    }
+
+    /// `token_url` with `{tenant}` filled in.
+    pub fn token_url_for(&self, tenant: &str) -> String {
+        self.token_url.replace("{tenant}", tenant)
+    }
+}
+
+/// Tenant id (`tid`) of personal Microsoft accounts.
+pub const MICROSOFT_CONSUMER_TENANT: &str = "9188040d-6c67-4c5b-b112-36a304b66dad";
+
+/// Authorities of the Microsoft identity platform that span tenants.
+const MICROSOFT_SHARED_AUTHORITIES: [&str; 3] = ["common", "organizations", "consumers"];
+
+/// Every provider the component knows without configuration.
+pub static PROVIDERS: [ProviderInfo; 12] = [
+    ProviderInfo {
+        id: "microsoft",
+        aliases: &["msgraph", "m365"],
+        provider: Some(OauthProvider::Microsoft),
+        display_name: "Microsoft",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/microsoft.svg",
+        authorize_url: "https://login.microsoftonline.com/{tenant}/oauth2/v2.0/authorize",
+        token_url: "https://login.microsoftonline.com/{tenant}/oauth2/v2.0/token",
+        default_scopes: &["openid", "profile", "offline_access", "User.Read"],
+        offline_access: OfflineAccess::Scope {
+            scope: "offline_access",
+        },
+        tenant_param: Some("tenant"),
+    },
+    ProviderInfo {
+        id: "google",
+        aliases: &[],
+        provider: Some(OauthProvider::Google),
+        display_name: "Google",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/google.svg",
+        authorize_url: "https://accounts.google.com/o/oauth2/v2/auth",
+        token_url: "https://oauth2.googleapis.com/token",
+        default_scopes: &["openid", "email", "profile"],
+        offline_access: OfflineAccess::Param {
+            name: "access_type",
+            value: "offline",
+        },
+        tenant_param: None,
+    },
+    ProviderInfo {
+        id: "github",
+        aliases: &[],
+        provider: Some(OauthProvider::Github),
+        display_name: "GitHub",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/github.svg",
+        authorize_url: "https://github.com/login/oauth/authorize",
+        token_url: "https://github.com/login/oauth/access_token",
+        default_scopes: &["read:user", "user:email"],
+        offline_access: OfflineAccess::Unavailable,
+        tenant_param: None,
+    },
+    ProviderInfo {
+        id: "slack",
+        aliases: &[],
+        provider: Some(OauthProvider::Slack),
+        display_name: "Slack",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/slack.svg",
+        authorize_url: "https://slack.com/openid/connect/authorize",
+        token_url: "https://slack.com/api/openid.connect.token",
+        default_scopes: &["openid", "email", "profile"],
+        offline_access: OfflineAccess::Unavailable,
+        tenant_param: Some("team"),
+    },
+    ProviderInfo {
+        id: "discord",
+        aliases: &[],
+        provider: Some(OauthProvider::Discord),
+        display_name: "Discord",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/discord.svg",
+        authorize_url: "https://discord.com/oauth2/authorize",
+        token_url: "https://discord.com/api/oauth2/token",
+        default_scopes: &["identify", "email"],
+        offline_access: OfflineAccess::Always,
+        tenant_param: Some("guild_id"),
+    },
+    ProviderInfo {
+        id: "salesforce",
+        aliases: &[],
+        provider: Some(OauthProvider::Salesforce),
+        display_name: "Salesforce",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/salesforce.svg",
+        authorize_url: "https://login.salesforce.com/services/oauth2/authorize",
+        token_url: "https://login.salesforce.com/services/oauth2/token",
+        default_scopes: &["openid", "api", "refresh_token"],
+        offline_access: OfflineAccess::Scope {
+            scope: "refresh_token",
+        },
+        tenant_param: None,
+    },
+    ProviderInfo {
+        id: "okta",
+        aliases: &[],
+        provider: Some(OauthProvider::Okta),
+        display_name: "Okta",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/okta.svg",
+        authorize_url: "https://{tenant}/oauth2/default/v1/authorize",
+        token_url: "https://{tenant}/oauth2/default/v1/token",
+        default_scopes: &["openid", "profile", "email", "offline_access"],
+        offline_access: OfflineAccess::Scope {
+            scope: "offline_access",
+        },
+        tenant_param: None,
+    },
+    ProviderInfo {
+        id: "auth0",
+        aliases: &[],
+        provider: Some(OauthProvider::Auth0),
+        display_name: "Auth0",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/auth0.svg",
+        authorize_url: "https://{tenant}/authorize",
+        token_url: "https://{tenant}/oauth/token",
+        default_scopes: &["openid", "profile", "email", "offline_access"],
+        offline_access: OfflineAccess::Scope {
+            scope: "offline_access",
+        },
+        tenant_param: Some("organization"),
+    },
+    ProviderInfo {
+        id: "atlassian",
+        aliases: &["jira", "confluence"],
+        provider: Some(OauthProvider::Atlassian),
+        display_name: "Atlassian",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/atlassian.svg",
+        authorize_url: "https://auth.atlassian.com/authorize",
+        token_url: "https://auth.atlassian.com/oauth/token",
+        default_scopes: &["read:me", "offline_access"],
+        offline_access: OfflineAccess::Scope {
+            scope: "offline_access",
+        },
+        tenant_param: None,
+    },
+    ProviderInfo {
+        id: "zoom",
+        aliases: &[],
+        provider: Some(OauthProvider::Zoom),
+        display_name: "Zoom",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/zoom.svg",
+        authorize_url: "https://zoom.us/oauth/authorize",
+        token_url: "https://zoom.us/oauth/token",
+        default_scopes: &["user:read"],
+        offline_access: OfflineAccess::Always,
+        tenant_param: None,
+    },
+    ProviderInfo {
+        id: "dropbox",
+        aliases: &[],
+        provider: Some(OauthProvider::Dropbox),
+        display_name: "Dropbox",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/dropbox.svg",
+        authorize_url: "https://www.dropbox.com/oauth2/authorize",
+        token_url: "https://api.dropboxapi.com/oauth2/token",
+        default_scopes: &["account_info.read"],
+        offline_access: OfflineAccess::Param {
+            name: "token_access_type",
+            value: "offline",
+        },
+        tenant_param: None,
+    },
+    ProviderInfo {
+        id: "gitlab",
+        aliases: &[],
+        provider: None,
+        display_name: "GitLab",
+        icon_url: "https://cdn.jsdelivr.net/npm/simple-icons@8/icons/gitlab.svg",
+        authorize_url: "https://gitlab.com/oauth/authorize",
+        token_url: "https://gitlab.com/oauth/token",
+        default_scopes: &["read_user"],
+        offline_access: OfflineAccess::Always,
+        tenant_param: None,
+    },
+];
+
+/// Registry entry for a provider id or alias (case-insensitive).
+pub fn provider_info(provider_id: &str) -> Option<&'static ProviderInfo> {
+    PROVIDERS.iter().find(|info| {
+        info.id.eq_ignore_ascii_case(provider_id)
+            || info
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(provider_id))
+    })
+}
+
+/// Fill what the provider config left open from the registry: default
+/// scopes and the offline-access consent parameter when a new grant starts
+/// (token lookups keep accepting any stored token), plus the provider
+/// config's `tenant` under the provider's tenant parameter in `extra_json`
+/// (keys the input already sets win). The input's own `tenant` is the
+/// Greentic tenant, not the identity provider's, so it is never sent.
+pub(crate) fn with_defaults(mut input: OAuthCardInput) -> OAuthCardInput {
+    let Some(info) = provider_info(&input.provider_id) else {
+        return input;
+    };
+    let starts_grant = matches!(
+        input.mode,
+        OAuthCardMode::StartSignIn | OAuthCardMode::DeviceCode
+    );
+    if starts_grant && input.scopes.is_empty() {
+        input.scopes = info
+            .default_scopes
+            .iter()
+            .filter_map(|scope| Scope::parse(scope).ok())
+            .collect();
+    }
+    let mut params = Vec::new();
+    if starts_grant && let OfflineAccess::Param { name, value } = info.offline_access {
+        params.push((name, value.to_string()));
+    }
+    let configured_tenant = provider_config::provider_config(&input.provider_id)
+        .and_then(|config| config.tenant)
+        .filter(|tenant| !tenant.trim().is_empty());
+    if let (Some(name), Some(tenant)) = (info.tenant_param, configured_tenant) {
+        params.push((name, tenant));
+    }
+    if params.is_empty() {
+        return input;
+    }
+    let mut extra = match input.extra_json.take() {
+        Some(Value::Object(extra)) => extra,
+        Some(other) => {
+            input.extra_json = Some(other);
+            return input;
+        }
+        None => serde_json::Map::new(),
+    };
+    for (name, value) in params {
+        extra.entry(name).or_insert_with(|| Value::String(value));
+    }
+    input.extra_json = Some(Value::Object(extra));
+    input
+}
+
+/// Microsoft authority (the `{tenant}` of the v2 endpoints) for a tenant:
+/// `common` without one; `common`, `organizations`, `consumers`, a tenant
+/// GUID or a verified domain as given (lowercased). Anything else, e.g. a
+/// Greentic tenant name, is no Microsoft tenant and fails validation.
+pub fn microsoft_authority(tenant: Option<&str>) -> Result<String, OAuthCardError> {
+    let tenant = tenant.unwrap_or_default().trim().to_ascii_lowercase();
+    if tenant.is_empty() {
+        return Ok("common".into());
+    }
+    let shared = MICROSOFT_SHARED_AUTHORITIES.contains(&tenant.as_str());
+    if shared || is_guid(&tenant) || is_microsoft_domain(&tenant) {
+        Ok(tenant)
+    } else {
+        Err(OAuthCardError::validation(
+            "extra_json.tenant",
+            format!(
+                "`{tenant}` is not a Microsoft tenant; use common, organizations, consumers, a tenant id or a verified domain"
+            ),
+        ))
+    }
+}
+
+/// Tenant at the identity provider `input` asks for: `extra_json.tenant`,
+/// set by the flow or from the provider config's `tenant` by
+/// [`with_defaults`]. Sign-ins, token endpoints and admin consent all read
+/// it; the input's own `tenant` is the Greentic one.
+pub(crate) fn requested_tenant(input: &OAuthCardInput) -> Option<&str> {
+    input
+        .extra_json
+        .as_ref()?
+        .get("tenant")?
+        .as_str()
+        .filter(|tenant| !tenant.trim().is_empty())
+}
+
+/// Authority a Microsoft request for `input` uses: that of its
+/// [`requested_tenant`], else `common`. `None` for other providers.
+pub(crate) fn requested_microsoft_authority(
+    input: &OAuthCardInput,
+) -> Result<Option<String>, OAuthCardError> {
+    if provider_info(&input.provider_id).is_none_or(|info| info.id != "microsoft") {
+        return Ok(None);
+    }
+    microsoft_authority(requested_tenant(input)).map(Some)
+}
+
+/// Discovery issuer of a Microsoft authority, whose document names the
+/// tenant a domain belongs to.
+pub(crate) fn microsoft_issuer(authority: &str) -> String {
+    format!("https://login.microsoftonline.com/{authority}/v2.0")
+}
+
+/// Tenant id in a Microsoft issuer (`https://login.microsoftonline.com/{tid}/v2.0`).
+pub(crate) fn microsoft_tenant_id(issuer: &str) -> Option<String> {
+    let tid = issuer
+        .trim_end_matches('/')
+        .strip_prefix("https://login.microsoftonline.com/")?
+        .strip_suffix("/v2.0")?;
+    is_guid(tid).then(|| tid.to_ascii_lowercase())
+}
+
+/// Refuse a Microsoft sign-in whose `tid` claim lies outside `authority`:
+/// another tenant than the requested GUID, a personal account for
+/// `organizations`, or a work account for `consumers`. Only `common` accepts
+/// a sign-in without `tid`. Domain authorities must be resolved to their
+/// tenant id first; unresolved ones are refused.
+pub(crate) fn check_microsoft_tenant(
+    authority: &str,
+    tid: Option<&str>,
+) -> Result<(), OAuthCardError> {
+    if authority == "common" {
+        return Ok(());
+    }
+    let Some(tid) = tid else {
+        return Err(OAuthCardError::TenantMismatch(format!(
+            "Microsoft sign-in carries no tenant id (`tid`) to check against `{authority}`"
+        )));
+    };
+    let personal = tid.eq_ignore_ascii_case(MICROSOFT_CONSUMER_TENANT);
+    let allowed = match authority {
+        "organizations" => !personal,
+        "consumers" => personal,
+        guid if is_guid(guid) => tid.eq_ignore_ascii_case(guid),
+        _ => false,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(OAuthCardError::TenantMismatch(format!(
+            "signed in to Microsoft tenant `{tid}`, expected `{authority}`"
+        )))
+    }
+}
+
+fn is_guid(text: &str) -> bool {
+    text.len() == 36
+        && text.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+pub(crate) fn is_microsoft_domain(text: &str) -> bool {
+    text.contains('.')
+        && text.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+use crate::clock::now_secs;
+use crate::{OAuthCardError, component_config, state_store};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub(crate) struct SignInRateLimit {
+    /// start-sign-in requests allowed per subject and provider in one window;
+    /// 0 disables the limit.
+    max_attempts: u32,
+    window_secs: u64,
+}
+
+const DEFAULT_SIGN_IN_RATE_LIMIT: SignInRateLimit = SignInRateLimit {
+    max_attempts: 10,
+    window_secs: 900,
+};
+
+#[derive(Deserialize)]
+struct ComponentRateLimit {
+    #[serde(default)]
+    sign_in_rate_limit: Option<SignInRateLimit>,
+}
+
+/// Load `sign_in_rate_limit` (`max_attempts`, `window_secs`) from the
+/// component configuration, replacing any previously loaded limit (a missing
+/// key restores the default of 10 attempts per 15 minutes). Other
+/// configuration keys are ignored.
+pub fn load_sign_in_rate_limit(config_json: &str) -> Result<(), OAuthCardError> {
+    let limit = parse(config_json)?;
+    component_config::update(|loaded| loaded.sign_in_rate_limit = limit);
+    Ok(())
+}
+
+pub(crate) fn parse(config_json: &str) -> Result<Option<SignInRateLimit>, OAuthCardError> {
+    let config: ComponentRateLimit = serde_json::from_str(config_json)
+        .map_err(|err| OAuthCardError::Parse(format!("rate limit config json: {err}")))?;
+    Ok(config.sign_in_rate_limit)
+}
+
+/// Sign-in attempts counted since `window_start`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Attempts {
+    window_start: u64,
+    count: u32,
+}
+
+/// Count a sign-in started for `subject` at `provider_id`, failing with
+/// [`OAuthCardError::RateLimited`] once the window's attempts are used up.
+///
+/// The count is one value in the active state store, opening a window at the
+/// first attempt after the previous one closed, so the limit holds across
+/// instances sharing the store; without a store (or one that keeps no values)
+/// nothing is limited.
+pub(crate) fn check_sign_in(provider_id: &str, subject: &str) -> Result<(), OAuthCardError> {
+    let Some(store) = state_store::active_state_store() else {
+        return Ok(());
+    };
+    let limit = component_config::loaded()
+        .sign_in_rate_limit
+        .unwrap_or(DEFAULT_SIGN_IN_RATE_LIMIT);
+    if limit.max_attempts == 0 || limit.window_secs == 0 {
+        return Ok(());
+    }
+    let now = now_secs();
+    let key = format!("rate:sign-in:{provider_id}:{subject}");
+    let mut attempts = store
+        .load_value(&key)?
+        .and_then(|json| serde_json::from_str::<Attempts>(&json).ok())
+        .filter(|attempts| now < attempts.window_start.saturating_add(limit.window_secs))
+        .unwrap_or(Attempts {
+            window_start: now,
+            count: 0,
+        });
+    let retry_after_secs = attempts
+        .window_start
+        .saturating_add(limit.window_secs)
+        .saturating_sub(now);
+    if attempts.count >= limit.max_attempts {
+        return Err(OAuthCardError::RateLimited { retry_after_secs });
+    }
+    attempts.count += 1;
+    let json = serde_json::to_string(&attempts)
+        .map_err(|err| OAuthCardError::Parse(format!("rate limit json: {err}")))?;
+    match store.save_value(&key, &json, retry_after_secs) {
+        Err(OAuthCardError::Unsupported(_)) => Ok(()),
+        saved => saved,
+    }
+}
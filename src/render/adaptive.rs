@@ -0,0 +1,103 @@
+use serde_json::{Value, json};
+
+use crate::model::{Action, Branding, MessageCard, MessageCardKind};
+
+const SCHEMA: &str = "http://adaptivecards.io/schemas/adaptive-card.json";
+const VERSION: &str = "1.5";
+
+/// Render a card as Adaptive Card 1.5 JSON for Teams and Web Chat. With an
+/// accent color in `branding`, the body is wrapped in an accent container.
+pub fn render(card: &MessageCard, branding: Option<&Branding>) -> Value {
+    let mut body = Vec::new();
+    if let Some(title) = &card.title {
+        body.push(json!({
+            "type": "TextBlock",
+            "text": title,
+            "size": "Medium",
+            "weight": "Bolder",
+            "color": title_color(&card.kind),
+            "wrap": true,
+        }));
+    }
+    for image in &card.images {
+        body.push(json!({
+            "type": "Image",
+            "url": image.url,
+            "altText": image.alt,
+            "size": "Small",
+        }));
+    }
+    if let Some(text) = &card.text {
+        body.push(json!({
+            "type": "TextBlock",
+            "text": text,
+            "wrap": true,
+        }));
+    }
+    if let Some(footer) = &card.footer {
+        body.push(json!({
+            "type": "TextBlock",
+            "text": footer,
+            "size": "Small",
+            "isSubtle": true,
+            "wrap": true,
+        }));
+    }
+
+    let actions: Vec<Value> = card.actions.iter().map(action).collect();
+    if branding.is_some_and(|b| b.accent_color.is_some()) {
+        body = vec![json!({
+            "type": "Container",
+            "style": "accent",
+            "bleed": true,
+            "items": body,
+        })];
+    }
+
+    json!({
+        "type": "AdaptiveCard",
+        "$schema": SCHEMA,
+        "version": VERSION,
+        "body": body,
+        "actions": actions,
+    })
+}
+
+fn title_color(kind: &MessageCardKind) -> &'static str {
+    match kind {
+        MessageCardKind::Warning => "Warning",
+        MessageCardKind::Confirmation => "Attention",
+        MessageCardKind::Standard | MessageCardKind::Oauth => "Default",
+    }
+}
+
+fn action(action: &Action) -> Value {
+    match action {
+        Action::OpenUrl { title, url } => json!({
+            "type": "Action.OpenUrl",
+            "title": title,
+            "url": url,
+        }),
+        Action::PostBack { title, data } | Action::Submit { title, data } => json!({
+            "type": "Action.Submit",
+            "title": title,
+            "data": data,
+        }),
+        Action::SignIn { title, url, .. } => json!({
+            "type": "Action.OpenUrl",
+            "title": title,
+            "url": url,
+        }),
+        Action::ShowCard { title, card } => {
+            let mut nested = render(card, None);
+            if let Some(nested) = nested.as_object_mut() {
+                nested.remove("$schema");
+            }
+            json!({
+                "type": "Action.ShowCard",
+                "title": title,
+                "card": nested,
+            })
+        }
+    }
+}
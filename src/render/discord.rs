@@ -0,0 +1,60 @@
+use serde_json::{Value, json};
+
+use super::{compact_post_back, flat_actions};
+use crate::correlation::CallContext;
+use crate::model::{Action, MessageCard};
+
+const BUTTON_STYLE_PRIMARY: u8 = 1;
+const BUTTON_STYLE_LINK: u8 = 5;
+const BUTTONS_PER_ROW: usize = 5;
+/// Discord caps `custom_id` at 100 characters.
+const CUSTOM_ID_MAX: usize = 100;
+
+/// Render a card as a Discord message body: one embed plus button components.
+pub fn render(ctx: &CallContext, card: &MessageCard) -> Value {
+    let mut embed = json!({});
+    if let Some(title) = &card.title {
+        embed["title"] = json!(title);
+    }
+    if let Some(text) = &card.text {
+        embed["description"] = json!(text);
+    }
+    if let Some(footer) = &card.footer {
+        embed["footer"] = json!({ "text": footer });
+    }
+    if let Some(image) = card.images.first() {
+        embed["thumbnail"] = json!({ "url": image.url });
+    }
+
+    let buttons: Vec<Value> = flat_actions(&card.actions)
+        .into_iter()
+        .filter_map(|action| button(ctx, action))
+        .collect();
+    let components: Vec<Value> = buttons
+        .chunks(BUTTONS_PER_ROW)
+        .map(|row| json!({ "type": 1, "components": row }))
+        .collect();
+
+    json!({
+        "embeds": [embed],
+        "components": components,
+    })
+}
+
+fn button(ctx: &CallContext, action: &Action) -> Option<Value> {
+    Some(match action {
+        Action::OpenUrl { title, url } | Action::SignIn { title, url, .. } => json!({
+            "type": 2,
+            "style": BUTTON_STYLE_LINK,
+            "label": title,
+            "url": url,
+        }),
+        Action::PostBack { title, data } | Action::Submit { title, data } => json!({
+            "type": 2,
+            "style": BUTTON_STYLE_PRIMARY,
+            "label": title,
+            "custom_id": compact_post_back(ctx, data, CUSTOM_ID_MAX),
+        }),
+        Action::ShowCard { .. } => return None,
+    })
+}
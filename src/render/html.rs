@@ -0,0 +1,93 @@
+use serde_json::Value;
+
+use crate::model::{Action, MessageCard};
+
+/// Render a card as a self-contained HTML fragment for web-chat surfaces.
+///
+/// All text is escaped and no scripts or inline event handlers are emitted:
+/// OpenUrl/SignIn actions become anchors, PostBack/Submit actions become plain
+/// form posts that carry the action data in a hidden `payload` field, and
+/// ShowCard actions become `<details>` disclosures of the nested card.
+pub fn render(card: &MessageCard) -> Value {
+    let mut html = String::from(r#"<div class="oauth-card">"#);
+    if let Some(title) = &card.title {
+        html.push_str(&format!(
+            r#"<h3 class="oauth-card__title">{}</h3>"#,
+            escape(title)
+        ));
+    }
+    for image in &card.images {
+        if is_safe_url(&image.url) {
+            html.push_str(&format!(
+                r#"<img class="oauth-card__image" src="{}" alt="{}">"#,
+                escape(&image.url),
+                escape(image.alt.as_deref().unwrap_or_default())
+            ));
+        }
+    }
+    if let Some(text) = &card.text {
+        html.push_str(&format!(
+            r#"<p class="oauth-card__text">{}</p>"#,
+            escape(text).replace('\n', "<br>")
+        ));
+    }
+    if !card.actions.is_empty() {
+        html.push_str(r#"<div class="oauth-card__actions">"#);
+        for action in &card.actions {
+            html.push_str(&render_action(action));
+        }
+        html.push_str("</div>");
+    }
+    if let Some(footer) = &card.footer {
+        html.push_str(&format!(
+            r#"<p class="oauth-card__footer">{}</p>"#,
+            escape(footer)
+        ));
+    }
+    html.push_str("</div>");
+    Value::String(html)
+}
+
+fn render_action(action: &Action) -> String {
+    match action {
+        Action::OpenUrl { title, url } | Action::SignIn { title, url, .. } if is_safe_url(url) => {
+            format!(
+                r#"<a class="oauth-card__button" href="{}" target="_blank" rel="noopener noreferrer">{}</a>"#,
+                escape(url),
+                escape(title)
+            )
+        }
+        Action::OpenUrl { .. } | Action::SignIn { .. } => String::new(),
+        Action::PostBack { title, data } | Action::Submit { title, data } => format!(
+            r#"<form class="oauth-card__form" method="post"><input type="hidden" name="payload" value="{}"><button class="oauth-card__button" type="submit">{}</button></form>"#,
+            escape(&data.to_string()),
+            escape(title)
+        ),
+        Action::ShowCard { title, card } => format!(
+            r#"<details class="oauth-card__details"><summary class="oauth-card__button">{}</summary>{}</details>"#,
+            escape(title),
+            render(card).as_str().unwrap_or_default()
+        ),
+    }
+}
+
+/// Only http(s) links are emitted; `javascript:`/`data:` and friends are dropped.
+fn is_safe_url(url: &str) -> bool {
+    let lower = url.trim_start().to_ascii_lowercase();
+    lower.starts_with("https://") || lower.starts_with("http://")
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
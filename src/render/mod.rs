@@ -0,0 +1,113 @@
+//! Channel-specific renderings of [`MessageCard`](crate::model::MessageCard).
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::correlation::CallContext;
+use crate::logging;
+use crate::model::{Action, MessageCard};
+use crate::state_store::active_state_store;
+
+pub mod adaptive;
+pub mod discord;
+pub mod html;
+#[cfg(feature = "qr")]
+pub mod qr;
+pub mod slack;
+pub mod telegram;
+pub mod text;
+pub mod whatsapp;
+
+/// Channels [`render_for_channel`] has a rendering for.
+pub const CHANNELS: [&str; 8] = [
+    "slack", "discord", "telegram", "whatsapp", "html", "teams", "webchat", "adaptive",
+];
+
+/// Render a card for a named channel; `None` when the channel has no renderer.
+/// `ctx` is the invocation's context, for logging post-back handles that
+/// could not be stored.
+pub fn render_for_channel(ctx: &CallContext, channel: &str, card: &MessageCard) -> Option<Value> {
+    match channel.to_ascii_lowercase().as_str() {
+        "slack" => Some(slack::render(card)),
+        "discord" => Some(discord::render(ctx, card)),
+        "telegram" => Some(telegram::render(ctx, card)),
+        "whatsapp" => Some(whatsapp::render(ctx, card)),
+        "html" => Some(html::render(card)),
+        "teams" | "webchat" | "adaptive" => card.adaptive.clone(),
+        _ => None,
+    }
+}
+
+/// Actions for channels without nested cards: each ShowCard is replaced by the
+/// actions of its card.
+pub(crate) fn flat_actions(actions: &[Action]) -> Vec<&Action> {
+    actions
+        .iter()
+        .flat_map(|action| match action {
+            Action::ShowCard { card, .. } => flat_actions(&card.actions),
+            other => vec![other],
+        })
+        .collect()
+}
+
+/// How long the PostBack behind a hashed handle can be resolved.
+const POST_BACK_HANDLE_TTL_SECS: u64 = 7 * 24 * 3_600;
+
+const HANDLE_PREFIX: &str = "oauth#";
+
+/// Channels with small callback payload limits only get the routing fields of
+/// a PostBack, `oauth:<mode>:<provider_id>:<state_id>`, when they fit in `max`
+/// bytes. Longer ones become a handle, `oauth#<digest of the PostBack data>`,
+/// whose data is kept in the active state store for [`resolve_post_back`].
+pub(crate) fn compact_post_back(ctx: &CallContext, data: &Value, max: usize) -> String {
+    let field = |name: &str| data.get(name).and_then(Value::as_str).unwrap_or_default();
+    let id = format!(
+        "oauth:{}:{}:{}",
+        field("mode"),
+        field("provider_id"),
+        field("state_id")
+    );
+    if id.len() <= max {
+        return id;
+    }
+    let payload = data.to_string();
+    let mut digest = URL_SAFE_NO_PAD.encode(Sha256::digest(payload.as_bytes()));
+    digest.truncate(max.saturating_sub(HANDLE_PREFIX.len()));
+    if let Some(store) = active_state_store()
+        && let Err(err) =
+            store.save_value(&handle_key(&digest), &payload, POST_BACK_HANDLE_TTL_SECS)
+    {
+        logging::warn(
+            ctx,
+            "post-back handle not stored",
+            json!({ "handle": digest, "error": err.to_string() }),
+        );
+    }
+    format!("{HANDLE_PREFIX}{digest}")
+}
+
+fn handle_key(digest: &str) -> String {
+    format!("post-back:{digest}")
+}
+
+/// The PostBack data behind a channel callback produced by a compact
+/// rendering (Discord `custom_id`, Telegram `callback_data`, WhatsApp reply
+/// id): the routing fields, or the stored data of a hashed handle. `None` for
+/// other callbacks and for handles that expired or were never stored.
+pub fn resolve_post_back(id: &str) -> Option<Value> {
+    if let Some(digest) = id.strip_prefix(HANDLE_PREFIX) {
+        let payload = active_state_store()?
+            .load_value(&handle_key(digest))
+            .ok()??;
+        return serde_json::from_str(&payload).ok();
+    }
+    let (mode, rest) = id.strip_prefix("oauth:")?.split_once(':')?;
+    let (provider_id, state_id) = rest.rsplit_once(':')?;
+    let mut data = json!({ "mode": mode, "provider_id": provider_id });
+    if !state_id.is_empty() {
+        data["state_id"] = json!(state_id);
+    }
+    Some(data)
+}
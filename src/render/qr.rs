@@ -0,0 +1,51 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use qrcode::{Color, QrCode};
+
+use crate::OAuthCardError;
+use crate::model::ImageRef;
+
+/// Pixels per QR module and quiet-zone width (in modules).
+const SCALE: usize = 4;
+const QUIET_ZONE: usize = 4;
+
+/// Encode `url` as a grayscale PNG QR code wrapped in a data-URI `ImageRef`.
+pub fn image_for_url(url: &str) -> Result<ImageRef, OAuthCardError> {
+    let code = QrCode::new(url.as_bytes())
+        .map_err(|err| OAuthCardError::Invalid(format!("qr code: {err}")))?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let side = (modules + 2 * QUIET_ZONE) * SCALE;
+
+    let mut pixels = vec![u8::MAX; side * side];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x0 = (index % modules + QUIET_ZONE) * SCALE;
+        let y0 = (index / modules + QUIET_ZONE) * SCALE;
+        for y in y0..y0 + SCALE {
+            pixels[y * side + x0..y * side + x0 + SCALE].fill(0);
+        }
+    }
+
+    let side_px =
+        u32::try_from(side).map_err(|_| OAuthCardError::Invalid("qr code too large".into()))?;
+    let mut png_bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png_bytes, side_px, side_px);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|err| OAuthCardError::Invalid(format!("qr png: {err}")))?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(|err| OAuthCardError::Invalid(format!("qr png: {err}")))?;
+    }
+
+    Ok(ImageRef {
+        url: format!("data:image/png;base64,{}", STANDARD.encode(png_bytes)),
+        alt: Some("Scan to sign in on your phone".into()),
+    })
+}
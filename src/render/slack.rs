@@ -0,0 +1,68 @@
+use serde_json::{Value, json};
+
+use super::flat_actions;
+use crate::model::{Action, MessageCard};
+
+/// Render a card as Slack Block Kit JSON (`{"blocks": [...]}`).
+pub fn render(card: &MessageCard) -> Value {
+    let mut blocks = Vec::new();
+    if let Some(title) = &card.title {
+        blocks.push(json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": title },
+        }));
+    }
+    if let Some(text) = &card.text {
+        blocks.push(json!({
+            "type": "section",
+            "text": {
+                "type": if card.allow_markdown { "mrkdwn" } else { "plain_text" },
+                "text": text,
+            },
+        }));
+    }
+    for image in &card.images {
+        blocks.push(json!({
+            "type": "image",
+            "image_url": image.url,
+            "alt_text": image.alt.clone().unwrap_or_default(),
+        }));
+    }
+    if let Some(footer) = &card.footer {
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{ "type": "mrkdwn", "text": footer }],
+        }));
+    }
+    if !card.actions.is_empty() {
+        let elements: Vec<Value> = flat_actions(&card.actions)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, action)| button(index, action))
+            .collect();
+        blocks.push(json!({ "type": "actions", "elements": elements }));
+    }
+
+    json!({ "blocks": blocks })
+}
+
+fn button(index: usize, action: &Action) -> Option<Value> {
+    Some(match action {
+        Action::OpenUrl { title, url } | Action::SignIn { title, url, .. } => json!({
+            "type": "button",
+            "text": { "type": "plain_text", "text": title },
+            "url": url,
+            "action_id": format!("oauth_open_url_{index}"),
+        }),
+        Action::PostBack { title, data } | Action::Submit { title, data } => json!({
+            "type": "button",
+            "text": { "type": "plain_text", "text": title },
+            "value": data.to_string(),
+            "action_id": format!(
+                "oauth_{}_{index}",
+                data.get("mode").and_then(Value::as_str).unwrap_or("post_back")
+            ),
+        }),
+        Action::ShowCard { .. } => return None,
+    })
+}
@@ -0,0 +1,97 @@
+use serde_json::{Value, json};
+
+use super::{compact_post_back, flat_actions};
+use crate::correlation::CallContext;
+use crate::model::{Action, MessageCard};
+
+/// Telegram limits `callback_data` to 64 bytes.
+const CALLBACK_DATA_MAX: usize = 64;
+
+/// Characters MarkdownV2 reserves; they must be backslash-escaped in text.
+const MARKDOWN_V2_SPECIAL: &str = "_*[]()~`>#+-=|{}.!\\";
+
+/// Render a card as a Telegram `sendMessage` body with an `InlineKeyboardMarkup`.
+pub fn render(ctx: &CallContext, card: &MessageCard) -> Value {
+    let mut lines = Vec::new();
+    if let Some(title) = &card.title {
+        lines.push(if card.allow_markdown {
+            format!("*{}*", escape(title))
+        } else {
+            title.clone()
+        });
+    }
+    if let Some(text) = &card.text {
+        lines.push(if card.allow_markdown {
+            markdown_v2(text)
+        } else {
+            text.clone()
+        });
+    }
+    if let Some(footer) = &card.footer {
+        lines.push(if card.allow_markdown {
+            format!("_{}_", escape(footer))
+        } else {
+            footer.clone()
+        });
+    }
+
+    // One button per row keeps long labels readable on phones.
+    let keyboard: Vec<Value> = flat_actions(&card.actions)
+        .into_iter()
+        .filter_map(|action| Some(json!([button(ctx, action)?])))
+        .collect();
+
+    let mut message = json!({
+        "text": lines.join("\n\n"),
+        "reply_markup": { "inline_keyboard": keyboard },
+    });
+    if card.allow_markdown {
+        message["parse_mode"] = json!("MarkdownV2");
+    }
+    message
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if MARKDOWN_V2_SPECIAL.contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Card text is CommonMark; keep its `**bold**` spans and escape everything
+/// else, including provider names and codes that contain reserved characters.
+fn markdown_v2(text: &str) -> String {
+    let spans: Vec<&str> = text.split("**").collect();
+    // An unpaired `**` leaves an even number of spans: print it all literally.
+    if spans.len().is_multiple_of(2) {
+        return escape(text);
+    }
+    spans
+        .iter()
+        .enumerate()
+        .map(|(index, span)| {
+            if !index.is_multiple_of(2) {
+                format!("*{}*", escape(span))
+            } else {
+                escape(span)
+            }
+        })
+        .collect()
+}
+
+fn button(ctx: &CallContext, action: &Action) -> Option<Value> {
+    Some(match action {
+        Action::OpenUrl { title, url } | Action::SignIn { title, url, .. } => {
+            json!({ "text": title, "url": url })
+        }
+        Action::PostBack { title, data } | Action::Submit { title, data } => json!({
+            "text": title,
+            "callback_data": compact_post_back(ctx, data, CALLBACK_DATA_MAX),
+        }),
+        Action::ShowCard { .. } => return None,
+    })
+}
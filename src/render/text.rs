@@ -0,0 +1,47 @@
+use super::flat_actions;
+use crate::model::{Action, MessageCard};
+
+/// Render a card as Markdown, or plain text when `allow_markdown` is false,
+/// with link targets inline so SMS-style channels stay usable.
+pub fn render(card: &MessageCard) -> String {
+    let markdown = card.allow_markdown;
+    let mut sections = Vec::new();
+    if let Some(title) = &card.title {
+        sections.push(if markdown {
+            format!("**{title}**")
+        } else {
+            title.clone()
+        });
+    }
+    if let Some(text) = &card.text {
+        sections.push(text.clone());
+    }
+
+    let actions: Vec<String> = flat_actions(&card.actions)
+        .into_iter()
+        .filter_map(|action| match action {
+            Action::OpenUrl { title, url } | Action::SignIn { title, url, .. } if markdown => {
+                Some(format!("- [{title}]({url})"))
+            }
+            Action::OpenUrl { title, url } | Action::SignIn { title, url, .. } => {
+                Some(format!("- {title}: {url}"))
+            }
+            Action::PostBack { title, .. } | Action::Submit { title, .. } => {
+                Some(format!("- Reply \"{title}\""))
+            }
+            Action::ShowCard { .. } => None,
+        })
+        .collect();
+    if !actions.is_empty() {
+        sections.push(actions.join("\n"));
+    }
+
+    if let Some(footer) = &card.footer {
+        sections.push(if markdown {
+            format!("_{footer}_")
+        } else {
+            footer.clone()
+        });
+    }
+    sections.join("\n\n")
+}
@@ -0,0 +1,98 @@
+use serde_json::{Value, json};
+
+use super::{compact_post_back, flat_actions};
+use crate::correlation::CallContext;
+use crate::model::{Action, MessageCard};
+
+/// WhatsApp Cloud API limits for interactive messages.
+const MAX_REPLY_BUTTONS: usize = 3;
+const BUTTON_TITLE_MAX: usize = 20;
+const BUTTON_ID_MAX: usize = 256;
+const HEADER_MAX: usize = 60;
+const FOOTER_MAX: usize = 60;
+
+/// Render a card as WhatsApp Cloud API interactive messages.
+///
+/// WhatsApp cannot mix URL and reply buttons, so the first OpenUrl becomes a
+/// `cta_url` message and up to three PostBacks follow as a reply-button message.
+/// The result is `{"messages": [...]}` without `to`/`messaging_product`, which
+/// the channel adapter fills in.
+pub fn render(ctx: &CallContext, card: &MessageCard) -> Value {
+    let body = body_text(card);
+    let mut messages = Vec::new();
+
+    let actions = flat_actions(&card.actions);
+    if let Some((title, url)) = actions.iter().find_map(|action| match action {
+        Action::OpenUrl { title, url } | Action::SignIn { title, url, .. } => Some((title, url)),
+        _ => None,
+    }) {
+        let mut interactive = json!({
+            "type": "cta_url",
+            "body": { "text": body },
+            "action": {
+                "name": "cta_url",
+                "parameters": { "display_text": truncate(title, BUTTON_TITLE_MAX), "url": url },
+            },
+        });
+        decorate(&mut interactive, card);
+        messages.push(json!({ "type": "interactive", "interactive": interactive }));
+    }
+
+    let buttons: Vec<Value> = actions
+        .iter()
+        .filter_map(|action| match action {
+            Action::PostBack { title, data } | Action::Submit { title, data } => Some(json!({
+                "type": "reply",
+                "reply": {
+                    "id": compact_post_back(ctx, data, BUTTON_ID_MAX),
+                    "title": truncate(title, BUTTON_TITLE_MAX),
+                },
+            })),
+            _ => None,
+        })
+        .take(MAX_REPLY_BUTTONS)
+        .collect();
+    if !buttons.is_empty() {
+        let standalone = messages.is_empty();
+        let text = if standalone {
+            body.clone()
+        } else {
+            "When you are done:".to_string()
+        };
+        let mut interactive = json!({
+            "type": "button",
+            "body": { "text": text },
+            "action": { "buttons": buttons },
+        });
+        if standalone {
+            decorate(&mut interactive, card);
+        }
+        messages.push(json!({ "type": "interactive", "interactive": interactive }));
+    }
+
+    if messages.is_empty() {
+        messages.push(json!({ "type": "text", "text": { "body": body } }));
+    }
+
+    json!({ "messages": messages })
+}
+
+fn body_text(card: &MessageCard) -> String {
+    card.text
+        .clone()
+        .or_else(|| card.title.clone())
+        .unwrap_or_default()
+}
+
+fn decorate(interactive: &mut Value, card: &MessageCard) {
+    if let Some(title) = &card.title {
+        interactive["header"] = json!({ "type": "text", "text": truncate(title, HEADER_MAX) });
+    }
+    if let Some(footer) = &card.footer {
+        interactive["footer"] = json!({ "text": truncate(footer, FOOTER_MAX) });
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    text.chars().take(max_chars).collect()
+}
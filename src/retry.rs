@@ -0,0 +1,403 @@
+use std::time::Duration;
+
+use crate::OAuthCardError;
+use crate::broker::OAuthBackend;
+use crate::correlation::CallContext;
+use crate::model::{
+    AccountSummary, BackendCapabilities, ConnectionSummary, DeviceCodeGrant, OidcEndpoints,
+    SignInProgress, TokenSet, UserProfile,
+};
+use crate::scopes::Scope;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Retries calls that fail with a transient error (see
+/// [`OAuthCardError::is_transient`]), sleeping with
+/// full-jitter exponential backoff between attempts (a random delay up to
+/// `initial * 2^retry`, capped at the maximum backoff).
+///
+/// Other errors are returned immediately. Calls that are not idempotent are
+/// attempted once, since a timed-out request may already have been processed
+/// and a replay would fail or mint a second token: those spending single-use
+/// credentials (`exchange_sso_token`, `exchange_token`, `poll_device_code`,
+/// and `exchange_code` unless it carries an idempotency key), rotating them
+/// (`refresh_token`) or creating server state (`start_device_code`).
+#[derive(Debug, Clone)]
+pub struct RetryingBackend<B> {
+    inner: B,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl<B: OAuthBackend> RetryingBackend<B> {
+    /// Three attempts with backoff starting at 200ms and capped at 5s.
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+
+    /// Total attempts per call, including the first (at least one).
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    fn retry<T>(
+        &self,
+        call: impl Fn(&B) -> Result<T, OAuthCardError>,
+    ) -> Result<T, OAuthCardError> {
+        self.retry_up_to(self.max_attempts, call)
+    }
+
+    /// Retry only when the broker can recognise the replay by `idempotency_key`.
+    fn retry_keyed<T>(
+        &self,
+        idempotency_key: Option<&str>,
+        call: impl Fn(&B) -> Result<T, OAuthCardError>,
+    ) -> Result<T, OAuthCardError> {
+        let max_attempts = if idempotency_key.is_some() {
+            self.max_attempts
+        } else {
+            1
+        };
+        self.retry_up_to(max_attempts, call)
+    }
+
+    fn retry_up_to<T>(
+        &self,
+        max_attempts: u32,
+        call: impl Fn(&B) -> Result<T, OAuthCardError>,
+    ) -> Result<T, OAuthCardError> {
+        let mut attempt = 1;
+        loop {
+            match call(&self.inner) {
+                Err(err) if err.is_transient() && attempt < max_attempts => {
+                    std::thread::sleep(self.backoff(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Jittered delay before retry number `retry` (starting at 1).
+    fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(1 << (retry - 1).min(16))
+            .min(self.max_backoff);
+        let millis = u64::try_from(ceiling.as_millis()).unwrap_or(u64::MAX);
+        if millis == 0 {
+            return Duration::ZERO;
+        }
+        // uuid's v4 generator is already a dependency and is backed by the
+        // platform RNG on native and wasi targets.
+        let random = uuid::Uuid::new_v4().as_u64_pair().0;
+        Duration::from_millis(random % (millis + 1))
+    }
+}
+
+impl<B: OAuthBackend> OAuthBackend for RetryingBackend<B> {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        self.retry(|inner| inner.get_token(ctx, provider_id, subject, scopes))
+    }
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        self.retry(|inner| {
+            inner.get_consent_url(
+                ctx,
+                provider_id,
+                subject,
+                scopes,
+                redirect_path,
+                extra_json.clone(),
+            )
+        })
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.retry_keyed(idempotency_key, |inner| {
+            inner.exchange_code(
+                ctx,
+                provider_id,
+                subject,
+                code,
+                redirect_path,
+                idempotency_key,
+            )
+        })
+    }
+
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.retry_keyed(idempotency_key, |inner| {
+            inner.exchange_code_with_extra(
+                ctx,
+                provider_id,
+                subject,
+                code,
+                redirect_path,
+                idempotency_key,
+                extra_json.clone(),
+            )
+        })
+    }
+
+    fn poll_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> Result<SignInProgress, OAuthCardError> {
+        self.retry(|inner| inner.poll_sign_in(ctx, provider_id, subject, state_id))
+    }
+
+    fn cancel_sign_in(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        state_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        self.retry(|inner| inner.cancel_sign_in(ctx, provider_id, subject, state_id))
+    }
+
+    fn exchange_sso_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        sso_token: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.inner
+            .exchange_sso_token(ctx, provider_id, subject, sso_token, scopes)
+    }
+
+    fn refresh_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.inner.refresh_token(ctx, provider_id, subject, scopes)
+    }
+
+    fn get_app_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.retry(|inner| inner.get_app_token(ctx, provider_id, scopes, extra_json.clone()))
+    }
+
+    fn exchange_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        subject_token: &str,
+        subject_token_type: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.inner.exchange_token(
+            ctx,
+            provider_id,
+            subject,
+            subject_token,
+            subject_token_type,
+            scopes,
+            extra_json,
+        )
+    }
+
+    fn revoke_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        self.retry(|inner| inner.revoke_token(ctx, provider_id, subject, scopes))
+    }
+
+    fn get_end_session_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.retry(|inner| inner.get_end_session_url(ctx, provider_id, subject))
+    }
+
+    fn get_admin_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.retry(|inner| {
+            inner.get_admin_consent_url(ctx, provider_id, tenant, scopes, redirect_path)
+        })
+    }
+
+    fn admin_consent_granted(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+    ) -> Result<bool, OAuthCardError> {
+        self.retry(|inner| inner.admin_consent_granted(ctx, provider_id, tenant, scopes))
+    }
+
+    fn invalidate_sessions(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        sub: Option<&str>,
+        sid: Option<&str>,
+    ) -> Result<Vec<String>, OAuthCardError> {
+        self.retry(|inner| inner.invalidate_sessions(ctx, provider_id, sub, sid))
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        self.retry(|inner| inner.list_connections(ctx, subject))
+    }
+
+    fn list_accounts(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<Vec<AccountSummary>, OAuthCardError> {
+        self.retry(|inner| inner.list_accounts(ctx, provider_id, subject))
+    }
+
+    fn select_account(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        account_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        self.retry(|inner| inner.select_account(ctx, provider_id, subject, account_id))
+    }
+
+    fn store_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        self.retry(|inner| inner.store_token(ctx, provider_id, subject, token))
+    }
+
+    fn get_userinfo(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+    ) -> Result<UserProfile, OAuthCardError> {
+        self.retry(|inner| inner.get_userinfo(ctx, provider_id, subject))
+    }
+
+    fn discover_oidc(
+        &self,
+        ctx: &CallContext,
+        issuer: &str,
+    ) -> Result<OidcEndpoints, OAuthCardError> {
+        self.retry(|inner| inner.discover_oidc(ctx, issuer))
+    }
+
+    fn start_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        extra_json: Option<String>,
+    ) -> Result<DeviceCodeGrant, OAuthCardError> {
+        self.inner
+            .start_device_code(ctx, provider_id, subject, scopes, extra_json)
+    }
+
+    fn poll_device_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        device_code: &str,
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        self.inner
+            .poll_device_code(ctx, provider_id, subject, device_code)
+    }
+
+    /// Probed once so the reported latency and reachability are not masked.
+    fn health(&self, ctx: &CallContext) -> Result<(), OAuthCardError> {
+        self.inner.health(ctx)
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        self.inner.capabilities()
+    }
+}
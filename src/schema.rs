@@ -0,0 +1,56 @@
+use serde_json::{Value, json};
+
+use crate::model::{OAuthCardInput, OAuthCardOutput};
+
+/// JSON Schemas of the component's I/O generated from the models, keyed by
+/// their path in the repository (as referenced by `describe_payload`).
+///
+/// `tests/schemas.rs` fails when the committed files differ; rerun it with
+/// `UPDATE_SCHEMAS=1` (`make schemas`) to rewrite them.
+pub fn schemas() -> Vec<(&'static str, Value)> {
+    vec![
+        ("schemas/io/input.schema.json", input_schema()),
+        ("schemas/io/output.schema.json", output_schema()),
+    ]
+}
+
+/// `OAuthCardInput`, either flat (version 1) or wrapped in the version 2
+/// `{"version": 2, "input": {...}}` envelope that `parse_input` unwraps.
+fn input_schema() -> Value {
+    let mut input = schemars::schema_for!(OAuthCardInput).to_value();
+    let defs = input
+        .as_object_mut()
+        .and_then(|input| input.remove("$defs"))
+        .unwrap_or_else(|| json!({}));
+    let schema = input
+        .as_object_mut()
+        .and_then(|input| input.remove("$schema"))
+        .unwrap_or(Value::Null);
+    let mut defs = defs.as_object().cloned().unwrap_or_default();
+    defs.insert("OAuthCardInput".into(), input);
+    json!({
+        "$schema": schema,
+        "title": "component-oauth-card input",
+        "description": "Card input as a flat version 1 payload or a version 2 envelope. Field names also accept their camelCase form (e.g. providerId).",
+        "anyOf": [
+            { "$ref": "#/$defs/OAuthCardInput" },
+            {
+                "type": "object",
+                "required": ["version", "input"],
+                "properties": {
+                    "version": { "const": 2 },
+                    "input": { "$ref": "#/$defs/OAuthCardInput" }
+                }
+            }
+        ],
+        "$defs": defs,
+    })
+}
+
+fn output_schema() -> Value {
+    let mut output = schemars::schema_for!(OAuthCardOutput).to_value();
+    if let Some(fields) = output.as_object_mut() {
+        fields.insert("title".into(), "component-oauth-card output".into());
+    }
+    output
+}
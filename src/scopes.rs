@@ -0,0 +1,226 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::OAuthCardError;
+use crate::providers;
+
+/// Standard OIDC scopes; matched case-insensitively and stored lowercase.
+const OIDC_SCOPES: [&str; 6] = [
+    "openid",
+    "profile",
+    "email",
+    "address",
+    "phone",
+    "offline_access",
+];
+
+/// One OAuth scope token (RFC 6749 §3.3), trimmed and validated.
+///
+/// Standard OIDC scopes are lowercased; provider scopes keep their case since
+/// providers compare them exactly.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct Scope(String);
+
+impl Scope {
+    pub fn parse(raw: &str) -> Result<Self, OAuthCardError> {
+        let scope = raw.trim();
+        if scope.is_empty() {
+            return Err(OAuthCardError::validation("scopes", "empty scope"));
+        }
+        if let Some(bad) = scope
+            .chars()
+            .find(|c| !matches!(c, '\x21' | '\x23'..='\x5b' | '\x5d'..='\x7e'))
+        {
+            return Err(OAuthCardError::validation(
+                "scopes",
+                format!("scope {scope:?} contains {bad:?}"),
+            ));
+        }
+        Ok(
+            match OIDC_SCOPES
+                .iter()
+                .find(|oidc| oidc.eq_ignore_ascii_case(scope))
+            {
+                Some(oidc) => Self((*oidc).to_string()),
+                None => Self(scope.to_string()),
+            },
+        )
+    }
+
+    /// Parse a scope list: space-delimited entries are split, every token is
+    /// validated and duplicates are dropped (first occurrence kept).
+    pub fn parse_list<S: AsRef<str>>(
+        raw: impl IntoIterator<Item = S>,
+    ) -> Result<Vec<Self>, OAuthCardError> {
+        let mut scopes = Vec::new();
+        for entry in raw {
+            for token in entry.as_ref().split_whitespace() {
+                scopes.push(Self::parse(token)?);
+            }
+        }
+        Ok(dedup(scopes))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Drop repeated scopes, keeping the first occurrence of each.
+pub fn dedup(scopes: Vec<Scope>) -> Vec<Scope> {
+    let mut unique: Vec<Scope> = Vec::with_capacity(scopes.len());
+    for scope in scopes {
+        if !unique.contains(&scope) {
+            unique.push(scope);
+        }
+    }
+    unique
+}
+
+impl<'de> Deserialize<'de> for Scope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        Self::parse(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+/// `deserialize_with` helper for scope lists in inputs and config, applying
+/// [`Scope::parse_list`].
+pub(crate) fn deserialize_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Scope>, D::Error> {
+    let raw = Vec::<String>::deserialize(deserializer)?;
+    Scope::parse_list(raw).map_err(serde::de::Error::custom)
+}
+
+impl FromStr for Scope {
+    type Err = OAuthCardError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        Self::parse(raw)
+    }
+}
+
+impl Deref for Scope {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Scope {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Scope {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for Scope {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Scope {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Scope {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl From<Scope> for String {
+    fn from(scope: Scope) -> Self {
+        scope.0
+    }
+}
+
+/// Friendly explanation of a scope, shown on consent cards.
+///
+/// Standard OIDC scopes are shared by every provider; the rest are looked up in
+/// the provider's catalog. Unknown scopes return `None` and are listed verbatim.
+pub fn describe(provider_id: &str, scope: &str) -> Option<&'static str> {
+    let oidc = match scope {
+        "openid" => Some("Sign you in"),
+        "profile" => Some("See your basic profile (name and picture)"),
+        "email" => Some("See your email address"),
+        "offline_access" => Some("Stay connected when you are not using the app"),
+        _ => None,
+    };
+    oidc.or_else(|| match providers::provider_info(provider_id)?.id {
+        "microsoft" => microsoft(scope),
+        "google" => google(scope),
+        "github" => github(scope),
+        _ => None,
+    })
+}
+
+fn microsoft(scope: &str) -> Option<&'static str> {
+    let scope = scope
+        .strip_prefix("https://graph.microsoft.com/")
+        .unwrap_or(scope);
+    match scope {
+        "User.Read" => Some("Read your profile"),
+        "Calendars.Read" => Some("Read your calendar"),
+        "Calendars.ReadWrite" => Some("Read and update your calendar"),
+        "Mail.Read" => Some("Read your email"),
+        "Mail.Send" => Some("Send email as you"),
+        "Files.Read" | "Files.Read.All" => Some("Read your files"),
+        "Files.ReadWrite" | "Files.ReadWrite.All" => Some("Read and update your files"),
+        "Chat.Read" => Some("Read your Teams chats"),
+        "Team.ReadBasic.All" => Some("See the teams you belong to"),
+        _ => None,
+    }
+}
+
+fn google(scope: &str) -> Option<&'static str> {
+    let scope = scope
+        .strip_prefix("https://www.googleapis.com/auth/")
+        .unwrap_or(scope);
+    match scope {
+        "calendar.readonly" => Some("Read your calendar"),
+        "calendar" | "calendar.events" => Some("Read and update your calendar"),
+        "gmail.readonly" => Some("Read your email"),
+        "gmail.send" => Some("Send email as you"),
+        "drive.readonly" => Some("Read your Drive files"),
+        "drive" | "drive.file" => Some("Read and update your Drive files"),
+        "userinfo.email" => Some("See your email address"),
+        "userinfo.profile" => Some("See your basic profile (name and picture)"),
+        _ => None,
+    }
+}
+
+fn github(scope: &str) -> Option<&'static str> {
+    match scope {
+        "repo" => Some("Read and write your repositories"),
+        "public_repo" => Some("Read and write your public repositories"),
+        "read:user" => Some("Read your profile"),
+        "user:email" => Some("See your email addresses"),
+        "read:org" => Some("See your organizations and teams"),
+        "gist" => Some("Create gists"),
+        "workflow" => Some("Update GitHub Actions workflows"),
+        _ => None,
+    }
+}
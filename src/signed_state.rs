@@ -0,0 +1,112 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{OAuthCardError, component_config};
+
+/// Environment variable (host secret) holding the `state_id` signing key, used
+/// when the component configuration sets none.
+pub const STATE_KEY_ENV: &str = "GREENTIC_OAUTH_STATE_KEY";
+
+#[derive(Deserialize)]
+struct ComponentStateKey {
+    #[serde(default)]
+    state_signing_key: Option<String>,
+}
+
+/// Load `state_signing_key` from the component configuration, replacing any
+/// previously loaded key (a missing key clears it). Other configuration keys
+/// are ignored.
+///
+/// With a key (from here or [`STATE_KEY_ENV`]), start-sign-in issues
+/// HMAC-signed `state_id`s and complete-sign-in rejects states that were not
+/// issued for the same provider and subject or are older than the sign-in TTL.
+pub fn load_state_signing_key(config_json: &str) -> Result<(), OAuthCardError> {
+    let key = parse(config_json)?;
+    component_config::update(|loaded| loaded.state_signing_key = key);
+    Ok(())
+}
+
+pub(crate) fn parse(config_json: &str) -> Result<Option<String>, OAuthCardError> {
+    let config: ComponentStateKey = serde_json::from_str(config_json)
+        .map_err(|err| OAuthCardError::Parse(format!("state key config json: {err}")))?;
+    Ok(config.state_signing_key.filter(|key| !key.is_empty()))
+}
+
+fn signing_key() -> Option<String> {
+    component_config::loaded()
+        .state_signing_key
+        .clone()
+        .or_else(|| std::env::var(STATE_KEY_ENV).ok())
+        .filter(|key| !key.is_empty())
+}
+
+pub(crate) fn enabled() -> bool {
+    signing_key().is_some()
+}
+
+/// A fresh `state_id`: a random id, or `{id}.{issued_at}.{signature}` when a
+/// signing key is configured.
+pub(crate) fn issue(provider_id: &str, subject: &str, now: u64) -> String {
+    match signing_key() {
+        Some(key) => {
+            let id = Uuid::new_v4().simple().to_string();
+            let signature = URL_SAFE_NO_PAD.encode(
+                mac(&key, &id, now, provider_id, subject)
+                    .finalize()
+                    .into_bytes(),
+            );
+            format!("{id}.{now}.{signature}")
+        }
+        None => Uuid::new_v4().to_string(),
+    }
+}
+
+/// Check that `state_id` was issued by [`issue`] for this provider and
+/// subject no more than `max_age_secs` ago. Always succeeds without a key.
+pub(crate) fn verify(
+    state_id: &str,
+    provider_id: &str,
+    subject: &str,
+    now: u64,
+    max_age_secs: u64,
+) -> Result<(), OAuthCardError> {
+    let Some(key) = signing_key() else {
+        return Ok(());
+    };
+    let mut parts = state_id.splitn(3, '.');
+    let (Some(id), Some(issued_at), Some(signature)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(OAuthCardError::InvalidState("state is not signed".into()));
+    };
+    let issued_at = issued_at
+        .parse::<u64>()
+        .map_err(|_| OAuthCardError::InvalidState("state timestamp is malformed".into()))?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| OAuthCardError::InvalidState("state signature is malformed".into()))?;
+    mac(&key, id, issued_at, provider_id, subject)
+        .verify_slice(&signature)
+        .map_err(|_| {
+            OAuthCardError::InvalidState(
+                "state was not issued for this provider and subject".into(),
+            )
+        })?;
+    if now.saturating_sub(issued_at) > max_age_secs {
+        return Err(OAuthCardError::InvalidState("state has expired".into()));
+    }
+    Ok(())
+}
+
+fn mac(key: &str, id: &str, issued_at: u64, provider_id: &str, subject: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .unwrap_or_else(|_| unreachable!("HMAC accepts keys of any length"));
+    for part in [id, &issued_at.to_string(), provider_id, subject] {
+        mac.update(&(part.len() as u64).to_be_bytes());
+        mac.update(part.as_bytes());
+    }
+    mac
+}
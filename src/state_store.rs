@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+
+use crate::OAuthCardError;
+use crate::clock::now_secs;
+use crate::model::PendingSignIn;
+
+/// How long a started sign-in can be completed.
+pub(crate) const SIGN_IN_STATE_TTL_SECS: u64 = 600;
+
+/// Server-side storage for pending sign-in flows, keyed by `state_id`.
+///
+/// start-sign-in saves what complete-sign-in needs, so the callback is checked
+/// against the original request instead of trusting re-submitted input.
+pub trait StateStore {
+    /// Store `state` until `ttl_secs` from now, replacing any previous value.
+    fn save(
+        &self,
+        state_id: &str,
+        state: &PendingSignIn,
+        ttl_secs: u64,
+    ) -> Result<(), OAuthCardError>;
+
+    /// The saved state, or `None` when unknown or expired.
+    fn load(&self, state_id: &str) -> Result<Option<PendingSignIn>, OAuthCardError>;
+
+    fn delete(&self, state_id: &str) -> Result<(), OAuthCardError>;
+
+    /// Atomically hold `key` (a used `state_id` or auth code, see
+    /// [`consumption_keys`]) for `owner` until `ttl_secs` from now, unless a
+    /// live claim already holds it. Returns that claim's owner, or `None` once
+    /// `owner` holds the key. The default keeps the owner in an otherwise
+    /// empty flow state under a `claim:` prefix, which is a separate read and
+    /// write; stores shared between instances should override it with a
+    /// compare-and-set.
+    fn claim(
+        &self,
+        key: &str,
+        owner: &str,
+        ttl_secs: u64,
+    ) -> Result<Option<String>, OAuthCardError> {
+        let marker = claim_marker(key);
+        if let Some(held) = self.load(&marker)? {
+            return Ok(Some(held.subject));
+        }
+        let held = PendingSignIn {
+            subject: owner.to_string(),
+            ..Default::default()
+        };
+        self.save(&marker, &held, ttl_secs)?;
+        Ok(None)
+    }
+
+    /// Drop a [`claim`](Self::claim), e.g. after the exchange it guarded failed.
+    fn release(&self, key: &str) -> Result<(), OAuthCardError> {
+        self.delete(&claim_marker(key))
+    }
+
+    /// Store an opaque component value (e.g. a sign-in rate-limit window) until
+    /// `ttl_secs` from now. Stores that only keep sign-in flows report
+    /// `Unsupported`, and callers fall back to process-local values.
+    fn save_value(&self, _key: &str, _value: &str, _ttl_secs: u64) -> Result<(), OAuthCardError> {
+        Err(OAuthCardError::Unsupported(
+            "this state store only keeps sign-in flows".into(),
+        ))
+    }
+
+    /// A value saved with [`save_value`](Self::save_value), or `None` when
+    /// unknown or expired.
+    fn load_value(&self, _key: &str) -> Result<Option<String>, OAuthCardError> {
+        Ok(None)
+    }
+
+    /// Drop a value saved with [`save_value`](Self::save_value).
+    fn delete_value(&self, _key: &str) -> Result<(), OAuthCardError> {
+        Ok(())
+    }
+}
+
+fn claim_marker(key: &str) -> String {
+    format!("claim:{key}")
+}
+
+/// Keys claimed by a callback: its `state_id` and a digest of its auth code
+/// (so raw codes are never stored).
+pub(crate) fn consumption_keys(
+    provider_id: &str,
+    state_id: Option<&str>,
+    code: &str,
+) -> Vec<String> {
+    let digest = Sha256::new()
+        .chain_update(provider_id.as_bytes())
+        .chain_update([0])
+        .chain_update(code.as_bytes())
+        .finalize();
+    let mut keys = vec![format!("code:{}", URL_SAFE_NO_PAD.encode(digest))];
+    if let Some(state_id) = state_id {
+        keys.push(format!("state:{provider_id}:{state_id}"));
+    }
+    keys
+}
+
+/// Process-local [`StateStore`] for native embedders and tests.
+#[derive(Debug, Default)]
+pub struct InMemoryStateStore {
+    entries: Mutex<BTreeMap<String, (PendingSignIn, u64)>>,
+    values: Mutex<BTreeMap<String, (String, u64)>>,
+    claims: Mutex<BTreeMap<String, (String, u64)>>,
+}
+
+impl InMemoryStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, (PendingSignIn, u64)>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl StateStore for InMemoryStateStore {
+    fn save(
+        &self,
+        state_id: &str,
+        state: &PendingSignIn,
+        ttl_secs: u64,
+    ) -> Result<(), OAuthCardError> {
+        let now = now_secs();
+        let mut entries = self.lock();
+        entries.retain(|_, (_, expires_at)| now < *expires_at);
+        entries.insert(
+            state_id.to_string(),
+            (state.clone(), now.saturating_add(ttl_secs)),
+        );
+        Ok(())
+    }
+
+    fn load(&self, state_id: &str) -> Result<Option<PendingSignIn>, OAuthCardError> {
+        let now = now_secs();
+        Ok(self
+            .lock()
+            .get(state_id)
+            .filter(|(_, expires_at)| now < *expires_at)
+            .map(|(state, _)| state.clone()))
+    }
+
+    fn delete(&self, state_id: &str) -> Result<(), OAuthCardError> {
+        self.lock().remove(state_id);
+        Ok(())
+    }
+
+    fn claim(
+        &self,
+        key: &str,
+        owner: &str,
+        ttl_secs: u64,
+    ) -> Result<Option<String>, OAuthCardError> {
+        let now = now_secs();
+        let mut claims = self
+            .claims
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        claims.retain(|_, (_, expires_at)| now < *expires_at);
+        if let Some((held, _)) = claims.get(key) {
+            return Ok(Some(held.clone()));
+        }
+        claims.insert(
+            key.to_string(),
+            (owner.to_string(), now.saturating_add(ttl_secs)),
+        );
+        Ok(None)
+    }
+
+    fn release(&self, key: &str) -> Result<(), OAuthCardError> {
+        self.claims
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(key);
+        Ok(())
+    }
+
+    fn save_value(&self, key: &str, value: &str, ttl_secs: u64) -> Result<(), OAuthCardError> {
+        let expires_at = now_secs().saturating_add(ttl_secs);
+        self.values
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(key.to_string(), (value.to_string(), expires_at));
+        Ok(())
+    }
+
+    fn load_value(&self, key: &str) -> Result<Option<String>, OAuthCardError> {
+        let now = now_secs();
+        Ok(self
+            .values
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(key)
+            .filter(|(_, expires_at)| now < *expires_at)
+            .map(|(value, _)| value.clone()))
+    }
+
+    fn delete_value(&self, key: &str) -> Result<(), OAuthCardError> {
+        self.values
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(key);
+        Ok(())
+    }
+}
+
+/// [`StateStore`] backed by the host key-value store.
+///
+/// The host keeps blobs without expiry, so each one is stored as JSON with
+/// its `expires_at`; reads treat (and delete) lapsed entries as missing.
+/// Calls pass no tenant context, leaving scoping to the host's exec context.
+#[cfg(target_arch = "wasm32")]
+#[derive(Default, Clone)]
+pub struct HostStateStore;
+
+/// What [`HostStateStore`] writes under a key.
+#[cfg(target_arch = "wasm32")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HostEntry<T> {
+    expires_at: u64,
+    value: T,
+}
+
+#[cfg(target_arch = "wasm32")]
+fn host_key(state_id: &str) -> String {
+    format!("oauth-card/sign-in/{state_id}")
+}
+
+#[cfg(target_arch = "wasm32")]
+fn value_key(key: &str) -> String {
+    format!("oauth-card/value/{key}")
+}
+
+#[cfg(target_arch = "wasm32")]
+fn host_error(err: state_store::HostError) -> OAuthCardError {
+    OAuthCardError::Backend(format!("state store: {} ({})", err.message, err.code))
+}
+
+#[cfg(target_arch = "wasm32")]
+impl HostStateStore {
+    fn write<T: serde::Serialize>(
+        &self,
+        key: &str,
+        value: T,
+        ttl_secs: u64,
+    ) -> Result<(), OAuthCardError> {
+        let entry = HostEntry {
+            expires_at: now_secs().saturating_add(ttl_secs),
+            value,
+        };
+        let bytes = serde_json::to_vec(&entry)
+            .map_err(|err| OAuthCardError::Parse(format!("state store json: {err}")))?;
+        state_store::write(key, &bytes, None).map_err(host_error)?;
+        Ok(())
+    }
+
+    fn read<T: serde::de::DeserializeOwned>(&self, key: &str) -> Result<Option<T>, OAuthCardError> {
+        let bytes = match state_store::read(key, None) {
+            Ok(bytes) if bytes.is_empty() => return Ok(None),
+            Ok(bytes) => bytes,
+            Err(err) if matches!(err.code.as_str(), "not_found" | "not-found") => return Ok(None),
+            Err(err) => return Err(host_error(err)),
+        };
+        let entry: HostEntry<T> = serde_json::from_slice(&bytes)
+            .map_err(|err| OAuthCardError::Parse(format!("state store json: {err}")))?;
+        if entry.expires_at <= now_secs() {
+            self.remove(key)?;
+            return Ok(None);
+        }
+        Ok(Some(entry.value))
+    }
+
+    fn remove(&self, key: &str) -> Result<(), OAuthCardError> {
+        match state_store::delete(key, None) {
+            Ok(_) => Ok(()),
+            Err(err) if matches!(err.code.as_str(), "not_found" | "not-found") => Ok(()),
+            Err(err) => Err(host_error(err)),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl StateStore for HostStateStore {
+    fn save(
+        &self,
+        state_id: &str,
+        state: &PendingSignIn,
+        ttl_secs: u64,
+    ) -> Result<(), OAuthCardError> {
+        self.write(&host_key(state_id), state, ttl_secs)
+    }
+
+    fn load(&self, state_id: &str) -> Result<Option<PendingSignIn>, OAuthCardError> {
+        self.read(&host_key(state_id))
+    }
+
+    fn delete(&self, state_id: &str) -> Result<(), OAuthCardError> {
+        self.remove(&host_key(state_id))
+    }
+
+    fn save_value(&self, key: &str, value: &str, ttl_secs: u64) -> Result<(), OAuthCardError> {
+        self.write(&value_key(key), value, ttl_secs)
+    }
+
+    fn load_value(&self, key: &str) -> Result<Option<String>, OAuthCardError> {
+        self.read(&value_key(key))
+    }
+
+    fn delete_value(&self, key: &str) -> Result<(), OAuthCardError> {
+        self.remove(&value_key(key))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+use greentic_interfaces_guest::state_store;
+
+static REGISTERED_STATE_STORE: OnceLock<Arc<dyn StateStore + Send + Sync>> = OnceLock::new();
+
+/// Install the process-wide store sign-in flows are persisted in. Wasm builds
+/// default to `HostStateStore`; native builds without a registered store keep
+/// trusting the re-submitted flow state. Only the first registration takes
+/// effect.
+pub fn register_state_store(
+    store: impl StateStore + Send + Sync + 'static,
+) -> Result<(), OAuthCardError> {
+    REGISTERED_STATE_STORE
+        .set(Arc::new(store))
+        .map_err(|_| OAuthCardError::Invalid("a state store is already registered".into()))
+}
+
+/// The registered store, else the platform default (none on native targets).
+pub(crate) fn active_state_store() -> Option<Arc<dyn StateStore + Send + Sync>> {
+    if let Some(store) = REGISTERED_STATE_STORE.get() {
+        return Some(store.clone());
+    }
+    #[cfg(target_arch = "wasm32")]
+    return Some(Arc::new(HostStateStore));
+    #[cfg(not(target_arch = "wasm32"))]
+    None
+}
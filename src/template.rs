@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::MessageCard;
+
+/// Flow-supplied copy for a card; each field may use `{{placeholder}}` variables.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct CardTemplate {
+    pub title: Option<String>,
+    pub text: Option<String>,
+    pub footer: Option<String>,
+}
+
+impl CardTemplate {
+    /// This template's fields, falling back to `base` for the ones it leaves unset.
+    pub fn over(self, base: CardTemplate) -> CardTemplate {
+        CardTemplate {
+            title: self.title.or(base.title),
+            text: self.text.or(base.text),
+            footer: self.footer.or(base.footer),
+        }
+    }
+
+    /// Overwrite the card copy with the rendered template fields that are set.
+    pub fn apply(&self, card: &mut MessageCard, vars: &BTreeMap<&str, String>) {
+        if let Some(title) = &self.title {
+            card.title = Some(render(title, vars));
+        }
+        if let Some(text) = &self.text {
+            card.text = Some(render(text, vars));
+        }
+        if let Some(footer) = &self.footer {
+            card.footer = Some(render(footer, vars));
+        }
+    }
+}
+
+/// Replace `{{name}}` placeholders; unknown names render as empty strings.
+pub fn render(template: &str, vars: &BTreeMap<&str, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[..end].trim();
+                if let Some(value) = vars.get(name) {
+                    out.push_str(value);
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Human-friendly duration such as "45 seconds", "12 minutes" or "3 hours".
+pub fn humanize_duration(secs: u64) -> String {
+    let (value, unit) = match secs {
+        0..60 => (secs, "second"),
+        60..3_600 => (secs / 60, "minute"),
+        3_600..86_400 => (secs / 3_600, "hour"),
+        _ => (secs / 86_400, "day"),
+    };
+    if value == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{value} {unit}s")
+    }
+}
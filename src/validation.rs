@@ -0,0 +1,121 @@
+use serde_json::{Value, json};
+
+use crate::OAuthCardError;
+use crate::model::{OAuthCardInput, OAuthCardMode, ValidationError};
+use crate::{provider_config, providers};
+
+/// Every field of an input payload that fails to deserialize, each checked on
+/// its own so one bad field does not hide the next, plus missing required
+/// fields (`provider_id` may be omitted in favour of the configured default).
+/// Empty when the payload is not an object or the failure is not tied to a
+/// single field.
+pub(crate) fn field_errors(payload: &Value) -> Vec<ValidationError> {
+    let Some(fields) = payload.as_object() else {
+        return Vec::new();
+    };
+    let mut errors = Vec::new();
+    for field in ["mode", "subject"] {
+        if !fields.contains_key(field) {
+            errors.push(error(field, "required"));
+        }
+    }
+    for (field, value) in fields {
+        let mut probe =
+            json!({ "mode": "status-card", "provider_id": "probe", "subject": "probe" });
+        if let Some(probe) = probe.as_object_mut() {
+            if field == "providerId" {
+                probe.remove("provider_id");
+            }
+            probe.insert(field.clone(), value.clone());
+        }
+        if let Err(err) = serde_json::from_value::<OAuthCardInput>(probe) {
+            errors.push(error(field, &err.to_string()));
+        }
+    }
+    errors
+}
+
+/// Check the semantic constraints of `input` that its mode depends on,
+/// reporting every violation at once.
+pub(crate) fn validate(input: &OAuthCardInput) -> Result<(), OAuthCardError> {
+    let mut errors = Vec::new();
+    let mut require = |ok: bool, field: &str, message: &str| {
+        if !ok {
+            errors.push(error(field, message));
+        }
+    };
+    let multi_provider = matches!(
+        input.mode,
+        OAuthCardMode::BatchEnsure | OAuthCardMode::ChooseProvider | OAuthCardMode::ListConnections
+    );
+    require(
+        multi_provider || !input.provider_id.trim().is_empty(),
+        "provider_id",
+        "must not be empty",
+    );
+    require(
+        input.mode == OAuthCardMode::ClientCredentials || !input.subject.trim().is_empty(),
+        "subject",
+        "must not be empty",
+    );
+    match input.mode {
+        OAuthCardMode::CompleteSignIn => require(
+            input.auth_code.is_some() || input.sso_token.is_some(),
+            "auth_code",
+            "required to complete sign-in without sso_token",
+        ),
+        OAuthCardMode::PollSignIn => require(
+            input.state_id.is_some(),
+            "state_id",
+            "required to poll sign-in",
+        ),
+        OAuthCardMode::CancelSignIn => require(
+            input.state_id.is_some(),
+            "state_id",
+            "required to cancel sign-in",
+        ),
+        OAuthCardMode::BatchEnsure => require(
+            !input.providers.is_empty(),
+            "providers",
+            "required for batch-ensure",
+        ),
+        OAuthCardMode::ChooseProvider => require(
+            !input.providers.is_empty(),
+            "providers",
+            "required for choose-provider",
+        ),
+        _ => {}
+    }
+    if let Err(err) = providers::requested_microsoft_authority(input) {
+        errors.extend(err.validation_errors());
+    }
+    if let Some(path) = input.redirect_path.as_deref() {
+        let config = provider_config::provider_config(&input.provider_id);
+        if let Err(err) =
+            provider_config::check_redirect_path(config.as_ref(), &input.provider_id, path)
+        {
+            errors.extend(err.validation_errors());
+        }
+    }
+    reject(errors)
+}
+
+/// Fail with `errors`: a single one as a plain `Validation` error, several
+/// as `Validations`.
+pub(crate) fn reject(mut errors: Vec<ValidationError>) -> Result<(), OAuthCardError> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => {
+            let ValidationError { field, message } = errors.remove(0);
+            Err(OAuthCardError::Validation { field, message })
+        }
+        _ => Err(OAuthCardError::Validations(errors)),
+    }
+}
+
+fn error(field: &str, message: &str) -> ValidationError {
+    ValidationError {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::invoke;
+use component_oauth_card::{InMemoryBroker, InMemoryStateStore, register_state_store};
+use serde_json::{Value, json};
+
+#[test]
+fn admin_consent_checks_need_the_saved_request() {
+    register_state_store(InMemoryStateStore::new()).expect("state store registration");
+    let broker = InMemoryBroker::new();
+    let request = |mode: &str, state_id: &Value, tenant: &str| {
+        json!({
+            "mode": mode,
+            "provider_id": "msgraph",
+            "subject": "admin-1",
+            "state_id": state_id,
+            "extra_json": { "tenant": tenant },
+            "scopes": ["User.Read.All"]
+        })
+    };
+
+    let requested = invoke(
+        &broker,
+        request("admin-consent", &Value::Null, "contoso.com"),
+    );
+    assert_eq!(requested["status"], "needs-consent", "{requested}");
+    let state_id = requested["state_id"].clone();
+
+    let forged = invoke(
+        &broker,
+        request("check-admin-consent", &json!("made-up"), "contoso.com"),
+    );
+    assert_eq!(forged["status"], "invalid-state", "{forged}");
+    let started = invoke(
+        &broker,
+        json!({ "mode": "start-sign-in", "provider_id": "msgraph", "subject": "admin-1" }),
+    );
+    let sign_in_state = invoke(
+        &broker,
+        request("check-admin-consent", &started["state_id"], "contoso.com"),
+    );
+    assert_eq!(sign_in_state["status"], "invalid-state", "{sign_in_state}");
+
+    // The saved tenant is checked, not the one the check names.
+    broker.grant_admin_consent("msgraph", "fabrikam.com");
+    let pending = invoke(
+        &broker,
+        request("check-admin-consent", &state_id, "fabrikam.com"),
+    );
+    assert_eq!(pending["status"], "needs-consent", "{pending}");
+    assert_eq!(
+        pending["card"]["oauth"]["metadata"]["tenant"],
+        "contoso.com"
+    );
+    let reused = invoke(
+        &broker,
+        request("check-admin-consent", &state_id, "contoso.com"),
+    );
+    assert_eq!(reused["status"], "invalid-state", "{reused}");
+
+    broker.grant_admin_consent("msgraph", "contoso.com");
+    let granted = invoke(
+        &broker,
+        request("check-admin-consent", &pending["state_id"], "fabrikam.com"),
+    );
+    assert_eq!(granted["status"], "ok", "{granted}");
+}
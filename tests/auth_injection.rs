@@ -0,0 +1,68 @@
+mod common;
+
+use component_oauth_card::{InMemoryBroker, TokenSet, load_provider_configs};
+use serde_json::{Value, json};
+
+fn ensure(broker: &InMemoryBroker, provider_id: &str) -> Value {
+    let input = json!({
+        "mode": "ensure-token",
+        "provider_id": provider_id,
+        "subject": "user-1"
+    });
+    common::invoke(broker, input)
+}
+
+#[test]
+fn provider_config_chooses_how_the_token_is_injected() {
+    load_provider_configs(
+        &json!({
+            "providers": {
+                "keyed": { "auth_injection": { "style": "header", "name": "X-Api-Key" } },
+                "query": { "auth_injection": { "style": "query", "param": "access_token" } },
+                "cookie": { "auth_injection": { "style": "cookie", "name": "session" } }
+            }
+        })
+        .to_string(),
+    )
+    .expect("provider config");
+    let broker = InMemoryBroker::new();
+    for provider_id in ["plain", "keyed", "query", "cookie"] {
+        broker.seed(
+            provider_id,
+            "user-1",
+            TokenSet {
+                access_token: "tok".into(),
+                ..Default::default()
+            },
+        );
+    }
+
+    let plain = ensure(&broker, "plain");
+    assert_eq!(
+        plain["auth_injection"],
+        json!({ "style": "header", "name": "Authorization", "value": "Bearer tok" })
+    );
+    assert_eq!(
+        plain["auth_header"]["headers"][0],
+        json!(["Authorization", "Bearer tok"])
+    );
+
+    let keyed = ensure(&broker, "keyed");
+    assert_eq!(
+        keyed["auth_header"]["headers"][0],
+        json!(["X-Api-Key", "tok"])
+    );
+
+    let query = ensure(&broker, "query");
+    assert_eq!(
+        query["auth_injection"],
+        json!({ "style": "query", "param": "access_token", "value": "tok" })
+    );
+    assert!(query["auth_header"].is_null());
+
+    let cookie = ensure(&broker, "cookie");
+    assert_eq!(
+        cookie["auth_header"]["headers"][0],
+        json!(["Cookie", "session=tok"])
+    );
+}
@@ -0,0 +1,150 @@
+#![cfg(feature = "verify-jwt")]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use component_oauth_card::{
+    CallContext, OAuthBackend, OAuthCardError, Scope, TokenSet, handle_message_with_backend,
+    load_provider_configs,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use serde_json::{Value, json};
+
+const LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// Broker whose sessions all belong to the logout token's `sub`.
+struct Sessions;
+
+impl OAuthBackend for Sessions {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(None)
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+        _extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        Err(OAuthCardError::Unsupported("consent".into()))
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported("exchange".into()))
+    }
+
+    fn invalidate_sessions(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        sub: Option<&str>,
+        _sid: Option<&str>,
+    ) -> Result<Vec<String>, OAuthCardError> {
+        Ok(sub.into_iter().map(str::to_string).collect())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[test]
+fn logout_tokens_must_be_signed_by_the_provider_and_recent() {
+    let rng = SystemRandom::new();
+    let pkcs8 =
+        EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).expect("generate key");
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+        .expect("load key");
+    let point = key_pair.public_key().as_ref();
+    load_provider_configs(
+        &json!({
+            "providers": {
+                "acme": {
+                    "id_token_verification": {
+                        "jwks": { "keys": [{
+                            "kty": "EC",
+                            "kid": "k1",
+                            "crv": "P-256",
+                            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                            "y": URL_SAFE_NO_PAD.encode(&point[33..])
+                        }] },
+                        "issuer": "https://login.acme.example",
+                        "audience": "client-1"
+                    }
+                }
+            }
+        })
+        .to_string(),
+    )
+    .expect("provider config");
+    let sign = |claims: Value| {
+        let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "ES256", "kid": "k1" }).to_string());
+        let signed = format!("{header}.{}", URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let signature = key_pair.sign(&rng, signed.as_bytes()).expect("sign");
+        format!("{signed}.{}", URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    };
+    let claims = |overrides: Value| {
+        let mut claims = json!({
+            "iss": "https://login.acme.example",
+            "aud": "client-1",
+            "iat": now(),
+            "sub": "user-1",
+            "events": { LOGOUT_EVENT: {} }
+        });
+        for (key, value) in overrides.as_object().into_iter().flatten() {
+            claims[key] = value.clone();
+        }
+        claims
+    };
+    let logout = |token: String| {
+        let input = json!({ "provider_id": "acme", "logout_token": token });
+        let response =
+            handle_message_with_backend(&Sessions, "backchannel-logout", &input.to_string());
+        serde_json::from_str::<Value>(&response).expect("valid json")
+    };
+
+    let unsigned = format!(
+        "eyJhbGciOiJub25lIn0.{}.sig",
+        URL_SAFE_NO_PAD.encode(claims(json!({})).to_string())
+    );
+    let rejected = [
+        unsigned,
+        sign(claims(json!({ "iss": "https://evil.example" }))),
+        sign(claims(json!({ "aud": "client-2" }))),
+        sign(claims(json!({ "iat": null }))),
+        sign(claims(json!({ "iat": now() - 3_600 }))),
+        sign(claims(json!({ "iat": now() + 3_600 }))),
+        sign(claims(json!({ "nonce": "n" }))),
+        sign(claims(json!({ "events": {} }))),
+    ];
+    for token in rejected {
+        let output = logout(token);
+        assert_eq!(output["status"], "error", "{output}");
+    }
+
+    let output = logout(sign(claims(json!({}))));
+    assert_eq!(output["status"], "ok", "{output}");
+    assert_eq!(output["subjects"], json!(["user-1"]));
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use component_oauth_card::{InMemoryBroker, TokenSet, register_clock};
+use serde_json::{Value, json};
+
+const NOW: u64 = 1_700_000_000;
+
+fn invoke(broker: &InMemoryBroker, mode: &str, clock_skew_secs: Option<u64>) -> Value {
+    let mut input = json!({
+        "mode": mode,
+        "provider_id": "acme",
+        "subject": "user-1"
+    });
+    if let Some(skew) = clock_skew_secs {
+        input["clockSkewSecs"] = json!(skew);
+    }
+    common::invoke(broker, input)
+}
+
+#[test]
+fn tokens_expiring_within_the_clock_skew_are_reported_expired() {
+    register_clock(|| NOW).expect("first clock registration");
+    assert!(register_clock(|| 0).is_err());
+
+    let broker = InMemoryBroker::new();
+    broker.seed(
+        "acme",
+        "user-1",
+        TokenSet {
+            access_token: "tok".into(),
+            expires_at: Some(NOW + 10),
+            ..Default::default()
+        },
+    );
+
+    for mode in ["ensure-token", "status-card"] {
+        let output = invoke(&broker, mode, None);
+        assert_eq!(output["status"], "expired", "{mode}: {output}");
+        assert!(output["auth_header"].is_null(), "{mode}: {output}");
+        assert!(output["auth_injection"].is_null(), "{mode}: {output}");
+    }
+
+    let strict = invoke(&broker, "ensure-token", Some(0));
+    assert_eq!(strict["status"], "ok", "{strict}");
+    assert_eq!(
+        strict["auth_header"]["headers"][0],
+        json!(["Authorization", "Bearer tok"])
+    );
+}
@@ -0,0 +1,9 @@
+use component_oauth_card::{OAuthBackend, handle_message_with_backend};
+use serde_json::Value;
+
+/// Run `input` through the `invoke` operation against `broker` and parse the
+/// output.
+pub fn invoke<B: OAuthBackend>(broker: &B, input: Value) -> Value {
+    let response = handle_message_with_backend(broker, "invoke", &input.to_string());
+    serde_json::from_str(&response).expect("valid json")
+}
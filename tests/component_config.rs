@@ -0,0 +1,133 @@
+mod common;
+
+use common::invoke;
+use component_oauth_card::{
+    InMemoryBroker, component_config, handle_message_with_backend, load_component_config,
+};
+use serde_json::{Value, json};
+
+#[test]
+fn component_config_supplies_invocation_defaults() {
+    load_component_config(
+        &json!({
+            "default_provider": "graph",
+            "redirect_base": "/auth/cb",
+            "refresh_margin_secs": 120,
+            "allowed_scopes": ["openid", "User.Read"],
+            "branding": { "logo_url": "https://cdn.example/logo.png" },
+            "card_templates": {
+                "start-sign-in": { "title": "Sign in to {{provider_name}}", "footer": "Acme IT" }
+            },
+            "providers": {
+                "graph": {
+                    "card_templates": { "start-sign-in": { "text": "Connect {{subject}}" } },
+                    "branding": { "product_name": "Graph Connect" }
+                }
+            }
+        })
+        .to_string(),
+    )
+    .expect("component config");
+    assert_eq!(component_config().refresh_margin_secs, Some(120));
+    let broker = InMemoryBroker::new();
+
+    let started = invoke(
+        &broker,
+        json!({
+            "mode": "start-sign-in",
+            "subject": "user-1",
+            "scopes": ["openid", "User.Read", "Mail.Send"]
+        }),
+    );
+    assert_eq!(started["status"], "ok");
+    assert_eq!(started["dropped_scopes"], json!(["Mail.Send"]));
+    let url = started["card"]["oauth"]["start_url"]
+        .as_str()
+        .expect("consent url");
+    assert!(url.starts_with("https://graph.memory.invalid/"), "{url}");
+    assert!(url.ends_with("redirect=/auth/cb/graph"), "{url}");
+    assert_eq!(
+        started["card"]["images"][0]["url"],
+        "https://cdn.example/logo.png"
+    );
+    assert_eq!(started["card"]["title"], "Sign in to graph");
+    assert_eq!(started["card"]["text"], "Connect user-1");
+    assert_eq!(started["card"]["footer"], "Acme IT");
+
+    let overridden = invoke(
+        &broker,
+        json!({
+            "mode": "start-sign-in",
+            "subject": "user-1",
+            "extra_json": { "card_templates": { "start-sign-in": { "footer": "Flow footer" } } }
+        }),
+    );
+    assert_eq!(overridden["card"]["text"], "Connect user-1");
+    assert_eq!(overridden["card"]["footer"], "Flow footer");
+
+    let rebranded = invoke(
+        &broker,
+        json!({
+            "mode": "status-card",
+            "subject": "user-1",
+            "extra_json": { "branding": { "logo_url": "https://cdn.example/flow.png" } }
+        }),
+    );
+    assert_eq!(
+        rebranded["card"]["images"][0]["url"],
+        "https://cdn.example/flow.png"
+    );
+    assert_eq!(
+        rebranded["card"]["images"][0]["alt"], "Graph Connect",
+        "{rebranded}"
+    );
+    assert_eq!(rebranded["card"]["footer"], "Secured by Graph Connect");
+
+    let outside = invoke(
+        &broker,
+        json!({
+            "mode": "start-sign-in",
+            "subject": "user-1",
+            "redirect_path": "/oauth/callback/graph"
+        }),
+    );
+    assert_eq!(outside["status"], "error");
+    assert_eq!(outside["error_detail"]["field"], "redirect_path");
+
+    let invalid = load_component_config(&json!({ "redirect_base": "//evil.example" }).to_string());
+    assert!(invalid.is_err());
+    assert_eq!(
+        component_config().redirect_base.as_deref(),
+        Some("/auth/cb")
+    );
+
+    // A bad key fails the whole configuration: the providers it names are
+    // not loaded either.
+    let configure = |config: Value| {
+        let response = handle_message_with_backend(&broker, "configure", &config.to_string());
+        serde_json::from_str::<Value>(&response).expect("valid json")
+    };
+    let rejected = configure(json!({
+        "default_provider": "github",
+        "providers": { "github": { "default_scopes": ["repo"] } },
+        "sign_in_rate_limit": { "max_attempts": "many" }
+    }));
+    assert_eq!(rejected["status"], "error", "{rejected}");
+    assert_eq!(
+        component_config().default_provider.as_deref(),
+        Some("graph")
+    );
+    let unchanged = invoke(
+        &broker,
+        json!({ "mode": "start-sign-in", "subject": "user-1" }),
+    );
+    assert_eq!(unchanged["card"]["text"], "Connect user-1", "{unchanged}");
+
+    let configured = configure(json!({ "default_provider": "github" }));
+    assert_eq!(configured["status"], "ok", "{configured}");
+    assert_eq!(
+        component_config().default_provider.as_deref(),
+        Some("github")
+    );
+    assert_eq!(component_config().redirect_base, None);
+}
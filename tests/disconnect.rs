@@ -0,0 +1,93 @@
+mod common;
+
+use common::invoke;
+use component_oauth_card::{
+    CallContext, InMemoryBroker, InMemoryStateStore, OAuthBackend, OAuthCardError, Scope, TokenSet,
+    register_state_store,
+};
+use serde_json::json;
+
+/// Broker that, like the host broker, can hand out tokens but not revoke them.
+struct NoRevoke(InMemoryBroker);
+
+impl OAuthBackend for NoRevoke {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        self.0.get_token(ctx, provider_id, subject, scopes)
+    }
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        self.0
+            .get_consent_url(ctx, provider_id, subject, scopes, redirect_path, extra_json)
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.0.exchange_code(
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+        )
+    }
+}
+
+#[test]
+fn disconnect_without_broker_revocation_forgets_the_connection_locally() {
+    register_state_store(InMemoryStateStore::new()).expect("first registration");
+    let broker = NoRevoke(InMemoryBroker::new());
+    broker.0.seed(
+        "demo",
+        "user-1",
+        TokenSet {
+            access_token: "host-token".into(),
+            ..Default::default()
+        },
+    );
+    let request = |mode: &str| json!({ "mode": mode, "provider_id": "demo", "subject": "user-1" });
+
+    let disconnected = invoke(&broker, request("disconnect"));
+    assert_eq!(disconnected["status"], "revoked", "{disconnected}");
+    assert!(
+        disconnected["card"]["text"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("unlink this app")
+    );
+    assert!(broker.0.stored("demo", "user-1").is_some());
+
+    let status = invoke(&broker, request("status-card"));
+    assert_eq!(status["status"], "needs-sign-in");
+    let ensured = invoke(&broker, request("ensure-token"));
+    assert_eq!(ensured["status"], "needs-sign-in");
+    assert!(ensured["auth_header"].is_null());
+
+    let started = invoke(&broker, request("start-sign-in"));
+    let mut complete = request("complete-sign-in");
+    complete["state_id"] = started["state_id"].clone();
+    complete["auth_code"] = json!("code-123");
+    assert_eq!(invoke(&broker, complete)["status"], "ok");
+    assert_eq!(invoke(&broker, request("ensure-token"))["status"], "ok");
+}
@@ -0,0 +1,197 @@
+#![cfg(feature = "dpop")]
+
+mod common;
+
+use common::invoke;
+use std::sync::Mutex;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use component_oauth_card::{
+    CallContext, InMemoryStateStore, OAuthBackend, OAuthCardError, Scope, TokenSet,
+    load_provider_configs, register_state_store,
+};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+#[derive(Default)]
+struct DpopBroker {
+    consent_extra: Mutex<Option<String>>,
+    exchange_extra: Mutex<Option<String>>,
+}
+
+impl OAuthBackend for DpopBroker {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(Some(TokenSet {
+            access_token: "bound-token".into(),
+            token_type: Some("DPoP".into()),
+            ..Default::default()
+        }))
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        *self.consent_extra.lock().expect("lock") = extra_json;
+        Ok("https://login.example/authorize".into())
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported("not used".into()))
+    }
+
+    fn exchange_code_with_extra(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+        extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        *self.exchange_extra.lock().expect("lock") = extra_json;
+        self.get_token(ctx, provider_id, subject, &[])
+            .map(|token| token.expect("token"))
+    }
+}
+
+fn decode(segment: &str) -> Value {
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(segment).expect("base64url")).expect("json")
+}
+
+/// Header and claims of a proof, checking it is signed with the key whose
+/// thumbprint is `jkt`.
+fn decode_proof(proof: &str, jkt: &str) -> (Value, Value) {
+    let segments: Vec<&str> = proof.split('.').collect();
+    assert_eq!(segments.len(), 3);
+    let header = decode(segments[0]);
+    assert_eq!(header["typ"], "dpop+jwt");
+    assert_eq!(header["alg"], "ES256");
+    let thumbprint = URL_SAFE_NO_PAD.encode(Sha256::digest(
+        json!({
+            "crv": header["jwk"]["crv"],
+            "kty": header["jwk"]["kty"],
+            "x": header["jwk"]["x"],
+            "y": header["jwk"]["y"],
+        })
+        .to_string()
+        .as_bytes(),
+    ));
+    assert_eq!(
+        thumbprint, jkt,
+        "proof is signed with the key sent on consent"
+    );
+    (header, decode(segments[1]))
+}
+
+#[test]
+fn dpop_providers_get_bound_consent_and_proof_headers() {
+    register_state_store(InMemoryStateStore::new()).expect("state store registration");
+    load_provider_configs(&json!({ "providers": { "acme": { "dpop": true } } }).to_string())
+        .expect("provider config");
+    let broker = DpopBroker::default();
+
+    let started = invoke(
+        &broker,
+        json!({ "mode": "start-sign-in", "provider_id": "acme", "subject": "user-1" }),
+    );
+    assert_eq!(started["status"], "ok", "{started}");
+    let extra: Value = serde_json::from_str(
+        broker
+            .consent_extra
+            .lock()
+            .expect("lock")
+            .as_deref()
+            .expect("consent extra_json"),
+    )
+    .expect("extra json");
+    let jkt = extra["dpop_jkt"].as_str().expect("dpop_jkt").to_string();
+
+    let complete = |extra_json: Value| {
+        invoke(
+            &broker,
+            json!({
+                "mode": "complete-sign-in",
+                "provider_id": "acme",
+                "subject": "user-1",
+                "state_id": started["state_id"],
+                "auth_code": "code-1",
+                "extra_json": extra_json
+            }),
+        )
+    };
+    let unknown_endpoint = complete(json!({}));
+    assert_eq!(unknown_endpoint["status"], "error", "{unknown_endpoint}");
+    let completed = complete(json!({
+        "oidc_endpoints": { "token_endpoint": "https://login.example/token" }
+    }));
+    assert_eq!(completed["status"], "ok", "{completed}");
+    let exchange_extra: Value = serde_json::from_str(
+        broker
+            .exchange_extra
+            .lock()
+            .expect("lock")
+            .as_deref()
+            .expect("exchange extra_json"),
+    )
+    .expect("extra json");
+    let (_, claims) = decode_proof(
+        exchange_extra["dpop_proof"].as_str().expect("dpop_proof"),
+        &jkt,
+    );
+    assert_eq!(claims["htm"], "POST");
+    assert_eq!(claims["htu"], "https://login.example/token");
+    assert!(claims.get("ath").is_none(), "{claims}");
+
+    let ensured = invoke(
+        &broker,
+        json!({
+            "mode": "ensure-token",
+            "provider_id": "acme",
+            "subject": "user-1",
+            "dpopRequest": { "method": "get", "url": "https://api.example/items?page=2" }
+        }),
+    );
+    assert_eq!(ensured["status"], "ok", "{ensured}");
+    let headers = &ensured["auth_header"]["headers"];
+    assert_eq!(headers[0], json!(["Authorization", "DPoP bound-token"]));
+    assert_eq!(headers[1][0], "DPoP");
+
+    let (_, claims) = decode_proof(headers[1][1].as_str().expect("proof"), &jkt);
+    assert_eq!(claims["htm"], "GET");
+    assert_eq!(claims["htu"], "https://api.example/items");
+    assert_eq!(
+        claims["ath"],
+        URL_SAFE_NO_PAD.encode(Sha256::digest(b"bound-token"))
+    );
+
+    let without_request = invoke(
+        &broker,
+        json!({ "mode": "ensure-token", "provider_id": "acme", "subject": "user-1" }),
+    );
+    assert_eq!(
+        without_request["auth_header"]["headers"],
+        json!([["Authorization", "DPoP bound-token"]])
+    );
+}
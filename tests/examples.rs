@@ -0,0 +1,325 @@
+use std::path::Path;
+
+use component_oauth_card::{
+    AccountSummary, CallContext, ConnectionSummary, DeviceCodeGrant, InMemoryBroker, OAuthBackend,
+    OAuthCardError, OAuthCardMode, Scope, SignInProgress, TokenSet, handle_message_with_backend,
+    register_clock,
+};
+use serde_json::{Value, json};
+
+const NOW: u64 = 1_700_000_000;
+const FIXTURES: &str = "tests/fixtures/examples.json";
+
+/// In-memory connections plus canned answers for the flows `InMemoryBroker`
+/// does not implement, so every mode has a successful sample.
+struct ExampleBroker(InMemoryBroker);
+
+fn issued(access_token: &str, scopes: &[Scope]) -> TokenSet {
+    TokenSet {
+        access_token: access_token.into(),
+        expires_at: Some(NOW + 3_600),
+        token_type: Some("Bearer".into()),
+        granted_scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+impl OAuthBackend for ExampleBroker {
+    fn get_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        self.0.get_token(ctx, provider_id, subject, scopes)
+    }
+
+    fn get_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        self.0
+            .get_consent_url(ctx, provider_id, subject, scopes, redirect_path, extra_json)
+    }
+
+    fn exchange_code(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        code: &str,
+        redirect_path: &str,
+        idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.0.exchange_code(
+            ctx,
+            provider_id,
+            subject,
+            code,
+            redirect_path,
+            idempotency_key,
+        )
+    }
+
+    fn poll_sign_in(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _state_id: &str,
+    ) -> Result<SignInProgress, OAuthCardError> {
+        Ok(SignInProgress::Pending)
+    }
+
+    fn cancel_sign_in(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _state_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        Ok(())
+    }
+
+    fn refresh_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<TokenSet, OAuthCardError> {
+        self.0.refresh_token(ctx, provider_id, subject, scopes)
+    }
+
+    fn get_app_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Ok(issued("example-app-token", scopes))
+    }
+
+    fn exchange_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _subject_token: &str,
+        _subject_token_type: &str,
+        scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Ok(issued("example-exchanged-token", scopes))
+    }
+
+    fn revoke_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        scopes: &[Scope],
+    ) -> Result<(), OAuthCardError> {
+        self.0.revoke_token(ctx, provider_id, subject, scopes)
+    }
+
+    fn get_end_session_url(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        _subject: &str,
+    ) -> Result<String, OAuthCardError> {
+        Ok(format!("https://{provider_id}.example/logout"))
+    }
+
+    fn get_admin_consent_url(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+        redirect_path: &str,
+    ) -> Result<String, OAuthCardError> {
+        self.0
+            .get_admin_consent_url(ctx, provider_id, tenant, scopes, redirect_path)
+    }
+
+    fn admin_consent_granted(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        tenant: &str,
+        scopes: &[Scope],
+    ) -> Result<bool, OAuthCardError> {
+        self.0
+            .admin_consent_granted(ctx, provider_id, tenant, scopes)
+    }
+
+    fn list_connections(
+        &self,
+        ctx: &CallContext,
+        subject: &str,
+    ) -> Result<Vec<ConnectionSummary>, OAuthCardError> {
+        self.0.list_connections(ctx, subject)
+    }
+
+    fn list_accounts(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+    ) -> Result<Vec<AccountSummary>, OAuthCardError> {
+        Ok(vec![
+            AccountSummary {
+                account_id: "work".into(),
+                label: Some("Work".into()),
+                email: Some("user@contoso.example".into()),
+                active: true,
+            },
+            AccountSummary {
+                account_id: "personal".into(),
+                label: Some("Personal".into()),
+                email: Some("user@mail.example".into()),
+                active: false,
+            },
+        ])
+    }
+
+    fn select_account(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _account_id: &str,
+    ) -> Result<(), OAuthCardError> {
+        Ok(())
+    }
+
+    fn store_token(
+        &self,
+        ctx: &CallContext,
+        provider_id: &str,
+        subject: &str,
+        token: &TokenSet,
+    ) -> Result<(), OAuthCardError> {
+        self.0.store_token(ctx, provider_id, subject, token)
+    }
+
+    fn start_device_code(
+        &self,
+        _ctx: &CallContext,
+        provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _extra_json: Option<String>,
+    ) -> Result<DeviceCodeGrant, OAuthCardError> {
+        Ok(DeviceCodeGrant {
+            device_code: "example-device-code".into(),
+            user_code: "WDJB-MJHT".into(),
+            verification_uri: format!("https://{provider_id}.example/device"),
+            verification_uri_complete: None,
+            expires_in: Some(900),
+            interval: Some(5),
+        })
+    }
+
+    fn poll_device_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _device_code: &str,
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(None)
+    }
+}
+
+/// Replace generated UUIDs (state ids) so recorded responses are stable.
+fn normalize(value: &mut Value) {
+    match value {
+        Value::String(text) => {
+            let mut start = 0;
+            while let Some(found) = text.get(start..).and_then(find_uuid) {
+                text.replace_range(start + found..start + found + 36, "<state-id>");
+                start += found + "<state-id>".len();
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(normalize),
+        Value::Object(fields) => fields.values_mut().for_each(normalize),
+        _ => {}
+    }
+}
+
+fn find_uuid(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    (0..bytes.len().saturating_sub(35)).find(|&start| {
+        bytes[start..start + 36]
+            .iter()
+            .enumerate()
+            .all(|(i, byte)| match i {
+                8 | 13 | 18 | 23 => *byte == b'-',
+                _ => byte.is_ascii_hexdigit(),
+            })
+    })
+}
+
+#[test]
+fn recorded_examples_match_the_component() {
+    register_clock(|| NOW).expect("first clock registration");
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(FIXTURES);
+    let committed = std::fs::read_to_string(&path).expect("read examples");
+    let mut fixtures: Value = serde_json::from_str(&committed).expect("examples json");
+    let connections = fixtures["connections"].to_string();
+
+    let examples = fixtures["examples"].as_array_mut().expect("examples");
+    let modes: Vec<Value> = examples
+        .iter()
+        .map(|example| example["mode"].clone())
+        .collect();
+    assert_eq!(
+        modes,
+        json!(OAuthCardMode::SUPPORTED)
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    );
+
+    for example in examples.iter_mut() {
+        let broker = ExampleBroker(InMemoryBroker::from_json(&connections).expect("connections"));
+        let response =
+            handle_message_with_backend(&broker, "invoke", &example["request"].to_string());
+        let mut response: Value = serde_json::from_str(&response).expect("response json");
+        normalize(&mut response);
+        assert_ne!(
+            response["status"], "error",
+            "{}: {response}",
+            example["mode"]
+        );
+        example["response"] = response;
+    }
+
+    let generated = format!(
+        "{}\n",
+        serde_json::to_string_pretty(&fixtures).expect("examples json")
+    );
+    if std::env::var_os("UPDATE_EXAMPLES").is_some() {
+        std::fs::write(&path, &generated).expect("write examples");
+        return;
+    }
+    assert!(
+        committed == generated,
+        "{FIXTURES} is out of date with the component; run `make examples`"
+    );
+
+    let catalog =
+        handle_message_with_backend(&ExampleBroker(InMemoryBroker::new()), "examples", "");
+    let catalog: Value = serde_json::from_str(&catalog).expect("catalog json");
+    assert_eq!(catalog["examples"], fixtures["examples"]);
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "keyring")]
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use component_oauth_card::{
+    CallContext, DEFAULT_KEYRING_SERVICE, KeyringBroker, OAuthBackend, TokenSet,
+    handle_message_with_backend,
+};
+use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+use serde_json::{Value, json};
+
+type Secrets = Arc<Mutex<HashMap<(String, String), Vec<u8>>>>;
+
+/// In-memory credential store shared by every entry, standing in for the OS
+/// keychain (the keyring crate's mock keeps each entry's secret to itself).
+#[derive(Debug, Default)]
+struct SharedStore(Secrets);
+
+#[derive(Debug)]
+struct SharedCredential {
+    secrets: Secrets,
+    key: (String, String),
+}
+
+impl CredentialApi for SharedCredential {
+    fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+        let mut secrets = self.secrets.lock().expect("secrets");
+        secrets.insert(self.key.clone(), secret.to_vec());
+        Ok(())
+    }
+
+    fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+        let secrets = self.secrets.lock().expect("secrets");
+        secrets
+            .get(&self.key)
+            .cloned()
+            .ok_or(keyring::Error::NoEntry)
+    }
+
+    fn delete_credential(&self) -> keyring::Result<()> {
+        let mut secrets = self.secrets.lock().expect("secrets");
+        secrets
+            .remove(&self.key)
+            .map(drop)
+            .ok_or(keyring::Error::NoEntry)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl CredentialBuilderApi for SharedStore {
+    fn build(
+        &self,
+        _target: Option<&str>,
+        service: &str,
+        user: &str,
+    ) -> keyring::Result<Box<Credential>> {
+        Ok(Box::new(SharedCredential {
+            secrets: Arc::clone(&self.0),
+            key: (service.to_string(), user.to_string()),
+        }))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[test]
+fn keyring_broker_keeps_tokens_in_the_credential_store() {
+    let store = SharedStore::default();
+    let secrets = Arc::clone(&store.0);
+    keyring::set_default_credential_builder(Box::new(store));
+
+    let broker = KeyringBroker::default();
+    assert!(
+        OAuthBackend::get_token(&broker, &CallContext::default(), "msgraph", "user-1", &[])
+            .expect("lookup")
+            .is_none()
+    );
+    broker
+        .save(
+            "msgraph",
+            "user-1",
+            &TokenSet {
+                access_token: "keyring-token".into(),
+                ..Default::default()
+            },
+        )
+        .expect("save");
+    let stored = secrets.lock().expect("secrets");
+    let key = (
+        DEFAULT_KEYRING_SERVICE.to_string(),
+        "msgraph:user-1".to_string(),
+    );
+    assert!(String::from_utf8_lossy(&stored[&key]).contains("keyring-token"));
+    drop(stored);
+
+    let invoke = |mode: &str| {
+        let input = json!({ "mode": mode, "provider_id": "msgraph", "subject": "user-1" });
+        let response = handle_message_with_backend(&broker, "invoke", &input.to_string());
+        serde_json::from_str::<Value>(&response).expect("valid json")
+    };
+    let ensured = invoke("ensure-token");
+    assert_eq!(ensured["status"], "ok", "{ensured}");
+    assert_eq!(
+        ensured["auth_header"]["headers"][0][1],
+        "Bearer keyring-token"
+    );
+
+    assert_eq!(invoke("disconnect")["status"], "revoked");
+    assert!(secrets.lock().expect("secrets").is_empty());
+    // A credential that vanished (e.g. after a reboot) reads as not connected.
+    assert_eq!(invoke("ensure-token")["status"], "needs-sign-in");
+}
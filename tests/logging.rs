@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+
+use component_oauth_card::{
+    CallContext, LogLevel, LogRecord, OAuthBackend, OAuthCardError, Scope, TokenSet,
+    handle_message_with_backend, register_logger,
+};
+use serde_json::{Value, json};
+
+static RECORDS: Mutex<Vec<LogRecord>> = Mutex::new(Vec::new());
+
+/// Broker with a half-usable stored token and no consent URL to offer.
+struct FlakyBroker;
+
+impl OAuthBackend for FlakyBroker {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(Some(TokenSet {
+            access_token: "stored-token".into(),
+            scope: Some("files.read".into()),
+            extra: Some(json!("not an object")),
+            ..Default::default()
+        }))
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+        _extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        Err(OAuthCardError::Backend(
+            "upstream rejected Authorization: Bearer abc123".into(),
+        ))
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported("not used".into()))
+    }
+}
+
+fn messages(level: LogLevel) -> Vec<String> {
+    RECORDS
+        .lock()
+        .expect("lock")
+        .iter()
+        .filter(|record| record.level == level)
+        .map(|record| record.message.clone())
+        .collect()
+}
+
+#[test]
+fn swallowed_problems_are_logged_with_tokens_redacted() {
+    register_logger(|record: &LogRecord| RECORDS.lock().expect("lock").push(record.clone()))
+        .expect("first registration");
+    assert!(register_logger(|_: &LogRecord| {}).is_err());
+
+    let input = json!({
+        "mode": "ensure-token",
+        "provider_id": "msgraph",
+        "subject": "user-1",
+        "scopes": ["files.read", "mail.send"],
+        "correlation_id": "req-42"
+    });
+    let response = handle_message_with_backend(&FlakyBroker, "invoke", &input.to_string());
+    let output: Value = serde_json::from_str(&response).expect("valid json");
+    assert_eq!(output["status"], "needs-consent");
+
+    assert_eq!(
+        messages(LogLevel::Info),
+        ["stored token lacks requested scopes"]
+    );
+    assert_eq!(
+        messages(LogLevel::Warn),
+        ["consent url unavailable", "sign-in card has no consent url"]
+    );
+
+    let records = RECORDS.lock().expect("lock");
+    let unavailable = records
+        .iter()
+        .find(|record| record.message == "consent url unavailable")
+        .expect("consent warning");
+    assert_eq!(unavailable.correlation_id.as_deref(), Some("req-42"));
+    let error = unavailable.fields["error"].as_str().expect("error field");
+    assert!(error.contains("Bearer [redacted]"), "{error}");
+    assert!(!error.contains("abc123"), "{error}");
+    let missing = records
+        .iter()
+        .find(|record| record.level == LogLevel::Info)
+        .expect("scope record");
+    assert_eq!(missing.fields["missing_scopes"], json!(["mail.send"]));
+}
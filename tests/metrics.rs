@@ -0,0 +1,40 @@
+use component_oauth_card::{
+    InMemoryBroker, LATENCY_BUCKETS_MS, MetricsSnapshot, handle_message_with_backend, reset_metrics,
+};
+use serde_json::json;
+
+#[test]
+fn metrics_count_invocations_errors_and_broker_latency() {
+    reset_metrics();
+    let backend = InMemoryBroker::new();
+    let ensure = json!({ "mode": "ensure-token", "provider_id": "msgraph", "subject": "user-1" });
+    for _ in 0..2 {
+        handle_message_with_backend(&backend, "invoke", &ensure.to_string());
+    }
+    let unknown = json!({ "mode": "teleport", "provider_id": "msgraph", "subject": "user-1" });
+    handle_message_with_backend(&backend, "invoke", &unknown.to_string());
+    handle_message_with_backend(&backend, "invoke", "not json");
+
+    let metrics: MetricsSnapshot =
+        serde_json::from_str(&handle_message_with_backend(&backend, "metrics", ""))
+            .expect("metrics json");
+    assert_eq!(metrics.invocations["ensure-token"], 2);
+    assert_eq!(metrics.invocations["teleport"], 1);
+    assert_eq!(metrics.invocations["unknown"], 1);
+    assert_eq!(metrics.statuses["needs-sign-in"], 2);
+    assert_eq!(metrics.statuses["error"], 2);
+    assert_eq!(metrics.errors["unsupported"], 1);
+    assert_eq!(metrics.errors["parse_error"], 1);
+
+    let get_token = &metrics.broker_calls["get_token"];
+    assert_eq!(get_token.count, 2);
+    assert_eq!(get_token.errors, 0);
+    assert_eq!(get_token.buckets.len(), LATENCY_BUCKETS_MS.len() + 1);
+    assert_eq!(get_token.buckets.iter().sum::<u64>(), 2);
+
+    reset_metrics();
+    let metrics: MetricsSnapshot =
+        serde_json::from_str(&handle_message_with_backend(&backend, "metrics", ""))
+            .expect("metrics json");
+    assert_eq!(metrics, MetricsSnapshot::default());
+}
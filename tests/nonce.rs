@@ -0,0 +1,115 @@
+use std::sync::Mutex;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use component_oauth_card::{
+    CallContext, InMemoryStateStore, OAuthBackend, OAuthCardError, Scope, TokenSet,
+    handle_message_with_backend, register_state_store,
+};
+use serde_json::{Value, json};
+
+/// Broker issuing whatever id_token claims the test sets, for the nonce it
+/// was sent on consent.
+#[derive(Default)]
+struct NonceBroker {
+    nonce: Mutex<Option<String>>,
+    id_token_claims: Mutex<Option<Value>>,
+}
+
+impl OAuthBackend for NonceBroker {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(None)
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        let extra: Value = serde_json::from_str(&extra_json.expect("extra_json")).expect("json");
+        *self.nonce.lock().expect("lock") = extra["nonce"].as_str().map(str::to_string);
+        Ok("https://login.example/authorize".into())
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        let id_token = self
+            .id_token_claims
+            .lock()
+            .expect("lock")
+            .as_ref()
+            .map(|claims| {
+                format!(
+                    "eyJhbGciOiJub25lIn0.{}.",
+                    URL_SAFE_NO_PAD.encode(claims.to_string())
+                )
+            });
+        Ok(TokenSet {
+            access_token: "access".into(),
+            id_token,
+            ..Default::default()
+        })
+    }
+}
+
+#[test]
+fn openid_sign_ins_must_return_the_nonce_they_were_sent() {
+    register_state_store(InMemoryStateStore::new()).expect("state store registration");
+    let broker = NonceBroker::default();
+    let sign_in = |scopes: Value, claims: Option<Value>| {
+        let invoke = |input: Value| {
+            let response = handle_message_with_backend(&broker, "invoke", &input.to_string());
+            serde_json::from_str::<Value>(&response).expect("valid json")
+        };
+        let request = |mode: &str| {
+            json!({
+                "mode": mode,
+                "provider_id": "acme",
+                "subject": "user-1",
+                "scopes": scopes
+            })
+        };
+        let started = invoke(request("start-sign-in"));
+        let nonce = broker.nonce.lock().expect("lock").clone().expect("nonce");
+        *broker.id_token_claims.lock().expect("lock") = claims.map(|mut claims| {
+            if claims["nonce"] == "<sent>" {
+                claims["nonce"] = json!(nonce);
+            }
+            claims
+        });
+        let mut complete = request("complete-sign-in");
+        complete["state_id"] = started["state_id"].clone();
+        complete["auth_code"] = json!(format!("code-{nonce}"));
+        invoke(complete)
+    };
+    let openid = json!(["openid"]);
+
+    let matching = sign_in(openid.clone(), Some(json!({ "nonce": "<sent>" })));
+    assert_eq!(matching["status"], "ok", "{matching}");
+    let other = sign_in(openid.clone(), Some(json!({ "nonce": "other" })));
+    assert_eq!(other["status"], "error", "{other}");
+    let missing = sign_in(openid.clone(), Some(json!({ "sub": "user-1" })));
+    assert_eq!(missing["status"], "error", "{missing}");
+    let no_id_token = sign_in(openid, None);
+    assert_eq!(no_id_token["status"], "error", "{no_id_token}");
+
+    let plain_oauth = sign_in(json!(["repo"]), None);
+    assert_eq!(plain_oauth["status"], "ok", "{plain_oauth}");
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use component_oauth_card::{InMemoryBroker, TokenSet, load_output_policy};
+use serde_json::{Value, json};
+
+fn status_card(broker: &InMemoryBroker, extra: Value) -> Value {
+    let mut input = json!({
+        "mode": "status-card",
+        "provider_id": "acme",
+        "subject": "user-1"
+    });
+    if let (Some(input), Some(extra)) = (input.as_object_mut(), extra.as_object()) {
+        input.extend(extra.clone());
+    }
+    common::invoke(broker, input)
+}
+
+#[test]
+fn auth_headers_can_be_left_out_per_input_or_by_component_config() {
+    let broker = InMemoryBroker::new();
+    broker.seed(
+        "acme",
+        "user-1",
+        TokenSet {
+            access_token: "tok".into(),
+            ..Default::default()
+        },
+    );
+
+    let default = status_card(&broker, json!({}));
+    assert_eq!(
+        default["auth_header"]["headers"][0],
+        json!(["Authorization", "Bearer tok"])
+    );
+
+    let card_only = status_card(&broker, json!({ "includeAuthHeader": false }));
+    assert_eq!(card_only["status"], "ok");
+    assert!(card_only["card"].is_object());
+    assert!(card_only["auth_header"].is_null());
+    assert!(card_only["auth_injection"].is_null());
+    assert!(!card_only.to_string().contains("tok\""));
+
+    load_output_policy(&json!({ "include_auth_header": false }).to_string())
+        .expect("output policy");
+    let forced = status_card(&broker, json!({ "include_auth_header": true }));
+    assert_eq!(forced["status"], "ok");
+    assert!(forced["auth_header"].is_null());
+    assert!(forced["auth_injection"].is_null());
+
+    load_output_policy(&json!({ "include_auth_header": true }).to_string()).expect("output policy");
+    let still_off = status_card(&broker, json!({ "include_auth_header": false }));
+    assert!(still_off["auth_header"].is_null());
+    let restored = status_card(&broker, json!({}));
+    assert!(restored["auth_header"].is_object());
+}
@@ -0,0 +1,42 @@
+use component_oauth_card::{
+    InMemoryBroker, InMemoryStateStore, handle_message_with_backend, register_state_store,
+    resolve_post_back,
+};
+use serde_json::{Value, json};
+
+#[test]
+fn long_post_backs_become_resolvable_handles() {
+    register_state_store(InMemoryStateStore::new()).expect("first registration");
+    let broker = InMemoryBroker::new();
+    let input = json!({
+        "mode": "start-sign-in",
+        "provider_id": "acme-enterprise",
+        "subject": "user-1",
+        "channel": "telegram"
+    });
+    let response = handle_message_with_backend(&broker, "invoke", &input.to_string());
+    let output: Value = serde_json::from_str(&response).expect("valid json");
+    let state_id = output["state_id"].as_str().expect("state id");
+
+    let callbacks: Vec<&str> = output["rendered"]["telegram"]["reply_markup"]["inline_keyboard"]
+        .as_array()
+        .expect("keyboard")
+        .iter()
+        .filter_map(|row| row[0]["callback_data"].as_str())
+        .collect();
+    assert!(!callbacks.is_empty(), "{output}");
+    for callback in callbacks {
+        assert!(callback.len() <= 64, "{callback}");
+        assert!(callback.starts_with("oauth#"), "{callback}");
+        let data = resolve_post_back(callback).expect("stored post-back");
+        assert_eq!(data["provider_id"], "acme-enterprise");
+        assert_eq!(data["state_id"], state_id);
+    }
+
+    assert_eq!(
+        resolve_post_back("oauth:disconnect:msgraph:"),
+        Some(json!({ "mode": "disconnect", "provider_id": "msgraph" }))
+    );
+    assert_eq!(resolve_post_back("oauth#unknown"), None);
+    assert_eq!(resolve_post_back("something-else"), None);
+}
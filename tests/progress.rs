@@ -0,0 +1,50 @@
+use component_oauth_card::{InMemoryBroker, Stage, handle_message_stream, register_backend};
+use serde_json::{Value, json};
+
+fn stream(input: Value) -> (Vec<Stage>, Value) {
+    let (stages, data) = handle_message_stream("invoke", &input.to_string());
+    (stages, serde_json::from_str(&data).expect("valid json"))
+}
+
+#[test]
+fn stream_reports_the_stages_each_invocation_reaches() {
+    register_backend(InMemoryBroker::new()).expect("first registration");
+
+    let (stages, started) = stream(json!({
+        "mode": "start-sign-in", "provider_id": "msgraph", "subject": "user-1"
+    }));
+    assert!(started["state_id"].is_string(), "{started}");
+    assert_eq!(
+        stages,
+        [Stage::Parsing, Stage::ConsentUrl, Stage::Rendering]
+    );
+
+    let (stages, completed) = stream(json!({
+        "mode": "complete-sign-in", "provider_id": "msgraph", "subject": "user-1",
+        "state_id": started["state_id"], "auth_code": "code-1"
+    }));
+    assert_eq!(completed["status"], "ok");
+    assert_eq!(stages, [Stage::Parsing, Stage::Exchange, Stage::Rendering]);
+
+    let (stages, ensured) = stream(json!({
+        "mode": "ensure-token", "provider_id": "msgraph", "subject": "user-1"
+    }));
+    assert_eq!(ensured["status"], "ok");
+    assert_eq!(stages, [Stage::Parsing, Stage::BrokerLookup]);
+
+    let percents: Vec<u8> = [
+        Stage::Parsing,
+        Stage::BrokerLookup,
+        Stage::ConsentUrl,
+        Stage::Exchange,
+        Stage::Rendering,
+    ]
+    .iter()
+    .map(|stage| stage.percent())
+    .collect();
+    assert!(percents.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(Stage::ConsentUrl.label(), "Preparing sign-in link");
+
+    let (stages, _) = stream(json!("not an input"));
+    assert_eq!(stages, [Stage::Parsing]);
+}
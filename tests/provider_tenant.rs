@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+
+use component_oauth_card::{
+    CallContext, OAuthBackend, OAuthCardError, Scope, TokenSet, handle_message_with_backend,
+    load_provider_configs,
+};
+use serde_json::{Value, json};
+
+/// Broker remembering the `extra_json` of the last consent request.
+#[derive(Default)]
+struct ConsentBroker {
+    extra: Mutex<Value>,
+}
+
+impl OAuthBackend for ConsentBroker {
+    fn get_token(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+    ) -> Result<Option<TokenSet>, OAuthCardError> {
+        Ok(None)
+    }
+
+    fn get_consent_url(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _scopes: &[Scope],
+        _redirect_path: &str,
+        extra_json: Option<String>,
+    ) -> Result<String, OAuthCardError> {
+        *self.extra.lock().expect("lock") =
+            serde_json::from_str(extra_json.as_deref().unwrap_or("{}")).expect("json");
+        Ok("https://login.example/authorize".into())
+    }
+
+    fn exchange_code(
+        &self,
+        _ctx: &CallContext,
+        _provider_id: &str,
+        _subject: &str,
+        _code: &str,
+        _redirect_path: &str,
+        _idempotency_key: Option<&str>,
+    ) -> Result<TokenSet, OAuthCardError> {
+        Err(OAuthCardError::Unsupported("consent only".into()))
+    }
+}
+
+#[test]
+fn tenant_parameters_come_from_the_provider_config() {
+    load_provider_configs(&json!({ "providers": { "slack": { "tenant": "T0001" } } }).to_string())
+        .expect("provider config");
+    let broker = ConsentBroker::default();
+    let start = |provider_id: &str| {
+        let input = json!({
+            "mode": "start-sign-in",
+            "provider_id": provider_id,
+            "subject": "user-1",
+            "tenant": "acme"
+        });
+        let response = handle_message_with_backend(&broker, "invoke", &input.to_string());
+        let output: Value = serde_json::from_str(&response).expect("valid json");
+        assert_eq!(output["status"], "ok", "{output}");
+        broker.extra.lock().expect("lock").clone()
+    };
+
+    assert_eq!(start("slack")["team"], "T0001");
+    let unconfigured = start("discord");
+    assert!(unconfigured.get("guild_id").is_none(), "{unconfigured}");
+}
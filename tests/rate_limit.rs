@@ -0,0 +1,62 @@
+mod common;
+
+use component_oauth_card::{
+    InMemoryBroker, InMemoryStateStore, load_sign_in_rate_limit, register_clock,
+    register_state_store,
+};
+use serde_json::{Value, json};
+
+fn start(broker: &InMemoryBroker, subject: &str) -> Value {
+    invoke(broker, "start-sign-in", subject)
+}
+
+fn invoke(broker: &InMemoryBroker, mode: &str, subject: &str) -> Value {
+    let input = json!({
+        "mode": mode,
+        "provider_id": "acme",
+        "subject": subject,
+        "allow_auto_sign_in": true
+    });
+    common::invoke(broker, input)
+}
+
+#[test]
+fn repeated_sign_in_starts_are_throttled_per_subject() {
+    register_clock(|| 1_700_000_100).expect("clock registration");
+    register_state_store(InMemoryStateStore::new()).expect("state store registration");
+    load_sign_in_rate_limit(
+        &json!({ "sign_in_rate_limit": { "max_attempts": 2, "window_secs": 600 } }).to_string(),
+    )
+    .expect("rate limit config");
+    let broker = InMemoryBroker::new();
+
+    for _ in 0..2 {
+        let started = start(&broker, "user-1");
+        assert_eq!(started["status"], "ok", "{started}");
+        assert!(started["state_id"].is_string());
+    }
+
+    let throttled = start(&broker, "user-1");
+    assert_eq!(throttled["status"], "error", "{throttled}");
+    assert_eq!(throttled["error_code"], "rate_limited");
+    assert_eq!(throttled["retryable"], true);
+    assert!(throttled["state_id"].is_null());
+    assert_eq!(throttled["error_detail"]["retry_after_secs"], 600);
+    assert_eq!(
+        throttled["card"]["text"],
+        "too many sign-in attempts, try again in 10 minutes"
+    );
+    assert!(throttled["card"]["actions"].is_null(), "{throttled}");
+
+    let auto = invoke(&broker, "ensure-token", "user-1");
+    assert_eq!(auto["error_code"], "rate_limited", "{auto}");
+    assert!(auto["state_id"].is_null());
+
+    assert_eq!(start(&broker, "user-2")["status"], "ok");
+
+    load_sign_in_rate_limit(
+        &json!({ "sign_in_rate_limit": { "max_attempts": 0, "window_secs": 600 } }).to_string(),
+    )
+    .expect("rate limit config");
+    assert_eq!(start(&broker, "user-1")["status"], "ok");
+}
@@ -0,0 +1,27 @@
+use component_oauth_card::{InMemoryBroker, TokenSet, handle_message, register_backend};
+
+#[test]
+fn handle_message_uses_registered_backend() {
+    let broker = InMemoryBroker::new();
+    broker.seed(
+        "demo",
+        "user-1",
+        TokenSet {
+            access_token: "host-token".into(),
+            ..Default::default()
+        },
+    );
+    register_backend(broker).expect("first registration");
+    assert!(register_backend(InMemoryBroker::new()).is_err());
+
+    let input = serde_json::json!({
+        "mode": "ensure-token",
+        "provider_id": "demo",
+        "subject": "user-1"
+    })
+    .to_string();
+    let response = handle_message("invoke", &input);
+    let json: serde_json::Value = serde_json::from_str(&response).expect("valid json");
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["auth_header"]["headers"][0][1], "Bearer host-token");
+}
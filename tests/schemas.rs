@@ -0,0 +1,25 @@
+#![cfg(feature = "schema")]
+
+use std::path::Path;
+
+use component_oauth_card::schemas;
+
+#[test]
+fn committed_schemas_match_the_models() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    for (path, schema) in schemas() {
+        let generated = format!(
+            "{}\n",
+            serde_json::to_string_pretty(&schema).expect("schema json")
+        );
+        if std::env::var_os("UPDATE_SCHEMAS").is_some() {
+            std::fs::write(root.join(path), &generated).expect("write schema");
+            continue;
+        }
+        let committed = std::fs::read_to_string(root.join(path)).expect("read schema");
+        assert!(
+            committed == generated,
+            "{path} is out of date with the models; run `make schemas`"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::invoke;
+use component_oauth_card::{InMemoryBroker, load_provider_configs};
+use serde_json::json;
+
+#[test]
+fn scopes_outside_the_provider_allowlist_are_dropped_or_rejected() {
+    load_provider_configs(
+        &json!({
+            "providers": {
+                "graph": { "allowed_scopes": ["openid", "User.Read"] },
+                "strict": {
+                    "allowed_scopes": ["read"],
+                    "reject_disallowed_scopes": true
+                }
+            }
+        })
+        .to_string(),
+    )
+    .expect("provider config");
+    let broker = InMemoryBroker::new();
+
+    let started = invoke(
+        &broker,
+        json!({
+            "mode": "start-sign-in",
+            "provider_id": "graph",
+            "subject": "user-1",
+            "scopes": ["openid", "User.Read", "Directory.ReadWrite.All"]
+        }),
+    );
+    assert_eq!(started["status"], "ok");
+    assert_eq!(
+        started["dropped_scopes"],
+        json!(["Directory.ReadWrite.All"])
+    );
+    assert_eq!(
+        started["card"]["oauth"]["scopes"],
+        json!(["openid", "User.Read"])
+    );
+
+    let rejected = invoke(
+        &broker,
+        json!({
+            "mode": "ensure-token",
+            "provider_id": "strict",
+            "subject": "user-1",
+            "scopes": ["read", "admin"]
+        }),
+    );
+    assert_eq!(rejected["status"], "error");
+    assert_eq!(rejected["error_detail"]["field"], "scopes");
+    assert!(
+        rejected["error"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("admin")
+    );
+
+    let batch = invoke(
+        &broker,
+        json!({
+            "mode": "batch-ensure",
+            "provider_id": "graph",
+            "subject": "user-1",
+            "providers": [
+                { "provider_id": "graph", "scopes": ["User.Read", "Mail.Send"] },
+                { "provider_id": "strict", "scopes": ["admin"] }
+            ]
+        }),
+    );
+    assert_eq!(
+        batch["provider_results"][0]["dropped_scopes"],
+        json!(["Mail.Send"])
+    );
+    assert_eq!(batch["provider_results"][1]["status"], "error");
+
+    let unrestricted = invoke(
+        &broker,
+        json!({
+            "mode": "start-sign-in",
+            "provider_id": "other",
+            "subject": "user-1",
+            "scopes": ["anything"]
+        }),
+    );
+    assert!(unrestricted.get("dropped_scopes").is_none());
+}
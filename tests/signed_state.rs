@@ -0,0 +1,60 @@
+mod common;
+
+use common::invoke;
+use component_oauth_card::{InMemoryBroker, TokenSet, load_state_signing_key};
+use serde_json::{Value, json};
+
+fn complete(broker: &InMemoryBroker, subject: &str, state_id: &str) -> Value {
+    invoke(
+        broker,
+        json!({
+            "mode": "complete-sign-in",
+            "provider_id": "demo",
+            "subject": subject,
+            "state_id": state_id,
+            "auth_code": "code-123"
+        }),
+    )
+}
+
+#[test]
+fn signed_states_are_verified_on_completion() {
+    load_state_signing_key(&json!({ "state_signing_key": "s3cret" }).to_string())
+        .expect("state key config");
+    let broker = InMemoryBroker::new();
+    broker.seed(
+        "demo",
+        "user-1",
+        TokenSet {
+            access_token: "tok".into(),
+            ..Default::default()
+        },
+    );
+
+    let started = invoke(
+        &broker,
+        json!({ "mode": "start-sign-in", "provider_id": "demo", "subject": "user-1" }),
+    );
+    let state_id = started["state_id"].as_str().expect("state id").to_string();
+    assert_eq!(state_id.split('.').count(), 3);
+
+    for (subject, state) in [
+        ("user-1", "made-up".to_string()),
+        ("user-2", state_id.clone()),
+        ("user-1", state_id.replacen('.', ".1", 1)),
+    ] {
+        let rejected = complete(&broker, subject, &state);
+        assert_eq!(rejected["status"], "invalid-state", "{subject} {state}");
+        assert_eq!(rejected["error_code"], "invalid_state");
+        assert!(rejected["auth_header"].is_null());
+        assert_eq!(rejected["card"]["actions"][0]["title"], "Start again");
+    }
+
+    let completed = complete(&broker, "user-1", &state_id);
+    assert_eq!(completed["status"], "ok", "{completed}");
+
+    // Without a key, states are opaque again.
+    load_state_signing_key("{}").expect("clear key");
+    let completed = complete(&broker, "user-1", "made-up");
+    assert_eq!(completed["status"], "ok");
+}
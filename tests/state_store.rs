@@ -0,0 +1,94 @@
+use component_oauth_card::{
+    InMemoryBroker, InMemoryStateStore, handle_message, register_backend, register_state_store,
+};
+use serde_json::{Value, json};
+
+fn invoke(input: Value) -> Value {
+    let response = handle_message("invoke", &input.to_string());
+    serde_json::from_str(&response).expect("valid json")
+}
+
+#[test]
+fn sign_in_state_is_checked_against_the_store() {
+    register_backend(InMemoryBroker::new()).expect("backend registration");
+    register_state_store(InMemoryStateStore::new()).expect("first registration");
+    assert!(register_state_store(InMemoryStateStore::new()).is_err());
+
+    let started = invoke(json!({
+        "mode": "start-sign-in",
+        "provider_id": "demo",
+        "subject": "user-1",
+        "scopes": ["read"],
+        "redirect_path": "/oauth/callback/custom"
+    }));
+    assert_eq!(started["status"], "ok");
+    let state_id = started["state_id"].as_str().expect("state id").to_string();
+
+    let complete = |subject: &str, state_id: &str| {
+        invoke(json!({
+            "mode": "complete-sign-in",
+            "provider_id": "demo",
+            "subject": subject,
+            "state_id": state_id,
+            "auth_code": "code-123"
+        }))
+    };
+    let forged = complete("user-1", "made-up");
+    assert_eq!(forged["status"], "error");
+    assert!(
+        forged["error"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("unknown")
+    );
+    assert_eq!(complete("user-2", &state_id)["status"], "error");
+
+    let completed = complete("user-1", &state_id);
+    assert_eq!(completed["status"], "ok");
+    assert_eq!(completed["auth_context"]["subject"], "user-1");
+
+    // Delivering the finished callback again (same state_id, so same
+    // idempotency key) is a replay and gets no token.
+    let replayed = complete("user-1", &state_id);
+    assert_eq!(replayed["status"], "invalid-state", "{replayed}");
+    assert_eq!(replayed["error_code"], "replay");
+    assert!(replayed["auth_header"].is_null());
+    assert!(replayed["auth_injection"].is_null());
+
+    // The same code under another sign-in attempt is a replay.
+    let fresh = invoke(json!({
+        "mode": "start-sign-in",
+        "provider_id": "demo",
+        "subject": "user-1"
+    }));
+    let fresh_state = fresh["state_id"].as_str().expect("state id");
+    let replayed = complete("user-1", fresh_state);
+    assert_eq!(replayed["status"], "invalid-state", "{replayed}");
+    assert_eq!(replayed["error_code"], "replay");
+    assert!(replayed["auth_header"].is_null());
+
+    let restarted = invoke(json!({
+        "mode": "start-sign-in",
+        "provider_id": "demo",
+        "subject": "user-1"
+    }));
+    let state_id = restarted["state_id"]
+        .as_str()
+        .expect("state id")
+        .to_string();
+    let cancelled = invoke(json!({
+        "mode": "cancel-sign-in",
+        "provider_id": "demo",
+        "subject": "user-1",
+        "state_id": state_id
+    }));
+    assert_eq!(cancelled["status"], "ok", "{cancelled}");
+    let after_cancel = invoke(json!({
+        "mode": "complete-sign-in",
+        "provider_id": "demo",
+        "subject": "user-1",
+        "state_id": state_id,
+        "auth_code": "code-456"
+    }));
+    assert_eq!(after_cancel["status"], "error", "{after_cancel}");
+}